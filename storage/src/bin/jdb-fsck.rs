@@ -0,0 +1,70 @@
+// storage/src/bin/jdb-fsck.rs
+
+//! Check a database file for corruption without modifying it.
+//!
+//! Usage: `jdb-fsck <file>`. Exits with 0 if the file is clean (warnings
+//! allowed), 1 if errors were found and 2 if the file could not be checked.
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+use storage::fsck;
+use storage::PageType;
+
+const PAGE_TYPES: [PageType; 6] = [
+    PageType::Data,
+    PageType::Index,
+    PageType::Overflow,
+    PageType::Free,
+    PageType::Directory,
+    PageType::CommitLog,
+];
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let (Some(path), None) = (args.next(), args.next()) else {
+        eprintln!("usage: jdb-fsck <file>");
+        return ExitCode::from(2);
+    };
+    let path = PathBuf::from(path);
+
+    let report = match fsck::check(&path) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("jdb-fsck: cannot check {}: {}", path.display(), e);
+            return ExitCode::from(2);
+        }
+    };
+
+    println!(
+        "{}: {} pages of {} bytes, {} checked, {} double-write slots skipped",
+        path.display(),
+        report.page_count,
+        report.page_size,
+        report.pages_checked,
+        report.pages_skipped
+    );
+    for page_type in PAGE_TYPES {
+        let count = report.page_types.get(&page_type).copied().unwrap_or(0);
+        println!("  {:<10} {}", format!("{:?}", page_type), count);
+    }
+    println!("  free list  {}", report.free_list_length);
+
+    for issue in &report.issues {
+        let level = if issue.is_warning() {
+            "warning"
+        } else {
+            "error"
+        };
+        println!("{}: {}", level, issue);
+    }
+
+    let errors = report.errors().count();
+    let warnings = report.warnings().count();
+    println!("{} error(s), {} warning(s)", errors, warnings);
+
+    if report.is_clean() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
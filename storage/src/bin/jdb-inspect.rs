@@ -0,0 +1,89 @@
+// storage/src/bin/jdb-inspect.rs
+
+//! Dump one page of a database file, in the spirit of PostgreSQL's
+//! pageinspect.
+//!
+//! Usage: `jdb-inspect <file> <page> [--json | --hex]`. The default is a
+//! readable summary; `--json` prints the same `PageInfo` as JSON (needs the
+//! `serde` feature) and `--hex` prints the raw bytes, which also works for
+//! pages too damaged to parse.
+
+use std::path::Path;
+use std::process::ExitCode;
+use storage::file::PageFile;
+use storage::page::hex_dump;
+use storage::PageInfo;
+
+enum Format {
+    Text,
+    Json,
+    Hex,
+}
+
+const USAGE: &str = "usage: jdb-inspect <file> <page> [--json | --hex]";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (path, page, format) = match args.as_slice() {
+        [path, page] => (path, page, Format::Text),
+        [path, page, flag] if flag == "--json" => (path, page, Format::Json),
+        [path, page, flag] if flag == "--hex" => (path, page, Format::Hex),
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    let Ok(page_id) = page.parse::<u32>() else {
+        eprintln!("jdb-inspect: '{}' is not a page number", page);
+        return ExitCode::from(2);
+    };
+
+    match run(Path::new(path), page_id, format) {
+        Ok(output) => {
+            println!("{}", output.trim_end());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("jdb-inspect: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(path: &Path, page_id: u32, format: Format) -> anyhow::Result<String> {
+    let file = PageFile::open_read_only(path)?;
+    let header = file.header();
+    if page_id >= header.page_count() {
+        anyhow::bail!(
+            "page {} is past the end of the file ({} pages)",
+            page_id,
+            header.page_count()
+        );
+    }
+
+    let bytes = file.read_page_bytes(page_id)?;
+    if let Format::Hex = format {
+        return Ok(hex_dump(&bytes));
+    }
+
+    if page_id == 0 {
+        anyhow::bail!("page 0 is the file header; use --hex to dump it");
+    }
+    let info = PageInfo::from_bytes(&bytes)
+        .map_err(|e| anyhow::anyhow!("page {} cannot be parsed ({}); try --hex", page_id, e))?;
+
+    match format {
+        Format::Json => to_json(&info),
+        _ => Ok(info.to_string()),
+    }
+}
+
+#[cfg(feature = "serde")]
+fn to_json(info: &PageInfo) -> anyhow::Result<String> {
+    Ok(serde_json::to_string_pretty(info)?)
+}
+
+#[cfg(not(feature = "serde"))]
+fn to_json(_info: &PageInfo) -> anyhow::Result<String> {
+    anyhow::bail!("JSON output needs jdb-inspect built with the `serde` feature")
+}
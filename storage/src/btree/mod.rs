@@ -0,0 +1,903 @@
+// storage/src/btree/mod.rs
+
+mod node;
+
+use crate::buffer::BufferPool;
+use crate::page::PageType;
+use crate::txn::Transaction;
+use crate::wal::LogBody;
+use crate::{Result, StorageError};
+use node::Node;
+use std::io;
+use std::ops::Bound;
+
+/// A disk-resident B+ tree mapping byte-string keys to byte-string values.
+///
+/// Nodes are `Index` pages in slotted layout: leaves hold the entries and
+/// are chained both ways for range scans, internal nodes hold separator
+/// keys and child page ids. The root stays at the same page for the life of
+/// the tree, so the root page id is all that needs to be remembered to open
+/// it again. Keys are compared bytewise and are unique.
+///
+/// Entries are limited to a quarter of a page (`max_entry_size`). Changes
+/// made for a transaction are logged so that they can be rolled back.
+pub struct BTree {
+    root: u32,
+    page_size: usize,
+}
+
+impl BTree {
+    /// Allocate an empty tree in the pool's file.
+    pub fn create(pool: &BufferPool) -> Result<Self> {
+        let root = allocate_node(pool)?;
+        Node::new_leaf(root).store(pool)?;
+        Ok(Self {
+            root,
+            page_size: pool.page_size(),
+        })
+    }
+
+    /// Open the tree whose root lives at `root`.
+    pub fn open(pool: &BufferPool, root: u32) -> Result<Self> {
+        Node::load(pool, root)?;
+        Ok(Self {
+            root,
+            page_size: pool.page_size(),
+        })
+    }
+
+    pub fn root_page(&self) -> u32 {
+        self.root
+    }
+
+    /// Free every node of the tree, root included.
+    pub fn destroy(self, pool: &BufferPool) -> Result<()> {
+        let mut pending = vec![self.root];
+        while let Some(page_id) = pending.pop() {
+            let node = Node::load(pool, page_id)?;
+            pending.extend(&node.children);
+            pool.delete_page(page_id)?;
+        }
+        Ok(())
+    }
+
+    /// Largest key plus value, in bytes, the tree accepts. It grows with
+    /// the file's page size.
+    pub fn max_entry_size(&self) -> usize {
+        node::max_entry_size(self.page_size) - Node::leaf_entry_size(&[], &[])
+    }
+
+    pub fn get(&self, pool: &BufferPool, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let leaf = self.find_leaf(pool, key, &mut Vec::new())?;
+        Ok(leaf
+            .search(key)
+            .ok()
+            .map(|index| leaf.values[index].clone()))
+    }
+
+    /// Insert or replace `key`, returning the value it replaced.
+    pub fn insert(
+        &self,
+        pool: &BufferPool,
+        txn: Option<&Transaction>,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        if Node::leaf_entry_size(key, value) > node::max_entry_size(self.page_size) {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Index entry of {} bytes exceeds the maximum of {}",
+                    key.len() + value.len(),
+                    self.max_entry_size()
+                ),
+            )));
+        }
+
+        let mut path = Vec::new();
+        let mut leaf = self.find_leaf(pool, key, &mut path)?;
+
+        let old = match leaf.search(key) {
+            Ok(index) => Some(std::mem::replace(&mut leaf.values[index], value.to_vec())),
+            Err(index) => {
+                leaf.keys.insert(index, key.to_vec());
+                leaf.values.insert(index, value.to_vec());
+                None
+            }
+        };
+
+        if let Some(txn) = txn {
+            txn.log_undo(LogBody::IndexChange {
+                root: self.root,
+                key: key.to_vec(),
+                before: old.clone(),
+            })?;
+        }
+        self.settle(pool, path, leaf)?;
+        Ok(old)
+    }
+
+    /// Remove `key`, returning its value if it was present.
+    pub fn delete(
+        &self,
+        pool: &BufferPool,
+        txn: Option<&Transaction>,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        let mut path = Vec::new();
+        let mut leaf = self.find_leaf(pool, key, &mut path)?;
+
+        let Ok(index) = leaf.search(key) else {
+            return Ok(None);
+        };
+        leaf.keys.remove(index);
+        let old = leaf.values.remove(index);
+
+        if let Some(txn) = txn {
+            txn.log_undo(LogBody::IndexChange {
+                root: self.root,
+                key: key.to_vec(),
+                before: Some(old.clone()),
+            })?;
+        }
+        self.settle(pool, path, leaf)?;
+        Ok(Some(old))
+    }
+
+    /// Entries with keys between `start` and `end`, in key order. The
+    /// iterator is double-ended, so `.rev()` scans backwards.
+    pub fn range<'a>(
+        &self,
+        pool: &'a BufferPool,
+        start: Bound<&[u8]>,
+        end: Bound<&[u8]>,
+    ) -> Result<RangeIter<'a>> {
+        let front = match start {
+            Bound::Unbounded => Cursor::new(self.edge_leaf(pool, true)?, 0),
+            Bound::Included(key) => {
+                let leaf = self.find_leaf(pool, key, &mut Vec::new())?;
+                let index = leaf.keys.partition_point(|k| k.as_slice() < key);
+                Cursor::new(leaf, index)
+            }
+            Bound::Excluded(key) => {
+                let leaf = self.find_leaf(pool, key, &mut Vec::new())?;
+                let index = leaf.keys.partition_point(|k| k.as_slice() <= key);
+                Cursor::new(leaf, index)
+            }
+        };
+
+        let back = match end {
+            Bound::Unbounded => {
+                let leaf = self.edge_leaf(pool, false)?;
+                let index = leaf.keys.len();
+                Cursor::new(leaf, index)
+            }
+            Bound::Included(key) => {
+                let leaf = self.find_leaf(pool, key, &mut Vec::new())?;
+                let index = leaf.keys.partition_point(|k| k.as_slice() <= key);
+                Cursor::new(leaf, index)
+            }
+            Bound::Excluded(key) => {
+                let leaf = self.find_leaf(pool, key, &mut Vec::new())?;
+                let index = leaf.keys.partition_point(|k| k.as_slice() < key);
+                Cursor::new(leaf, index)
+            }
+        };
+
+        Ok(RangeIter {
+            pool,
+            front,
+            back,
+            start: start.map(<[u8]>::to_vec),
+            end: end.map(<[u8]>::to_vec),
+            last_front: None,
+            last_back: None,
+            done: false,
+        })
+    }
+
+    /// All entries in key order.
+    pub fn iter<'a>(&self, pool: &'a BufferPool) -> Result<RangeIter<'a>> {
+        self.range(pool, Bound::Unbounded, Bound::Unbounded)
+    }
+
+    /// Descend to the leaf that holds or would hold `key`, recording each
+    /// internal node passed and the child taken.
+    fn find_leaf(
+        &self,
+        pool: &BufferPool,
+        key: &[u8],
+        path: &mut Vec<(Node, usize)>,
+    ) -> Result<Node> {
+        let mut node = Node::load(pool, self.root)?;
+        while !node.is_leaf() {
+            let index = node.child_index(key);
+            let child = Node::load(pool, node.children[index])?;
+            path.push((node, index));
+            node = child;
+        }
+        Ok(node)
+    }
+
+    fn edge_leaf(&self, pool: &BufferPool, leftmost: bool) -> Result<Node> {
+        let mut node = Node::load(pool, self.root)?;
+        while !node.is_leaf() {
+            let child = if leftmost {
+                node.children[0]
+            } else {
+                node.children[node.children.len() - 1]
+            };
+            node = Node::load(pool, child)?;
+        }
+        Ok(node)
+    }
+
+    /// Write back a modified node, splitting it if it overflowed and
+    /// rebalancing it if it underflowed, then do the same for each parent
+    /// the change spreads to.
+    fn settle(
+        &self,
+        pool: &BufferPool,
+        mut path: Vec<(Node, usize)>,
+        mut node: Node,
+    ) -> Result<()> {
+        loop {
+            if !node.fits(self.page_size) {
+                if node.page_id == self.root {
+                    return self.grow(pool, node);
+                }
+
+                let (mut parent, index) = path.pop().expect("non-root node has a parent");
+                let right_id = allocate_node(pool)?;
+                let (separator, right) = node.split(right_id);
+
+                if right.is_leaf() && right.next != 0 {
+                    let mut after = Node::load(pool, right.next)?;
+                    after.prev = right_id;
+                    after.store(pool)?;
+                }
+                node.store(pool)?;
+                right.store(pool)?;
+
+                parent.keys.insert(index, separator);
+                parent.children.insert(index + 1, right_id);
+                node = parent;
+                continue;
+            }
+
+            if node.page_id == self.root {
+                // A root with a single child hands its page to that child
+                if !node.is_leaf() && node.keys.is_empty() {
+                    let mut child = Node::load(pool, node.children[0])?;
+                    let child_id = child.page_id;
+                    child.page_id = self.root;
+                    pool.delete_page(child_id)?;
+                    node = child;
+                    continue;
+                }
+                return node.store(pool);
+            }
+
+            if !node.underflows(self.page_size) {
+                return node.store(pool);
+            }
+
+            // Pair the node with a sibling under the same parent
+            let (mut parent, index) = path.pop().expect("non-root node has a parent");
+            let (mut left, mut right, separator_index) = if index > 0 {
+                (
+                    Node::load(pool, parent.children[index - 1])?,
+                    node,
+                    index - 1,
+                )
+            } else {
+                let right = Node::load(pool, parent.children[1])?;
+                (node, right, 0)
+            };
+
+            let separator = parent.keys[separator_index].clone();
+            match left.merge_or_redistribute(&mut right, separator, self.page_size) {
+                None => {
+                    if left.is_leaf() && left.next != 0 {
+                        let mut after = Node::load(pool, left.next)?;
+                        after.prev = left.page_id;
+                        after.store(pool)?;
+                    }
+                    left.store(pool)?;
+                    pool.delete_page(right.page_id)?;
+
+                    parent.keys.remove(separator_index);
+                    parent.children.remove(separator_index + 1);
+                }
+                Some(separator) => {
+                    left.store(pool)?;
+                    right.store(pool)?;
+                    parent.keys[separator_index] = separator;
+                }
+            }
+            node = parent;
+        }
+    }
+
+    /// Split an overflowing root into two new children, keeping the root
+    /// at its page.
+    fn grow(&self, pool: &BufferPool, mut root: Node) -> Result<()> {
+        let left_id = allocate_node(pool)?;
+        let right_id = allocate_node(pool)?;
+
+        root.page_id = left_id;
+        let (separator, right) = root.split(right_id);
+        root.store(pool)?;
+        right.store(pool)?;
+
+        let level = root.level + 1;
+        Node::new_internal(self.root, level, vec![separator], vec![left_id, right_id]).store(pool)
+    }
+}
+
+/// Allocate a page for a new node, which the caller then stores.
+fn allocate_node(pool: &BufferPool) -> Result<u32> {
+    Ok(pool.new_page(PageType::Index)?.header().page_id)
+}
+
+struct Cursor {
+    leaf: Node,
+    index: usize, // next entry forward, or one past the next entry backward
+}
+
+impl Cursor {
+    fn new(leaf: Node, index: usize) -> Self {
+        Self { leaf, index }
+    }
+}
+
+/// Iterator over a key range of a `BTree`, yielding `(key, value)` pairs.
+///
+/// The two ends walk the leaf chain independently and stop when they meet.
+/// An I/O error is yielded once and ends the iteration.
+pub struct RangeIter<'a> {
+    pool: &'a BufferPool,
+    front: Cursor,
+    back: Cursor,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
+    last_front: Option<Vec<u8>>,
+    last_back: Option<Vec<u8>>,
+    done: bool,
+}
+
+impl RangeIter<'_> {
+    fn advance_front(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        while self.front.index >= self.front.leaf.keys.len() {
+            if self.front.leaf.next == 0 {
+                return Ok(None);
+            }
+            self.front = Cursor::new(Node::load(self.pool, self.front.leaf.next)?, 0);
+        }
+
+        let key = &self.front.leaf.keys[self.front.index];
+        let in_range = match &self.end {
+            Bound::Included(end) => key <= end,
+            Bound::Excluded(end) => key < end,
+            Bound::Unbounded => true,
+        };
+        let before_back = self.last_back.as_ref().is_none_or(|back| key < back);
+        if !in_range || !before_back {
+            return Ok(None);
+        }
+
+        let entry = (
+            key.clone(),
+            self.front.leaf.values[self.front.index].clone(),
+        );
+        self.front.index += 1;
+        self.last_front = Some(entry.0.clone());
+        Ok(Some(entry))
+    }
+
+    fn advance_back(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        while self.back.index == 0 {
+            if self.back.leaf.prev == 0 {
+                return Ok(None);
+            }
+            let leaf = Node::load(self.pool, self.back.leaf.prev)?;
+            let index = leaf.keys.len();
+            self.back = Cursor::new(leaf, index);
+        }
+
+        let key = &self.back.leaf.keys[self.back.index - 1];
+        let in_range = match &self.start {
+            Bound::Included(start) => key >= start,
+            Bound::Excluded(start) => key > start,
+            Bound::Unbounded => true,
+        };
+        let after_front = self.last_front.as_ref().is_none_or(|front| key > front);
+        if !in_range || !after_front {
+            return Ok(None);
+        }
+
+        self.back.index -= 1;
+        let entry = (key.clone(), self.back.leaf.values[self.back.index].clone());
+        self.last_back = Some(entry.0.clone());
+        Ok(Some(entry))
+    }
+
+    fn finish(
+        &mut self,
+        result: Result<Option<(Vec<u8>, Vec<u8>)>>,
+    ) -> Option<Result<(Vec<u8>, Vec<u8>)>> {
+        match result {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl Iterator for RangeIter<'_> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.advance_front();
+        self.finish(result)
+    }
+}
+
+impl DoubleEndedIterator for RangeIter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let result = self.advance_back();
+        self.finish(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::FileOptions;
+    use crate::file::PageFile;
+    use crate::wal::Wal;
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+    use tempfile::{tempdir, TempDir};
+
+    fn setup() -> (TempDir, BufferPool, BTree) {
+        let dir = tempdir().unwrap();
+        let pool = BufferPool::new(
+            PageFile::create_new(&dir.path().join("test.jdb")).unwrap(),
+            64,
+        );
+        let tree = BTree::create(&pool).unwrap();
+        (dir, pool, tree)
+    }
+
+    fn key(n: u32) -> Vec<u8> {
+        format!("key-{:06}", n).into_bytes()
+    }
+
+    /// Variable-length values so splits and merges are uneven
+    fn value(n: u32) -> Vec<u8> {
+        vec![n as u8; 10 + (n as usize * 37) % 200]
+    }
+
+    fn collect(iter: impl Iterator<Item = Result<(Vec<u8>, Vec<u8>)>>) -> Vec<Vec<u8>> {
+        iter.map(|entry| entry.unwrap().0).collect()
+    }
+
+    /// Walk the whole tree checking key order, separators, node fill and the
+    /// leaf chain, returning the height.
+    fn check_invariants(pool: &BufferPool, tree: &BTree) -> u16 {
+        fn walk(
+            pool: &BufferPool,
+            page_id: u32,
+            low: Option<&[u8]>,
+            high: Option<&[u8]>,
+            is_root: bool,
+            leaves: &mut Vec<u32>,
+        ) -> u16 {
+            let node = Node::load(pool, page_id).unwrap();
+            assert!(node.fits(pool.page_size()));
+            assert!(
+                is_root || !node.underflows(pool.page_size()),
+                "page {} underflows",
+                page_id
+            );
+            assert!(node.keys.windows(2).all(|w| w[0] < w[1]));
+            if let Some(low) = low {
+                assert!(node.keys.iter().all(|k| k.as_slice() >= low));
+            }
+            if let Some(high) = high {
+                assert!(node.keys.iter().all(|k| k.as_slice() < high));
+            }
+
+            if node.is_leaf() {
+                leaves.push(page_id);
+                return 0;
+            }
+
+            assert_eq!(node.children.len(), node.keys.len() + 1);
+            let mut level = None;
+            for (index, &child) in node.children.iter().enumerate() {
+                let child_low = if index == 0 {
+                    low
+                } else {
+                    Some(node.keys[index - 1].as_slice())
+                };
+                let child_high = node.keys.get(index).map(|k| k.as_slice()).or(high);
+                let child_level = walk(pool, child, child_low, child_high, false, leaves);
+                assert_eq!(*level.get_or_insert(child_level), child_level);
+            }
+            assert_eq!(level.unwrap() + 1, node.level);
+            node.level
+        }
+
+        let mut leaves = Vec::new();
+        let height = walk(pool, tree.root_page(), None, None, true, &mut leaves);
+
+        for (index, &leaf) in leaves.iter().enumerate() {
+            let node = Node::load(pool, leaf).unwrap();
+            let prev = if index == 0 { 0 } else { leaves[index - 1] };
+            let next = leaves.get(index + 1).copied().unwrap_or(0);
+            assert_eq!((node.prev, node.next), (prev, next), "leaf {}", leaf);
+        }
+
+        height
+    }
+
+    #[test]
+    fn test_insert_and_get() {
+        let (_dir, pool, tree) = setup();
+
+        assert_eq!(tree.insert(&pool, None, b"apple", b"red").unwrap(), None);
+        assert_eq!(
+            tree.insert(&pool, None, b"banana", b"yellow").unwrap(),
+            None
+        );
+
+        assert_eq!(tree.get(&pool, b"apple").unwrap().unwrap(), b"red");
+        assert_eq!(tree.get(&pool, b"banana").unwrap().unwrap(), b"yellow");
+        assert_eq!(tree.get(&pool, b"cherry").unwrap(), None);
+    }
+
+    #[test]
+    fn test_insert_replaces_existing_value() {
+        let (_dir, pool, tree) = setup();
+
+        tree.insert(&pool, None, b"k", b"first").unwrap();
+        let old = tree.insert(&pool, None, b"k", b"second").unwrap();
+
+        assert_eq!(old.unwrap(), b"first");
+        assert_eq!(tree.get(&pool, b"k").unwrap().unwrap(), b"second");
+        assert_eq!(collect(tree.iter(&pool).unwrap()).len(), 1);
+    }
+
+    #[test]
+    fn test_many_inserts_split_nodes() {
+        let (_dir, pool, tree) = setup();
+
+        let mut order: Vec<u32> = (0..3000).collect();
+        order.shuffle(&mut StdRng::seed_from_u64(7));
+        for &n in &order {
+            tree.insert(&pool, None, &key(n), &value(n)).unwrap();
+        }
+
+        assert!(check_invariants(&pool, &tree) >= 1);
+        for n in 0..3000 {
+            assert_eq!(tree.get(&pool, &key(n)).unwrap().unwrap(), value(n));
+        }
+
+        let keys = collect(tree.iter(&pool).unwrap());
+        assert_eq!(keys, (0..3000).map(key).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_delete_merges_back_to_single_leaf() {
+        let (_dir, pool, tree) = setup();
+
+        let mut order: Vec<u32> = (0..2000).collect();
+        for &n in &order {
+            tree.insert(&pool, None, &key(n), &value(n)).unwrap();
+        }
+        let grown_to = pool.store().page_count();
+
+        order.shuffle(&mut StdRng::seed_from_u64(11));
+        for (deleted, &n) in order.iter().enumerate() {
+            assert_eq!(
+                tree.delete(&pool, None, &key(n)).unwrap().unwrap(),
+                value(n)
+            );
+            if deleted % 250 == 0 {
+                check_invariants(&pool, &tree);
+            }
+        }
+
+        assert_eq!(check_invariants(&pool, &tree), 0);
+        assert!(collect(tree.iter(&pool).unwrap()).is_empty());
+
+        // Freed nodes are reused instead of growing the file
+        for &n in &order {
+            tree.insert(&pool, None, &key(n), &value(n)).unwrap();
+        }
+        assert!(pool.store().page_count() <= grown_to + 2);
+    }
+
+    #[test]
+    fn test_long_keys_grow_and_shrink_height() {
+        let (_dir, pool, tree) = setup();
+        let long_key = |n: u32| {
+            let mut k = key(n);
+            k.resize(500, b'.');
+            k
+        };
+
+        for n in 0..600 {
+            tree.insert(&pool, None, &long_key(n), b"v").unwrap();
+        }
+        assert!(check_invariants(&pool, &tree) >= 2);
+
+        for n in (0..600).rev() {
+            tree.delete(&pool, None, &long_key(n)).unwrap().unwrap();
+        }
+        assert_eq!(check_invariants(&pool, &tree), 0);
+    }
+
+    #[test]
+    fn test_delete_missing_key() {
+        let (_dir, pool, tree) = setup();
+        tree.insert(&pool, None, b"a", b"1").unwrap();
+
+        assert_eq!(tree.delete(&pool, None, b"b").unwrap(), None);
+        assert_eq!(tree.delete(&pool, None, b"a").unwrap().unwrap(), b"1");
+        assert_eq!(tree.delete(&pool, None, b"a").unwrap(), None);
+    }
+
+    #[test]
+    fn test_matches_model_under_mixed_workload() {
+        let (_dir, pool, tree) = setup();
+        mixed_workload(&pool, &tree);
+    }
+
+    #[test]
+    fn test_matches_model_with_logged_record_changes() {
+        let (dir, mut pool, tree) = setup();
+        pool.attach_wal(Arc::new(Wal::open(&dir.path().join("wal")).unwrap()));
+        mixed_workload(&pool, &tree);
+    }
+
+    /// Random inserts, replacements and deletes checked against a `BTreeMap`.
+    fn mixed_workload(pool: &BufferPool, tree: &BTree) {
+        let mut model = BTreeMap::new();
+        let mut rng = StdRng::seed_from_u64(3);
+
+        for round in 0..6000u32 {
+            let n = rng.gen_range(0..800);
+            if round % 3 == 2 {
+                assert_eq!(
+                    tree.delete(pool, None, &key(n)).unwrap(),
+                    model.remove(&key(n))
+                );
+            } else {
+                let v = value(round);
+                assert_eq!(
+                    tree.insert(pool, None, &key(n), &v).unwrap(),
+                    model.insert(key(n), v)
+                );
+            }
+        }
+
+        check_invariants(pool, tree);
+        let entries: Vec<_> = tree.iter(pool).unwrap().map(|e| e.unwrap()).collect();
+        assert_eq!(entries, model.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_range_bounds() {
+        let (_dir, pool, tree) = setup();
+        for n in (0..1000).step_by(2) {
+            tree.insert(&pool, None, &key(n), &value(n)).unwrap();
+        }
+
+        let (k100, k200, k101) = (key(100), key(200), key(101));
+
+        let inclusive = tree
+            .range(&pool, Bound::Included(&k100), Bound::Included(&k200))
+            .unwrap();
+        assert_eq!(
+            collect(inclusive),
+            (100..=200).step_by(2).map(key).collect::<Vec<_>>()
+        );
+
+        let exclusive = tree
+            .range(&pool, Bound::Excluded(&k100), Bound::Excluded(&k200))
+            .unwrap();
+        assert_eq!(
+            collect(exclusive),
+            (102..200).step_by(2).map(key).collect::<Vec<_>>()
+        );
+
+        // Bounds that are not keys in the tree
+        let from_missing = tree
+            .range(&pool, Bound::Included(&k101), Bound::Unbounded)
+            .unwrap();
+        assert_eq!(
+            collect(from_missing),
+            (102..1000).step_by(2).map(key).collect::<Vec<_>>()
+        );
+
+        let empty = tree
+            .range(&pool, Bound::Included(&k200), Bound::Excluded(&k100))
+            .unwrap();
+        assert!(collect(empty).is_empty());
+    }
+
+    #[test]
+    fn test_range_backward() {
+        let (_dir, pool, tree) = setup();
+        for n in 0..1500 {
+            tree.insert(&pool, None, &key(n), &value(n)).unwrap();
+        }
+
+        let all = collect(tree.iter(&pool).unwrap().rev());
+        assert_eq!(all, (0..1500).rev().map(key).collect::<Vec<_>>());
+
+        let (k10, k1200) = (key(10), key(1200));
+        let bounded = tree
+            .range(&pool, Bound::Excluded(&k10), Bound::Included(&k1200))
+            .unwrap()
+            .rev();
+        assert_eq!(
+            collect(bounded),
+            (11..=1200).rev().map(key).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_range_ends_meet() {
+        let (_dir, pool, tree) = setup();
+        for n in 0..800 {
+            tree.insert(&pool, None, &key(n), &value(n)).unwrap();
+        }
+
+        let mut iter = tree.iter(&pool).unwrap();
+        let mut seen = Vec::new();
+        loop {
+            match (iter.next(), iter.next_back()) {
+                (Some(front), Some(back)) => {
+                    seen.push(front.unwrap().0);
+                    seen.push(back.unwrap().0);
+                }
+                (Some(front), None) => seen.push(front.unwrap().0),
+                (None, _) => break,
+            }
+        }
+
+        seen.sort();
+        assert_eq!(seen, (0..800).map(key).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_variable_length_keys() {
+        let (_dir, pool, tree) = setup();
+
+        let keys: Vec<Vec<u8>> = (0..400u32)
+            .map(|n| vec![b'a' + (n % 26) as u8; 1 + (n as usize * 13) % 600])
+            .collect();
+        for (n, k) in keys.iter().enumerate() {
+            tree.insert(&pool, None, k, &n.to_le_bytes()).unwrap();
+        }
+        tree.insert(&pool, None, b"", b"empty key").unwrap();
+
+        check_invariants(&pool, &tree);
+        assert_eq!(tree.get(&pool, b"").unwrap().unwrap(), b"empty key");
+
+        let mut expected: Vec<Vec<u8>> = keys.clone();
+        expected.push(Vec::new());
+        expected.sort();
+        expected.dedup();
+        assert_eq!(collect(tree.iter(&pool).unwrap()), expected);
+    }
+
+    #[test]
+    fn test_oversized_entry_rejected() {
+        let (_dir, pool, tree) = setup();
+
+        let big = vec![0u8; tree.max_entry_size() + 1];
+        assert!(tree.insert(&pool, None, b"k", &big).is_err());
+
+        let fits = vec![0u8; tree.max_entry_size() - 1];
+        tree.insert(&pool, None, b"k", &fits).unwrap();
+    }
+
+    #[test]
+    fn test_page_sizes() {
+        let dir = tempdir().unwrap();
+        let mut rng = StdRng::seed_from_u64(18);
+
+        for page_size in [4096, 65536] {
+            let options = FileOptions {
+                page_size,
+                ..FileOptions::default()
+            };
+            let path = dir.path().join(format!("{}.jdb", page_size));
+            let pool = BufferPool::new(PageFile::create_new_with(&path, options).unwrap(), 64);
+            let tree = BTree::create(&pool).unwrap();
+
+            // Entries are limited to a quarter of whatever the page size is
+            let big = vec![1u8; tree.max_entry_size()];
+            tree.insert(&pool, None, b"b", &big[1..]).unwrap();
+            assert!(tree.insert(&pool, None, b"b", &big).is_err());
+
+            let mut keys: Vec<u32> = (0..3000).collect();
+            keys.shuffle(&mut rng);
+            for &n in &keys {
+                tree.insert(&pool, None, &key(n), &value(n)).unwrap();
+            }
+            for &n in &keys[..2000] {
+                tree.delete(&pool, None, &key(n)).unwrap();
+            }
+
+            check_invariants(&pool, &tree);
+            assert_eq!(collect(tree.iter(&pool).unwrap()).len(), 1001);
+            assert_eq!(tree.get(&pool, b"b").unwrap().unwrap(), &big[1..]);
+        }
+    }
+
+    #[test]
+    fn test_reopen_tree() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+
+        let root = {
+            let pool = BufferPool::new(PageFile::create_new(&path).unwrap(), 64);
+            let tree = BTree::create(&pool).unwrap();
+            for n in 0..500 {
+                tree.insert(&pool, None, &key(n), &value(n)).unwrap();
+            }
+            pool.flush_all().unwrap();
+            tree.root_page()
+        };
+
+        let pool = BufferPool::new(PageFile::open(&path).unwrap(), 64);
+        let tree = BTree::open(&pool, root).unwrap();
+        assert_eq!(tree.get(&pool, &key(321)).unwrap().unwrap(), value(321));
+        assert_eq!(collect(tree.iter(&pool).unwrap()).len(), 500);
+    }
+
+    #[test]
+    fn test_destroy_frees_every_node() {
+        let (_dir, pool, tree) = setup();
+        for n in 0..2000 {
+            tree.insert(&pool, None, &key(n), &value(n)).unwrap();
+        }
+        let pages = pool.store().page_count();
+        tree.destroy(&pool).unwrap();
+
+        let tree = BTree::create(&pool).unwrap();
+        for n in 0..2000 {
+            tree.insert(&pool, None, &key(n), &value(n)).unwrap();
+        }
+        assert_eq!(pool.store().page_count(), pages);
+    }
+
+    #[test]
+    fn test_open_rejects_non_index_page() {
+        let (_dir, pool, _tree) = setup();
+        let page_id = pool.store().allocate_page().unwrap();
+
+        assert!(BTree::open(&pool, page_id).is_err());
+    }
+}
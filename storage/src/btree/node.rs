@@ -0,0 +1,429 @@
+// storage/src/btree/node.rs
+
+use crate::buffer::{BufferPool, PageWriteGuard};
+use crate::page::{Page, PageType};
+use crate::wal::{LogBody, NO_TXN};
+use crate::{Result, StorageError};
+use std::collections::HashMap;
+use std::io;
+
+/// Bytes available for slots and records on an index page
+fn node_capacity(page_size: usize) -> usize {
+    page_size - Page::HEADER_SIZE
+}
+
+/// Largest entry (record plus slot) a node accepts. Keeping entries to a
+/// quarter of a page guarantees that both halves of a split fit.
+pub(crate) fn max_entry_size(page_size: usize) -> usize {
+    node_capacity(page_size) / 4
+}
+
+/// Nodes using less than this are merged with or refilled from a sibling
+fn min_fill(page_size: usize) -> usize {
+    node_capacity(page_size) / 4
+}
+
+const LEAF_KEY_LEN_SIZE: usize = 2;
+const CHILD_SIZE: usize = 4;
+
+/// In-memory form of a B+ tree node, decoded from an `Index` page.
+///
+/// Leaf records are `key_len u16 | key | value`, in any slot order so that
+/// a single entry can be changed without moving the others. Internal
+/// records are `child u32 | key`, in key order; the first record's key is
+/// empty and the child it points to holds everything below the second
+/// record's key. Leaves at the same level are chained through
+/// `next_page`/`prev_page`.
+pub(crate) struct Node {
+    pub page_id: u32,
+    pub level: u16,
+    pub prev: u32,
+    pub next: u32,
+    pub keys: Vec<Vec<u8>>,
+    pub values: Vec<Vec<u8>>, // leaves only
+    pub children: Vec<u32>,   // internal nodes only, one more than keys
+}
+
+impl Node {
+    pub fn new_leaf(page_id: u32) -> Self {
+        Self {
+            page_id,
+            level: 0,
+            prev: 0,
+            next: 0,
+            keys: Vec::new(),
+            values: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn new_internal(page_id: u32, level: u16, keys: Vec<Vec<u8>>, children: Vec<u32>) -> Self {
+        Self {
+            page_id,
+            level,
+            prev: 0,
+            next: 0,
+            keys,
+            values: Vec::new(),
+            children,
+        }
+    }
+
+    pub fn leaf_entry_size(key: &[u8], value: &[u8]) -> usize {
+        LEAF_KEY_LEN_SIZE + key.len() + value.len() + Page::SLOT_SIZE
+    }
+
+    fn internal_entry_size(key: &[u8]) -> usize {
+        CHILD_SIZE + key.len() + Page::SLOT_SIZE
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.level == 0
+    }
+
+    pub fn load(pool: &BufferPool, page_id: u32) -> Result<Self> {
+        let page = pool.fetch_page(page_id)?;
+        let header = page.header();
+
+        if header.page_type != PageType::Index {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Page {} is not an index page", page_id),
+            )));
+        }
+
+        let mut node = Self::new_internal(page_id, header.level, Vec::new(), Vec::new());
+        node.prev = header.prev_page;
+        node.next = header.next_page;
+
+        for (index, record) in page.iter().enumerate() {
+            if node.is_leaf() {
+                let (len, rest) = Self::split_record(page_id, record, LEAF_KEY_LEN_SIZE)?;
+                let key_len = u16::from_le_bytes(len.try_into().unwrap()) as usize;
+                if key_len > rest.len() {
+                    return Err(Self::corrupt(page_id));
+                }
+                node.keys.push(rest[..key_len].to_vec());
+                node.values.push(rest[key_len..].to_vec());
+            } else {
+                let (child, key) = Self::split_record(page_id, record, CHILD_SIZE)?;
+                node.children
+                    .push(u32::from_le_bytes(child.try_into().unwrap()));
+                if index > 0 {
+                    node.keys.push(key.to_vec());
+                }
+            }
+        }
+
+        if !node.is_leaf() && node.children.is_empty() {
+            return Err(Self::corrupt(page_id));
+        }
+
+        if node.is_leaf() && !node.keys.is_sorted() {
+            let mut entries: Vec<_> = node.keys.drain(..).zip(node.values.drain(..)).collect();
+            entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+            (node.keys, node.values) = entries.into_iter().unzip();
+        }
+
+        Ok(node)
+    }
+
+    /// Write the node to its page. A leaf already on the page is updated
+    /// entry by entry, logging each inserted, replaced or removed record;
+    /// anything else, or changes that would log more than a page, is
+    /// written and logged as a whole page image.
+    pub fn store(&self, pool: &BufferPool) -> Result<()> {
+        let mut target = pool.fetch_page_mut(self.page_id)?;
+        if self.store_leaf_changes(&mut target)? {
+            return Ok(());
+        }
+
+        let mut page = Page::with_size(pool.page_size(), self.page_id, PageType::Index);
+        {
+            let header = page.header_mut();
+            header.level = self.level;
+            header.prev_page = self.prev;
+            header.next_page = self.next;
+        }
+
+        for index in 0..self.entry_count() {
+            if page.add_record(&self.record(index)).is_none() {
+                return Err(StorageError::PageFull(self.page_id));
+            }
+        }
+
+        target.as_bytes_mut().copy_from_slice(page.as_bytes());
+        target.log_image()
+    }
+
+    /// Bring `target` up to date with this leaf one record at a time,
+    /// logging each change as it is made. `false` if the page does not
+    /// already hold this leaf, the changes are too large, or one does not
+    /// fit without rebuilding the page; whatever was changed by then is
+    /// logged, and replaced by the rebuilt page.
+    fn store_leaf_changes(&self, target: &mut PageWriteGuard<'_>) -> Result<bool> {
+        let header = target.header();
+        // A page that was never logged needs its first image
+        if !self.is_leaf()
+            || header.lsn == 0
+            || header.page_type != PageType::Index
+            || header.level != self.level
+            || header.prev_page != self.prev
+            || header.next_page != self.next
+        {
+            return Ok(false);
+        }
+
+        let mut stored: HashMap<&[u8], (u16, &[u8])> = HashMap::new();
+        for (slot, record) in target.iter_with_slots() {
+            let (len, rest) = Self::split_record(self.page_id, record, LEAF_KEY_LEN_SIZE)?;
+            let key_len = u16::from_le_bytes(len.try_into().unwrap()) as usize;
+            if key_len > rest.len() {
+                return Err(Self::corrupt(self.page_id));
+            }
+            stored.insert(&rest[..key_len], (slot as u16, record));
+        }
+
+        let mut updates = Vec::new();
+        let mut inserts = Vec::new();
+        for (index, key) in self.keys.iter().enumerate() {
+            let record = self.record(index);
+            match stored.remove(key.as_slice()) {
+                Some((_, before)) if before == record.as_slice() => {}
+                Some((slot, before)) => updates.push((slot, before.to_vec(), record)),
+                None => inserts.push(record),
+            }
+        }
+        let mut deletes: Vec<(u16, Vec<u8>)> = stored
+            .into_values()
+            .map(|(slot, data)| (slot, data.to_vec()))
+            .collect();
+        deletes.sort_unstable_by_key(|&(slot, _)| slot);
+
+        let logged = deletes.iter().map(|(_, data)| data.len()).sum::<usize>()
+            + updates
+                .iter()
+                .map(|(_, before, after)| before.len() + after.len())
+                .sum::<usize>()
+            + inserts.iter().map(Vec::len).sum::<usize>();
+        if logged >= target.size() {
+            return Ok(false);
+        }
+
+        // Made with the same calls recovery replays them with
+        let page_id = self.page_id;
+        for (slot, data) in deletes {
+            target.delete_record(slot as usize);
+            target.log_change(
+                NO_TXN,
+                LogBody::Delete {
+                    page_id,
+                    slot,
+                    data,
+                },
+            )?;
+        }
+        for (slot, before, after) in updates {
+            match target.update_record(slot as usize, &after) {
+                Ok(()) => {}
+                Err(StorageError::PageFull(_)) => return Ok(false),
+                Err(e) => return Err(e),
+            }
+            target.log_change(
+                NO_TXN,
+                LogBody::Update {
+                    page_id,
+                    slot,
+                    before,
+                    after,
+                },
+            )?;
+        }
+        for data in inserts {
+            let slot_count = target.header().slot_count as usize;
+            let slot = (0..slot_count)
+                .find(|&i| target.get_slot(i).is_some_and(|slot| slot.length == 0))
+                .unwrap_or(slot_count);
+            match target.insert_record_at(slot, &data) {
+                Ok(()) => {}
+                Err(StorageError::PageFull(_)) => return Ok(false),
+                Err(e) => return Err(e),
+            }
+            target.log_change(
+                NO_TXN,
+                LogBody::Insert {
+                    page_id,
+                    slot: slot as u16,
+                    data,
+                },
+            )?;
+        }
+
+        Ok(true)
+    }
+
+    /// The stored form of entry `index`.
+    fn record(&self, index: usize) -> Vec<u8> {
+        let mut record = Vec::new();
+        if self.is_leaf() {
+            let key = &self.keys[index];
+            record.extend_from_slice(&(key.len() as u16).to_le_bytes());
+            record.extend_from_slice(key);
+            record.extend_from_slice(&self.values[index]);
+        } else {
+            record.extend_from_slice(&self.children[index].to_le_bytes());
+            if index > 0 {
+                record.extend_from_slice(&self.keys[index - 1]);
+            }
+        }
+        record
+    }
+
+    fn entry_count(&self) -> usize {
+        if self.is_leaf() {
+            self.keys.len()
+        } else {
+            self.children.len()
+        }
+    }
+
+    /// Bytes of the page this node occupies below the header.
+    pub fn size(&self) -> usize {
+        if self.is_leaf() {
+            self.keys
+                .iter()
+                .zip(&self.values)
+                .map(|(key, value)| Self::leaf_entry_size(key, value))
+                .sum()
+        } else {
+            Self::internal_entry_size(&[])
+                + self
+                    .keys
+                    .iter()
+                    .map(|key| Self::internal_entry_size(key))
+                    .sum::<usize>()
+        }
+    }
+
+    pub fn fits(&self, page_size: usize) -> bool {
+        self.size() <= node_capacity(page_size)
+    }
+
+    pub fn underflows(&self, page_size: usize) -> bool {
+        self.size() < min_fill(page_size)
+    }
+
+    /// Index of the child whose subtree may contain `key`.
+    pub fn child_index(&self, key: &[u8]) -> usize {
+        self.keys.partition_point(|k| k.as_slice() <= key)
+    }
+
+    pub fn search(&self, key: &[u8]) -> std::result::Result<usize, usize> {
+        self.keys.binary_search_by(|k| k.as_slice().cmp(key))
+    }
+
+    /// Move the upper half of an overfull node into a new node stored at
+    /// `right_id`, returning the separator key for the parent.
+    pub fn split(&mut self, right_id: u32) -> (Vec<u8>, Node) {
+        if self.is_leaf() {
+            let sizes: Vec<usize> = self
+                .keys
+                .iter()
+                .zip(&self.values)
+                .map(|(key, value)| Self::leaf_entry_size(key, value))
+                .collect();
+            let at = (midpoint(&sizes) + 1).clamp(1, self.keys.len() - 1);
+
+            let mut right = Node::new_leaf(right_id);
+            right.keys = self.keys.split_off(at);
+            right.values = self.values.split_off(at);
+            right.prev = self.page_id;
+            right.next = self.next;
+            self.next = right_id;
+
+            (right.keys[0].clone(), right)
+        } else {
+            let sizes: Vec<usize> = self
+                .keys
+                .iter()
+                .map(|key| Self::internal_entry_size(key))
+                .collect();
+            let up = midpoint(&sizes);
+
+            let mut right_keys = self.keys.split_off(up);
+            let separator = right_keys.remove(0);
+            let right_children = self.children.split_off(up + 1);
+
+            let right = Node::new_internal(right_id, self.level, right_keys, right_children);
+            (separator, right)
+        }
+    }
+
+    /// Rebalance this node with its right sibling, `separator` being the
+    /// parent key between them. Returns `None` when everything fits here and
+    /// `right` should be dropped, or the new separator after entries were
+    /// shared out evenly.
+    pub fn merge_or_redistribute(
+        &mut self,
+        right: &mut Node,
+        separator: Vec<u8>,
+        page_size: usize,
+    ) -> Option<Vec<u8>> {
+        if self.is_leaf() {
+            self.keys.append(&mut right.keys);
+            self.values.append(&mut right.values);
+
+            if self.fits(page_size) {
+                self.next = right.next;
+                return None;
+            }
+
+            // Sibling links are unchanged: only the entries move
+            let (separator, upper) = self.split(right.page_id);
+            self.next = right.page_id;
+            right.keys = upper.keys;
+            right.values = upper.values;
+            Some(separator)
+        } else {
+            self.keys.push(separator);
+            self.keys.append(&mut right.keys);
+            self.children.append(&mut right.children);
+
+            if self.fits(page_size) {
+                return None;
+            }
+
+            let (separator, upper) = self.split(right.page_id);
+            right.keys = upper.keys;
+            right.children = upper.children;
+            Some(separator)
+        }
+    }
+
+    fn split_record(page_id: u32, record: &[u8], at: usize) -> Result<(&[u8], &[u8])> {
+        if record.len() < at {
+            return Err(Self::corrupt(page_id));
+        }
+        Ok(record.split_at(at))
+    }
+
+    fn corrupt(page_id: u32) -> StorageError {
+        StorageError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Malformed index record on page {}", page_id),
+        ))
+    }
+}
+
+/// First index at which the running total reaches half of `sizes`' sum.
+fn midpoint(sizes: &[usize]) -> usize {
+    let total: usize = sizes.iter().sum();
+    let mut running = 0;
+    for (index, size) in sizes.iter().enumerate() {
+        running += size;
+        if running * 2 >= total {
+            return index;
+        }
+    }
+    sizes.len().saturating_sub(1)
+}
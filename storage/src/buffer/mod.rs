@@ -0,0 +1,1046 @@
+// storage/src/buffer/mod.rs
+
+use crate::page::{Page, PageType};
+use crate::store::PageStore;
+use crate::wal::{LogBody, TxnId, Wal, NO_TXN};
+use crate::{Result, StorageError};
+use parking_lot::{Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::collections::HashMap;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+
+mod replacer;
+
+pub use replacer::{ClockReplacer, EvictionPolicy, LruKReplacer, Replacer, TwoQReplacer};
+
+/// Index of a frame inside the buffer pool
+pub type FrameId = usize;
+
+struct Frame {
+    page: RwLock<Box<Page>>,
+    pin_count: AtomicU32,
+    dirty: AtomicBool,
+}
+
+impl Frame {
+    fn new(page_size: usize) -> Self {
+        Self {
+            page: RwLock::new(Page::with_size(page_size, 0, PageType::Free)),
+            pin_count: AtomicU32::new(0),
+            dirty: AtomicBool::new(false),
+        }
+    }
+}
+
+/// Where the pool keeps a page, and whether it can be used yet.
+#[derive(Debug, Clone, Copy)]
+enum Slot {
+    /// Being read in or written back; wait on `io_done` and look again
+    InFlight,
+    Ready(FrameId),
+}
+
+struct PoolState {
+    page_table: HashMap<u32, Slot>, // page id -> frame holding it
+    frame_pages: Vec<Option<u32>>,  // frame -> page id it holds
+    free_frames: Vec<FrameId>,
+    replacer: Box<dyn Replacer>,
+}
+
+/// Cache counters, for comparing eviction policies under a workload.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BufferPoolStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+impl BufferPoolStats {
+    pub fn hit_ratio(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / total as f64
+    }
+}
+
+#[derive(Default)]
+struct StatCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+/// A fixed-size cache of pages sitting between callers and a `PageStore`,
+/// normally a `PageFile`.
+///
+/// Pages are pinned for as long as a `PageReadGuard` or `PageWriteGuard` is
+/// alive; only unpinned frames are considered for eviction. Modified pages
+/// are written back when they are evicted or flushed, after the attached
+/// write-ahead log is flushed up to the LSN stamped in them. Which unpinned
+/// page goes is decided by the pool's `Replacer`.
+///
+/// The pool lock is never held across I/O. A page being read in or written
+/// back has an in-flight placeholder in the page table, and anyone else
+/// after that page waits for it on `io_done` instead of repeating the I/O.
+pub struct BufferPool {
+    store: Box<dyn PageStore>,
+    frames: Box<[Frame]>,
+    state: Mutex<PoolState>,
+    io_done: Condvar,
+    stats: StatCounters,
+    checksums: bool,
+    wal: Option<Arc<Wal>>,
+}
+
+impl BufferPool {
+    pub fn new(store: impl PageStore + 'static, capacity: usize) -> Self {
+        Self::with_policy(store, capacity, EvictionPolicy::default())
+    }
+
+    pub fn with_policy(
+        store: impl PageStore + 'static,
+        capacity: usize,
+        policy: EvictionPolicy,
+    ) -> Self {
+        assert!(capacity > 0, "buffer pool needs at least one frame");
+
+        let frames = (0..capacity)
+            .map(|_| Frame::new(store.page_size()))
+            .collect();
+        let state = PoolState {
+            page_table: HashMap::with_capacity(capacity),
+            frame_pages: vec![None; capacity],
+            // Reversed so frames are handed out in order
+            free_frames: (0..capacity).rev().collect(),
+            replacer: policy.build(capacity),
+        };
+
+        Self {
+            checksums: store.checksums_enabled(),
+            store: Box::new(store),
+            frames,
+            state: Mutex::new(state),
+            io_done: Condvar::new(),
+            stats: StatCounters::default(),
+            wal: None,
+        }
+    }
+
+    /// Enforce write-ahead logging: a page is only written back once the
+    /// log is durable up to the LSN stamped in it, and changes made
+    /// through `PageWriteGuard::log_change` are logged to `wal`.
+    pub fn attach_wal(&mut self, wal: Arc<Wal>) {
+        self.wal = Some(wal);
+    }
+
+    /// The store the pool caches pages of, for what lives outside pages.
+    pub fn store(&self) -> &dyn PageStore {
+        self.store.as_ref()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Size of the pages in the underlying store.
+    pub fn page_size(&self) -> usize {
+        self.store.page_size()
+    }
+
+    /// Pin a page for reading, loading it from disk if it is not cached.
+    pub fn fetch_page(&self, page_id: u32) -> Result<PageReadGuard<'_>> {
+        let frame_id = self.pin(page_id, |page_id| self.store.read_page(page_id))?;
+        let guard = self.frames[frame_id].page.read();
+
+        Ok(PageReadGuard {
+            pool: self,
+            frame_id,
+            guard: Some(guard),
+        })
+    }
+
+    /// Pin a page for writing, loading it from disk if it is not cached.
+    pub fn fetch_page_mut(&self, page_id: u32) -> Result<PageWriteGuard<'_>> {
+        let frame_id = self.pin(page_id, |page_id| self.store.read_page(page_id))?;
+        let guard = self.frames[frame_id].page.write();
+
+        Ok(PageWriteGuard {
+            pool: self,
+            frame_id,
+            guard: Some(guard),
+        })
+    }
+
+    /// Pin a page for recovery to redo a change on. A page the store cannot
+    /// read, because it is past the end or torn, comes back blank when
+    /// `replaces_page` says the change rewrites the whole page anyway.
+    pub(crate) fn fetch_page_for_redo(
+        &self,
+        page_id: u32,
+        replaces_page: bool,
+    ) -> Result<PageWriteGuard<'_>> {
+        let frame_id = self.pin(page_id, |page_id| {
+            let blank = || Page::with_size(self.store.page_size(), page_id, PageType::Free);
+            if page_id >= self.store.page_count() {
+                return Ok(blank());
+            }
+            match self.store.read_page(page_id) {
+                Err(_) if replaces_page => Ok(blank()),
+                read => read,
+            }
+        })?;
+        let guard = self.frames[frame_id].page.write();
+
+        Ok(PageWriteGuard {
+            pool: self,
+            frame_id,
+            guard: Some(guard),
+        })
+    }
+
+    /// Allocate a page in the store and return it pinned and initialised.
+    pub fn new_page(&self, page_type: PageType) -> Result<PageWriteGuard<'_>> {
+        let (frame_id, victim) = self.reserve_frame(&mut self.state.lock())?;
+        let allocated = self
+            .evict(frame_id, victim)
+            .and_then(|()| self.store.allocate_page());
+
+        let mut state = self.state.lock();
+        let page_id = match allocated {
+            Ok(page_id) => page_id,
+            Err(e) => {
+                self.release_frame(&mut state, frame_id);
+                return Err(e);
+            }
+        };
+
+        let frame = &self.frames[frame_id];
+        *frame.page.write() = Page::with_size(self.store.page_size(), page_id, page_type);
+        frame.dirty.store(true, Ordering::Release);
+
+        state.page_table.insert(page_id, Slot::Ready(frame_id));
+        state.frame_pages[frame_id] = Some(page_id);
+        state.replacer.record_access(frame_id, page_id);
+        drop(state);
+
+        // Dirty from the start so the initial page reaches disk even if the
+        // caller never modifies it
+        Ok(PageWriteGuard {
+            pool: self,
+            frame_id,
+            guard: Some(frame.page.write()),
+        })
+    }
+
+    /// Drop a page from the pool and return it to the store.
+    pub fn delete_page(&self, page_id: u32) -> Result<()> {
+        let mut state = self.state.lock();
+
+        if let Some(frame_id) = self.wait_for_page(&mut state, page_id) {
+            let frame = &self.frames[frame_id];
+            if frame.pin_count.load(Ordering::Acquire) > 0 {
+                return Err(StorageError::PagePinned(page_id));
+            }
+
+            frame.dirty.store(false, Ordering::Release);
+            state.page_table.remove(&page_id);
+            state.frame_pages[frame_id] = None;
+            state.replacer.remove(frame_id);
+            state.free_frames.push(frame_id);
+        }
+
+        self.store.free_page(page_id)
+    }
+
+    /// Write a cached page back to disk if it has been modified.
+    pub fn flush_page(&self, page_id: u32) -> Result<()> {
+        let frame_id = {
+            let mut state = self.state.lock();
+            match self.wait_for_page(&mut state, page_id) {
+                Some(frame_id) => {
+                    self.frames[frame_id]
+                        .pin_count
+                        .fetch_add(1, Ordering::AcqRel);
+                    frame_id
+                }
+                None => return Ok(()),
+            }
+        };
+
+        // The frame is pinned, so it cannot be evicted while we wait for the
+        // page latch without holding the pool lock.
+        let result = {
+            let frame = &self.frames[frame_id];
+            let page = frame.page.write();
+            self.write_back(frame, &page)
+        };
+        self.unpin(frame_id);
+
+        result
+    }
+
+    /// Write every modified page back to the store and sync it.
+    pub fn flush_all(&self) -> Result<()> {
+        let page_ids: Vec<u32> = self.state.lock().page_table.keys().copied().collect();
+
+        for page_id in page_ids {
+            self.flush_page(page_id)?;
+        }
+
+        self.store.sync()
+    }
+
+    /// Number of pages currently cached.
+    pub fn cached_pages(&self) -> usize {
+        let state = self.state.lock();
+        state
+            .page_table
+            .values()
+            .filter(|slot| matches!(slot, Slot::Ready(_)))
+            .count()
+    }
+
+    pub fn stats(&self) -> BufferPoolStats {
+        BufferPoolStats {
+            hits: self.stats.hits.load(Ordering::Relaxed),
+            misses: self.stats.misses.load(Ordering::Relaxed),
+            evictions: self.stats.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn reset_stats(&self) {
+        self.stats.hits.store(0, Ordering::Relaxed);
+        self.stats.misses.store(0, Ordering::Relaxed);
+        self.stats.evictions.store(0, Ordering::Relaxed);
+    }
+
+    fn pin(&self, page_id: u32, load: impl FnOnce(u32) -> Result<Box<Page>>) -> Result<FrameId> {
+        let mut state = self.state.lock();
+
+        if let Some(frame_id) = self.wait_for_page(&mut state, page_id) {
+            self.frames[frame_id]
+                .pin_count
+                .fetch_add(1, Ordering::AcqRel);
+            state.replacer.record_access(frame_id, page_id);
+            self.stats.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(frame_id);
+        }
+
+        self.stats.misses.fetch_add(1, Ordering::Relaxed);
+        let (frame_id, victim) = self.reserve_frame(&mut state)?;
+        // Later misses on the page wait for this read rather than repeat it
+        state.page_table.insert(page_id, Slot::InFlight);
+        drop(state);
+
+        let frame = &self.frames[frame_id];
+        let loaded = self
+            .evict(frame_id, victim)
+            .and_then(|()| load(page_id))
+            .map(|page| {
+                *frame.page.write() = page;
+                frame.dirty.store(false, Ordering::Release);
+            });
+
+        let mut state = self.state.lock();
+        if let Err(e) = loaded {
+            state.page_table.remove(&page_id);
+            self.release_frame(&mut state, frame_id);
+            return Err(e);
+        }
+
+        state.page_table.insert(page_id, Slot::Ready(frame_id));
+        state.frame_pages[frame_id] = Some(page_id);
+        state.replacer.record_access(frame_id, page_id);
+        self.io_done.notify_all();
+
+        Ok(frame_id)
+    }
+
+    fn unpin(&self, frame_id: FrameId) {
+        let previous = self.frames[frame_id]
+            .pin_count
+            .fetch_sub(1, Ordering::AcqRel);
+        debug_assert!(previous > 0, "unpinned frame {} too many times", frame_id);
+    }
+
+    /// The frame holding `page_id`, once no I/O on it is in flight.
+    fn wait_for_page(
+        &self,
+        state: &mut MutexGuard<'_, PoolState>,
+        page_id: u32,
+    ) -> Option<FrameId> {
+        loop {
+            match state.page_table.get(&page_id) {
+                Some(&Slot::Ready(frame_id)) => return Some(frame_id),
+                Some(Slot::InFlight) => self.io_done.wait(state),
+                None => return None,
+            }
+        }
+    }
+
+    /// Claim an empty frame, or an unpinned one to evict, and pin it so
+    /// nothing else can. Returns the page the frame still holds, which the
+    /// caller passes to `evict` once the pool lock is released.
+    fn reserve_frame(&self, state: &mut PoolState) -> Result<(FrameId, Option<u32>)> {
+        let frame_id = match state.free_frames.pop() {
+            Some(frame_id) => frame_id,
+            None => {
+                let frames = &self.frames;
+                state
+                    .replacer
+                    .victim(&|frame_id| frames[frame_id].pin_count.load(Ordering::Acquire) == 0)
+                    .ok_or(StorageError::NoFreeFrames)?
+            }
+        };
+        self.frames[frame_id].pin_count.store(1, Ordering::Release);
+
+        let victim = state.frame_pages[frame_id];
+        if let Some(page_id) = victim {
+            // Fetches of the old page wait until it is safely on disk
+            state.page_table.insert(page_id, Slot::InFlight);
+        }
+
+        Ok((frame_id, victim))
+    }
+
+    /// Write back the page a reserved frame still holds and drop it from
+    /// the pool. On failure the page stays put for `release_frame`.
+    fn evict(&self, frame_id: FrameId, victim: Option<u32>) -> Result<()> {
+        let Some(page_id) = victim else {
+            return Ok(());
+        };
+
+        // Only the reserving caller can reach the frame, so the latch is free
+        let frame = &self.frames[frame_id];
+        self.write_back(frame, &frame.page.write())?;
+
+        let mut state = self.state.lock();
+        state.page_table.remove(&page_id);
+        state.frame_pages[frame_id] = None;
+        self.stats.evictions.fetch_add(1, Ordering::Relaxed);
+        self.io_done.notify_all();
+
+        Ok(())
+    }
+
+    /// Give back a reserved frame that did not receive its new page. A page
+    /// it failed to evict stays cached for the replacer to consider again.
+    fn release_frame(&self, state: &mut PoolState, frame_id: FrameId) {
+        self.frames[frame_id].pin_count.store(0, Ordering::Release);
+        match state.frame_pages[frame_id] {
+            Some(page_id) => {
+                state.page_table.insert(page_id, Slot::Ready(frame_id));
+                state.replacer.record_access(frame_id, page_id);
+            }
+            None => state.free_frames.push(frame_id),
+        }
+        self.io_done.notify_all();
+    }
+
+    fn write_back(&self, frame: &Frame, page: &Page) -> Result<()> {
+        if frame.dirty.load(Ordering::Acquire) {
+            // The log describing the page's changes must be durable first
+            if let Some(wal) = &self.wal {
+                wal.flush_to(page.header().lsn)?;
+            }
+            self.store.write_page(page)?;
+            frame.dirty.store(false, Ordering::Release);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for BufferPool {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush_all() {
+            log::warn!("Failed to flush buffer pool on drop: {}", e);
+        }
+    }
+}
+
+/// Shared access to a pinned page. The page is unpinned when dropped.
+pub struct PageReadGuard<'a> {
+    pool: &'a BufferPool,
+    frame_id: FrameId,
+    guard: Option<RwLockReadGuard<'a, Box<Page>>>,
+}
+
+impl Deref for PageReadGuard<'_> {
+    type Target = Page;
+
+    fn deref(&self) -> &Page {
+        self.guard.as_ref().expect("guard is only taken on drop")
+    }
+}
+
+impl Drop for PageReadGuard<'_> {
+    fn drop(&mut self) {
+        // Release the latch before the pin so an evictor never waits on it
+        self.guard.take();
+        self.pool.unpin(self.frame_id);
+    }
+}
+
+/// Exclusive access to a pinned page. Mutable access marks the page dirty,
+/// and the page is unpinned when dropped.
+pub struct PageWriteGuard<'a> {
+    pool: &'a BufferPool,
+    frame_id: FrameId,
+    guard: Option<RwLockWriteGuard<'a, Box<Page>>>,
+}
+
+impl PageWriteGuard<'_> {
+    /// Log a change already made to the page on behalf of `txn_id`,
+    /// stamping the record's LSN into the page. Without a write-ahead log
+    /// attached to the pool there is nothing to do.
+    ///
+    /// The first change to a page since the log's redo point also logs an
+    /// image of it, so recovery can rebuild the page should its next write
+    /// tear. A redo-only change is logged as an image of the result; a
+    /// transaction's change, which must stay in the log to be undone, is
+    /// preceded by an image of the page without it. Each change must be
+    /// logged before the next one is made.
+    pub fn log_change(&mut self, txn_id: TxnId, body: LogBody) -> Result<()> {
+        let pool = self.pool;
+        let Some(wal) = &pool.wal else {
+            return Ok(());
+        };
+
+        let redo_lsn = wal.hold_redo_lsn();
+        let replaces_page = matches!(body, LogBody::PageImage { .. });
+        if !replaces_page && self.header().lsn < *redo_lsn {
+            let page_id = self.header().page_id;
+            if txn_id == NO_TXN {
+                let image = self.as_bytes().to_vec();
+                wal.log_page(NO_TXN, self, LogBody::PageImage { page_id, image })?;
+                return Ok(());
+            }
+
+            let undo = match &body {
+                LogBody::Compensation { redo, .. } => redo.inverse(),
+                body => body.inverse(),
+            };
+            let Some(undo) = undo else {
+                return Err(StorageError::Io(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Only page operations can be logged against a page",
+                )));
+            };
+            let mut before = self.to_owned();
+            undo.apply(&mut before)?;
+            let image = before.as_bytes().to_vec();
+            wal.append(NO_TXN, LogBody::PageImage { page_id, image })?;
+        }
+
+        wal.log_page(txn_id, self, body)?;
+        Ok(())
+    }
+
+    /// Log an image of the whole page outside any transaction. The record
+    /// is redo-only, so recovery can rebuild the page however the change
+    /// it carries is later undone.
+    pub fn log_image(&mut self) -> Result<()> {
+        let page_id = self.header().page_id;
+        let image = self.as_bytes().to_vec();
+        self.log_change(NO_TXN, LogBody::PageImage { page_id, image })
+    }
+
+    fn mark_dirty(&mut self) {
+        self.pool.frames[self.frame_id]
+            .dirty
+            .store(true, Ordering::Release);
+    }
+}
+
+impl Deref for PageWriteGuard<'_> {
+    type Target = Page;
+
+    fn deref(&self) -> &Page {
+        self.guard.as_ref().expect("guard is only taken on drop")
+    }
+}
+
+impl DerefMut for PageWriteGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Page {
+        self.mark_dirty();
+        self.guard.as_mut().expect("guard is only taken on drop")
+    }
+}
+
+impl Drop for PageWriteGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(mut page) = self.guard.take() {
+            // Keep cached pages verifiable once they hit the disk
+            if self.pool.checksums
+                && self.pool.frames[self.frame_id]
+                    .dirty
+                    .load(Ordering::Acquire)
+            {
+                page.update_checksum();
+            }
+        }
+        self.pool.unpin(self.frame_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::PageFile;
+    use crate::store::{FaultOp, FaultyStore, MemoryStore};
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    fn new_pool(capacity: usize) -> (tempfile::TempDir, BufferPool) {
+        let dir = tempdir().unwrap();
+        let file = PageFile::create_new(&dir.path().join("test.jdb")).unwrap();
+        (dir, BufferPool::new(file, capacity))
+    }
+
+    #[test]
+    fn test_new_page_then_fetch() {
+        let (_dir, pool) = new_pool(4);
+
+        let page_id = {
+            let mut page = pool.new_page(PageType::Data).unwrap();
+            page.add_record(b"cached").unwrap();
+            page.header().page_id
+        };
+
+        let page = pool.fetch_page(page_id).unwrap();
+        assert_eq!(page.header().page_type, PageType::Data);
+        assert_eq!(page.get_record(0).unwrap(), b"cached");
+        assert_eq!(pool.cached_pages(), 1);
+    }
+
+    #[test]
+    fn test_eviction_writes_back_dirty_pages() {
+        let (_dir, pool) = new_pool(2);
+
+        let mut ids = Vec::new();
+        for i in 0..5 {
+            let mut page = pool.new_page(PageType::Data).unwrap();
+            page.add_record(format!("page{}", i).as_bytes()).unwrap();
+            ids.push(page.header().page_id);
+        }
+        assert_eq!(pool.cached_pages(), 2);
+
+        // Earlier pages were evicted and must come back from disk intact
+        for (i, &page_id) in ids.iter().enumerate() {
+            let page = pool.fetch_page(page_id).unwrap();
+            assert_eq!(page.get_record(0).unwrap(), format!("page{}", i).as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_pinned_pages_are_not_evicted() {
+        let (_dir, pool) = new_pool(2);
+
+        let first = pool.new_page(PageType::Data).unwrap();
+        let second = pool.new_page(PageType::Data).unwrap();
+
+        assert!(matches!(
+            pool.new_page(PageType::Data),
+            Err(StorageError::NoFreeFrames)
+        ));
+
+        drop(second);
+        let third = pool.new_page(PageType::Data).unwrap();
+        assert_ne!(third.header().page_id, first.header().page_id);
+    }
+
+    #[test]
+    fn test_shared_guards_on_same_page() {
+        let (_dir, pool) = new_pool(2);
+
+        let page_id = pool.new_page(PageType::Data).unwrap().header().page_id;
+
+        let a = pool.fetch_page(page_id).unwrap();
+        let b = pool.fetch_page(page_id).unwrap();
+        assert_eq!(a.header().page_id, b.header().page_id);
+        assert_eq!(pool.cached_pages(), 1);
+    }
+
+    #[test]
+    fn test_flush_all_persists_pages() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+
+        let page_id = {
+            let pool = BufferPool::new(PageFile::create_new(&path).unwrap(), 4);
+            let mut page = pool.new_page(PageType::Data).unwrap();
+            page.add_record(b"durable").unwrap();
+            let page_id = page.header().page_id;
+            drop(page);
+            pool.flush_all().unwrap();
+            page_id
+        };
+
+        let file = PageFile::open(&path).unwrap();
+        let page = file.read_page(page_id).unwrap();
+        assert_eq!(page.get_record(0).unwrap(), b"durable");
+        assert!(page.verify_checksum());
+    }
+
+    #[test]
+    fn test_delete_page() {
+        let (_dir, pool) = new_pool(4);
+
+        let page = pool.new_page(PageType::Data).unwrap();
+        let page_id = page.header().page_id;
+
+        assert!(matches!(
+            pool.delete_page(page_id),
+            Err(StorageError::PagePinned(id)) if id == page_id
+        ));
+
+        drop(page);
+        pool.delete_page(page_id).unwrap();
+        assert_eq!(pool.cached_pages(), 0);
+
+        // The freed page is handed out again
+        let reused = pool.new_page(PageType::Data).unwrap();
+        assert_eq!(reused.header().page_id, page_id);
+    }
+
+    #[test]
+    fn test_stats_count_hits_misses_and_evictions() {
+        let (_dir, pool) = new_pool(2);
+
+        let ids: Vec<u32> = (0..3)
+            .map(|_| pool.new_page(PageType::Data).unwrap().header().page_id)
+            .collect();
+        pool.reset_stats();
+
+        drop(pool.fetch_page(ids[2]).unwrap()); // cached
+        drop(pool.fetch_page(ids[0]).unwrap()); // evicted earlier, evicts again
+
+        let stats = pool.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.hit_ratio(), 0.5);
+    }
+
+    #[test]
+    fn test_every_policy_round_trips_pages() {
+        for policy in [
+            EvictionPolicy::Clock,
+            EvictionPolicy::LruK { k: 2 },
+            EvictionPolicy::TwoQ,
+        ] {
+            let dir = tempdir().unwrap();
+            let file = PageFile::create_new(&dir.path().join("test.jdb")).unwrap();
+            let pool = BufferPool::with_policy(file, 3, policy);
+
+            let mut ids = Vec::new();
+            for i in 0..8u32 {
+                let mut page = pool.new_page(PageType::Data).unwrap();
+                page.add_record(&i.to_le_bytes()).unwrap();
+                ids.push(page.header().page_id);
+            }
+
+            for (i, &page_id) in ids.iter().enumerate().rev() {
+                let page = pool.fetch_page(page_id).unwrap();
+                assert_eq!(page.get_record(0).unwrap(), (i as u32).to_le_bytes());
+            }
+            assert!(pool.stats().evictions > 0, "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn test_concurrent_access() {
+        // One frame per thread so pins alone can never exhaust the pool
+        let (_dir, pool) = new_pool(4);
+        let pool = Arc::new(pool);
+
+        let ids: Vec<u32> = (0..6)
+            .map(|_| pool.new_page(PageType::Data).unwrap().header().page_id)
+            .collect();
+
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let pool = Arc::clone(&pool);
+                let ids = ids.clone();
+                std::thread::spawn(move || {
+                    for round in 0..50 {
+                        let page_id = ids[(t + round) % ids.len()];
+                        let mut page = pool.fetch_page_mut(page_id).unwrap();
+                        page.add_record(&[t as u8; 8]).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let total: usize = ids
+            .iter()
+            .map(|&id| pool.fetch_page(id).unwrap().active_records())
+            .sum();
+        assert_eq!(total, 200);
+    }
+
+    #[test]
+    fn test_first_change_since_checkpoint_logs_an_image() {
+        let (dir, mut pool) = new_pool(4);
+        let wal = Arc::new(Wal::open(&dir.path().join("wal")).unwrap());
+        pool.attach_wal(Arc::clone(&wal));
+
+        let mut txn_page = pool.new_page(PageType::Data).unwrap();
+        let txn_page_id = txn_page.header().page_id;
+        txn_page.add_record(b"old").unwrap();
+        txn_page.log_image().unwrap();
+        let mut page = pool.new_page(PageType::Data).unwrap();
+        let page_id = page.header().page_id;
+        page.log_image().unwrap();
+        wal.checkpoint(|| Ok(())).unwrap();
+        let redo_lsn = wal.redo_lsn();
+
+        // A transaction's change follows an image of the page without it
+        txn_page.update_record(0, b"new").unwrap();
+        let update = LogBody::Update {
+            page_id: txn_page_id,
+            slot: 0,
+            before: b"old".to_vec(),
+            after: b"new".to_vec(),
+        };
+        txn_page.log_change(7, update.clone()).unwrap();
+        txn_page.add_record(b"more").unwrap();
+        let insert = LogBody::Insert {
+            page_id: txn_page_id,
+            slot: 1,
+            data: b"more".to_vec(),
+        };
+        txn_page.log_change(7, insert.clone()).unwrap();
+
+        // A redo-only change is logged as an image of the result
+        page.add_record(b"redo only").unwrap();
+        let body = LogBody::Insert {
+            page_id,
+            slot: 0,
+            data: b"redo only".to_vec(),
+        };
+        page.log_change(NO_TXN, body).unwrap();
+
+        let records: Vec<_> = wal
+            .iter_from(redo_lsn)
+            .map(|r| r.unwrap())
+            .filter(|r| r.body.page_id().is_some())
+            .collect();
+        assert_eq!(records.len(), 4);
+        let LogBody::PageImage { image, .. } = &records[0].body else {
+            panic!("expected an image, got {:?}", records[0].body);
+        };
+        assert_eq!(records[0].txn_id, NO_TXN);
+        assert_eq!(
+            Page::from_bytes(image).unwrap().get_record(0).unwrap(),
+            b"old"
+        );
+        assert_eq!((records[1].txn_id, &records[1].body), (7, &update));
+        assert_eq!((records[2].txn_id, &records[2].body), (7, &insert));
+        let LogBody::PageImage { image, .. } = &records[3].body else {
+            panic!("expected an image, got {:?}", records[3].body);
+        };
+        let image = Page::from_bytes(image).unwrap();
+        assert_eq!(image.get_record(0).unwrap(), b"redo only");
+    }
+
+    #[test]
+    fn test_crash_keeps_only_synced_pages() {
+        let store = Arc::new(FaultyStore::new(MemoryStore::new()));
+        let pool = BufferPool::new(Arc::clone(&store), 4);
+
+        let page_id = {
+            let mut page = pool.new_page(PageType::Data).unwrap();
+            page.add_record(b"synced").unwrap();
+            page.header().page_id
+        };
+        pool.flush_all().unwrap();
+
+        // Written back, but never synced
+        pool.fetch_page_mut(page_id)
+            .unwrap()
+            .update_record(0, b"unsynced")
+            .unwrap();
+        pool.flush_page(page_id).unwrap();
+        store.crash();
+        drop(pool);
+
+        let pool = BufferPool::new(Arc::clone(&store), 4);
+        let page = pool.fetch_page(page_id).unwrap();
+        assert_eq!(page.get_record(0).unwrap(), b"synced");
+    }
+
+    #[test]
+    fn test_failed_write_back_keeps_page_dirty() {
+        let store = Arc::new(FaultyStore::new(MemoryStore::new()));
+        let pool = BufferPool::new(Arc::clone(&store), 4);
+
+        let page_id = {
+            let mut page = pool.new_page(PageType::Data).unwrap();
+            page.add_record(b"retried").unwrap();
+            page.header().page_id
+        };
+
+        store.fail_next(FaultOp::Write, 1);
+        assert!(pool.flush_all().is_err());
+        pool.flush_all().unwrap();
+        store.crash();
+
+        let page = store.read_page(page_id).unwrap();
+        assert_eq!(page.get_record(0).unwrap(), b"retried");
+    }
+
+    /// Holds every read until the test opens the gate.
+    struct GatedStore {
+        inner: MemoryStore,
+        reads: AtomicUsize,
+        open: Mutex<bool>,
+        opened: Condvar,
+    }
+
+    impl GatedStore {
+        fn set_open(&self, open: bool) {
+            *self.open.lock() = open;
+            self.opened.notify_all();
+        }
+    }
+
+    impl PageStore for GatedStore {
+        fn read_page(&self, page_id: u32) -> Result<Box<Page>> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            let mut open = self.open.lock();
+            while !*open {
+                self.opened.wait(&mut open);
+            }
+            drop(open);
+            self.inner.read_page(page_id)
+        }
+
+        fn write_page(&self, page: &Page) -> Result<()> {
+            self.inner.write_page(page)
+        }
+
+        fn allocate_page(&self) -> Result<u32> {
+            self.inner.allocate_page()
+        }
+
+        fn free_page(&self, page_id: u32) -> Result<()> {
+            self.inner.free_page(page_id)
+        }
+
+        fn sync(&self) -> Result<()> {
+            self.inner.sync()
+        }
+
+        fn page_count(&self) -> u32 {
+            self.inner.page_count()
+        }
+
+        fn page_size(&self) -> usize {
+            self.inner.page_size()
+        }
+
+        fn checksums_enabled(&self) -> bool {
+            self.inner.checksums_enabled()
+        }
+
+        fn data_pages(&self) -> (u32, u32) {
+            self.inner.data_pages()
+        }
+
+        fn set_data_pages(&self, first: u32, last: u32) -> Result<()> {
+            self.inner.set_data_pages(first, last)
+        }
+
+        fn directory_page(&self) -> u32 {
+            self.inner.directory_page()
+        }
+
+        fn set_directory_page(&self, page_id: u32) -> Result<()> {
+            self.inner.set_directory_page(page_id)
+        }
+
+        fn allocate_relation_id(&self) -> Result<u32> {
+            self.inner.allocate_relation_id()
+        }
+
+        fn next_txn_id(&self) -> TxnId {
+            self.inner.next_txn_id()
+        }
+
+        fn set_next_txn_id(&self, txn_id: TxnId) -> Result<()> {
+            self.inner.set_next_txn_id(txn_id)
+        }
+
+        fn commit_log_page(&self) -> u32 {
+            self.inner.commit_log_page()
+        }
+
+        fn set_commit_log_page(&self, page_id: u32) -> Result<()> {
+            self.inner.set_commit_log_page(page_id)
+        }
+    }
+
+    #[test]
+    fn test_concurrent_misses_share_one_read() {
+        let store = Arc::new(GatedStore {
+            inner: MemoryStore::new(),
+            reads: AtomicUsize::new(0),
+            open: Mutex::new(true),
+            opened: Condvar::new(),
+        });
+        let ids: Vec<u32> = (0..2)
+            .map(|i| {
+                let page_id = store.allocate_page().unwrap();
+                let mut page = Page::with_size(store.page_size(), page_id, PageType::Data);
+                page.add_record(format!("page{}", i).as_bytes()).unwrap();
+                store.write_page(&page).unwrap();
+                page_id
+            })
+            .collect();
+
+        let pool = Arc::new(BufferPool::new(Arc::clone(&store), 4));
+        drop(pool.fetch_page(ids[1]).unwrap());
+        store.set_open(false);
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let pool = Arc::clone(&pool);
+                let page_id = ids[0];
+                std::thread::spawn(move || {
+                    let page = pool.fetch_page(page_id).unwrap();
+                    assert_eq!(page.get_record(0).unwrap(), b"page0");
+                })
+            })
+            .collect();
+        while store.reads.load(Ordering::SeqCst) < 2 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // The read in flight does not hold up cached pages
+        assert_eq!(
+            pool.fetch_page(ids[1]).unwrap().get_record(0).unwrap(),
+            b"page1"
+        );
+        std::thread::sleep(Duration::from_millis(20));
+        store.set_open(true);
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // The other threads waited on the first one's read
+        assert_eq!(store.reads.load(Ordering::SeqCst), 2);
+        assert_eq!(pool.stats().misses, 2);
+        assert_eq!(pool.cached_pages(), 2);
+    }
+}
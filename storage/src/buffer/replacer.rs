@@ -0,0 +1,335 @@
+// storage/src/buffer/replacer.rs
+
+use super::FrameId;
+use std::collections::VecDeque;
+
+/// Chooses which cached page the buffer pool evicts next.
+///
+/// The pool calls `record_access` every time a frame is pinned, `remove`
+/// when a frame's page leaves the pool for any other reason, and `victim`
+/// when it needs a frame. A frame returned by `victim` is forgotten by the
+/// replacer until it is accessed again.
+pub trait Replacer: Send {
+    /// Note that `frame_id` (holding `page_id`) was just accessed.
+    fn record_access(&mut self, frame_id: FrameId, page_id: u32);
+
+    /// Forget a frame whose page was dropped from the pool.
+    fn remove(&mut self, frame_id: FrameId);
+
+    /// Pick a frame to evict among the frames `evictable` accepts.
+    fn victim(&mut self, evictable: &dyn Fn(FrameId) -> bool) -> Option<FrameId>;
+}
+
+/// Replacement policy selected when the buffer pool is built.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Second-chance sweep over a reference bit per frame
+    #[default]
+    Clock,
+    /// Evict the frame whose K-th most recent access is oldest
+    LruK { k: usize },
+    /// FIFO probation queue in front of an LRU main queue
+    TwoQ,
+}
+
+impl EvictionPolicy {
+    pub fn build(self, capacity: usize) -> Box<dyn Replacer> {
+        match self {
+            EvictionPolicy::Clock => Box::new(ClockReplacer::new(capacity)),
+            EvictionPolicy::LruK { k } => Box::new(LruKReplacer::new(capacity, k)),
+            EvictionPolicy::TwoQ => Box::new(TwoQReplacer::new(capacity)),
+        }
+    }
+}
+
+pub struct ClockReplacer {
+    present: Vec<bool>,
+    referenced: Vec<bool>,
+    hand: usize,
+}
+
+impl ClockReplacer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            present: vec![false; capacity],
+            referenced: vec![false; capacity],
+            hand: 0,
+        }
+    }
+}
+
+impl Replacer for ClockReplacer {
+    fn record_access(&mut self, frame_id: FrameId, _page_id: u32) {
+        self.present[frame_id] = true;
+        self.referenced[frame_id] = true;
+    }
+
+    fn remove(&mut self, frame_id: FrameId) {
+        self.present[frame_id] = false;
+        self.referenced[frame_id] = false;
+    }
+
+    fn victim(&mut self, evictable: &dyn Fn(FrameId) -> bool) -> Option<FrameId> {
+        let capacity = self.present.len();
+
+        // Two full sweeps: the first may only clear reference bits
+        for _ in 0..capacity * 2 {
+            let frame_id = self.hand;
+            self.hand = (self.hand + 1) % capacity;
+
+            if !self.present[frame_id] || !evictable(frame_id) {
+                continue;
+            }
+
+            if self.referenced[frame_id] {
+                self.referenced[frame_id] = false;
+                continue;
+            }
+
+            self.present[frame_id] = false;
+            return Some(frame_id);
+        }
+
+        None
+    }
+}
+
+pub struct LruKReplacer {
+    k: usize,
+    history: Vec<VecDeque<u64>>, // last k access timestamps per frame, oldest first
+    clock: u64,
+}
+
+impl LruKReplacer {
+    pub fn new(capacity: usize, k: usize) -> Self {
+        assert!(k > 0, "LRU-K needs k >= 1");
+
+        Self {
+            k,
+            history: vec![VecDeque::new(); capacity],
+            clock: 0,
+        }
+    }
+}
+
+impl Replacer for LruKReplacer {
+    fn record_access(&mut self, frame_id: FrameId, _page_id: u32) {
+        self.clock += 1;
+
+        let history = &mut self.history[frame_id];
+        if history.len() == self.k {
+            history.pop_front();
+        }
+        history.push_back(self.clock);
+    }
+
+    fn remove(&mut self, frame_id: FrameId) {
+        self.history[frame_id].clear();
+    }
+
+    fn victim(&mut self, evictable: &dyn Fn(FrameId) -> bool) -> Option<FrameId> {
+        // Frames with fewer than k accesses have infinite backward distance
+        // and go first, oldest first access breaking ties. Otherwise the
+        // frame whose k-th most recent access is oldest goes.
+        let victim = self
+            .history
+            .iter()
+            .enumerate()
+            .filter(|(frame_id, history)| !history.is_empty() && evictable(*frame_id))
+            .min_by_key(|(_, history)| (history.len() >= self.k, history[0]))
+            .map(|(frame_id, _)| frame_id)?;
+
+        self.history[victim].clear();
+        Some(victim)
+    }
+}
+
+pub struct TwoQReplacer {
+    frame_pages: Vec<Option<u32>>,
+    a1_in: VecDeque<FrameId>, // first-time pages, FIFO
+    am: VecDeque<FrameId>,    // re-referenced pages, LRU at the front
+    a1_out: VecDeque<u32>,    // ghost entries for pages recently evicted from a1_in
+    kin: usize,
+    kout: usize,
+}
+
+impl TwoQReplacer {
+    pub fn new(capacity: usize) -> Self {
+        // Tuning suggested by the 2Q paper: 25% probation, ghosts for 50%
+        Self {
+            frame_pages: vec![None; capacity],
+            a1_in: VecDeque::new(),
+            am: VecDeque::new(),
+            a1_out: VecDeque::new(),
+            kin: (capacity / 4).max(1),
+            kout: (capacity / 2).max(1),
+        }
+    }
+
+    fn evict_from_a1_in(&mut self, evictable: &dyn Fn(FrameId) -> bool) -> Option<FrameId> {
+        let position = self.a1_in.iter().position(|&f| evictable(f))?;
+        let frame_id = self.a1_in.remove(position)?;
+
+        if let Some(page_id) = self.frame_pages[frame_id].take() {
+            if self.a1_out.len() == self.kout {
+                self.a1_out.pop_front();
+            }
+            self.a1_out.push_back(page_id);
+        }
+
+        Some(frame_id)
+    }
+
+    fn evict_from_am(&mut self, evictable: &dyn Fn(FrameId) -> bool) -> Option<FrameId> {
+        let position = self.am.iter().position(|&f| evictable(f))?;
+        let frame_id = self.am.remove(position)?;
+        self.frame_pages[frame_id] = None;
+        Some(frame_id)
+    }
+}
+
+impl Replacer for TwoQReplacer {
+    fn record_access(&mut self, frame_id: FrameId, page_id: u32) {
+        if let Some(position) = self.am.iter().position(|&f| f == frame_id) {
+            self.am.remove(position);
+            self.am.push_back(frame_id);
+            return;
+        }
+
+        // Hits while on probation are treated as correlated and ignored
+        if self.a1_in.contains(&frame_id) {
+            return;
+        }
+
+        self.frame_pages[frame_id] = Some(page_id);
+        if let Some(position) = self.a1_out.iter().position(|&p| p == page_id) {
+            self.a1_out.remove(position);
+            self.am.push_back(frame_id);
+        } else {
+            self.a1_in.push_back(frame_id);
+        }
+    }
+
+    fn remove(&mut self, frame_id: FrameId) {
+        self.a1_in.retain(|&f| f != frame_id);
+        self.am.retain(|&f| f != frame_id);
+        self.frame_pages[frame_id] = None;
+    }
+
+    fn victim(&mut self, evictable: &dyn Fn(FrameId) -> bool) -> Option<FrameId> {
+        if self.a1_in.len() > self.kin || self.am.is_empty() {
+            self.evict_from_a1_in(evictable)
+                .or_else(|| self.evict_from_am(evictable))
+        } else {
+            self.evict_from_am(evictable)
+                .or_else(|| self.evict_from_a1_in(evictable))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn any(_: FrameId) -> bool {
+        true
+    }
+
+    #[test]
+    fn test_clock_gives_second_chance() {
+        let mut clock = ClockReplacer::new(3);
+        for frame_id in 0..3 {
+            clock.record_access(frame_id, frame_id as u32 + 1);
+        }
+
+        // All referenced: the first sweep clears bits, then frame 0 goes
+        assert_eq!(clock.victim(&any), Some(0));
+
+        // Frame 1 is touched again, so frame 2 is the next victim
+        clock.record_access(1, 2);
+        assert_eq!(clock.victim(&any), Some(2));
+        assert_eq!(clock.victim(&any), Some(1));
+        assert_eq!(clock.victim(&any), None);
+    }
+
+    #[test]
+    fn test_clock_skips_unevictable() {
+        let mut clock = ClockReplacer::new(2);
+        clock.record_access(0, 1);
+        clock.record_access(1, 2);
+
+        assert_eq!(clock.victim(&|f| f != 0), Some(1));
+        assert_eq!(clock.victim(&|f| f != 0), None);
+    }
+
+    #[test]
+    fn test_lru_k_prefers_frames_without_k_history() {
+        let mut lru = LruKReplacer::new(3, 2);
+
+        lru.record_access(0, 1);
+        lru.record_access(0, 1);
+        lru.record_access(1, 2);
+        lru.record_access(2, 3);
+        lru.record_access(2, 3);
+
+        // Frame 1 has a single access, so its k-distance is infinite
+        assert_eq!(lru.victim(&any), Some(1));
+        // Frame 0's second most recent access is older than frame 2's
+        assert_eq!(lru.victim(&any), Some(0));
+        assert_eq!(lru.victim(&any), Some(2));
+        assert_eq!(lru.victim(&any), None);
+    }
+
+    #[test]
+    fn test_lru_1_is_plain_lru() {
+        let mut lru = LruKReplacer::new(3, 1);
+        lru.record_access(0, 1);
+        lru.record_access(1, 2);
+        lru.record_access(2, 3);
+        lru.record_access(0, 1);
+
+        assert_eq!(lru.victim(&any), Some(1));
+        assert_eq!(lru.victim(&any), Some(2));
+        assert_eq!(lru.victim(&any), Some(0));
+    }
+
+    #[test]
+    fn test_two_q_protects_hot_pages_from_scans() {
+        let mut two_q = TwoQReplacer::new(4);
+
+        // Page 100 is loaded, evicted, and re-referenced while a ghost
+        two_q.record_access(0, 100);
+        assert_eq!(two_q.victim(&any), Some(0));
+        two_q.record_access(0, 100);
+
+        // A scan streams one-off pages through the remaining frames
+        let mut next_page = 200;
+        for frame_id in 1..4 {
+            two_q.record_access(frame_id, next_page);
+            next_page += 1;
+        }
+        for _ in 0..10 {
+            let frame_id = two_q.victim(&any).unwrap();
+            assert_ne!(frame_id, 0, "hot page evicted by scan");
+            two_q.record_access(frame_id, next_page);
+            next_page += 1;
+        }
+    }
+
+    #[test]
+    fn test_remove_forgets_frame() {
+        for policy in [
+            EvictionPolicy::Clock,
+            EvictionPolicy::LruK { k: 2 },
+            EvictionPolicy::TwoQ,
+        ] {
+            let mut replacer = policy.build(2);
+            replacer.record_access(0, 1);
+            replacer.record_access(1, 2);
+            replacer.remove(0);
+
+            assert_eq!(replacer.victim(&any), Some(1), "{:?}", policy);
+            assert_eq!(replacer.victim(&any), None, "{:?}", policy);
+        }
+    }
+}
@@ -0,0 +1,379 @@
+// storage/src/database/mod.rs
+
+use crate::buffer::BufferPool;
+use crate::file::PageFile;
+use crate::recovery::{self, RecoveryReport};
+use crate::store::PageStore;
+use crate::txn::TransactionManager;
+use crate::wal::{Lsn, Wal};
+use crate::Result;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Number of buffer pool frames used by `Database::create` and `open`
+pub const DEFAULT_POOL_SIZE: usize = 256;
+
+/// A data file together with its write-ahead log, buffer pool and
+/// transaction manager. `with_store` puts any other `PageStore` in place of
+/// the file, such as a `FaultyStore` to crash in tests.
+///
+/// The log lives in a directory next to the data file, named after it with
+/// a `-wal` suffix. Opening a database runs crash recovery once, through
+/// the pool, before any page is handed out; heaps, trees and the relation
+/// directory are then used through `pool`, and transactions through
+/// `transactions`. Pages torn by a crash are rebuilt by recovery from the
+/// image logged with their first change since the last checkpoint, which is
+/// also taken when the database is dropped.
+pub struct Database {
+    wal: Arc<Wal>,
+    pool: Arc<BufferPool>,
+    txns: TransactionManager,
+    recovery: RecoveryReport,
+}
+
+impl Database {
+    pub fn create(path: &Path) -> Result<Self> {
+        Self::start_file(PageFile::create_new(path)?, path)
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::start_file(PageFile::open(path)?, path)
+    }
+
+    fn start_file(mut file: PageFile, path: &Path) -> Result<Self> {
+        let wal = Arc::new(Wal::open(&Self::wal_dir(path))?);
+        file.attach_wal(Arc::clone(&wal));
+        Self::with_store(file, wal)
+    }
+
+    /// Put the pool in front of `store`, whose changes are logged to `wal`,
+    /// recover it and load the commit log. A `PageFile` needs `wal`
+    /// attached already, so its header changes are logged as well.
+    pub fn with_store(store: impl PageStore + 'static, wal: Arc<Wal>) -> Result<Self> {
+        let mut pool = BufferPool::new(store, DEFAULT_POOL_SIZE);
+        pool.attach_wal(Arc::clone(&wal));
+
+        let recovery = recovery::recover(&pool, &wal)?;
+        if !recovery.losers.is_empty() {
+            log::info!(
+                "Recovery rolled back {} transaction(s): {:?}",
+                recovery.losers.len(),
+                recovery.losers
+            );
+        }
+
+        let pool = Arc::new(pool);
+        let txns = TransactionManager::new(Arc::clone(&pool), Arc::clone(&wal))?;
+        Ok(Self {
+            wal,
+            pool,
+            txns,
+            recovery,
+        })
+    }
+
+    /// Directory holding the write-ahead log for the data file at `path`.
+    pub fn wal_dir(path: &Path) -> PathBuf {
+        let mut name = OsString::from(path.as_os_str());
+        name.push("-wal");
+        PathBuf::from(name)
+    }
+
+    pub fn pool(&self) -> &BufferPool {
+        &self.pool
+    }
+
+    pub fn wal(&self) -> &Arc<Wal> {
+        &self.wal
+    }
+
+    pub fn transactions(&self) -> &TransactionManager {
+        &self.txns
+    }
+
+    /// What recovery did when this database was opened.
+    pub fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery
+    }
+
+    /// Write every dirty page and move the log's redo point up to now,
+    /// dropping the segments recovery no longer needs. Returns the new
+    /// redo point.
+    pub fn checkpoint(&self) -> Result<Lsn> {
+        self.wal.checkpoint(|| self.pool.flush_all())
+    }
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        if let Err(e) = self.checkpoint() {
+            log::warn!("Checkpoint on close failed: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::btree::BTree;
+    use crate::heap::HeapFile;
+    use crate::mvcc::MvccHeap;
+    use crate::page::PageType;
+    use crate::store::{FaultyStore, MemoryStore};
+    use crate::wal::LogBody;
+    use std::fs::{self, OpenOptions};
+    use std::io::{Seek, SeekFrom, Write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_open_recovers_committed_work() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+
+        let page_id = {
+            let db = Database::create(&path).unwrap();
+            let mut page = db.pool().new_page(PageType::Data).unwrap();
+            let page_id = page.header().page_id;
+
+            let image = page.as_bytes().to_vec();
+            db.wal()
+                .log_page(1, &mut page, LogBody::PageImage { page_id, image })
+                .unwrap();
+            page.add_record(b"survives").unwrap();
+            let body = LogBody::Insert {
+                page_id,
+                slot: 0,
+                data: b"survives".to_vec(),
+            };
+            db.wal().log_page(1, &mut page, body).unwrap();
+            db.wal().append(1, LogBody::Commit).unwrap();
+            db.wal().flush().unwrap();
+            drop(page);
+
+            // Crash: the dirty page never leaves the buffer pool
+            std::mem::forget(db);
+            page_id
+        };
+
+        // The header growing the file never reached it either
+        let db = Database::open(&path).unwrap();
+        assert_eq!(db.recovery_report().records_redone, 3);
+
+        let page = db.pool().fetch_page(page_id).unwrap();
+        assert_eq!(page.get_record(0).unwrap(), b"survives");
+    }
+
+    #[test]
+    fn test_open_recovers_heap_insert() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        drop(Database::create(&path).unwrap());
+
+        let rid = {
+            let before = fs::read(&path).unwrap();
+            let db = Database::open(&path).unwrap();
+            let mut heap = HeapFile::open(db.pool()).unwrap();
+            let rid = heap.insert(db.pool(), b"survives").unwrap();
+            db.wal().flush().unwrap();
+
+            // Crash: the log reached the disk, the data file writes did not
+            std::mem::forget(db);
+            fs::write(&path, before).unwrap();
+            rid
+        };
+
+        let db = Database::open(&path).unwrap();
+        assert!(db.recovery_report().losers.is_empty());
+        assert!(db.recovery_report().records_redone > 0);
+
+        let heap = HeapFile::open(db.pool()).unwrap();
+        assert_eq!(heap.get(db.pool(), rid).unwrap().unwrap(), b"survives");
+    }
+
+    #[test]
+    fn test_open_recovers_heap_and_index_record_changes() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        let (rids, root) = {
+            let db = Database::create(&path).unwrap();
+            let tree = BTree::create(db.pool()).unwrap();
+            let root = tree.root_page();
+            db.pool().flush_all().unwrap();
+            drop(db);
+
+            let before = fs::read(&path).unwrap();
+            let db = Database::open(&path).unwrap();
+            let pool = db.pool();
+            let mut heap = HeapFile::open(pool).unwrap();
+            let rids: Vec<_> = (0..50u32)
+                .map(|n| heap.insert(pool, &n.to_le_bytes()).unwrap())
+                .collect();
+            heap.update(pool, rids[1], b"updated").unwrap();
+            heap.delete(pool, rids[2]).unwrap();
+
+            let tree = BTree::open(pool, root).unwrap();
+            for n in 0..500u32 {
+                tree.insert(pool, None, &n.to_be_bytes(), &[n as u8; 20])
+                    .unwrap();
+            }
+            for n in (0..500u32).step_by(3) {
+                tree.delete(pool, None, &n.to_be_bytes()).unwrap();
+            }
+            tree.insert(pool, None, &1u32.to_be_bytes(), b"replaced")
+                .unwrap();
+
+            // Record changes are logged as such, not as page images
+            let bodies: Vec<_> = db.wal().iter_from(0).map(|r| r.unwrap().body).collect();
+            assert!(bodies.iter().any(|b| matches!(b, LogBody::Insert { .. })));
+            assert!(bodies.iter().any(|b| matches!(b, LogBody::Update { .. })));
+            assert!(bodies.iter().any(|b| matches!(b, LogBody::Delete { .. })));
+            db.wal().flush().unwrap();
+
+            // Crash: none of the data file writes happened
+            std::mem::forget(db);
+            fs::write(&path, before).unwrap();
+            (rids, root)
+        };
+
+        let db = Database::open(&path).unwrap();
+        let pool = db.pool();
+
+        let heap = HeapFile::open(pool).unwrap();
+        assert_eq!(
+            heap.get(pool, rids[0]).unwrap().unwrap(),
+            0u32.to_le_bytes()
+        );
+        assert_eq!(heap.get(pool, rids[1]).unwrap().unwrap(), b"updated");
+        assert!(heap.get(pool, rids[2]).unwrap().is_none());
+        assert_eq!(heap.scan(pool).count(), 49);
+
+        let tree = BTree::open(pool, root).unwrap();
+        for n in 0..500u32 {
+            let expected = match n {
+                1 => Some(b"replaced".to_vec()),
+                _ if n % 3 == 0 => None,
+                _ => Some(vec![n as u8; 20]),
+            };
+            assert_eq!(tree.get(pool, &n.to_be_bytes()).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_torn_page_is_rebuilt_from_the_log() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        let first = {
+            let db = Database::create(&path).unwrap();
+            let mut heap = HeapFile::open(db.pool()).unwrap();
+            heap.insert(db.pool(), &0u32.to_le_bytes()).unwrap()
+        };
+        assert!(PageFile::open(&path).unwrap().double_write_area().is_none());
+
+        let rids = {
+            let db = Database::open(&path).unwrap();
+            let pool = db.pool();
+            let mut heap = HeapFile::open(pool).unwrap();
+            let mut rids = vec![first];
+            rids.extend((1..20u32).map(|n| heap.insert(pool, &n.to_le_bytes()).unwrap()));
+            assert!(rids.iter().all(|rid| rid.page_id == first.page_id));
+            pool.flush_all().unwrap();
+            let page_size = pool.page_size() as u64;
+
+            // Crash: the last write of the page tore halfway through
+            std::mem::forget(db);
+            let mut raw = OpenOptions::new().write(true).open(&path).unwrap();
+            let offset = rids[0].page_id as u64 * page_size + page_size / 2;
+            raw.seek(SeekFrom::Start(offset)).unwrap();
+            raw.write_all(&vec![0xAB; page_size as usize / 2]).unwrap();
+            rids
+        };
+
+        let db = Database::open(&path).unwrap();
+        let heap = HeapFile::open(db.pool()).unwrap();
+        for (n, &rid) in rids.iter().enumerate() {
+            let record = heap.get(db.pool(), rid).unwrap().unwrap();
+            assert_eq!(record, (n as u32).to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn test_close_takes_a_checkpoint() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        {
+            let db = Database::create(&path).unwrap();
+            let mut heap = HeapFile::open(db.pool()).unwrap();
+            for n in 0..100u32 {
+                heap.insert(db.pool(), &n.to_le_bytes()).unwrap();
+            }
+        }
+
+        // Nothing before the checkpoint is read again
+        let db = Database::open(&path).unwrap();
+        assert_eq!(db.recovery_report().records_scanned, 1);
+        assert_eq!(db.recovery_report().records_redone, 0);
+        let heap = HeapFile::open(db.pool()).unwrap();
+        assert_eq!(heap.scan(db.pool()).count(), 100);
+    }
+
+    #[test]
+    fn test_recovers_torn_and_lost_writes_of_faulty_store() {
+        let dir = tempdir().unwrap();
+        let store = Arc::new(FaultyStore::new(MemoryStore::new()));
+        let start = || {
+            let wal = Arc::new(Wal::open(dir.path()).unwrap());
+            Database::with_store(Arc::clone(&store), wal).unwrap()
+        };
+
+        let kept = {
+            let db = start();
+            let mut heap = MvccHeap::new(HeapFile::open(db.pool()).unwrap());
+            let txn = db.transactions().begin().unwrap();
+            let kept = heap.insert(db.pool(), &txn, b"kept").unwrap();
+            db.transactions().commit(txn).unwrap();
+            kept
+        };
+
+        let (committed, lost) = {
+            let db = start();
+            let (pool, manager) = (db.pool(), db.transactions());
+            let mut heap = MvccHeap::new(HeapFile::open(pool).unwrap());
+
+            let txn = manager.begin().unwrap();
+            let committed: Vec<_> = (0..20u32)
+                .map(|n| heap.insert(pool, &txn, &n.to_le_bytes()).unwrap())
+                .collect();
+            manager.commit(txn).unwrap();
+            let loser = manager.begin().unwrap();
+            let lost = heap.insert(pool, &loser, b"lost").unwrap();
+            std::mem::forget(loser);
+
+            // Written back, never synced, and torn by the crash
+            assert_eq!(committed[0].page_id, kept.page_id);
+            pool.flush_page(kept.page_id).unwrap();
+            store.crash_tearing(&[kept.page_id]);
+            std::mem::forget(db);
+            (committed, lost)
+        };
+
+        let db = start();
+        let (pool, manager) = (db.pool(), db.transactions());
+        assert_eq!(db.recovery_report().losers.len(), 1);
+
+        let heap = MvccHeap::new(HeapFile::open(pool).unwrap());
+        let reader = manager.begin().unwrap();
+        let get = |rid| heap.get(pool, manager, reader.snapshot(), rid).unwrap();
+        assert_eq!(get(kept).unwrap(), b"kept");
+        for (n, &rid) in committed.iter().enumerate() {
+            assert_eq!(get(rid).unwrap(), (n as u32).to_le_bytes());
+        }
+        assert_eq!(get(lost), None);
+    }
+
+    #[test]
+    fn test_wal_dir_sits_next_to_data_file() {
+        let path = Path::new("/tmp/data/app.jdb");
+        assert_eq!(Database::wal_dir(path), Path::new("/tmp/data/app.jdb-wal"));
+    }
+}
@@ -0,0 +1,323 @@
+// storage/src/file/compressed.rs
+
+use super::positional;
+use crate::{Result, StorageError};
+use parking_lot::RwLock;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io;
+
+/// Extents start on a sector boundary and span whole sectors
+const SECTOR_SIZE: u64 = 512;
+
+const EXTENT_MAGIC: [u8; 4] = *b"JDBX";
+
+/// Magic, page id, generation, payload length, codec, padding and checksum
+const EXTENT_HEADER_SIZE: usize = 28;
+
+/// The payload is the page as is, because compressing did not shrink it
+const CODEC_RAW: u16 = 0;
+const CODEC_LZ4: u16 = 1;
+
+/// A run of sectors holding one stored page.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Extent {
+    sector: u64,
+    sectors: u64,
+    generation: u64,
+}
+
+/// Page storage for files created with `Compression::Lz4`.
+///
+/// The header page stays at the front of the file. After it, every page is
+/// stored as an extent: a short header naming the page and carrying a
+/// checksum, then the page compressed with LZ4, padded to whole sectors.
+/// Pages are never overwritten in place. A write puts a new extent in free
+/// space and the one it replaces is only reused after a sync has made the
+/// new one durable, so a crash leaves one version or the other intact.
+///
+/// Each extent also carries a generation number. The page-to-extent map
+/// is not stored; opening the file rebuilds it by scanning the extents and
+/// keeping the newest intact one for each page.
+pub(crate) struct CompressedPages {
+    page_size: usize,
+    map: RwLock<ExtentMap>,
+}
+
+struct ExtentMap {
+    pages: HashMap<u32, Extent>,
+    /// Unused runs of sectors, first sector -> length
+    free: BTreeMap<u64, u64>,
+    /// Extents replaced since the last sync
+    superseded: Vec<Extent>,
+    /// First sector past the last extent
+    end: u64,
+    next_generation: u64,
+}
+
+/// An intact extent read back from the file.
+struct StoredPage {
+    page_id: u32,
+    extent: Extent,
+    bytes: Vec<u8>,
+}
+
+impl CompressedPages {
+    /// Rebuild the page-to-extent map of `file` from the extents in it.
+    pub(crate) fn load(file: &File, page_size: usize) -> Result<Self> {
+        let len = file.metadata().map_err(StorageError::Io)?.len();
+        let first = page_size as u64 / SECTOR_SIZE;
+        let last = len.div_ceil(SECTOR_SIZE);
+
+        // Anything that is not an intact extent, such as a torn write or
+        // the leftovers of a reused one, is stepped over a sector at a time
+        let mut pages: HashMap<u32, Extent> = HashMap::new();
+        let mut sector = first;
+        while sector < last {
+            let Some(stored) = read_extent(file, page_size, sector).map_err(StorageError::Io)?
+            else {
+                sector += 1;
+                continue;
+            };
+
+            let extent = stored.extent;
+            sector += extent.sectors;
+            if pages
+                .get(&stored.page_id)
+                .is_none_or(|current| current.generation < extent.generation)
+            {
+                pages.insert(stored.page_id, extent);
+            }
+        }
+
+        let mut live: Vec<Extent> = pages.values().copied().collect();
+        live.sort_unstable_by_key(|extent| extent.sector);
+
+        let mut free = BTreeMap::new();
+        let mut end = first;
+        for extent in &live {
+            if extent.sector > end {
+                free.insert(end, extent.sector - end);
+            }
+            end = extent.sector + extent.sectors;
+        }
+        let next_generation = live.iter().map(|e| e.generation).max().unwrap_or(0) + 1;
+
+        Ok(Self {
+            page_size,
+            map: RwLock::new(ExtentMap {
+                pages,
+                free,
+                superseded: Vec::new(),
+                end,
+                next_generation,
+            }),
+        })
+    }
+
+    pub(crate) fn read(&self, file: &File, page_id: u32) -> Result<Vec<u8>> {
+        // Holding the map keeps the extent from being reused while it is read
+        let map = self.map.read();
+        let Some(extent) = map.pages.get(&page_id).copied() else {
+            // Like a hole in an uncompressed file
+            return Ok(vec![0; self.page_size]);
+        };
+
+        match read_extent(file, self.page_size, extent.sector).map_err(StorageError::Io)? {
+            Some(stored)
+                if stored.page_id == page_id && stored.extent.generation == extent.generation =>
+            {
+                Ok(stored.bytes)
+            }
+            _ => Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Compressed page {} is damaged", page_id),
+            ))),
+        }
+    }
+
+    pub(crate) fn write(&self, file: &File, page_id: u32, bytes: &[u8]) -> Result<()> {
+        let compressed = lz4_flex::block::compress(bytes);
+        let (codec, payload) = if compressed.len() < bytes.len() {
+            (CODEC_LZ4, &compressed[..])
+        } else {
+            (CODEC_RAW, bytes)
+        };
+        let sectors = extent_sectors(payload.len());
+
+        // Space is claimed under the lock but filled outside it; nothing
+        // else can reach the extent until it is installed
+        let extent = self.map.write().allocate(sectors);
+        let buffer = encode(page_id, extent, codec, payload);
+        if let Err(e) = positional::write_all_at(file, &buffer, extent.sector * SECTOR_SIZE) {
+            self.map.write().superseded.push(extent);
+            return Err(StorageError::Io(e));
+        }
+
+        self.map.write().install(page_id, extent);
+        Ok(())
+    }
+
+    /// Extents replaced so far. Once a sync has made their replacements
+    /// durable they can be handed to `release`.
+    pub(crate) fn take_superseded(&self) -> Vec<Extent> {
+        std::mem::take(&mut self.map.write().superseded)
+    }
+
+    pub(crate) fn release(&self, extents: Vec<Extent>) {
+        let mut map = self.map.write();
+        for extent in extents {
+            map.release(extent);
+        }
+    }
+}
+
+impl ExtentMap {
+    /// Claim `sectors` sectors from the first hole big enough, or from the
+    /// end of the file.
+    fn allocate(&mut self, sectors: u64) -> Extent {
+        let hole = self
+            .free
+            .iter()
+            .find(|(_, &len)| len >= sectors)
+            .map(|(&start, &len)| (start, len));
+
+        let sector = match hole {
+            Some((start, len)) => {
+                self.free.remove(&start);
+                if len > sectors {
+                    self.free.insert(start + sectors, len - sectors);
+                }
+                start
+            }
+            None => {
+                self.end += sectors;
+                self.end - sectors
+            }
+        };
+
+        let generation = self.next_generation;
+        self.next_generation += 1;
+        Extent {
+            sector,
+            sectors,
+            generation,
+        }
+    }
+
+    fn install(&mut self, page_id: u32, extent: Extent) {
+        match self.pages.get(&page_id) {
+            // A racing write of the same page started later but finished first
+            Some(current) if current.generation > extent.generation => {
+                self.superseded.push(extent);
+            }
+            _ => {
+                if let Some(old) = self.pages.insert(page_id, extent) {
+                    self.superseded.push(old);
+                }
+            }
+        }
+    }
+
+    /// Return an extent's sectors to the free runs, merging neighbours.
+    fn release(&mut self, extent: Extent) {
+        let mut start = extent.sector;
+        let mut len = extent.sectors;
+
+        if let Some((&before, &before_len)) = self.free.range(..start).next_back() {
+            if before + before_len == start {
+                self.free.remove(&before);
+                start = before;
+                len += before_len;
+            }
+        }
+        if let Some(after_len) = self.free.remove(&(start + len)) {
+            len += after_len;
+        }
+
+        if start + len == self.end {
+            self.end = start;
+        } else {
+            self.free.insert(start, len);
+        }
+    }
+}
+
+fn extent_sectors(payload_len: usize) -> u64 {
+    ((EXTENT_HEADER_SIZE + payload_len) as u64).div_ceil(SECTOR_SIZE)
+}
+
+fn checksum(header: &[u8], payload: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&header[..EXTENT_HEADER_SIZE - 4]);
+    hasher.update(payload);
+    hasher.finalize()
+}
+
+fn encode(page_id: u32, extent: Extent, codec: u16, payload: &[u8]) -> Vec<u8> {
+    let mut buffer = vec![0u8; (extent.sectors * SECTOR_SIZE) as usize];
+    buffer[0..4].copy_from_slice(&EXTENT_MAGIC);
+    buffer[4..8].copy_from_slice(&page_id.to_le_bytes());
+    buffer[8..16].copy_from_slice(&extent.generation.to_le_bytes());
+    buffer[16..20].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+    buffer[20..22].copy_from_slice(&codec.to_le_bytes());
+
+    let body = EXTENT_HEADER_SIZE..EXTENT_HEADER_SIZE + payload.len();
+    buffer[body].copy_from_slice(payload);
+    let checksum = checksum(&buffer, payload);
+    buffer[24..28].copy_from_slice(&checksum.to_le_bytes());
+    buffer
+}
+
+/// Read the extent starting at `sector`, if there is an intact one.
+fn read_extent(file: &File, page_size: usize, sector: u64) -> io::Result<Option<StoredPage>> {
+    let offset = sector * SECTOR_SIZE;
+    let mut header = [0u8; EXTENT_HEADER_SIZE];
+    match positional::read_exact_at(file, &mut header, offset) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    if header[0..4] != EXTENT_MAGIC {
+        return Ok(None);
+    }
+
+    let page_id = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    let generation = u64::from_le_bytes(header[8..16].try_into().unwrap());
+    let length = u32::from_le_bytes(header[16..20].try_into().unwrap()) as usize;
+    let codec = u16::from_le_bytes(header[20..22].try_into().unwrap());
+    let stored_checksum = u32::from_le_bytes(header[24..28].try_into().unwrap());
+    if length > page_size {
+        return Ok(None);
+    }
+
+    let mut payload = vec![0u8; length];
+    match positional::read_exact_at(file, &mut payload, offset + EXTENT_HEADER_SIZE as u64) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    if checksum(&header, &payload) != stored_checksum {
+        return Ok(None);
+    }
+
+    let bytes = match codec {
+        CODEC_RAW if length == page_size => payload,
+        CODEC_LZ4 => {
+            let mut bytes = vec![0u8; page_size];
+            match lz4_flex::block::decompress_into(&payload, &mut bytes) {
+                Ok(n) if n == page_size => bytes,
+                _ => return Ok(None),
+            }
+        }
+        _ => return Ok(None),
+    };
+
+    Ok(Some(StoredPage {
+        page_id,
+        extent: Extent {
+            sector,
+            sectors: extent_sectors(length),
+            generation,
+        },
+        bytes,
+    }))
+}
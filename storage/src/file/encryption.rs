@@ -0,0 +1,138 @@
+// storage/src/file/encryption.rs
+
+use crate::{Result, StorageError};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::io;
+
+const NONCE_SIZE: usize = 24;
+const TAG_SIZE: usize = 16;
+
+/// Bytes an encrypted page takes on disk beyond the page itself
+pub(crate) const OVERHEAD: usize = NONCE_SIZE + TAG_SIZE;
+
+/// Size of the seal authenticating the file header
+pub(crate) const SEAL_SIZE: usize = NONCE_SIZE + TAG_SIZE;
+
+pub(crate) const SALT_SIZE: usize = 16;
+pub(crate) const KEY_CHECK_SIZE: usize = 16;
+
+/// Shortest master key accepted
+pub const MIN_MASTER_KEY_SIZE: usize = 16;
+
+/// Encrypts and authenticates pages with XChaCha20-Poly1305.
+///
+/// The key is derived with HKDF-SHA256 from the user's master key and a
+/// random salt stored in the file header, which also records a key-check
+/// value derived the same way so a wrong key is told apart from damage.
+///
+/// Each page's nonce is its page id, its LSN and 96 random bits. The
+/// random part keeps nonces unique when a page is written again without
+/// its LSN moving, as pages outside the log are. The page id is also bound
+/// as associated data, so a page copied into another page's place fails
+/// authentication.
+pub(crate) struct PageCipher {
+    cipher: XChaCha20Poly1305,
+}
+
+impl PageCipher {
+    /// Derive the cipher and key-check value for `master_key`.
+    pub(crate) fn derive(
+        master_key: &[u8],
+        salt: &[u8; SALT_SIZE],
+    ) -> Result<(Self, [u8; KEY_CHECK_SIZE])> {
+        if master_key.len() < MIN_MASTER_KEY_SIZE {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Master key must be at least {} bytes", MIN_MASTER_KEY_SIZE),
+            )));
+        }
+
+        let hkdf = Hkdf::<Sha256>::new(Some(salt), master_key);
+        let mut key = [0u8; 32];
+        let mut key_check = [0u8; KEY_CHECK_SIZE];
+        hkdf.expand(b"jdb page key", &mut key).unwrap();
+        hkdf.expand(b"jdb key check", &mut key_check).unwrap();
+
+        let cipher = XChaCha20Poly1305::new(&key.into());
+        Ok((Self { cipher }, key_check))
+    }
+
+    /// Encrypt a page for storage as page `page_id`, returning the nonce,
+    /// the ciphertext and the tag.
+    pub(crate) fn seal_page(&self, page_id: u32, bytes: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_SIZE];
+        nonce[0..4].copy_from_slice(&page_id.to_le_bytes());
+        // The LSN, from the page header
+        nonce[4..12].copy_from_slice(&bytes[16..24]);
+        nonce[12..].copy_from_slice(&rand::random::<[u8; 12]>());
+
+        let aad = page_id.to_le_bytes();
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: bytes,
+                    aad: &aad,
+                },
+            )
+            .expect("page fits in a single message");
+
+        let mut sealed = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        sealed
+    }
+
+    /// Decrypt what `seal_page` stored as page `page_id`.
+    pub(crate) fn open_page(&self, page_id: u32, sealed: &[u8]) -> Result<Vec<u8>> {
+        let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+        let aad = page_id.to_le_bytes();
+        self.cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| StorageError::AuthenticationFailed(page_id))
+    }
+
+    /// Authenticate the file header: a nonce and the tag of an empty
+    /// message with the header as associated data.
+    pub(crate) fn seal_header(&self, header: &[u8]) -> [u8; SEAL_SIZE] {
+        let nonce: [u8; NONCE_SIZE] = rand::random();
+        let tag = self
+            .cipher
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &[],
+                    aad: header,
+                },
+            )
+            .expect("empty message");
+
+        let mut seal = [0u8; SEAL_SIZE];
+        seal[..NONCE_SIZE].copy_from_slice(&nonce);
+        seal[NONCE_SIZE..].copy_from_slice(&tag);
+        seal
+    }
+
+    pub(crate) fn verify_header(&self, header: &[u8], seal: &[u8; SEAL_SIZE]) -> bool {
+        let (nonce, tag) = seal.split_at(NONCE_SIZE);
+        self.cipher
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: tag,
+                    aad: header,
+                },
+            )
+            .is_ok()
+    }
+}
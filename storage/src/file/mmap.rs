@@ -0,0 +1,104 @@
+// storage/src/file/mmap.rs
+
+use crate::page::Page;
+use crate::Result;
+use memmap2::{MmapMut, MmapOptions};
+use parking_lot::{MappedRwLockReadGuard, Mutex, RwLock, RwLockReadGuard};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io;
+
+/// A shared, writable mapping of a whole page file.
+///
+/// Pages are read and written as plain memory copies; the kernel pages the
+/// file in and out behind them. Writing past the end grows the file and
+/// replaces the mapping, so the map lock is taken exclusively for every
+/// write and views hold it shared for as long as they live. Pages written
+/// since the last `flush` are tracked so only those ranges are synced.
+pub(crate) struct MappedFile {
+    page_size: usize,
+    map: RwLock<MmapMut>,
+    dirty: Mutex<BTreeSet<u32>>,
+}
+
+impl MappedFile {
+    pub(crate) fn new(file: &File, page_size: usize) -> io::Result<Self> {
+        Ok(Self {
+            page_size,
+            map: RwLock::new(Self::map(file)?),
+            dirty: Mutex::new(BTreeSet::new()),
+        })
+    }
+
+    fn map(file: &File) -> io::Result<MmapMut> {
+        // SAFETY: the file is opened read-write by this process alone and
+        // every access goes through the lock around the mapping; another
+        // process truncating it underneath us is outside what we guard against.
+        unsafe { MmapOptions::new().map_mut(file) }
+    }
+
+    fn range(&self, page_id: u32) -> std::ops::Range<usize> {
+        let start = page_id as usize * self.page_size;
+        start..start + self.page_size
+    }
+
+    fn page_bytes<'a>(&self, map: &'a MmapMut, page_id: u32) -> io::Result<&'a [u8]> {
+        map.get(self.range(page_id))
+            .ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+    }
+
+    pub(crate) fn read(&self, page_id: u32) -> io::Result<Vec<u8>> {
+        self.page_bytes(&self.map.read(), page_id)
+            .map(<[u8]>::to_vec)
+    }
+
+    pub(crate) fn write(&self, file: &File, page_id: u32, bytes: &[u8]) -> io::Result<()> {
+        let mut map = self.map.write();
+
+        let range = self.range(page_id);
+        if range.end > map.len() {
+            // Dirty pages in the old mapping must reach the file before it goes
+            map.flush()?;
+            file.set_len(range.end as u64)?;
+            *map = Self::map(file)?;
+        }
+
+        map[range].copy_from_slice(bytes);
+        self.dirty.lock().insert(page_id);
+        Ok(())
+    }
+
+    /// Borrow a page straight out of the mapping. Writes, and so growth,
+    /// wait until the view is dropped.
+    pub(crate) fn view(&self, page_id: u32) -> Result<MappedRwLockReadGuard<'_, Page>> {
+        let map = self.map.read();
+        Page::view(self.page_bytes(&map, page_id)?)?;
+
+        // Checked above, and the mapping cannot change while it is locked
+        Ok(RwLockReadGuard::map(map, |map| {
+            Page::view(self.page_bytes(map, page_id).unwrap()).unwrap()
+        }))
+    }
+
+    /// Write every page dirtied since the last flush back to the file,
+    /// one contiguous run at a time.
+    pub(crate) fn flush(&self) -> io::Result<()> {
+        let map = self.map.read();
+        let mut dirty = self.dirty.lock();
+
+        let mut pages = dirty.iter().copied().peekable();
+        while let Some(first) = pages.next() {
+            let mut last = first;
+            while pages.next_if_eq(&(last + 1)).is_some() {
+                last += 1;
+            }
+
+            let offset = first as usize * self.page_size;
+            let len = (last - first + 1) as usize * self.page_size;
+            map.flush_range(offset, len)?;
+        }
+
+        dirty.clear();
+        Ok(())
+    }
+}
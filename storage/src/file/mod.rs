@@ -1,18 +1,50 @@
 // storage/src/file/mod.rs
 
-use crate::page::{Page, PageType, PAGE_SIZE};
+#[cfg(feature = "compression")]
+mod compressed;
+mod double_write;
+#[cfg(feature = "encryption")]
+mod encryption;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod positional;
+
+use crate::page::{self, Page, PageType, PAGE_SIZE};
+use crate::wal::{LogBody, Lsn, TxnId, Wal, NO_TXN};
 use crate::{Result, StorageError};
+use double_write::DoubleWrite;
+#[cfg(feature = "encryption")]
+use encryption::PageCipher;
+#[cfg(feature = "encryption")]
+pub use encryption::MIN_MASTER_KEY_SIZE;
+use parking_lot::Mutex;
+use std::collections::HashSet;
 use std::fs::{File, OpenOptions};
-use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::io;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[cfg(feature = "mmap")]
+/// A page borrowed from the memory map; see `PageFile::view_page`
+pub type PageView<'a> = parking_lot::MappedRwLockReadGuard<'a, Page>;
 
 /// Magic number to identify our database files
 const DB_MAGIC: [u8; 4] = *b"JDB1"; // JDB version 1
 
-const FILE_VERSION: u32 = 1;
+/// Format version of new files. Version 2 added subtransaction ids to
+/// `TupleHeader`; version 1 files are still opened, and their tuples kept
+/// in the old layout.
+pub const FILE_VERSION: u32 = 2;
 
 const HEADER_SIZE: usize = 512;
 
+/// `FileHeader::encryption` value for pages sealed with XChaCha20-Poly1305
+const ENCRYPTION_XCHACHA20_POLY1305: u32 = 1;
+
+/// Fewest slots the double-write area may have; see `enable_double_write`
+pub const MIN_DOUBLE_WRITE_SLOTS: u32 = 2;
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct FileHeader {
@@ -20,7 +52,7 @@ pub struct FileHeader {
     magic: [u8; 4],   // "JDB1"
     version: u32,     // File format version
     header_size: u32, // Size of this header (512)
-    page_size: u32,   // Page size (8192)
+    page_size: u32,   // Page size (PAGE_SIZE unless chosen at creation)
 
     // Page management (16 bytes)
     page_count: u32,      // Total pages in file
@@ -36,12 +68,36 @@ pub struct FileHeader {
     header_checksum: u32,    // CRC32 of header
     data_checksum_flag: u32, // 0 = off, 1 = on for data pages
 
+    // Torn-page protection (8 bytes)
+    double_write_start: u32, // First page of the double-write area (0 = none)
+    double_write_slots: u32, // Pages in the double-write area
+
+    // Relations (8 bytes)
+    directory_page: u32,   // First page of the relation directory (0 = none)
+    next_relation_id: u32, // Id given to the next relation created
+
+    // Storage (4 bytes)
+    compression: u32, // 0 = none, 1 = LZ4; see `Compression`
+
+    // Encryption (76 bytes)
+    encryption: u32,       // 0 = none, 1 = XChaCha20-Poly1305
+    key_salt: [u8; 16],    // Salt for deriving keys from the master key
+    key_check: [u8; 16],   // Derived from the master key to recognise it
+    header_seal: [u8; 40], // Authenticates the header (0 = unencrypted)
+
+    // Transactions (12 bytes)
+    next_txn_id: u64,     // No transaction id from here on has been handed out
+    commit_log_page: u32, // First page of the commit log (0 = none yet)
+
+    // Recovery (8 bytes)
+    lsn: Lsn, // Log record of the last header change written
+
     // Future expansion
-    _reserved: [u8; 456], // 512 - 56 = 456 bytes for future use
+    _reserved: [u8; 340], // 512 - 172 = 340 bytes for future use
 }
 
 impl FileHeader {
-    fn new() -> Self {
+    fn new(page_size: usize, compression: Compression) -> Self {
         use std::time::{SystemTime, UNIX_EPOCH};
 
         let now = SystemTime::now()
@@ -53,7 +109,7 @@ impl FileHeader {
             magic: DB_MAGIC,
             version: FILE_VERSION,
             header_size: HEADER_SIZE as u32,
-            page_size: PAGE_SIZE as u32,
+            page_size: page_size as u32,
 
             page_count: 1, // Start with 1 to account for header page
             free_list_head: 0,
@@ -66,7 +122,25 @@ impl FileHeader {
             header_checksum: 0,
             data_checksum_flag: 1, // Enable checksums by default
 
-            _reserved: [0; 456],
+            double_write_start: 0,
+            double_write_slots: 0,
+
+            directory_page: 0,
+            next_relation_id: 1,
+
+            compression: compression.code(),
+
+            encryption: 0,
+            key_salt: [0; 16],
+            key_check: [0; 16],
+            header_seal: [0; 40],
+
+            next_txn_id: 0,
+            commit_log_page: 0,
+
+            lsn: 0,
+
+            _reserved: [0; 340],
         }
     }
 
@@ -85,20 +159,33 @@ impl FileHeader {
             )));
         }
 
-        if self.page_size != PAGE_SIZE as u32 {
+        if !page::is_valid_page_size(self.page_size as usize) {
             return Err(StorageError::Io(io::Error::new(
                 io::ErrorKind::InvalidData,
-                format!(
-                    "Invalid page size: expected {}, got {}",
-                    PAGE_SIZE, self.page_size
-                ),
+                format!("Invalid page size: {}", self.page_size),
+            )));
+        }
+
+        if Compression::from_code(self.compression).is_none() {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported page compression: {}", self.compression),
+            )));
+        }
+
+        let encryption_supported = self.encryption == 0
+            || (self.encryption == ENCRYPTION_XCHACHA20_POLY1305 && cfg!(feature = "encryption"));
+        if !encryption_supported {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unsupported page encryption: {}", self.encryption),
             )));
         }
 
         Ok(())
     }
 
-    fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+    fn to_bytes(self) -> [u8; HEADER_SIZE] {
         let mut bytes = [0u8; HEADER_SIZE];
 
         // Core identification (16 bytes)
@@ -121,8 +208,32 @@ impl FileHeader {
         bytes[48..52].copy_from_slice(&self.header_checksum.to_le_bytes());
         bytes[52..56].copy_from_slice(&self.data_checksum_flag.to_le_bytes());
 
+        // Torn-page protection (8 bytes)
+        bytes[56..60].copy_from_slice(&self.double_write_start.to_le_bytes());
+        bytes[60..64].copy_from_slice(&self.double_write_slots.to_le_bytes());
+
+        // Relations (8 bytes)
+        bytes[64..68].copy_from_slice(&self.directory_page.to_le_bytes());
+        bytes[68..72].copy_from_slice(&self.next_relation_id.to_le_bytes());
+
+        // Storage (4 bytes)
+        bytes[72..76].copy_from_slice(&self.compression.to_le_bytes());
+
+        // Encryption (76 bytes)
+        bytes[76..80].copy_from_slice(&self.encryption.to_le_bytes());
+        bytes[80..96].copy_from_slice(&self.key_salt);
+        bytes[96..112].copy_from_slice(&self.key_check);
+        bytes[112..152].copy_from_slice(&self.header_seal);
+
+        // Transactions (12 bytes)
+        bytes[152..160].copy_from_slice(&self.next_txn_id.to_le_bytes());
+        bytes[160..164].copy_from_slice(&self.commit_log_page.to_le_bytes());
+
+        // Recovery (8 bytes)
+        bytes[164..172].copy_from_slice(&self.lsn.to_le_bytes());
+
         // Reserved bytes
-        bytes[56..512].copy_from_slice(&self._reserved);
+        bytes[172..512].copy_from_slice(&self._reserved);
 
         bytes
     }
@@ -152,7 +263,25 @@ impl FileHeader {
             header_checksum: u32::from_le_bytes(bytes[48..52].try_into().unwrap()),
             data_checksum_flag: u32::from_le_bytes(bytes[52..56].try_into().unwrap()),
 
-            _reserved: bytes[56..512].try_into().unwrap(),
+            double_write_start: u32::from_le_bytes(bytes[56..60].try_into().unwrap()),
+            double_write_slots: u32::from_le_bytes(bytes[60..64].try_into().unwrap()),
+
+            directory_page: u32::from_le_bytes(bytes[64..68].try_into().unwrap()),
+            next_relation_id: u32::from_le_bytes(bytes[68..72].try_into().unwrap()),
+
+            compression: u32::from_le_bytes(bytes[72..76].try_into().unwrap()),
+
+            encryption: u32::from_le_bytes(bytes[76..80].try_into().unwrap()),
+            key_salt: bytes[80..96].try_into().unwrap(),
+            key_check: bytes[96..112].try_into().unwrap(),
+            header_seal: bytes[112..152].try_into().unwrap(),
+
+            next_txn_id: u64::from_le_bytes(bytes[152..160].try_into().unwrap()),
+            commit_log_page: u32::from_le_bytes(bytes[160..164].try_into().unwrap()),
+
+            lsn: u64::from_le_bytes(bytes[164..172].try_into().unwrap()),
+
+            _reserved: bytes[172..512].try_into().unwrap(),
         };
 
         header.validate()?;
@@ -172,6 +301,82 @@ impl FileHeader {
         self.header_checksum = hasher.finalize();
     }
 
+    /// Format version the file was written in.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size as usize
+    }
+
+    pub fn page_count(&self) -> u32 {
+        self.page_count
+    }
+
+    pub fn compression(&self) -> Compression {
+        // Checked by `validate`
+        Compression::from_code(self.compression).unwrap_or_default()
+    }
+
+    pub fn encrypted(&self) -> bool {
+        self.encryption != 0
+    }
+
+    /// Turn on encryption for a new file, returning the cipher derived
+    /// from `master_key` and a fresh salt.
+    #[cfg(feature = "encryption")]
+    fn enable_encryption(&mut self, master_key: &[u8]) -> Result<PageCipher> {
+        let salt = rand::random();
+        let (cipher, key_check) = PageCipher::derive(master_key, &salt)?;
+        self.encryption = ENCRYPTION_XCHACHA20_POLY1305;
+        self.key_salt = salt;
+        self.key_check = key_check;
+        Ok(cipher)
+    }
+
+    /// Everything the seal covers: the header minus its checksum and the
+    /// seal itself.
+    #[cfg(feature = "encryption")]
+    fn sealed_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut temp = *self;
+        temp.header_checksum = 0;
+        temp.header_seal = [0; 40];
+        temp.to_bytes()
+    }
+
+    #[cfg(feature = "encryption")]
+    fn seal(&mut self, cipher: &PageCipher) {
+        self.header_seal = cipher.seal_header(&self.sealed_bytes());
+    }
+
+    #[cfg(feature = "encryption")]
+    fn verify_seal(&self, cipher: &PageCipher) -> bool {
+        cipher.verify_header(&self.sealed_bytes(), &self.header_seal)
+    }
+
+    pub fn free_list_head(&self) -> u32 {
+        self.free_list_head
+    }
+
+    pub fn checksums_enabled(&self) -> bool {
+        self.data_checksum_flag != 0
+    }
+
+    /// Page range `(start, slots)` of the double-write area, if enabled.
+    pub fn double_write_area(&self) -> Option<(u32, u32)> {
+        (self.double_write_slots != 0).then_some((self.double_write_start, self.double_write_slots))
+    }
+
+    fn update_modified_time(&mut self) {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        self.last_modified = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+    }
+
     fn verify_checksum(&self) -> bool {
         let stored_checksum = self.header_checksum;
         let mut temp = *self;
@@ -186,13 +391,141 @@ impl FileHeader {
     }
 }
 
+/// How page contents move between the file and memory, chosen when the
+/// file is opened.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FileBackend {
+    /// Positional reads and writes into owned buffers
+    #[default]
+    Positional,
+    /// A shared memory map of the whole file; also allows zero-copy page
+    /// views through `PageFile::view_page`
+    #[cfg(feature = "mmap")]
+    Mmap,
+}
+
+/// How pages are laid out on disk, chosen when the file is created and
+/// recorded in its header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Each page is stored as is, at a fixed offset
+    #[default]
+    None,
+    /// Each page is compressed with LZ4 and packed into a variable-size
+    /// extent, which saves a lot of space on cold tables whose pages are
+    /// mostly empty. Pages are never overwritten in place, so compressed
+    /// files have no double-write area; they also need the positional
+    /// backend.
+    #[cfg(feature = "compression")]
+    Lz4,
+}
+
+impl Compression {
+    fn code(self) -> u32 {
+        match self {
+            Compression::None => 0,
+            #[cfg(feature = "compression")]
+            Compression::Lz4 => 1,
+        }
+    }
+
+    fn from_code(code: u32) -> Option<Self> {
+        match code {
+            0 => Some(Compression::None),
+            #[cfg(feature = "compression")]
+            1 => Some(Compression::Lz4),
+            _ => None,
+        }
+    }
+}
+
+/// Settings fixed when a file is created; see `PageFile::create_new_with`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileOptions {
+    /// Size of every page in the file, from `page::MIN_PAGE_SIZE` to
+    /// `page::MAX_PAGE_SIZE`. Larger pages suit scan-heavy tables.
+    pub page_size: usize,
+    pub backend: FileBackend,
+    pub compression: Compression,
+}
+
+impl Default for FileOptions {
+    fn default() -> Self {
+        Self {
+            page_size: PAGE_SIZE,
+            backend: FileBackend::default(),
+            compression: Compression::default(),
+        }
+    }
+}
+
+/// A database file of fixed-size pages.
+///
+/// The page size is chosen when the file is created and recorded in its
+/// header, along with whether pages are compressed or encrypted on disk.
+/// All page I/O is positional, so a `PageFile` can be shared between
+/// threads and reads proceed in parallel. The header has its own lock,
+/// held across each free-list or header update so those stay atomic, and
+/// the double-write area has another, held across the copy-then-write
+/// sequence. When both are needed the header lock is taken first.
 pub struct PageFile {
     file: File,
-    header: FileHeader,
+    page_size: usize,
+    backend: FileBackend,
+    #[cfg(feature = "mmap")]
+    map: Option<mmap::MappedFile>,
+    #[cfg(feature = "compression")]
+    compressed: Option<compressed::CompressedPages>,
+    #[cfg(feature = "encryption")]
+    cipher: Option<PageCipher>,
+    header: Mutex<FileHeader>,
+    /// The header has changes the log holds but the file does not yet
+    header_unwritten: AtomicBool,
+    checksums: bool,
+    wal: Option<Arc<Wal>>,
+    /// `(start, slots)`; fixed once enabled, so checks need no lock
+    double_write_area: Option<(u32, u32)>,
+    double_write: Mutex<Option<DoubleWrite>>,
+    /// Pages on the free list, read in by the first `free_page` and kept up
+    /// to date under the header lock from then on
+    free_pages: Mutex<Option<HashSet<u32>>>,
 }
 
 impl PageFile {
     pub fn create_new(path: &Path) -> Result<Self> {
+        Self::create_new_with(path, FileOptions::default())
+    }
+
+    pub fn create_new_with(path: &Path, options: FileOptions) -> Result<Self> {
+        Self::create(path, options, None)
+    }
+
+    /// Create a file whose pages are encrypted at rest, authenticated and
+    /// bound to their page ids, with keys derived from `master_key`. The
+    /// header stays readable but is authenticated with the same key. The
+    /// file can only be opened again through `open_encrypted` with the same
+    /// master key, and needs the positional backend.
+    #[cfg(feature = "encryption")]
+    pub fn create_encrypted(path: &Path, options: FileOptions, master_key: &[u8]) -> Result<Self> {
+        Self::create(path, options, Some(master_key))
+    }
+
+    fn create(path: &Path, options: FileOptions, master_key: Option<&[u8]>) -> Result<Self> {
+        if !page::is_valid_page_size(options.page_size) {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unsupported page size: {}", options.page_size),
+            )));
+        }
+        Self::check_layout(options.compression, master_key.is_some(), options.backend)?;
+
+        let mut header = FileHeader::new(options.page_size, options.compression);
+        #[cfg(feature = "encryption")]
+        if let Some(master_key) = master_key {
+            let cipher = header.enable_encryption(master_key)?;
+            header.seal(&cipher);
+        }
+
         let file = OpenOptions::new()
             .read(true)
             .write(true)
@@ -200,30 +533,241 @@ impl PageFile {
             .open(path)
             .map_err(StorageError::Io)?;
 
-        let mut header = FileHeader::new();
-        header.update_checksum();
-
-        let mut page_file = Self { file, header };
-
         // Write the header
-        page_file.write_header()?;
+        Self::write_header(&file, &mut header)?;
 
-        Ok(page_file)
+        Self::from_parts(file, header, options.backend, master_key)
     }
 
     pub fn open(path: &Path) -> Result<Self> {
-        let mut file = OpenOptions::new()
+        Self::open_with(path, FileBackend::default())
+    }
+
+    pub fn open_with(path: &Path, backend: FileBackend) -> Result<Self> {
+        Self::open_file(path, backend, None)
+    }
+
+    /// Open a file made by `create_encrypted`. A key other than the one it
+    /// was created with fails with `StorageError::WrongKey`.
+    #[cfg(feature = "encryption")]
+    pub fn open_encrypted(path: &Path, backend: FileBackend, master_key: &[u8]) -> Result<Self> {
+        Self::open_file(path, backend, Some(master_key))
+    }
+
+    fn open_file(path: &Path, backend: FileBackend, master_key: Option<&[u8]>) -> Result<Self> {
+        let file = OpenOptions::new()
             .read(true)
             .write(true)
             .open(path)
             .map_err(StorageError::Io)?;
 
-        let header = Self::read_header(&mut file)?;
+        let header = Self::read_header(&file)?;
+
+        Self::from_parts(file, header, backend, master_key)
+    }
+
+    fn from_parts(
+        file: File,
+        header: FileHeader,
+        backend: FileBackend,
+        master_key: Option<&[u8]>,
+    ) -> Result<Self> {
+        Self::check_layout(header.compression(), header.encrypted(), backend)?;
+
+        #[cfg(feature = "encryption")]
+        let cipher = Self::unlock(&header, master_key)?;
+        // Keys are only taken with the encryption feature
+        #[cfg(not(feature = "encryption"))]
+        let _ = master_key;
+
+        #[cfg(feature = "mmap")]
+        let map = match backend {
+            FileBackend::Mmap => {
+                Some(mmap::MappedFile::new(&file, header.page_size()).map_err(StorageError::Io)?)
+            }
+            FileBackend::Positional => None,
+        };
+
+        #[cfg(feature = "compression")]
+        let compressed = match header.compression() {
+            Compression::Lz4 => {
+                // Free space may hold pages the last run replaced without a
+                // sync; their replacements must be durable before it is reused
+                file.sync_data().map_err(StorageError::Io)?;
+                Some(compressed::CompressedPages::load(
+                    &file,
+                    header.page_size(),
+                )?)
+            }
+            Compression::None => None,
+        };
+
+        let mut page_file = Self {
+            file,
+            page_size: header.page_size(),
+            backend,
+            #[cfg(feature = "mmap")]
+            map,
+            #[cfg(feature = "compression")]
+            compressed,
+            #[cfg(feature = "encryption")]
+            cipher,
+            checksums: header.data_checksum_flag != 0,
+            header: Mutex::new(header),
+            header_unwritten: AtomicBool::new(false),
+            wal: None,
+            double_write_area: None,
+            double_write: Mutex::new(None),
+            free_pages: Mutex::new(None),
+        };
+
+        let double_write = page_file.load_double_write(&header)?;
+        page_file.double_write_area = double_write.as_ref().map(|dw| (dw.start(), dw.slots()));
+        *page_file.double_write.get_mut() = double_write;
+
+        Ok(page_file)
+    }
+
+    fn check_layout(compression: Compression, encrypted: bool, backend: FileBackend) -> Result<()> {
+        let compressed = compression != Compression::None;
+        if (compressed || encrypted) && backend != FileBackend::Positional {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Compressed and encrypted files need the positional backend",
+            )));
+        }
+
+        if compressed && encrypted {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Files cannot be both compressed and encrypted",
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Derive the cipher for an encrypted file, checking the key against
+    /// the header's key-check value and then the header's seal.
+    #[cfg(feature = "encryption")]
+    fn unlock(header: &FileHeader, master_key: Option<&[u8]>) -> Result<Option<PageCipher>> {
+        let master_key = match (header.encrypted(), master_key) {
+            (false, None) => return Ok(None),
+            (true, Some(master_key)) => master_key,
+            (true, None) => {
+                return Err(StorageError::Io(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "File is encrypted; open it with its key",
+                )))
+            }
+            (false, Some(_)) => {
+                return Err(StorageError::Io(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "File is not encrypted",
+                )))
+            }
+        };
+
+        let (cipher, key_check) = PageCipher::derive(master_key, &header.key_salt)?;
+        if key_check != header.key_check {
+            return Err(StorageError::WrongKey);
+        }
+        if !header.verify_seal(&cipher) {
+            return Err(StorageError::AuthenticationFailed(0));
+        }
+
+        Ok(Some(cipher))
+    }
+
+    pub fn backend(&self) -> FileBackend {
+        self.backend
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.header.lock().compression()
+    }
+
+    pub fn encrypted(&self) -> bool {
+        self.header.lock().encrypted()
+    }
+
+    /// Size of every page in this file, as chosen when it was created.
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Format version the file was written in; see `FILE_VERSION`.
+    pub fn version(&self) -> u32 {
+        self.header.lock().version
+    }
+
+    /// Reserve `slots` pages at the end of the file as a double-write area.
+    ///
+    /// From then on every `write_page` first writes and syncs a copy of the
+    /// page there, and `read_page` repairs a page whose in-place write was
+    /// torn by a crash from that copy. The setting is stored in the file
+    /// header and stays in effect across reopens. At least
+    /// `MIN_DOUBLE_WRITE_SLOTS` slots are needed so that a slot is never
+    /// reused while the write it protects is still in flight.
+    pub fn enable_double_write(&mut self, slots: u32) -> Result<()> {
+        if self.double_write_area.is_some() {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Double-write area is already enabled",
+            )));
+        }
+
+        if self.compression() != Compression::None {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Compressed files never overwrite pages in place and need no double-write area",
+            )));
+        }
+
+        if slots < MIN_DOUBLE_WRITE_SLOTS {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Double-write area needs at least {} slots",
+                    MIN_DOUBLE_WRITE_SLOTS
+                ),
+            )));
+        }
 
-        Ok(Self { file, header })
+        let start = self.header.get_mut().page_count;
+        let empty = vec![0u8; self.page_size];
+        for slot in 0..slots {
+            self.write_raw(start + slot, &empty)?;
+        }
+
+        let mut header = self.header.lock();
+        header.page_count += slots;
+        header.double_write_start = start;
+        header.double_write_slots = slots;
+        header.update_modified_time();
+        self.store_header(&mut header)?;
+        drop(header);
+        self.sync_all()?;
+
+        self.double_write_area = Some((start, slots));
+        *self.double_write.get_mut() = Some(DoubleWrite::new(start, vec![0; slots as usize]));
+        Ok(())
+    }
+
+    /// Page range `(start, slots)` of the double-write area, if enabled.
+    pub fn double_write_area(&self) -> Option<(u32, u32)> {
+        self.double_write_area
+    }
+
+    /// Enforce write-ahead logging: before a page is written, the log is
+    /// flushed up to the LSN stamped in the page header. From then on every
+    /// header change is logged too, and only written out by the next
+    /// `sync`, and so are the pages of the free list.
+    pub fn attach_wal(&mut self, wal: Arc<Wal>) {
+        self.wal = Some(wal);
     }
 
-    pub fn write_page(&mut self, page: &Page) -> Result<()> {
+    pub fn write_page(&self, page: &Page) -> Result<()> {
         let page_id = page.header().page_id;
 
         // Page 0 is reserved for the file header
@@ -234,28 +778,79 @@ impl PageFile {
             )));
         }
 
-        // Seek to page position
-        let offset = page_id as u64 * PAGE_SIZE as u64;
-        self.file
-            .seek(SeekFrom::Start(offset))
-            .map_err(StorageError::Io)?;
-
-        // Write page data
-        self.file
-            .write_all(page.as_bytes())
-            .map_err(StorageError::Io)?;
+        self.reject_double_write_page(page_id)?;
+        self.write_unchecked(page)?;
 
         // Update header if this extends the file
-        if page_id >= self.header.page_count {
-            self.header.page_count = page_id + 1;
-            self.update_modified_time();
-            self.write_header()?;
+        let mut header = self.header.lock();
+        if page_id >= header.page_count {
+            header.page_count = page_id + 1;
+            header.update_modified_time();
+            self.store_header(&mut header)?;
         }
 
         Ok(())
     }
 
-    pub fn read_page(&mut self, page_id: u32) -> Result<Page> {
+    /// Log an image of `page` to the attached write-ahead log, stamping the
+    /// record's LSN into it.
+    fn log_image(&self, page: &mut Page) -> Result<()> {
+        if let Some(wal) = &self.wal {
+            let page_id = page.header().page_id;
+            let image = page.as_bytes().to_vec();
+            wal.log_page(NO_TXN, page, LogBody::PageImage { page_id, image })?;
+        }
+        Ok(())
+    }
+
+    /// Write a page without validating its id or touching the header, so
+    /// callers already holding the header lock can use it.
+    fn write_unchecked(&self, page: &Page) -> Result<()> {
+        let page_id = page.header().page_id;
+
+        // The log describing this page's changes must be durable first
+        if let Some(wal) = &self.wal {
+            wal.flush_to(page.header().lsn)?;
+        }
+
+        if page.size() != self.page_size {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Page {} is {} bytes but the file uses {} byte pages",
+                    page_id,
+                    page.size(),
+                    self.page_size
+                ),
+            )));
+        }
+
+        // Torn-page detection relies on every written page carrying a checksum
+        let mut stamped;
+        let mut bytes = page.as_bytes();
+        if self.checksums {
+            stamped = Page::from_bytes(bytes)?;
+            stamped.update_checksum();
+            bytes = stamped.as_bytes();
+        }
+
+        // The copy must be durable before the in-place write can tear, and
+        // the slot must not be reclaimed until the in-place write is issued
+        let mut double_write = self.double_write.lock();
+        if let Some(dw) = double_write.as_mut() {
+            let slot = dw.claim(page_id);
+            if dw.needs_sync_before(slot) {
+                self.sync_data()?;
+            }
+            self.write_raw(dw.slot_page(slot), bytes)?;
+            self.sync_data()?;
+            dw.mark_unsynced(slot);
+        }
+
+        self.write_raw(page_id, bytes)
+    }
+
+    pub fn read_page(&self, page_id: u32) -> Result<Box<Page>> {
         if page_id == 0 {
             return Err(StorageError::Io(io::Error::new(
                 io::ErrorKind::InvalidInput,
@@ -263,107 +858,1508 @@ impl PageFile {
             )));
         }
 
-        if page_id >= self.header.page_count {
+        if page_id >= self.page_count() {
             return Err(StorageError::PageNotFound(page_id));
         }
 
-        let offset = page_id as u64 * PAGE_SIZE as u64;
-        self.file
-            .seek(SeekFrom::Start(offset))
-            .map_err(StorageError::Io)?;
+        self.reject_double_write_page(page_id)?;
+        self.read_unchecked(page_id)
+    }
 
-        let mut buffer = [0u8; PAGE_SIZE];
-        self.file
-            .read_exact(&mut buffer)
-            .map_err(StorageError::Io)?;
+    /// Read and verify a page without the bounds check, which would need
+    /// the header lock.
+    fn read_unchecked(&self, page_id: u32) -> Result<Box<Page>> {
+        let buffer = match self.read_raw(page_id) {
+            Ok(buffer) => buffer,
+            // A torn write of an encrypted page fails authentication
+            Err(error @ StorageError::AuthenticationFailed(_)) => {
+                return self.restore_from_double_write(page_id)?.ok_or(error);
+            }
+            Err(e) => return Err(e),
+        };
 
-        let page = Page::from_bytes(&buffer)?;
+        let error = match Page::from_bytes(&buffer) {
+            // Verify checksum if enabled
+            Ok(page) if self.checksums && !page.verify_checksum() => {
+                StorageError::ChecksumMismatch(page_id)
+            }
+            Ok(page) => return Ok(page),
+            Err(e) => e,
+        };
 
-        // Verify checksum if enabled
-        if self.header.data_checksum_flag != 0 && !page.verify_checksum() {
-            return Err(StorageError::ChecksumMismatch(page_id));
+        // A damaged page may be the victim of a torn write
+        match self.restore_from_double_write(page_id)? {
+            Some(page) => Ok(page),
+            None => Err(error),
         }
-
-        Ok(page)
     }
 
-    pub fn allocate_page(&mut self) -> Result<u32> {
-        // For now, just append a new page
-        // TODO: Later implement free list management
-        let page_id = self.header.page_count;
-        self.header.page_count += 1;
+    /// Borrow a page straight out of the memory map instead of copying it,
+    /// verifying it the way `read_page` does. Only available with the
+    /// `Mmap` backend.
+    ///
+    /// The view keeps the mapping locked: every page write, in any thread,
+    /// waits until it is dropped, so a thread must not write while holding one.
+    #[cfg(feature = "mmap")]
+    pub fn view_page(&self, page_id: u32) -> Result<PageView<'_>> {
+        let Some(map) = &self.map else {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Page views need the mmap backend",
+            )));
+        };
 
-        // Create and write an empty page
-        let mut page = Page::new(page_id, PageType::Free);
-        if self.header.data_checksum_flag != 0 {
-            page.update_checksum();
+        if page_id == 0 {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot read page 0 as a data page (it's the header)",
+            )));
         }
 
-        let offset = page_id as u64 * PAGE_SIZE as u64;
-        self.file
-            .seek(SeekFrom::Start(offset))
-            .map_err(StorageError::Io)?;
-        self.file
-            .write_all(page.as_bytes())
-            .map_err(StorageError::Io)?;
-
-        self.update_modified_time();
-        self.write_header()?;
+        if page_id >= self.page_count() {
+            return Err(StorageError::PageNotFound(page_id));
+        }
 
-        Ok(page_id)
-    }
+        self.reject_double_write_page(page_id)?;
 
-    pub fn page_count(&self) -> u32 {
-        self.header.page_count
-    }
+        let error = match map.view(page_id) {
+            Ok(view) if self.checksums && !view.verify_checksum() => {
+                StorageError::ChecksumMismatch(page_id)
+            }
+            Ok(view) => return Ok(view),
+            Err(e) => e,
+        };
 
-    pub fn sync(&mut self) -> Result<()> {
-        self.file.sync_all().map_err(StorageError::Io)
+        // The view is gone, so the repair is free to write through the map
+        match self.restore_from_double_write(page_id)? {
+            Some(_) => map.view(page_id),
+            None => Err(error),
+        }
     }
 
-    fn write_header(&mut self) -> Result<()> {
-        self.header.update_checksum();
+    /// Replace a damaged page with its copy in the double-write area, if
+    /// there is an intact one.
+    fn restore_from_double_write(&self, page_id: u32) -> Result<Option<Box<Page>>> {
+        let double_write = self.double_write.lock();
+        let Some(dw) = double_write.as_ref() else {
+            return Ok(None);
+        };
+        let Some(slot) = dw.slot_of(page_id) else {
+            return Ok(None);
+        };
 
-        // Create a full page for the header (for alignment)
-        let mut header_page = [0u8; PAGE_SIZE];
-        let header_bytes = self.header.to_bytes();
-        header_page[0..HEADER_SIZE].copy_from_slice(&header_bytes);
+        let buffer = match self.read_raw(dw.slot_page(slot)) {
+            Ok(buffer) => buffer,
+            Err(StorageError::AuthenticationFailed(_)) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let page = match Page::from_bytes(&buffer) {
+            Ok(page) if page.header().page_id == page_id && page.verify_checksum() => page,
+            _ => return Ok(None),
+        };
 
-        self.file
-            .seek(SeekFrom::Start(0))
-            .map_err(StorageError::Io)?;
-        self.file
-            .write_all(&header_page)
-            .map_err(StorageError::Io)?;
+        self.write_raw(page_id, &buffer)?;
+        self.sync_data()?;
+        log::warn!("Restored torn page {} from the double-write area", page_id);
 
-        Ok(())
+        Ok(Some(page))
     }
 
-    fn read_header(file: &mut File) -> Result<FileHeader> {
-        file.seek(SeekFrom::Start(0)).map_err(StorageError::Io)?;
+    pub fn allocate_page(&self) -> Result<u32> {
+        let mut header = self.header.lock();
 
-        let mut buffer = [0u8; PAGE_SIZE];
-        file.read_exact(&mut buffer).map_err(StorageError::Io)?;
+        // Reuse a previously freed page before growing the file
+        if header.free_list_head != 0 {
+            return self.pop_free_page(&mut header);
+        }
 
-        let header = FileHeader::from_bytes(&buffer[0..HEADER_SIZE])?;
+        let page_id = header.page_count;
+        header.page_count += 1;
 
-        // Verify checksum
-        if !header.verify_checksum() {
+        // Not logged: should the header not reach the file either, the
+        // page is past its end again, and redo rebuilds whatever is logged
+        // for it later
+        let page = Page::with_size(self.page_size, page_id, PageType::Free);
+        self.write_unchecked(&page)?;
+
+        header.update_modified_time();
+        self.store_header(&mut header)?;
+
+        Ok(page_id)
+    }
+
+    /// Release a page back to the file, threading it onto the on-disk free
+    /// list so a later `allocate_page` can hand it out again.
+    ///
+    /// Freeing a page that is already on the free list is an error, since
+    /// threading it on a second time would create a cycle.
+    pub fn free_page(&self, page_id: u32) -> Result<()> {
+        if page_id == 0 {
             return Err(StorageError::Io(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "File header checksum mismatch",
+                io::ErrorKind::InvalidInput,
+                "Cannot free page 0 (reserved for file header)",
             )));
         }
 
-        Ok(header)
-    }
+        let mut header = self.header.lock();
+        if page_id >= header.page_count {
+            return Err(StorageError::PageNotFound(page_id));
+        }
 
-    fn update_modified_time(&mut self) {
-        use std::time::{SystemTime, UNIX_EPOCH};
+        self.reject_double_write_page(page_id)?;
 
-        self.header.last_modified = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+        // Freshly allocated pages are typed Free as well, so only a page
+        // that is actually on the list is a double free
+        let mut free_pages = self.free_pages.lock();
+        if free_pages.is_none() {
+            *free_pages = Some(self.read_free_list(&header)?);
+        }
+        let free_pages = free_pages.as_mut().unwrap();
+        if free_pages.contains(&page_id) {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Page {} is already free", page_id),
+            )));
+        }
+
+        // Write the page before the header so a crash in between only leaks it
+        self.write_free_page(page_id, header.free_list_head)?;
+
+        header.free_list_head = page_id;
+        header.update_modified_time();
+        self.store_header(&mut header)?;
+        free_pages.insert(page_id);
+
+        Ok(())
+    }
+
+    pub fn checksums_enabled(&self) -> bool {
+        self.checksums
+    }
+
+    pub fn free_list_head(&self) -> u32 {
+        self.header.lock().free_list_head
+    }
+
+    /// First and last page of the heap's data page chain (0 = none).
+    pub fn data_pages(&self) -> (u32, u32) {
+        let header = self.header.lock();
+        (header.first_data_page, header.last_data_page)
+    }
+
+    pub fn set_data_pages(&self, first: u32, last: u32) -> Result<()> {
+        let mut header = self.header.lock();
+        header.first_data_page = first;
+        header.last_data_page = last;
+        header.update_modified_time();
+        self.store_header(&mut header)
+    }
+
+    /// First page of the relation directory (0 = none yet).
+    pub fn directory_page(&self) -> u32 {
+        self.header.lock().directory_page
+    }
+
+    pub fn set_directory_page(&self, page_id: u32) -> Result<()> {
+        let mut header = self.header.lock();
+        header.directory_page = page_id;
+        header.update_modified_time();
+        self.store_header(&mut header)
+    }
+
+    /// Hand out a relation id; ids are never reused within a file.
+    pub fn allocate_relation_id(&self) -> Result<u32> {
+        let mut header = self.header.lock();
+
+        // Files written before relations existed have zero here
+        let id = header.next_relation_id.max(1);
+        header.next_relation_id = id + 1;
+        header.update_modified_time();
+        self.store_header(&mut header)?;
+        Ok(id)
+    }
+
+    /// No transaction id from this one on has been handed out.
+    pub fn next_txn_id(&self) -> TxnId {
+        self.header.lock().next_txn_id
+    }
+
+    pub(crate) fn set_next_txn_id(&self, txn_id: TxnId) -> Result<()> {
+        let mut header = self.header.lock();
+        header.next_txn_id = txn_id;
+        header.update_modified_time();
+        self.store_header(&mut header)
+    }
+
+    /// First page of the commit log (0 = none yet).
+    pub fn commit_log_page(&self) -> u32 {
+        self.header.lock().commit_log_page
+    }
+
+    pub(crate) fn set_commit_log_page(&self, page_id: u32) -> Result<()> {
+        let mut header = self.header.lock();
+        header.commit_log_page = page_id;
+        header.update_modified_time();
+        self.store_header(&mut header)
+    }
+
+    fn pop_free_page(&self, header: &mut FileHeader) -> Result<u32> {
+        let page_id = header.free_list_head;
+        let page = self.read_unchecked(page_id)?;
+
+        if page.header().page_type != PageType::Free {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Free list entry {} is not a free page", page_id),
+            )));
+        }
+
+        // Hand out a clean page rather than whatever the previous owner left
+        self.write_free_page(page_id, 0)?;
+
+        header.free_list_head = page.header().next_page;
+        header.update_modified_time();
+        self.store_header(header)?;
+        if let Some(free_pages) = self.free_pages.lock().as_mut() {
+            free_pages.remove(&page_id);
+        }
+
+        Ok(page_id)
+    }
+
+    fn read_free_list(&self, header: &FileHeader) -> Result<HashSet<u32>> {
+        let mut free_pages = HashSet::new();
+        let mut next = header.free_list_head;
+        // A damaged list cannot be longer than the file
+        while next != 0 && free_pages.len() < header.page_count as usize {
+            free_pages.insert(next);
+            next = self.read_unchecked(next)?.header().next_page;
+        }
+
+        Ok(free_pages)
+    }
+
+    fn write_free_page(&self, page_id: u32, next_page: u32) -> Result<()> {
+        let mut page = Page::with_size(self.page_size, page_id, PageType::Free);
+        page.header_mut().next_page = next_page;
+        self.log_image(&mut page)?;
+        self.write_unchecked(&page)
+    }
+
+    fn reject_double_write_page(&self, page_id: u32) -> Result<()> {
+        if self
+            .double_write_area
+            .is_some_and(|(start, slots)| page_id >= start && page_id - start < slots)
+        {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Page {} belongs to the double-write area", page_id),
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn write_raw(&self, page_id: u32, bytes: &[u8]) -> Result<()> {
+        #[cfg(feature = "compression")]
+        if let Some(compressed) = &self.compressed {
+            return compressed.write(&self.file, page_id, bytes);
+        }
+
+        // Encrypted pages are stored whole, so each takes a little more room
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            let sealed = cipher.seal_page(page_id, bytes);
+            let offset = page_id as u64 * sealed.len() as u64;
+            return positional::write_all_at(&self.file, &sealed, offset).map_err(StorageError::Io);
+        }
+
+        #[cfg(feature = "mmap")]
+        if let Some(map) = &self.map {
+            return map
+                .write(&self.file, page_id, bytes)
+                .map_err(StorageError::Io);
+        }
+
+        let offset = page_id as u64 * self.page_size as u64;
+        positional::write_all_at(&self.file, bytes, offset).map_err(StorageError::Io)
+    }
+
+    fn read_raw(&self, page_id: u32) -> Result<Vec<u8>> {
+        #[cfg(feature = "compression")]
+        if let Some(compressed) = &self.compressed {
+            return compressed.read(&self.file, page_id);
+        }
+
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            let stride = self.page_size + encryption::OVERHEAD;
+            let mut sealed = vec![0u8; stride];
+            positional::read_exact_at(&self.file, &mut sealed, page_id as u64 * stride as u64)
+                .map_err(StorageError::Io)?;
+            return cipher.open_page(page_id, &sealed);
+        }
+
+        #[cfg(feature = "mmap")]
+        if let Some(map) = &self.map {
+            return map.read(page_id).map_err(StorageError::Io);
+        }
+
+        let offset = page_id as u64 * self.page_size as u64;
+        let mut buffer = vec![0u8; self.page_size];
+        positional::read_exact_at(&self.file, &mut buffer, offset).map_err(StorageError::Io)?;
+        Ok(buffer)
+    }
+
+    /// Make page writes durable; pages written through the map are flushed
+    /// first, since syncing the file alone need not cover them everywhere.
+    fn sync_data(&self) -> Result<()> {
+        #[cfg(feature = "mmap")]
+        if let Some(map) = &self.map {
+            map.flush().map_err(StorageError::Io)?;
+        }
+
+        self.file.sync_data().map_err(StorageError::Io)
+    }
+
+    fn sync_all(&self) -> Result<()> {
+        #[cfg(feature = "mmap")]
+        if let Some(map) = &self.map {
+            map.flush().map_err(StorageError::Io)?;
+        }
+
+        self.file.sync_all().map_err(StorageError::Io)
+    }
+
+    /// Rebuild which page each double-write slot holds from the slots' page
+    /// headers. Slots that never held a page are all zeroes.
+    fn load_double_write(&self, header: &FileHeader) -> Result<Option<DoubleWrite>> {
+        if header.double_write_slots == 0 {
+            return Ok(None);
+        }
+
+        let start = header.double_write_start;
+        let mut occupants = Vec::with_capacity(header.double_write_slots as usize);
+        for slot in 0..header.double_write_slots {
+            // A slot whose write was torn holds nothing usable
+            let page_id = match self.read_raw(start + slot) {
+                Ok(buffer) => u32::from_le_bytes(buffer[0..4].try_into().unwrap()),
+                Err(StorageError::AuthenticationFailed(_)) => 0,
+                Err(e) => return Err(e),
+            };
+
+            // A page id can only appear once; a stale duplicate is ignored
+            if page_id == 0 || occupants.contains(&page_id) {
+                occupants.push(0);
+            } else {
+                occupants.push(page_id);
+            }
+        }
+
+        Ok(Some(DoubleWrite::new(start, occupants)))
+    }
+
+    pub fn page_count(&self) -> u32 {
+        self.header.lock().page_count
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        {
+            let mut header = self.header.lock();
+            if self.header_unwritten.load(Ordering::Acquire) {
+                if let Some(wal) = &self.wal {
+                    wal.flush_to(header.lsn)?;
+                }
+                self.write_stored_header(&mut header)?;
+                self.header_unwritten.store(false, Ordering::Release);
+            }
+        }
+
+        // Writes are held off so none can slip in between the sync and
+        // marking every slot as durable
+        let mut double_write = self.double_write.lock();
+
+        // Only what was replaced before the sync is safe to reuse after it.
+        // Should the sync fail, the space is left unused until the file is
+        // reopened.
+        #[cfg(feature = "compression")]
+        let superseded = self
+            .compressed
+            .as_ref()
+            .map(compressed::CompressedPages::take_superseded);
+
+        self.sync_all()?;
+        if let Some(dw) = double_write.as_mut() {
+            dw.mark_synced();
+        }
+
+        #[cfg(feature = "compression")]
+        if let (Some(compressed), Some(extents)) = (&self.compressed, superseded) {
+            compressed.release(extents);
+        }
+        Ok(())
+    }
+
+    /// Write the header, sealing it first if the file is encrypted. With a
+    /// log attached the change is only logged, and written by `sync` once
+    /// the log is flushed past it, so recovery never finds a header newer
+    /// than the log it replays.
+    fn store_header(&self, header: &mut FileHeader) -> Result<()> {
+        if let Some(wal) = &self.wal {
+            let image = header.to_bytes().to_vec();
+            header.lsn = wal.append(NO_TXN, LogBody::FileHeader { image })?;
+            self.header_unwritten.store(true, Ordering::Release);
+            return Ok(());
+        }
+        self.write_stored_header(header)
+    }
+
+    /// Replay a header image logged at `lsn`, unless the header on disk
+    /// is already as new. Returns whether it was replayed.
+    pub(crate) fn redo_header(&self, image: &[u8], lsn: Lsn) -> Result<bool> {
+        let mut header = self.header.lock();
+        if header.lsn >= lsn {
+            return Ok(false);
+        }
+
+        *header = FileHeader {
+            lsn,
+            ..FileHeader::from_bytes(image)?
+        };
+        self.write_stored_header(&mut header)?;
+        // The free list may have changed with it; read it again when needed
+        *self.free_pages.lock() = None;
+        Ok(true)
+    }
+
+    fn write_stored_header(&self, header: &mut FileHeader) -> Result<()> {
+        #[cfg(feature = "encryption")]
+        if let Some(cipher) = &self.cipher {
+            header.seal(cipher);
+        }
+
+        Self::write_header(&self.file, header)
+    }
+
+    fn write_header(file: &File, header: &mut FileHeader) -> Result<()> {
+        header.update_checksum();
+
+        // Create a full page for the header (for alignment)
+        let mut header_page = vec![0u8; header.page_size()];
+        let header_bytes = header.to_bytes();
+        header_page[0..HEADER_SIZE].copy_from_slice(&header_bytes);
+
+        positional::write_all_at(file, &header_page, 0).map_err(StorageError::Io)
+    }
+
+    /// Open a file for offline inspection: nothing is ever written, so
+    /// damaged pages are seen as they are rather than repaired.
+    pub fn open_read_only(path: &Path) -> Result<ReadOnlyFile> {
+        let file = File::open(path).map_err(StorageError::Io)?;
+        let header = Self::read_header(&file)?;
+
+        #[cfg(feature = "compression")]
+        let compressed = match header.compression() {
+            Compression::Lz4 => Some(compressed::CompressedPages::load(
+                &file,
+                header.page_size(),
+            )?),
+            Compression::None => None,
+        };
+
+        Ok(ReadOnlyFile {
+            file,
+            header,
+            #[cfg(feature = "compression")]
+            compressed,
+        })
+    }
+
+    fn read_header(file: &File) -> Result<FileHeader> {
+        // The page size is in the header, so only the header itself is read
+        let mut buffer = [0u8; HEADER_SIZE];
+        positional::read_exact_at(file, &mut buffer, 0).map_err(StorageError::Io)?;
+
+        let header = FileHeader::from_bytes(&buffer)?;
+
+        // Verify checksum
+        if !header.verify_checksum() {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "File header checksum mismatch",
+            )));
+        }
+
+        Ok(header)
+    }
+}
+
+/// A database file opened by `PageFile::open_read_only`.
+pub struct ReadOnlyFile {
+    file: File,
+    header: FileHeader,
+    #[cfg(feature = "compression")]
+    compressed: Option<compressed::CompressedPages>,
+}
+
+impl ReadOnlyFile {
+    pub fn header(&self) -> &FileHeader {
+        &self.header
+    }
+
+    /// Bytes of a page as stored, decompressed if the file is compressed.
+    /// Page 0 is the header page. Pages of encrypted files cannot be read,
+    /// as there is no key to decrypt them with.
+    pub fn read_page_bytes(&self, page_id: u32) -> Result<Vec<u8>> {
+        if self.header.encrypted() && page_id != 0 {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Page {} is encrypted", page_id),
+            )));
+        }
+
+        #[cfg(feature = "compression")]
+        if let Some(compressed) = self.compressed.as_ref().filter(|_| page_id != 0) {
+            return compressed.read(&self.file, page_id);
+        }
+
+        let page_size = self.header.page_size();
+        let offset = page_id as u64 * page_size as u64;
+        let mut buffer = vec![0u8; page_size];
+        positional::read_exact_at(&self.file, &mut buffer, offset).map_err(StorageError::Io)?;
+        Ok(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Seek, SeekFrom, Write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_allocate_appends_when_free_list_empty() {
+        let dir = tempdir().unwrap();
+        let file = PageFile::create_new(&dir.path().join("test.jdb")).unwrap();
+
+        assert_eq!(file.allocate_page().unwrap(), 1);
+        assert_eq!(file.allocate_page().unwrap(), 2);
+        assert_eq!(file.page_count(), 3);
+        assert_eq!(file.free_list_head(), 0);
+    }
+
+    #[test]
+    fn test_free_page_is_reused() {
+        let dir = tempdir().unwrap();
+        let file = PageFile::create_new(&dir.path().join("test.jdb")).unwrap();
+
+        let a = file.allocate_page().unwrap();
+        file.allocate_page().unwrap();
+        let c = file.allocate_page().unwrap();
+
+        file.free_page(a).unwrap();
+        file.free_page(c).unwrap();
+        assert_eq!(file.free_list_head(), c);
+
+        // Free list is LIFO and the file does not grow while it has entries
+        assert_eq!(file.allocate_page().unwrap(), c);
+        assert_eq!(file.allocate_page().unwrap(), a);
+        assert_eq!(file.free_list_head(), 0);
+        assert_eq!(file.page_count(), 4);
+
+        // Once drained, allocation appends again
+        assert_eq!(file.allocate_page().unwrap(), 4);
+    }
+
+    #[test]
+    fn test_free_page_links_through_free_pages() {
+        let dir = tempdir().unwrap();
+        let file = PageFile::create_new(&dir.path().join("test.jdb")).unwrap();
+
+        let a = file.allocate_page().unwrap();
+        let b = file.allocate_page().unwrap();
+        file.free_page(a).unwrap();
+        file.free_page(b).unwrap();
+
+        let head = file.read_page(b).unwrap();
+        assert_eq!(head.header().page_type, PageType::Free);
+        assert_eq!(head.header().next_page, a);
+
+        let tail = file.read_page(a).unwrap();
+        assert_eq!(tail.header().page_type, PageType::Free);
+        assert_eq!(tail.header().next_page, 0);
+    }
+
+    #[test]
+    fn test_reused_page_is_cleared() {
+        let dir = tempdir().unwrap();
+        let file = PageFile::create_new(&dir.path().join("test.jdb")).unwrap();
+
+        let page_id = file.allocate_page().unwrap();
+        let mut page = Page::new(page_id, PageType::Data);
+        page.add_record(b"stale row").unwrap();
+        page.update_checksum();
+        file.write_page(&page).unwrap();
+
+        file.free_page(page_id).unwrap();
+        assert_eq!(file.allocate_page().unwrap(), page_id);
+
+        let reused = file.read_page(page_id).unwrap();
+        assert_eq!(reused.header().page_type, PageType::Free);
+        assert_eq!(reused.header().slot_count, 0);
+        assert_eq!(reused.header().next_page, 0);
+    }
+
+    #[test]
+    fn test_free_list_survives_reopen() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+
+        {
+            let file = PageFile::create_new(&path).unwrap();
+            let a = file.allocate_page().unwrap();
+            file.allocate_page().unwrap();
+            file.free_page(a).unwrap();
+            file.sync().unwrap();
+        }
+
+        let file = PageFile::open(&path).unwrap();
+        assert_eq!(file.free_list_head(), 1);
+        assert_eq!(file.allocate_page().unwrap(), 1);
+        assert_eq!(file.page_count(), 3);
+    }
+
+    #[test]
+    fn test_free_invalid_pages() {
+        let dir = tempdir().unwrap();
+        let file = PageFile::create_new(&dir.path().join("test.jdb")).unwrap();
+
+        assert!(file.free_page(0).is_err());
+        assert!(matches!(
+            file.free_page(7),
+            Err(StorageError::PageNotFound(7))
+        ));
+    }
+
+    #[test]
+    fn test_double_free_is_rejected() {
+        let dir = tempdir().unwrap();
+        let file = PageFile::create_new(&dir.path().join("test.jdb")).unwrap();
+
+        let a = file.allocate_page().unwrap();
+        let b = file.allocate_page().unwrap();
+        file.free_page(a).unwrap();
+        file.free_page(b).unwrap();
+
+        // Neither the head nor the tail of the list can be freed again
+        assert!(file.free_page(b).is_err());
+        assert!(file.free_page(a).is_err());
+        assert_eq!(file.free_list_head(), b);
+        assert_eq!(file.allocate_page().unwrap(), b);
+        assert_eq!(file.allocate_page().unwrap(), a);
+        assert_eq!(file.free_list_head(), 0);
+    }
+
+    #[test]
+    fn test_double_free_is_rejected_after_reopen() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+
+        let pages: Vec<u32> = {
+            let file = PageFile::create_new(&path).unwrap();
+            let pages: Vec<u32> = (0..4).map(|_| file.allocate_page().unwrap()).collect();
+            file.free_page(pages[1]).unwrap();
+            file.free_page(pages[2]).unwrap();
+            file.sync().unwrap();
+            pages
+        };
+
+        // The free list is read back from disk, not just remembered
+        let file = PageFile::open(&path).unwrap();
+        assert!(file.free_page(pages[1]).is_err());
+        assert!(file.free_page(pages[2]).is_err());
+        file.free_page(pages[3]).unwrap();
+        assert!(file.free_page(pages[3]).is_err());
+
+        // A page handed out again can be freed again
+        assert_eq!(file.allocate_page().unwrap(), pages[3]);
+        file.free_page(pages[3]).unwrap();
+        file.free_page(pages[0]).unwrap();
+        assert_eq!(file.free_list_head(), pages[0]);
+    }
+
+    /// Overwrite the second half of a page on disk, as a crash midway
+    /// through its write would.
+    fn tear_page(path: &Path, page_size: usize, page_id: u32) {
+        let mut raw = OpenOptions::new().write(true).open(path).unwrap();
+        let offset = page_id as u64 * page_size as u64 + page_size as u64 / 2;
+        raw.seek(SeekFrom::Start(offset)).unwrap();
+        raw.write_all(&vec![0xAB; page_size / 2]).unwrap();
+        raw.sync_all().unwrap();
+    }
+
+    fn write_record(file: &PageFile, page_id: u32, record: &[u8]) {
+        let mut page = Page::with_size(file.page_size(), page_id, PageType::Data);
+        page.add_record(record).unwrap();
+        file.write_page(&page).unwrap();
+    }
+
+    #[test]
+    fn test_torn_page_restored_from_double_write() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        let mut file = PageFile::create_new(&path).unwrap();
+        file.enable_double_write(4).unwrap();
+
+        let page_id = file.allocate_page().unwrap();
+        write_record(&file, page_id, b"precious");
+        tear_page(&path, PAGE_SIZE, page_id);
+
+        let page = file.read_page(page_id).unwrap();
+        assert_eq!(page.get_record(0).unwrap(), b"precious");
+
+        // The repair was written back in place
+        let on_disk = file.read_raw(page_id).unwrap();
+        assert_eq!(&on_disk, page.as_bytes());
+    }
+
+    #[test]
+    fn test_torn_page_without_double_write_is_reported() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        let file = PageFile::create_new(&path).unwrap();
+
+        let page_id = file.allocate_page().unwrap();
+        write_record(&file, page_id, b"precious");
+        tear_page(&path, PAGE_SIZE, page_id);
+
+        assert!(matches!(
+            file.read_page(page_id),
+            Err(StorageError::ChecksumMismatch(id)) if id == page_id
+        ));
+    }
+
+    #[test]
+    fn test_double_write_restores_latest_version() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        let mut file = PageFile::create_new(&path).unwrap();
+        file.enable_double_write(2).unwrap();
+
+        let target = file.allocate_page().unwrap();
+        write_record(&file, target, b"old");
+
+        // Cycle other pages through the area, then write the target again
+        for _ in 0..3 {
+            let other = file.allocate_page().unwrap();
+            write_record(&file, other, b"filler");
+        }
+        write_record(&file, target, b"new");
+        tear_page(&path, PAGE_SIZE, target);
+
+        let page = file.read_page(target).unwrap();
+        assert_eq!(page.get_record(0).unwrap(), b"new");
+    }
+
+    #[test]
+    fn test_double_write_survives_reopen() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+
+        let (page_id, area) = {
+            let mut file = PageFile::create_new(&path).unwrap();
+            file.enable_double_write(4).unwrap();
+            let page_id = file.allocate_page().unwrap();
+            write_record(&file, page_id, b"durable");
+            file.sync().unwrap();
+            (page_id, file.double_write_area())
+        };
+        tear_page(&path, PAGE_SIZE, page_id);
+
+        let file = PageFile::open(&path).unwrap();
+        assert_eq!(file.double_write_area(), area);
+        let page = file.read_page(page_id).unwrap();
+        assert_eq!(page.get_record(0).unwrap(), b"durable");
+    }
+
+    #[test]
+    fn test_double_write_area_is_off_limits() {
+        let dir = tempdir().unwrap();
+        let mut file = PageFile::create_new(&dir.path().join("test.jdb")).unwrap();
+
+        assert!(file.enable_double_write(1).is_err());
+        file.enable_double_write(2).unwrap();
+        assert!(file.enable_double_write(2).is_err());
+        assert_eq!(file.double_write_area(), Some((1, 2)));
+
+        // The area is never handed out, read or freed as a regular page
+        assert_eq!(file.allocate_page().unwrap(), 3);
+        assert!(file.read_page(1).is_err());
+        assert!(file.write_page(&Page::new(2, PageType::Data)).is_err());
+        assert!(file.free_page(1).is_err());
+    }
+
+    /// A page whose single record is `byte` repeated, so any mix of two
+    /// writes shows up as a record with more than one distinct byte.
+    fn pattern_page(page_id: u32, byte: u8) -> Box<Page> {
+        let mut page = Page::new(page_id, PageType::Data);
+        page.add_record(&[byte; 4000]).unwrap();
+        page
+    }
+
+    fn assert_pattern(page: &Page, page_id: u32) -> u8 {
+        assert_eq!(page.header().page_id, page_id);
+        assert!(page.verify_checksum());
+        let record = page.get_record(0).unwrap();
+        assert_eq!(record.len(), 4000);
+        assert!(
+            record.iter().all(|&b| b == record[0]),
+            "page {} is mixed",
+            page_id
+        );
+        record[0]
+    }
+
+    fn backends() -> Vec<FileBackend> {
+        vec![
+            FileBackend::Positional,
+            #[cfg(feature = "mmap")]
+            FileBackend::Mmap,
+        ]
+    }
+
+    fn options(page_size: usize, backend: FileBackend) -> FileOptions {
+        FileOptions {
+            page_size,
+            backend,
+            ..FileOptions::default()
+        }
+    }
+
+    #[test]
+    fn test_concurrent_access_does_not_interleave() {
+        const THREADS: usize = 8;
+        const PAGES_PER_THREAD: u32 = 4;
+        const SHARED_PAGES: u32 = 8;
+
+        let configs = backends()
+            .into_iter()
+            .flat_map(|backend| [(backend, false), (backend, true)]);
+        for (backend, double_write) in configs {
+            let dir = tempdir().unwrap();
+            let path = dir.path().join("test.jdb");
+            let mut file = PageFile::create_new_with(&path, options(PAGE_SIZE, backend)).unwrap();
+            if double_write {
+                file.enable_double_write(MIN_DOUBLE_WRITE_SLOTS).unwrap();
+            }
+            let rounds = if double_write { 5 } else { 50 };
+
+            // Pages every thread reads but none writes
+            let shared: Vec<u32> = (0..SHARED_PAGES)
+                .map(|i| {
+                    let page_id = file.allocate_page().unwrap();
+                    file.write_page(&pattern_page(page_id, i as u8)).unwrap();
+                    page_id
+                })
+                .collect();
+
+            let file = Arc::new(file);
+            let handles: Vec<_> = (0..THREADS)
+                .map(|t| {
+                    let file = Arc::clone(&file);
+                    let shared = shared.clone();
+                    std::thread::spawn(move || {
+                        let owned: Vec<u32> = (0..PAGES_PER_THREAD)
+                            .map(|_| file.allocate_page().unwrap())
+                            .collect();
+
+                        for round in 0..rounds {
+                            let byte = (t * rounds + round) as u8;
+                            for &page_id in &owned {
+                                file.write_page(&pattern_page(page_id, byte)).unwrap();
+                            }
+                            for &page_id in &owned {
+                                let page = file.read_page(page_id).unwrap();
+                                assert_eq!(assert_pattern(&page, page_id), byte);
+                            }
+                            for (i, &page_id) in shared.iter().enumerate() {
+                                let page = file.read_page(page_id).unwrap();
+                                assert_eq!(assert_pattern(&page, page_id), i as u8);
+                            }
+                        }
+                        owned
+                    })
+                })
+                .collect();
+
+            let mut allocated: Vec<u32> = handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect();
+
+            // Concurrent allocations never handed out the same page twice
+            allocated.sort_unstable();
+            allocated.dedup();
+            assert_eq!(allocated.len(), THREADS * PAGES_PER_THREAD as usize);
+
+            let expected = 1 + SHARED_PAGES + (THREADS as u32) * PAGES_PER_THREAD;
+            let area = file.double_write_area().map_or(0, |(_, slots)| slots);
+            assert_eq!(file.page_count(), expected + area);
+
+            // The header written last reflects every allocation
+            drop(file);
+            let file = PageFile::open(&path).unwrap();
+            assert_eq!(file.page_count(), expected + area);
+            for &page_id in &allocated {
+                assert_pattern(&file.read_page(page_id).unwrap(), page_id);
+            }
+        }
+    }
+
+    #[test]
+    fn test_page_size_chosen_at_creation() {
+        for backend in backends() {
+            for page_size in [4096, 16384, 32768, 65536] {
+                let dir = tempdir().unwrap();
+                let path = dir.path().join("test.jdb");
+                {
+                    let mut file =
+                        PageFile::create_new_with(&path, options(page_size, backend)).unwrap();
+                    assert_eq!(file.page_size(), page_size);
+                    file.enable_double_write(MIN_DOUBLE_WRITE_SLOTS).unwrap();
+
+                    let first = file.allocate_page().unwrap();
+                    let second = file.allocate_page().unwrap();
+                    let record = vec![5u8; Page::max_record_size(page_size)];
+                    write_record(&file, first, &record);
+                    write_record(&file, second, b"second");
+
+                    // Pages must match the file's page size
+                    let other = if page_size == PAGE_SIZE {
+                        4096
+                    } else {
+                        PAGE_SIZE
+                    };
+                    let wrong = Page::with_size(other, second, PageType::Data);
+                    assert!(file.write_page(&wrong).is_err());
+                    file.sync().unwrap();
+                }
+                assert_eq!(
+                    std::fs::metadata(&path).unwrap().len(),
+                    5 * page_size as u64
+                );
+
+                // The size comes from the header on open
+                tear_page(&path, page_size, 4);
+                let file = PageFile::open_with(&path, backend).unwrap();
+                assert_eq!(file.page_size(), page_size);
+                let page = file.read_page(3).unwrap();
+                assert_eq!(page.size(), page_size);
+                assert_eq!(
+                    page.get_record(0).unwrap().len(),
+                    Page::max_record_size(page_size)
+                );
+                assert_eq!(file.read_page(4).unwrap().get_record(0).unwrap(), b"second");
+            }
+        }
+    }
+
+    #[test]
+    fn test_unsupported_page_sizes_rejected() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        for page_size in [0, 2048, 12288, 131072] {
+            let options = options(page_size, FileBackend::default());
+            assert!(PageFile::create_new_with(&path, options).is_err());
+            assert!(!path.exists());
+        }
+
+        // A header claiming an odd page size is refused on open
+        PageFile::create_new(&path).unwrap();
+        let mut raw = OpenOptions::new().write(true).open(&path).unwrap();
+        raw.seek(SeekFrom::Start(12)).unwrap();
+        raw.write_all(&12288u32.to_le_bytes()).unwrap();
+        drop(raw);
+        assert!(PageFile::open(&path).is_err());
+    }
+
+    /// Set the format version recorded in the header of the file at `path`.
+    fn set_version(path: &Path, version: u32) {
+        let mut bytes = std::fs::read(path).unwrap();
+        let mut header = FileHeader::from_bytes(&bytes[..HEADER_SIZE]).unwrap();
+        header.version = version;
+        header.update_checksum();
+        bytes[..HEADER_SIZE].copy_from_slice(&header.to_bytes());
+        std::fs::write(path, &bytes).unwrap();
+    }
+
+    #[test]
+    fn test_newer_file_version_rejected() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        PageFile::create_new(&path).unwrap();
+
+        set_version(&path, FILE_VERSION + 1);
+        assert!(PageFile::open(&path).is_err());
+    }
+
+    #[test]
+    fn test_version_1_file_still_opens() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        let page_id = {
+            let file = PageFile::create_new(&path).unwrap();
+            let page_id = file.allocate_page().unwrap();
+            write_record(&file, page_id, b"old");
+            file.sync().unwrap();
+            page_id
+        };
+
+        set_version(&path, 1);
+        let file = PageFile::open(&path).unwrap();
+        assert_eq!(file.version(), 1);
+        assert_eq!(
+            file.read_page(page_id).unwrap().get_record(0).unwrap(),
+            b"old"
+        );
+
+        // Later header updates keep the version the file was written in
+        file.allocate_page().unwrap();
+        drop(file);
+        assert_eq!(PageFile::open(&path).unwrap().version(), 1);
+    }
+
+    #[test]
+    fn test_page_file_is_shareable() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<PageFile>();
+    }
+
+    #[cfg(feature = "compression")]
+    fn compressed(path: &Path) -> PageFile {
+        let options = FileOptions {
+            compression: Compression::Lz4,
+            ..FileOptions::default()
+        };
+        PageFile::create_new_with(path, options).unwrap()
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compressed_pages_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        let noise: Vec<u8> = (0..4000).map(|_| rand::random()).collect();
+        {
+            let file = compressed(&path);
+            assert_eq!(file.compression(), Compression::Lz4);
+            for i in 0..100u32 {
+                let page_id = file.allocate_page().unwrap();
+                write_record(&file, page_id, format!("record {}", i).as_bytes());
+            }
+            // Pages that do not compress are stored as they are
+            write_record(&file, 100, &noise);
+            for page_id in 91..=95 {
+                file.free_page(page_id).unwrap();
+            }
+            file.sync().unwrap();
+        }
+
+        // Mostly empty pages take a fraction of their size on disk
+        let len = std::fs::metadata(&path).unwrap().len();
+        assert!(len < 101 * PAGE_SIZE as u64 / 4, "{} bytes", len);
+
+        let file = PageFile::open(&path).unwrap();
+        assert_eq!(file.compression(), Compression::Lz4);
+        assert_eq!(file.page_count(), 101);
+        for i in 0..90u32 {
+            let page = file.read_page(i + 1).unwrap();
+            assert_eq!(
+                page.get_record(0).unwrap(),
+                format!("record {}", i).as_bytes()
+            );
+        }
+        assert_eq!(file.read_page(100).unwrap().get_record(0).unwrap(), noise);
+        assert_eq!(file.allocate_page().unwrap(), 95);
+        assert_eq!(
+            file.read_page(95).unwrap().header().page_type,
+            PageType::Free
+        );
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compressed_space_reused_after_sync() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        let file = compressed(&path);
+        let page_id = file.allocate_page().unwrap();
+        let len = || std::fs::metadata(&path).unwrap().len();
+
+        // Until a sync, every rewrite needs new space
+        write_record(&file, page_id, b"first");
+        let before = len();
+        for _ in 0..10 {
+            write_record(&file, page_id, b"again");
+        }
+        assert!(len() > before);
+
+        // Afterwards the space the old versions took is reused
+        file.sync().unwrap();
+        let synced = len();
+        for _ in 0..10 {
+            write_record(&file, page_id, b"again");
+            file.sync().unwrap();
+        }
+        assert_eq!(len(), synced);
+        assert_eq!(
+            file.read_page(page_id).unwrap().get_record(0).unwrap(),
+            b"again"
+        );
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compressed_torn_write_keeps_previous_version() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        {
+            let file = compressed(&path);
+            let page_id = file.allocate_page().unwrap();
+            write_record(&file, page_id, b"first version");
+            file.sync().unwrap();
+            write_record(&file, page_id, b"second version");
+        }
+
+        // Damage the new extent, as a crash midway through writing it would
+        let mut bytes = std::fs::read(&path).unwrap();
+        let at = bytes
+            .windows(14)
+            .position(|window| window == b"second version")
+            .unwrap();
+        bytes[at] ^= 0xFF;
+        std::fs::write(&path, &bytes).unwrap();
+
+        let file = PageFile::open(&path).unwrap();
+        assert_eq!(
+            file.read_page(1).unwrap().get_record(0).unwrap(),
+            b"first version"
+        );
+
+        // Writes after the reopen do not disturb it
+        let page_id = file.allocate_page().unwrap();
+        write_record(&file, page_id, b"other");
+        file.sync().unwrap();
+        assert_eq!(
+            file.read_page(1).unwrap().get_record(0).unwrap(),
+            b"first version"
+        );
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compressed_concurrent_writes() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        let file = Arc::new(compressed(&path));
+
+        let handles: Vec<_> = (0..4u8)
+            .map(|t| {
+                let file = Arc::clone(&file);
+                std::thread::spawn(move || {
+                    let owned: Vec<u32> = (0..4).map(|_| file.allocate_page().unwrap()).collect();
+                    for round in 0..20u8 {
+                        let byte = t * 20 + round;
+                        for &page_id in &owned {
+                            file.write_page(&pattern_page(page_id, byte)).unwrap();
+                            assert_eq!(
+                                assert_pattern(&file.read_page(page_id).unwrap(), page_id),
+                                byte
+                            );
+                        }
+                        file.sync().unwrap();
+                    }
+                    owned
+                })
+            })
+            .collect();
+        let owned: Vec<u32> = handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect();
+
+        drop(file);
+        let file = PageFile::open(&path).unwrap();
+        for page_id in owned {
+            assert_pattern(&file.read_page(page_id).unwrap(), page_id);
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compressed_file_restrictions() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        let mut file = compressed(&path);
+        assert!(file.enable_double_write(MIN_DOUBLE_WRITE_SLOTS).is_err());
+        drop(file);
+
+        #[cfg(feature = "mmap")]
+        {
+            assert!(PageFile::open_with(&path, FileBackend::Mmap).is_err());
+
+            let options = FileOptions {
+                backend: FileBackend::Mmap,
+                compression: Compression::Lz4,
+                ..FileOptions::default()
+            };
+            let other = dir.path().join("mapped.jdb");
+            assert!(PageFile::create_new_with(&other, options).is_err());
+            assert!(!other.exists());
+        }
+    }
+
+    #[cfg(feature = "encryption")]
+    const MASTER_KEY: &[u8] = b"correct horse battery staple";
+
+    /// Flip a byte in the middle of a page of an encrypted file.
+    #[cfg(feature = "encryption")]
+    fn flip_encrypted_byte(path: &Path, page_id: u32) {
+        let stride = (PAGE_SIZE + encryption::OVERHEAD) as u64;
+        let mut raw = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        let offset = page_id as u64 * stride + stride / 2;
+        let mut byte = [0u8];
+        positional::read_exact_at(&raw, &mut byte, offset).unwrap();
+        raw.seek(SeekFrom::Start(offset)).unwrap();
+        raw.write_all(&[byte[0] ^ 0xFF]).unwrap();
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_pages_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        {
+            let file =
+                PageFile::create_encrypted(&path, FileOptions::default(), MASTER_KEY).unwrap();
+            assert!(file.encrypted());
+            for _ in 0..3 {
+                let page_id = file.allocate_page().unwrap();
+                write_record(&file, page_id, b"customer 42: Jane Doe");
+            }
+            file.free_page(3).unwrap();
+            file.sync().unwrap();
+        }
+
+        // Nothing readable reaches the disk
+        let bytes = std::fs::read(&path).unwrap();
+        assert!(!bytes.windows(8).any(|window| window == b"Jane Doe"));
+
+        let file = PageFile::open_encrypted(&path, FileBackend::Positional, MASTER_KEY).unwrap();
+        assert!(file.encrypted());
+        for page_id in 1..3 {
+            let page = file.read_page(page_id).unwrap();
+            assert_eq!(page.get_record(0).unwrap(), b"customer 42: Jane Doe");
+        }
+        assert_eq!(file.allocate_page().unwrap(), 3);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_file_needs_its_key() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        drop(PageFile::create_encrypted(&path, FileOptions::default(), MASTER_KEY).unwrap());
+
+        assert!(PageFile::open(&path).is_err());
+        assert!(matches!(
+            PageFile::open_encrypted(&path, FileBackend::Positional, b"not the right key at all"),
+            Err(StorageError::WrongKey)
+        ));
+        assert!(PageFile::open_encrypted(&path, FileBackend::Positional, MASTER_KEY).is_ok());
+
+        // Nor can a key be used on a plain file, or be too short
+        let plain = dir.path().join("plain.jdb");
+        drop(PageFile::create_new(&plain).unwrap());
+        assert!(PageFile::open_encrypted(&plain, FileBackend::Positional, MASTER_KEY).is_err());
+        let short = dir.path().join("short.jdb");
+        assert!(PageFile::create_encrypted(&short, FileOptions::default(), b"short").is_err());
+        assert!(!short.exists());
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_tampering_fails_authentication() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        {
+            let file =
+                PageFile::create_encrypted(&path, FileOptions::default(), MASTER_KEY).unwrap();
+            for _ in 0..3 {
+                let page_id = file.allocate_page().unwrap();
+                write_record(&file, page_id, b"balance: 100");
+            }
+            file.sync().unwrap();
+        }
+
+        // A changed byte is told apart from a checksum mismatch
+        flip_encrypted_byte(&path, 1);
+        let file = PageFile::open_encrypted(&path, FileBackend::Positional, MASTER_KEY).unwrap();
+        assert!(matches!(
+            file.read_page(1),
+            Err(StorageError::AuthenticationFailed(1))
+        ));
+        drop(file);
+
+        // So is a page copied over another
+        let stride = PAGE_SIZE + encryption::OVERHEAD;
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.copy_within(2 * stride..3 * stride, 3 * stride);
+        std::fs::write(&path, &bytes).unwrap();
+        let file = PageFile::open_encrypted(&path, FileBackend::Positional, MASTER_KEY).unwrap();
+        assert!(file.read_page(2).is_ok());
+        assert!(matches!(
+            file.read_page(3),
+            Err(StorageError::AuthenticationFailed(3))
+        ));
+        drop(file);
+
+        // The header is covered too, even with its checksum fixed up
+        let mut header = FileHeader::from_bytes(&bytes[..HEADER_SIZE]).unwrap();
+        header.free_list_head = 2;
+        header.update_checksum();
+        bytes[..HEADER_SIZE].copy_from_slice(&header.to_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            PageFile::open_encrypted(&path, FileBackend::Positional, MASTER_KEY),
+            Err(StorageError::AuthenticationFailed(0))
+        ));
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_torn_page_restored_from_double_write() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        {
+            let mut file =
+                PageFile::create_encrypted(&path, FileOptions::default(), MASTER_KEY).unwrap();
+            file.enable_double_write(MIN_DOUBLE_WRITE_SLOTS).unwrap();
+            let page_id = file.allocate_page().unwrap();
+            write_record(&file, page_id, b"sealed");
+            file.sync().unwrap();
+        }
+        flip_encrypted_byte(&path, 3);
+
+        let file = PageFile::open_encrypted(&path, FileBackend::Positional, MASTER_KEY).unwrap();
+        assert_eq!(file.double_write_area(), Some((1, 2)));
+        assert_eq!(file.read_page(3).unwrap().get_record(0).unwrap(), b"sealed");
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_encrypted_file_restrictions() {
+        let dir = tempdir().unwrap();
+
+        #[cfg(feature = "compression")]
+        {
+            let options = FileOptions {
+                compression: Compression::Lz4,
+                ..FileOptions::default()
+            };
+            let path = dir.path().join("compressed.jdb");
+            assert!(PageFile::create_encrypted(&path, options, MASTER_KEY).is_err());
+        }
+
+        #[cfg(feature = "mmap")]
+        {
+            let path = dir.path().join("mapped.jdb");
+            let options = options(PAGE_SIZE, FileBackend::Mmap);
+            assert!(PageFile::create_encrypted(&path, options, MASTER_KEY).is_err());
+
+            drop(PageFile::create_encrypted(&path, FileOptions::default(), MASTER_KEY).unwrap());
+            assert!(PageFile::open_encrypted(&path, FileBackend::Mmap, MASTER_KEY).is_err());
+        }
+
+        // Offline tools can read the header but not the pages
+        let path = dir.path().join("test.jdb");
+        let file = PageFile::create_encrypted(&path, FileOptions::default(), MASTER_KEY).unwrap();
+        write_record(&file, file.allocate_page().unwrap(), b"secret");
+        drop(file);
+        let file = PageFile::open_read_only(&path).unwrap();
+        assert!(file.header().encrypted());
+        assert!(file.read_page_bytes(1).is_err());
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mmap_views_and_growth() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        let file = PageFile::create_new_with(&path, options(PAGE_SIZE, FileBackend::Mmap)).unwrap();
+        assert_eq!(file.backend(), FileBackend::Mmap);
+
+        // Every allocation grows the file past the current mapping
+        let pages: Vec<u32> = (0..20).map(|_| file.allocate_page().unwrap()).collect();
+        for (i, &page_id) in pages.iter().enumerate() {
+            file.write_page(&pattern_page(page_id, i as u8)).unwrap();
+        }
+
+        for (i, &page_id) in pages.iter().enumerate() {
+            let view = file.view_page(page_id).unwrap();
+            assert_eq!(assert_pattern(&view, page_id), i as u8);
+
+            // The view borrows the mapping itself rather than a copy
+            let copy = file.read_page(page_id).unwrap();
+            assert_eq!(view.as_bytes(), copy.as_bytes());
+        }
+
+        assert!(file.view_page(0).is_err());
+        assert!(matches!(
+            file.view_page(100),
+            Err(StorageError::PageNotFound(100))
+        ));
+        file.sync().unwrap();
+        drop(file);
+
+        // The positional path sees exactly what went through the map
+        let file = PageFile::open(&path).unwrap();
+        assert_eq!(file.page_count(), 21);
+        for (i, &page_id) in pages.iter().enumerate() {
+            let page = file.read_page(page_id).unwrap();
+            assert_eq!(assert_pattern(&page, page_id), i as u8);
+        }
+        assert!(file.view_page(pages[0]).is_err());
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mmap_torn_page_restored_from_double_write() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        {
+            let mut file =
+                PageFile::create_new_with(&path, options(PAGE_SIZE, FileBackend::Mmap)).unwrap();
+            file.enable_double_write(4).unwrap();
+            let page_id = file.allocate_page().unwrap();
+            write_record(&file, page_id, b"mapped");
+            file.sync().unwrap();
+        }
+        tear_page(&path, PAGE_SIZE, 5);
+
+        let file = PageFile::open_with(&path, FileBackend::Mmap).unwrap();
+        assert_eq!(file.double_write_area(), Some((1, 4)));
+        let view = file.view_page(5).unwrap();
+        assert_eq!(view.get_record(0).unwrap(), b"mapped");
+        drop(view);
+
+        // The repair went back into the file through the map
+        file.sync().unwrap();
+        let file = PageFile::open(&path).unwrap();
+        assert_eq!(file.read_page(5).unwrap().get_record(0).unwrap(), b"mapped");
     }
 }
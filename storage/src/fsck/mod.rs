@@ -0,0 +1,665 @@
+// storage/src/fsck/mod.rs
+
+//! Offline consistency checking for database files.
+//!
+//! `check` walks every page of a file without modifying it and reports
+//! what it finds; the `jdb-fsck` binary is a thin wrapper around it.
+
+use crate::file::{PageFile, ReadOnlyFile};
+use crate::page::{Page, PageType};
+use crate::{Result, StorageError};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+/// Something wrong with one page of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsckIssue {
+    pub page_id: u32,
+    pub kind: IssueKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IssueKind {
+    /// The page could not be read or is not a valid page at all
+    Unreadable(String),
+    /// The page type byte holds a value naming no `PageType`
+    BadPageType(u8),
+    /// The page header names a different page
+    WrongPageId { found: u32 },
+    /// Contents do not match the stored checksum; `repairable` if the
+    /// double-write area holds an intact copy
+    ChecksumMismatch { repairable: bool },
+    /// `free_space_start`/`free_space_end` do not bracket the free space
+    /// between the slot array and the records
+    BadFreeSpace {
+        start: u16,
+        end: usize,
+        slot_array_end: usize,
+    },
+    /// A live slot points outside the record area
+    BadSlot {
+        index: usize,
+        offset: u16,
+        length: u16,
+    },
+    /// Two live slots claim overlapping bytes
+    OverlappingSlots { first: usize, second: usize },
+    /// The free list points at a page that does not exist
+    FreeListOutOfRange,
+    /// The free list reaches this page a second time
+    FreeListCycle,
+    /// The free list runs through a page that is not free
+    FreeListNotFree(PageType),
+    /// A free page that is not on the free list and so can never be reused
+    LeakedFreePage,
+}
+
+impl FsckIssue {
+    /// Warnings leave the file usable; a crash can leave a leaked page
+    /// behind, for instance.
+    pub fn is_warning(&self) -> bool {
+        matches!(self.kind, IssueKind::LeakedFreePage)
+    }
+}
+
+impl fmt::Display for FsckIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "page {}: ", self.page_id)?;
+        match &self.kind {
+            IssueKind::Unreadable(reason) => write!(f, "unreadable ({})", reason),
+            IssueKind::BadPageType(value) => write!(f, "unknown page type {}", value),
+            IssueKind::WrongPageId { found } => write!(f, "header says page {}", found),
+            IssueKind::ChecksumMismatch { repairable: true } => {
+                write!(
+                    f,
+                    "checksum mismatch, repairable from the double-write area"
+                )
+            }
+            IssueKind::ChecksumMismatch { repairable: false } => write!(f, "checksum mismatch"),
+            IssueKind::BadFreeSpace {
+                start,
+                end,
+                slot_array_end,
+            } => write!(
+                f,
+                "free space {}..{} does not fit a slot array ending at {}",
+                start, end, slot_array_end
+            ),
+            IssueKind::BadSlot {
+                index,
+                offset,
+                length,
+            } => write!(
+                f,
+                "slot {} points outside the record area ({} bytes at {})",
+                index, length, offset
+            ),
+            IssueKind::OverlappingSlots { first, second } => {
+                write!(f, "slots {} and {} overlap", first, second)
+            }
+            IssueKind::FreeListOutOfRange => write!(f, "free list entry is out of range"),
+            IssueKind::FreeListCycle => write!(f, "free list loops back here"),
+            IssueKind::FreeListNotFree(page_type) => {
+                write!(f, "free list entry is a {:?} page", page_type)
+            }
+            IssueKind::LeakedFreePage => write!(f, "free page is not on the free list"),
+        }
+    }
+}
+
+/// What `check` found.
+#[derive(Debug, Clone, Default)]
+pub struct FsckReport {
+    /// Page size recorded in the header
+    pub page_size: usize,
+    /// Pages in the file according to its header, including page 0
+    pub page_count: u32,
+    /// Pages that were examined
+    pub pages_checked: u32,
+    /// Double-write slots, which hold copies rather than pages of their own
+    pub pages_skipped: u32,
+    /// Valid pages of each type
+    pub page_types: HashMap<PageType, u32>,
+    /// Pages reachable from the free list head
+    pub free_list_length: u32,
+    pub issues: Vec<FsckIssue>,
+}
+
+impl FsckReport {
+    pub fn errors(&self) -> impl Iterator<Item = &FsckIssue> {
+        self.issues.iter().filter(|issue| !issue.is_warning())
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &FsckIssue> {
+        self.issues.iter().filter(|issue| issue.is_warning())
+    }
+
+    /// No errors; warnings are allowed.
+    pub fn is_clean(&self) -> bool {
+        self.errors().next().is_none()
+    }
+
+    fn report(&mut self, page_id: u32, kind: IssueKind) {
+        self.issues.push(FsckIssue { page_id, kind });
+    }
+}
+
+/// Check the database file at `path` without modifying it.
+///
+/// Every page from 1 to the header's page count is parsed and, if the
+/// file has checksums, verified; slot arrays and free space bounds are
+/// validated; and the free list is walked to make sure it ends, stays in
+/// range and only links free pages. An `Err` means the file could not be
+/// examined at all, e.g. because its header is damaged or it is encrypted;
+/// problems with individual pages are listed in the report instead.
+pub fn check(path: &Path) -> Result<FsckReport> {
+    let file = PageFile::open_read_only(path)?;
+    let header = file.header();
+    if header.encrypted() {
+        return Err(StorageError::Io(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Encrypted files cannot be checked without their key",
+        )));
+    }
+    let double_write = header.double_write_area();
+    let in_double_write = |page_id: u32| {
+        double_write.is_some_and(|(start, slots)| page_id >= start && page_id - start < slots)
+    };
+
+    let mut report = FsckReport {
+        page_size: header.page_size(),
+        page_count: header.page_count(),
+        ..FsckReport::default()
+    };
+
+    // Pages that parsed, and where each free page links to
+    let mut page_types = HashMap::new();
+    let mut free_links = HashMap::new();
+    for page_id in 1..header.page_count() {
+        if in_double_write(page_id) {
+            report.pages_skipped += 1;
+            continue;
+        }
+
+        report.pages_checked += 1;
+        let Some(page) = check_page(&file, page_id, &mut report) else {
+            continue;
+        };
+
+        let page_type = page.header().page_type;
+        *report.page_types.entry(page_type).or_insert(0) += 1;
+        page_types.insert(page_id, page_type);
+        if page_type == PageType::Free {
+            free_links.insert(page_id, page.header().next_page);
+        }
+    }
+
+    // Walk the free list, stopping at the first broken link
+    let mut on_free_list = HashSet::new();
+    let mut next = header.free_list_head();
+    let mut previous = 0;
+    while next != 0 {
+        if next >= header.page_count() || in_double_write(next) {
+            report.report(previous, IssueKind::FreeListOutOfRange);
+            break;
+        }
+        if !on_free_list.insert(next) {
+            report.report(next, IssueKind::FreeListCycle);
+            break;
+        }
+
+        report.free_list_length += 1;
+        match page_types.get(&next) {
+            Some(PageType::Free) => {
+                previous = next;
+                next = free_links[&next];
+            }
+            Some(&page_type) => {
+                report.report(next, IssueKind::FreeListNotFree(page_type));
+                break;
+            }
+            // Already reported as unreadable
+            None => break,
+        }
+    }
+
+    // Past a broken link there is no telling which free pages were leaked
+    if next != 0 {
+        return Ok(report);
+    }
+
+    let mut leaked: Vec<u32> = free_links
+        .keys()
+        .filter(|page_id| !on_free_list.contains(*page_id))
+        .copied()
+        .collect();
+    leaked.sort_unstable();
+    for page_id in leaked {
+        report.report(page_id, IssueKind::LeakedFreePage);
+    }
+
+    Ok(report)
+}
+
+/// Check one page, returning it if it is sound enough to look at further.
+fn check_page(file: &ReadOnlyFile, page_id: u32, report: &mut FsckReport) -> Option<Box<Page>> {
+    let bytes = match file.read_page_bytes(page_id) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            report.report(page_id, IssueKind::Unreadable(e.to_string()));
+            return None;
+        }
+    };
+
+    // Checked on the raw byte, before anything reads it as a `PageType`
+    if let Some(&value) = bytes.get(Page::PAGE_TYPE_OFFSET) {
+        if PageType::from_u8(value).is_none() {
+            report.report(page_id, IssueKind::BadPageType(value));
+            return None;
+        }
+    }
+
+    let page = match Page::from_bytes(&bytes) {
+        Ok(page) => page,
+        Err(e) => {
+            report.report(page_id, IssueKind::Unreadable(e.to_string()));
+            return None;
+        }
+    };
+
+    let found = page.header().page_id;
+    if found != page_id {
+        report.report(page_id, IssueKind::WrongPageId { found });
+        return None;
+    }
+
+    if file.header().checksums_enabled() && !page.verify_checksum() {
+        let repairable = has_double_write_copy(file, page_id);
+        report.report(page_id, IssueKind::ChecksumMismatch { repairable });
+        return None;
+    }
+
+    check_layout(&page, page_id, report);
+    Some(page)
+}
+
+/// Validate the slot array and free space bounds. `free_space_start` is
+/// not advanced as slots are added, so it only has to lie between the
+/// header and the slot array's end.
+fn check_layout(page: &Page, page_id: u32, report: &mut FsckReport) {
+    let header = page.header();
+    let slot_count = header.slot_count as usize;
+    let slot_array_end = Page::HEADER_SIZE + slot_count * Page::SLOT_SIZE;
+    let start = header.free_space_start;
+    let end = page.free_space_end();
+
+    if (start as usize) < Page::HEADER_SIZE
+        || start as usize > slot_array_end
+        || slot_array_end > end
+        || end > page.size()
+    {
+        report.report(
+            page_id,
+            IssueKind::BadFreeSpace {
+                start,
+                end,
+                slot_array_end,
+            },
+        );
+        return;
+    }
+
+    let mut live = Vec::with_capacity(slot_count);
+    for index in 0..slot_count {
+        let slot = page.get_slot(index).expect("slot array fits the page");
+        if slot.length == 0 {
+            continue;
+        }
+
+        let record_end = slot.offset as usize + slot.length as usize;
+        if (slot.offset as usize) < end || record_end > page.size() {
+            report.report(
+                page_id,
+                IssueKind::BadSlot {
+                    index,
+                    offset: slot.offset,
+                    length: slot.length,
+                },
+            );
+            continue;
+        }
+        live.push((slot.offset as usize, record_end, index));
+    }
+
+    live.sort_unstable();
+    for pair in live.windows(2) {
+        let (_, first_end, first) = pair[0];
+        let (second_start, _, second) = pair[1];
+        if second_start < first_end {
+            report.report(
+                page_id,
+                IssueKind::OverlappingSlots {
+                    first: first.min(second),
+                    second: first.max(second),
+                },
+            );
+        }
+    }
+}
+
+fn has_double_write_copy(file: &ReadOnlyFile, page_id: u32) -> bool {
+    let Some((start, slots)) = file.header().double_write_area() else {
+        return false;
+    };
+
+    (start..start + slots).any(|slot_page| {
+        file.read_page_bytes(slot_page)
+            .and_then(|bytes| Page::from_bytes(&bytes))
+            .is_ok_and(|copy| copy.header().page_id == page_id && copy.verify_checksum())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::FileOptions;
+    use crate::page::PAGE_SIZE;
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom, Write};
+    use tempfile::tempdir;
+
+    fn write_bytes(path: &Path, page_id: u32, offset: usize, bytes: &[u8]) {
+        let mut raw = OpenOptions::new().write(true).open(path).unwrap();
+        raw.seek(SeekFrom::Start(
+            page_id as u64 * PAGE_SIZE as u64 + offset as u64,
+        ))
+        .unwrap();
+        raw.write_all(bytes).unwrap();
+    }
+
+    /// Rewrite a page on disk. The checksum is cleared, which means "not
+    /// checksummed", so the checks behind it get to see the damage.
+    fn corrupt(path: &Path, page_id: u32, edit: impl FnOnce(&mut [u8])) {
+        let file = PageFile::open_read_only(path).unwrap();
+        let mut bytes = file.read_page_bytes(page_id).unwrap();
+        edit(&mut bytes);
+        bytes[24..28].fill(0);
+        write_bytes(path, page_id, 0, &bytes);
+    }
+
+    fn set_slot(bytes: &mut [u8], index: usize, offset: u16, length: u16) {
+        let at = Page::HEADER_SIZE + index * Page::SLOT_SIZE;
+        bytes[at..at + 2].copy_from_slice(&offset.to_le_bytes());
+        bytes[at + 2..at + 4].copy_from_slice(&length.to_le_bytes());
+    }
+
+    /// A file with three data pages and a two page free list.
+    fn populated(path: &Path, double_write: bool) -> Vec<u32> {
+        let mut file = PageFile::create_new(path).unwrap();
+        if double_write {
+            file.enable_double_write(2).unwrap();
+        }
+
+        let pages: Vec<u32> = (0..5).map(|_| file.allocate_page().unwrap()).collect();
+        for &page_id in &pages[..3] {
+            let mut page = Page::new(page_id, PageType::Data);
+            page.add_record(b"one").unwrap();
+            page.add_record(b"two").unwrap();
+            page.delete_record(0);
+            file.write_page(&page).unwrap();
+        }
+        file.free_page(pages[3]).unwrap();
+        file.free_page(pages[4]).unwrap();
+        file.sync().unwrap();
+        pages
+    }
+
+    #[test]
+    fn test_clean_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        populated(&path, true);
+
+        let report = check(&path).unwrap();
+        assert!(report.issues.is_empty(), "{:?}", report.issues);
+        assert!(report.is_clean());
+        assert_eq!(report.page_count, 8);
+        assert_eq!(report.pages_checked, 5);
+        assert_eq!(report.pages_skipped, 2);
+        assert_eq!(report.page_types[&PageType::Data], 3);
+        assert_eq!(report.page_types[&PageType::Free], 2);
+        assert_eq!(report.free_list_length, 2);
+    }
+
+    #[test]
+    fn test_largest_pages() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        let options = FileOptions {
+            page_size: 65536,
+            ..FileOptions::default()
+        };
+        let file = PageFile::create_new_with(&path, options).unwrap();
+
+        // An empty 64K page stores the end of its free space as 0
+        let pages: Vec<u32> = (0..3).map(|_| file.allocate_page().unwrap()).collect();
+        let mut page = Page::with_size(65536, pages[0], PageType::Data);
+        page.add_record(&[9; 40_000]).unwrap();
+        file.write_page(&page).unwrap();
+        file.write_page(&Page::with_size(65536, pages[1], PageType::Data))
+            .unwrap();
+        file.free_page(pages[2]).unwrap();
+        file.sync().unwrap();
+        drop(file);
+
+        let report = check(&path).unwrap();
+        assert!(report.issues.is_empty(), "{:?}", report.issues);
+        assert_eq!(report.page_size, 65536);
+        assert_eq!(report.page_types[&PageType::Data], 2);
+        assert_eq!(report.free_list_length, 1);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compressed_file() {
+        use crate::file::Compression;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        let options = FileOptions {
+            compression: Compression::Lz4,
+            ..FileOptions::default()
+        };
+        let file = PageFile::create_new_with(&path, options).unwrap();
+        let pages: Vec<u32> = (0..4).map(|_| file.allocate_page().unwrap()).collect();
+        for &page_id in &pages[..3] {
+            let mut page = Page::new(page_id, PageType::Data);
+            page.add_record(b"compressed").unwrap();
+            file.write_page(&page).unwrap();
+        }
+        file.free_page(pages[3]).unwrap();
+        file.sync().unwrap();
+        drop(file);
+
+        // Pages are found through their extents rather than at fixed offsets
+        let report = check(&path).unwrap();
+        assert!(report.issues.is_empty(), "{:?}", report.issues);
+        assert_eq!(report.pages_checked, 4);
+        assert_eq!(report.page_types[&PageType::Data], 3);
+        assert_eq!(report.free_list_length, 1);
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        let pages = populated(&path, true);
+
+        // Freeing two pages since pushed this page's copy out of both slots
+        write_bytes(&path, pages[0], PAGE_SIZE - 1, &[0xFF]);
+        // The last write is still there to repair from
+        write_bytes(&path, pages[4], PAGE_SIZE - 1, &[0xFF]);
+
+        let report = check(&path).unwrap();
+        assert!(!report.is_clean());
+        assert_eq!(
+            report.issues,
+            vec![
+                FsckIssue {
+                    page_id: pages[0],
+                    kind: IssueKind::ChecksumMismatch { repairable: false },
+                },
+                FsckIssue {
+                    page_id: pages[4],
+                    kind: IssueKind::ChecksumMismatch { repairable: true },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bad_layout() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        let pages = populated(&path, false);
+
+        corrupt(&path, pages[0], |bytes| {
+            bytes[6..8].copy_from_slice(&10u16.to_le_bytes())
+        });
+        // Revive the deleted record so it overlaps the live one
+        corrupt(&path, pages[1], |bytes| {
+            set_slot(bytes, 0, PAGE_SIZE as u16 - 5, 3)
+        });
+        corrupt(&path, pages[2], |bytes| {
+            set_slot(bytes, 1, PAGE_SIZE as u16 - 1, 3)
+        });
+
+        let report = check(&path).unwrap();
+        assert_eq!(
+            report.issues,
+            vec![
+                FsckIssue {
+                    page_id: pages[0],
+                    kind: IssueKind::BadFreeSpace {
+                        start: 10,
+                        end: PAGE_SIZE - 6,
+                        slot_array_end: Page::HEADER_SIZE + 2 * Page::SLOT_SIZE,
+                    },
+                },
+                FsckIssue {
+                    page_id: pages[1],
+                    kind: IssueKind::OverlappingSlots {
+                        first: 0,
+                        second: 1
+                    },
+                },
+                FsckIssue {
+                    page_id: pages[2],
+                    kind: IssueKind::BadSlot {
+                        index: 1,
+                        offset: PAGE_SIZE as u16 - 1,
+                        length: 3,
+                    },
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_broken_free_list() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        let pages = populated(&path, false);
+
+        // The list is pages[4] -> pages[3]; loop it back to pages[4]
+        let link =
+            |next: u32| move |bytes: &mut [u8]| bytes[32..36].copy_from_slice(&next.to_le_bytes());
+        corrupt(&path, pages[3], link(pages[4]));
+
+        let report = check(&path).unwrap();
+        assert_eq!(report.free_list_length, 2);
+        assert_eq!(
+            report.issues,
+            vec![FsckIssue {
+                page_id: pages[4],
+                kind: IssueKind::FreeListCycle,
+            }]
+        );
+
+        // Now run it into a data page
+        corrupt(&path, pages[4], link(pages[0]));
+
+        let report = check(&path).unwrap();
+        assert_eq!(
+            report.issues,
+            vec![FsckIssue {
+                page_id: pages[0],
+                kind: IssueKind::FreeListNotFree(PageType::Data),
+            }]
+        );
+
+        // And out of the file
+        corrupt(&path, pages[4], link(1000));
+        let report = check(&path).unwrap();
+        assert_eq!(report.issues[0].kind, IssueKind::FreeListOutOfRange);
+    }
+
+    #[test]
+    fn test_leaked_free_page_is_a_warning() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        let pages = populated(&path, false);
+
+        // Cut the list short after its first page
+        corrupt(&path, pages[4], |bytes| bytes[32..36].fill(0));
+
+        let report = check(&path).unwrap();
+        assert_eq!(report.free_list_length, 1);
+        assert_eq!(
+            report.issues,
+            vec![FsckIssue {
+                page_id: pages[3],
+                kind: IssueKind::LeakedFreePage,
+            }]
+        );
+        assert_eq!(report.warnings().count(), 1);
+        assert!(report.is_clean());
+    }
+
+    #[test]
+    fn test_unreadable_and_misplaced_pages() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        let pages = populated(&path, false);
+
+        corrupt(&path, pages[0], |bytes| {
+            bytes[0..4].copy_from_slice(&42u32.to_le_bytes())
+        });
+        corrupt(&path, pages[1], |bytes| bytes[8..10].fill(0xFF));
+        corrupt(&path, pages[2], |bytes| {
+            bytes[Page::PAGE_TYPE_OFFSET] = 0xC8
+        });
+
+        let report = check(&path).unwrap();
+        assert_eq!(report.pages_checked, 5);
+        assert_eq!(report.issues.len(), 3);
+        assert_eq!(report.issues[0].kind, IssueKind::WrongPageId { found: 42 });
+        assert_eq!(report.issues[1].page_id, pages[1]);
+        assert!(matches!(report.issues[1].kind, IssueKind::Unreadable(_)));
+        assert_eq!(
+            report.issues[1].to_string(),
+            format!(
+                "page {}: unreadable (IO error: free_space_end exceeds page size)",
+                pages[1]
+            )
+        );
+        assert_eq!(report.issues[2].page_id, pages[2]);
+        assert_eq!(report.issues[2].kind, IssueKind::BadPageType(0xC8));
+        assert_eq!(
+            report.issues[2].to_string(),
+            format!("page {}: unknown page type 200", pages[2])
+        );
+    }
+}
@@ -0,0 +1,96 @@
+// storage/src/heap/fsm.rs
+
+use std::collections::{BTreeSet, HashMap};
+
+/// Granularity of free space tracking, in bytes
+const CATEGORY_SIZE: usize = 128;
+
+/// Free space map: tracks roughly how much room each heap page has, so an
+/// insert can go straight to a page that will take it.
+///
+/// Pages are bucketed by free space rounded down to `CATEGORY_SIZE`, which
+/// makes the map conservative: a page is only offered for a request it is
+/// known to satisfy.
+pub(crate) struct FreeSpaceMap {
+    categories: Vec<BTreeSet<u32>>,
+    page_category: HashMap<u32, usize>,
+}
+
+impl FreeSpaceMap {
+    pub fn new(page_size: usize) -> Self {
+        Self {
+            categories: vec![BTreeSet::new(); page_size / CATEGORY_SIZE + 1],
+            page_category: HashMap::new(),
+        }
+    }
+
+    /// Record that `page_id` now has `free` bytes available.
+    pub fn update(&mut self, page_id: u32, free: usize) {
+        let category = (free / CATEGORY_SIZE).min(self.categories.len() - 1);
+
+        if let Some(old) = self.page_category.insert(page_id, category) {
+            if old == category {
+                return;
+            }
+            self.categories[old].remove(&page_id);
+        }
+        self.categories[category].insert(page_id);
+    }
+
+    /// A page with at least `needed` free bytes, preferring the fullest
+    /// such page and, among equals, the lowest page id.
+    pub fn find(&self, needed: usize) -> Option<u32> {
+        let first = needed.div_ceil(CATEGORY_SIZE);
+        self.categories
+            .get(first..)?
+            .iter()
+            .find_map(|pages| pages.first().copied())
+    }
+
+    pub fn len(&self) -> usize {
+        self.page_category.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page::PAGE_SIZE;
+
+    #[test]
+    fn test_find_returns_page_with_room() {
+        let mut fsm = FreeSpaceMap::new(PAGE_SIZE);
+        fsm.update(1, 100);
+        fsm.update(2, 4000);
+        fsm.update(3, 1000);
+
+        assert_eq!(fsm.find(50), Some(3));
+        assert_eq!(fsm.find(896), Some(3));
+        assert_eq!(fsm.find(900), Some(2));
+        assert_eq!(fsm.find(1001), Some(2));
+        assert_eq!(fsm.find(5000), None);
+        assert_eq!(fsm.len(), 3);
+    }
+
+    #[test]
+    fn test_update_moves_page_between_categories() {
+        let mut fsm = FreeSpaceMap::new(PAGE_SIZE);
+        fsm.update(1, 4000);
+        fsm.update(1, 10);
+
+        assert_eq!(fsm.find(1000), None);
+        assert_eq!(fsm.len(), 1);
+
+        fsm.update(1, 8000);
+        assert_eq!(fsm.find(7000), Some(1));
+    }
+
+    #[test]
+    fn test_find_is_conservative() {
+        let mut fsm = FreeSpaceMap::new(PAGE_SIZE);
+        fsm.update(1, 200); // rounds down to 128
+
+        assert_eq!(fsm.find(128), Some(1));
+        assert_eq!(fsm.find(129), None);
+    }
+}
@@ -0,0 +1,869 @@
+// storage/src/heap/mod.rs
+
+mod fsm;
+
+use crate::buffer::{BufferPool, PageReadGuard, PageWriteGuard};
+use crate::overflow::{self, OverflowPointer};
+use crate::page::{Page, PageType};
+use crate::wal::{LogBody, NO_TXN};
+use crate::{Result, StorageError};
+use fsm::FreeSpaceMap;
+use std::fmt;
+use std::io;
+
+/// Values longer than this are moved to an overflow chain
+fn inline_limit(page_size: usize) -> usize {
+    Page::max_record_size(page_size) / 4
+}
+
+// Flag byte at the start of every stored record
+const FLAG_OVERFLOW: u8 = 0x01; // payload is an OverflowPointer
+const FLAG_FORWARD: u8 = 0x02; // payload is the RecordId the value moved to
+const FLAG_MOVED: u8 = 0x04; // value living away from its home slot
+const FLAG_PADDED: u8 = 0x08; // short payload, its length in the last byte
+
+/// No stored record is shorter than a forwarding stub, so any record can
+/// be turned into one in place.
+const MIN_STORED_SIZE: usize = 1 + RecordId::ENCODED_SIZE;
+
+/// Stable address of a record in a `HeapFile`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RecordId {
+    pub page_id: u32,
+    pub slot: u16,
+}
+
+impl RecordId {
+    pub const ENCODED_SIZE: usize = 6;
+
+    pub fn new(page_id: u32, slot: u16) -> Self {
+        Self { page_id, slot }
+    }
+
+    pub fn to_bytes(self) -> [u8; Self::ENCODED_SIZE] {
+        let mut bytes = [0u8; Self::ENCODED_SIZE];
+        bytes[0..4].copy_from_slice(&self.page_id.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.slot.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != Self::ENCODED_SIZE {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid record id size",
+            )));
+        }
+
+        Ok(Self {
+            page_id: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            slot: u16::from_le_bytes(bytes[4..6].try_into().unwrap()),
+        })
+    }
+}
+
+impl fmt::Display for RecordId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({}, {})", self.page_id, self.slot)
+    }
+}
+
+/// An unordered collection of records stored in a chain of `Data` pages.
+///
+/// `open` uses the file's default heap, whose chain ends are recorded in the
+/// file header. `create` and `open_at` manage a heap identified by its first
+/// page instead, as tables in the relation directory are; that page is
+/// allocated up front and never changes.
+///
+/// A record keeps its `RecordId` for life. An update that no longer fits on
+/// the record's page moves the value elsewhere and leaves a forwarding stub
+/// behind; values over a quarter page go to an overflow chain.
+///
+/// Free space per page is tracked in memory and rebuilt by `open`, which
+/// reads every page of the heap once.
+pub struct HeapFile {
+    first_page: u32,
+    last_page: u32,
+    fsm: FreeSpaceMap,
+    in_header: bool, // chain ends are kept in the file header
+}
+
+impl HeapFile {
+    /// Open the file's default heap.
+    pub fn open(pool: &BufferPool) -> Result<Self> {
+        let (first_page, _) = pool.store().data_pages();
+        Self::load(pool, first_page, true)
+    }
+
+    /// Start a new heap with one empty page.
+    pub fn create(pool: &BufferPool) -> Result<Self> {
+        let mut heap = Self::load(pool, 0, false)?;
+        heap.extend(pool)?;
+        Ok(heap)
+    }
+
+    /// Open the heap whose chain starts at `first_page`.
+    pub fn open_at(pool: &BufferPool, first_page: u32) -> Result<Self> {
+        Self::load(pool, first_page, false)
+    }
+
+    /// A handle on no chain in particular, for rewriting and deleting
+    /// records already stored, as rolling back does. It must not insert.
+    pub(crate) fn unchained(pool: &BufferPool) -> Self {
+        Self {
+            first_page: 0,
+            last_page: 0,
+            fsm: FreeSpaceMap::new(pool.page_size()),
+            in_header: false,
+        }
+    }
+
+    fn load(pool: &BufferPool, first_page: u32, in_header: bool) -> Result<Self> {
+        let mut fsm = FreeSpaceMap::new(pool.page_size());
+        let mut last_page = 0;
+
+        let mut page_id = first_page;
+        while page_id != 0 {
+            let page = read_data_page(pool, page_id)?;
+            fsm.update(page_id, page.free_space());
+            last_page = page_id;
+            page_id = page.header().next_page;
+        }
+
+        Ok(Self {
+            first_page,
+            last_page,
+            fsm,
+            in_header,
+        })
+    }
+
+    pub fn first_page(&self) -> u32 {
+        self.first_page
+    }
+
+    /// Identifies the heap within its file: its first page, or 0 for the
+    /// default heap, which only gets a first page once something is stored.
+    pub fn id(&self) -> u32 {
+        if self.in_header {
+            0
+        } else {
+            self.first_page
+        }
+    }
+
+    /// Free every page the heap owns, including overflow chains.
+    pub fn destroy(self, pool: &BufferPool) -> Result<()> {
+        let mut page_id = self.first_page;
+        while page_id != 0 {
+            let page = read_data_page(pool, page_id)?.to_owned();
+            for record in page.iter() {
+                release(pool, record)?;
+            }
+            pool.delete_page(page_id)?;
+            page_id = page.header().next_page;
+        }
+
+        if self.in_header {
+            pool.store().set_data_pages(0, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Number of pages in the heap's chain.
+    pub fn page_count(&self) -> usize {
+        self.fsm.len()
+    }
+
+    pub fn insert(&mut self, pool: &BufferPool, data: &[u8]) -> Result<RecordId> {
+        let record = encode(pool, data)?;
+        self.place(pool, &record)
+    }
+
+    /// The record at `rid`, or `None` if it was deleted or never existed.
+    pub fn get(&self, pool: &BufferPool, rid: RecordId) -> Result<Option<Vec<u8>>> {
+        let Some(home) = raw_record(pool, rid)? else {
+            return Ok(None);
+        };
+        let (flags, payload) = split_flags(&home)?;
+
+        if flags & FLAG_MOVED != 0 {
+            return Ok(None);
+        }
+
+        if flags & FLAG_FORWARD != 0 {
+            let target = RecordId::from_bytes(payload)?;
+            let moved = raw_record(pool, target)?.ok_or_else(|| broken_forward(rid))?;
+            return read_value(pool, &moved).map(Some);
+        }
+
+        read_value(pool, &home).map(Some)
+    }
+
+    pub fn update(&mut self, pool: &BufferPool, rid: RecordId, data: &[u8]) -> Result<()> {
+        let home = home_record(pool, rid)?;
+        let record = encode(pool, data)?;
+        let (flags, payload) = split_flags(&home)?;
+
+        if flags & FLAG_FORWARD != 0 {
+            let target = RecordId::from_bytes(payload)?;
+            let old = raw_record(pool, target)?.ok_or_else(|| broken_forward(rid))?;
+
+            let moved = with_flag(record, FLAG_MOVED);
+            if !self.rewrite(pool, target, &moved)? {
+                let new_target = self.place(pool, &moved)?;
+                self.remove(pool, target)?;
+                self.forward(pool, rid, new_target)?;
+            }
+            return release(pool, &old);
+        }
+
+        if !self.rewrite(pool, rid, &record)? {
+            let target = self.place(pool, &with_flag(record, FLAG_MOVED))?;
+            self.forward(pool, rid, target)?;
+        }
+        release(pool, &home)
+    }
+
+    pub fn delete(&mut self, pool: &BufferPool, rid: RecordId) -> Result<()> {
+        let home = home_record(pool, rid)?;
+        let (flags, payload) = split_flags(&home)?;
+
+        if flags & FLAG_FORWARD != 0 {
+            let target = RecordId::from_bytes(payload)?;
+            let moved = raw_record(pool, target)?.ok_or_else(|| broken_forward(rid))?;
+            self.remove(pool, target)?;
+            release(pool, &moved)?;
+        }
+
+        self.remove(pool, rid)?;
+        release(pool, &home)
+    }
+
+    /// Every live record, in page chain and slot order.
+    pub fn scan<'a>(&self, pool: &'a BufferPool) -> HeapScan<'a> {
+        HeapScan {
+            pool,
+            page: None,
+            next_page: self.first_page,
+            slot: 0,
+            done: false,
+        }
+    }
+
+    /// Store an encoded record on a page with room for it.
+    fn place(&mut self, pool: &BufferPool, record: &[u8]) -> Result<RecordId> {
+        let needed = record.len() + Page::SLOT_SIZE;
+
+        let (page_id, fresh) = match self.fsm.find(needed) {
+            Some(page_id) => (page_id, false),
+            None => (self.extend(pool)?, true),
+        };
+
+        let mut page = write_data_page(pool, page_id)?;
+        let Some(slot) = page.add_record(record) else {
+            if fresh {
+                return Err(StorageError::PageFull(page_id));
+            }
+            // The map overestimated; correct it and use a new page
+            drop(page);
+            self.fsm.update(page_id, 0);
+            return self.place(pool, record);
+        };
+
+        let body = LogBody::Insert {
+            page_id,
+            slot: slot as u16,
+            data: record.to_vec(),
+        };
+        page.log_change(NO_TXN, body)?;
+        self.fsm.update(page_id, page.free_space());
+        Ok(RecordId::new(page_id, slot as u16))
+    }
+
+    /// Replace the record at `rid` if the new one fits on its page.
+    fn rewrite(&mut self, pool: &BufferPool, rid: RecordId, record: &[u8]) -> Result<bool> {
+        let mut page = write_data_page(pool, rid.page_id)?;
+        let before = page
+            .get_record(rid.slot as usize)
+            .map(<[u8]>::to_vec)
+            .unwrap_or_default();
+
+        match page.update_record(rid.slot as usize, record) {
+            Ok(()) => {
+                let body = LogBody::Update {
+                    page_id: rid.page_id,
+                    slot: rid.slot,
+                    before,
+                    after: record.to_vec(),
+                };
+                page.log_change(NO_TXN, body)?;
+                self.fsm.update(rid.page_id, page.free_space());
+                Ok(true)
+            }
+            Err(StorageError::PageFull(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Turn the record at `rid` into a stub pointing at `target`.
+    fn forward(&mut self, pool: &BufferPool, rid: RecordId, target: RecordId) -> Result<()> {
+        let mut stub = vec![FLAG_FORWARD];
+        stub.extend_from_slice(&target.to_bytes());
+
+        if self.rewrite(pool, rid, &stub)? {
+            Ok(())
+        } else {
+            Err(StorageError::PageFull(rid.page_id))
+        }
+    }
+
+    fn remove(&mut self, pool: &BufferPool, rid: RecordId) -> Result<()> {
+        let mut page = write_data_page(pool, rid.page_id)?;
+        let Some(data) = page.get_record(rid.slot as usize).map(<[u8]>::to_vec) else {
+            return Err(StorageError::InvalidSlot {
+                page_id: rid.page_id,
+                index: rid.slot as usize,
+            });
+        };
+        page.delete_record(rid.slot as usize);
+
+        // Not logged: redo makes room again where it needs it
+        if page.should_compact() {
+            page.compact();
+        }
+
+        let body = LogBody::Delete {
+            page_id: rid.page_id,
+            slot: rid.slot,
+            data,
+        };
+        page.log_change(NO_TXN, body)?;
+        self.fsm.update(rid.page_id, page.free_space());
+        Ok(())
+    }
+
+    /// Append an empty page to the heap's chain.
+    fn extend(&mut self, pool: &BufferPool) -> Result<u32> {
+        let mut page = pool.new_page(PageType::Data)?;
+        let page_id = page.header().page_id;
+        page.header_mut().prev_page = self.last_page;
+        page.log_image()?;
+        let free_space = page.free_space();
+        drop(page);
+
+        if self.last_page == 0 {
+            self.first_page = page_id;
+        } else {
+            let mut last = write_data_page(pool, self.last_page)?;
+            last.header_mut().next_page = page_id;
+            last.log_image()?;
+        }
+
+        self.last_page = page_id;
+        if self.in_header {
+            pool.store()
+                .set_data_pages(self.first_page, self.last_page)?;
+        }
+        self.fsm.update(page_id, free_space);
+
+        Ok(page_id)
+    }
+}
+
+/// Iterator over the live records of a `HeapFile`, yielding each record's
+/// id and value. Moved values are reported at their home `RecordId`.
+pub struct HeapScan<'a> {
+    pool: &'a BufferPool,
+    page: Option<Box<Page>>,
+    next_page: u32,
+    slot: usize,
+    done: bool,
+}
+
+impl HeapScan<'_> {
+    fn advance(&mut self) -> Result<Option<(RecordId, Vec<u8>)>> {
+        loop {
+            if self.page.is_none() {
+                if self.next_page == 0 {
+                    return Ok(None);
+                }
+                let page = read_data_page(self.pool, self.next_page)?.to_owned();
+                self.next_page = page.header().next_page;
+                self.slot = 0;
+                self.page = Some(page);
+            }
+            let page = self.page.as_ref().unwrap();
+
+            if self.slot >= page.header().slot_count as usize {
+                self.page = None;
+                continue;
+            }
+
+            let rid = RecordId::new(page.header().page_id, self.slot as u16);
+            self.slot += 1;
+
+            let Some(record) = page.get_record(rid.slot as usize) else {
+                continue;
+            };
+            let (flags, payload) = split_flags(record)?;
+
+            if flags & FLAG_MOVED != 0 {
+                continue;
+            }
+
+            if flags & FLAG_FORWARD != 0 {
+                let target = RecordId::from_bytes(payload)?;
+                let moved = raw_record(self.pool, target)?.ok_or_else(|| broken_forward(rid))?;
+                return Ok(Some((rid, read_value(self.pool, &moved)?)));
+            }
+
+            let record = record.to_vec();
+            return Ok(Some((rid, read_value(self.pool, &record)?)));
+        }
+    }
+}
+
+impl Iterator for HeapScan<'_> {
+    type Item = Result<(RecordId, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.advance() {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+fn read_data_page(pool: &BufferPool, page_id: u32) -> Result<PageReadGuard<'_>> {
+    let page = pool.fetch_page(page_id)?;
+    check_data_page(&page)?;
+    Ok(page)
+}
+
+fn write_data_page(pool: &BufferPool, page_id: u32) -> Result<PageWriteGuard<'_>> {
+    let page = pool.fetch_page_mut(page_id)?;
+    check_data_page(&page)?;
+    Ok(page)
+}
+
+fn check_data_page(page: &Page) -> Result<()> {
+    if page.header().page_type != PageType::Data {
+        return Err(StorageError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Page {} is not a heap data page", page.header().page_id),
+        )));
+    }
+    Ok(())
+}
+
+fn raw_record(pool: &BufferPool, rid: RecordId) -> Result<Option<Vec<u8>>> {
+    let page = read_data_page(pool, rid.page_id)?;
+    Ok(page.get_record(rid.slot as usize).map(<[u8]>::to_vec))
+}
+
+/// The record a caller's `rid` refers to, which must be live and not the
+/// moved-away half of another record.
+fn home_record(pool: &BufferPool, rid: RecordId) -> Result<Vec<u8>> {
+    match raw_record(pool, rid)? {
+        Some(record) if record[0] & FLAG_MOVED == 0 => Ok(record),
+        _ => Err(StorageError::InvalidSlot {
+            page_id: rid.page_id,
+            index: rid.slot as usize,
+        }),
+    }
+}
+
+/// Build the stored form of `data`, spilling it to an overflow chain if it
+/// is too large to keep on a page.
+fn encode(pool: &BufferPool, data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() > inline_limit(pool.page_size()) {
+        let pointer = overflow::write_value(pool, data)?;
+        let mut record = vec![FLAG_OVERFLOW];
+        record.extend_from_slice(&pointer.to_bytes());
+        return Ok(record);
+    }
+
+    let mut record = Vec::with_capacity(MIN_STORED_SIZE.max(1 + data.len()));
+    record.push(0);
+    record.extend_from_slice(data);
+
+    if record.len() < MIN_STORED_SIZE {
+        record[0] |= FLAG_PADDED;
+        record.resize(MIN_STORED_SIZE - 1, 0);
+        record.push(data.len() as u8);
+    }
+
+    Ok(record)
+}
+
+fn with_flag(mut record: Vec<u8>, flag: u8) -> Vec<u8> {
+    record[0] |= flag;
+    record
+}
+
+fn split_flags(record: &[u8]) -> Result<(u8, &[u8])> {
+    match record.split_first() {
+        Some((&flags, payload)) => Ok((flags, payload)),
+        None => Err(StorageError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Empty heap record",
+        ))),
+    }
+}
+
+/// Decode the value held by a stored (non-forwarding) record.
+fn read_value(pool: &BufferPool, record: &[u8]) -> Result<Vec<u8>> {
+    let (flags, payload) = split_flags(record)?;
+
+    if flags & FLAG_OVERFLOW != 0 {
+        return overflow::read_value(pool, OverflowPointer::from_bytes(payload)?);
+    }
+
+    if flags & FLAG_PADDED != 0 {
+        let len = payload.last().copied().unwrap_or(0) as usize;
+        return Ok(payload[..len.min(payload.len())].to_vec());
+    }
+
+    Ok(payload.to_vec())
+}
+
+/// Free anything a record owned outside its page.
+fn release(pool: &BufferPool, record: &[u8]) -> Result<()> {
+    let (flags, payload) = split_flags(record)?;
+
+    if flags & FLAG_OVERFLOW != 0 {
+        overflow::free_chain(pool, OverflowPointer::from_bytes(payload)?)?;
+    }
+    Ok(())
+}
+
+fn broken_forward(rid: RecordId) -> StorageError {
+    StorageError::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Record {} forwards to a missing record", rid),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::PageFile;
+    use tempfile::{tempdir, TempDir};
+
+    fn setup() -> (TempDir, BufferPool, HeapFile) {
+        let dir = tempdir().unwrap();
+        let pool = BufferPool::new(
+            PageFile::create_new(&dir.path().join("test.jdb")).unwrap(),
+            64,
+        );
+        let heap = HeapFile::open(&pool).unwrap();
+        (dir, pool, heap)
+    }
+
+    fn row(n: usize, len: usize) -> Vec<u8> {
+        let mut row = format!("row-{}-", n).into_bytes();
+        row.resize(len, b'x');
+        row
+    }
+
+    #[test]
+    fn test_insert_and_get() {
+        let (_dir, pool, mut heap) = setup();
+
+        let a = heap.insert(&pool, b"alpha").unwrap();
+        let b = heap.insert(&pool, b"beta").unwrap();
+
+        assert_ne!(a, b);
+        assert_eq!(heap.get(&pool, a).unwrap().unwrap(), b"alpha");
+        assert_eq!(heap.get(&pool, b).unwrap().unwrap(), b"beta");
+        assert_eq!(heap.page_count(), 1);
+    }
+
+    #[test]
+    fn test_short_records_round_trip() {
+        let (_dir, pool, mut heap) = setup();
+
+        for value in [&b""[..], b"a", b"abcde", b"abcdef", b"abcdefg"] {
+            let rid = heap.insert(&pool, value).unwrap();
+            assert_eq!(heap.get(&pool, rid).unwrap().unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_inserts_extend_page_chain() {
+        let (_dir, pool, mut heap) = setup();
+
+        let rids: Vec<_> = (0..200)
+            .map(|n| heap.insert(&pool, &row(n, 200)).unwrap())
+            .collect();
+        assert!(heap.page_count() >= 5);
+
+        // The header knows the chain ends and the pages link both ways
+        let (first, last) = pool.store().data_pages();
+        assert_eq!(first, rids[0].page_id);
+        assert_eq!(last, rids[199].page_id);
+
+        let mut page_id = first;
+        let mut prev = 0;
+        while page_id != 0 {
+            let page = pool.fetch_page(page_id).unwrap();
+            assert_eq!(page.header().prev_page, prev);
+            prev = page_id;
+            page_id = page.header().next_page;
+        }
+        assert_eq!(prev, last);
+
+        for (n, rid) in rids.iter().enumerate() {
+            assert_eq!(heap.get(&pool, *rid).unwrap().unwrap(), row(n, 200));
+        }
+    }
+
+    #[test]
+    fn test_update_in_place() {
+        let (_dir, pool, mut heap) = setup();
+        let rid = heap.insert(&pool, b"original value").unwrap();
+
+        heap.update(&pool, rid, b"short").unwrap();
+        assert_eq!(heap.get(&pool, rid).unwrap().unwrap(), b"short");
+
+        heap.update(&pool, rid, b"a somewhat longer value").unwrap();
+        assert_eq!(
+            heap.get(&pool, rid).unwrap().unwrap(),
+            b"a somewhat longer value"
+        );
+    }
+
+    #[test]
+    fn test_update_that_outgrows_page_forwards() {
+        let (_dir, pool, mut heap) = setup();
+
+        // Fill the first page
+        let rids: Vec<_> = (0..40)
+            .map(|n| heap.insert(&pool, &row(n, 190)).unwrap())
+            .collect();
+        let rid = rids[0];
+        let home_page = rid.page_id;
+
+        let grown = row(0, 1500);
+        heap.update(&pool, rid, &grown).unwrap();
+        assert_eq!(heap.get(&pool, rid).unwrap().unwrap(), grown);
+
+        // The home slot now holds a stub pointing at another page
+        let stub = raw_record(&pool, rid).unwrap().unwrap();
+        assert_eq!(stub[0], FLAG_FORWARD);
+        let target = RecordId::from_bytes(&stub[1..]).unwrap();
+        assert_ne!(target.page_id, home_page);
+
+        // The moved value is only reachable through its home id
+        assert_eq!(heap.get(&pool, target).unwrap(), None);
+        assert!(heap.update(&pool, target, b"x").is_err());
+
+        // Updating again goes through the stub
+        heap.update(&pool, rid, b"small again").unwrap();
+        assert_eq!(heap.get(&pool, rid).unwrap().unwrap(), b"small again");
+
+        let scanned: Vec<_> = heap.scan(&pool).map(|r| r.unwrap()).collect();
+        assert_eq!(scanned.len(), 40);
+        assert_eq!(scanned[0], (rid, b"small again".to_vec()));
+
+        // Deleting removes the stub and the moved value
+        heap.delete(&pool, rid).unwrap();
+        assert_eq!(heap.get(&pool, rid).unwrap(), None);
+        assert_eq!(raw_record(&pool, target).unwrap(), None);
+        assert_eq!(heap.scan(&pool).count(), 39);
+    }
+
+    #[test]
+    fn test_forwarded_record_relocates_again() {
+        let (_dir, pool, mut heap) = setup();
+
+        for n in 0..40 {
+            heap.insert(&pool, &row(n, 190)).unwrap();
+        }
+        let rid = RecordId::new(pool.store().data_pages().0, 0);
+        heap.update(&pool, rid, &row(0, 1500)).unwrap();
+        let first_target =
+            RecordId::from_bytes(&raw_record(&pool, rid).unwrap().unwrap()[1..]).unwrap();
+
+        // Fill the page the value moved to, then outgrow it
+        let mut n = 40;
+        while heap.page_count() < 3 {
+            heap.insert(&pool, &row(n, 190)).unwrap();
+            n += 1;
+        }
+        let value = row(0, 2000);
+        heap.update(&pool, rid, &value).unwrap();
+
+        let second_target =
+            RecordId::from_bytes(&raw_record(&pool, rid).unwrap().unwrap()[1..]).unwrap();
+        assert_ne!(second_target.page_id, first_target.page_id);
+        assert_eq!(raw_record(&pool, first_target).unwrap(), None);
+        assert_eq!(heap.get(&pool, rid).unwrap().unwrap(), value);
+        assert_eq!(heap.scan(&pool).count(), n);
+    }
+
+    #[test]
+    fn test_large_records_use_overflow() {
+        let (_dir, pool, mut heap) = setup();
+        let big = row(1, 50_000);
+
+        let rid = heap.insert(&pool, &big).unwrap();
+        assert_eq!(heap.get(&pool, rid).unwrap().unwrap(), big);
+        assert_eq!(heap.page_count(), 1);
+
+        // Shrinking frees the chain, so the next large value reuses it
+        let pages = pool.store().page_count();
+        heap.update(&pool, rid, b"tiny").unwrap();
+        let other = heap.insert(&pool, &big).unwrap();
+        assert_eq!(pool.store().page_count(), pages);
+
+        heap.delete(&pool, other).unwrap();
+        assert_eq!(heap.get(&pool, rid).unwrap().unwrap(), b"tiny");
+    }
+
+    #[test]
+    fn test_delete() {
+        let (_dir, pool, mut heap) = setup();
+        let a = heap.insert(&pool, b"a").unwrap();
+        let b = heap.insert(&pool, b"b").unwrap();
+
+        heap.delete(&pool, a).unwrap();
+        assert_eq!(heap.get(&pool, a).unwrap(), None);
+        assert_eq!(heap.get(&pool, b).unwrap().unwrap(), b"b");
+
+        assert!(matches!(
+            heap.delete(&pool, a),
+            Err(StorageError::InvalidSlot { .. })
+        ));
+        assert!(heap.update(&pool, a, b"again").is_err());
+    }
+
+    #[test]
+    fn test_free_space_map_reuses_room() {
+        let (_dir, pool, mut heap) = setup();
+
+        let rids: Vec<_> = (0..150)
+            .map(|n| heap.insert(&pool, &row(n, 300)).unwrap())
+            .collect();
+        let first = rids[0].page_id;
+        let pages = heap.page_count();
+
+        // Clear out the first page
+        let cleared: Vec<_> = rids.iter().filter(|rid| rid.page_id == first).collect();
+        for rid in &cleared {
+            heap.delete(&pool, **rid).unwrap();
+        }
+
+        // As many rows as were deleted fit without growing the heap
+        let reinserted: Vec<_> = (0..cleared.len())
+            .map(|n| heap.insert(&pool, &row(1000 + n, 300)).unwrap())
+            .collect();
+        assert!(reinserted.iter().any(|rid| rid.page_id == first));
+        assert_eq!(heap.page_count(), pages);
+    }
+
+    #[test]
+    fn test_scan_returns_live_records() {
+        let (_dir, pool, mut heap) = setup();
+
+        let rids: Vec<_> = (0..100)
+            .map(|n| heap.insert(&pool, &row(n, 150)).unwrap())
+            .collect();
+        for rid in rids.iter().step_by(3) {
+            heap.delete(&pool, *rid).unwrap();
+        }
+
+        let scanned: Vec<_> = heap.scan(&pool).map(|r| r.unwrap()).collect();
+        let expected: Vec<_> = (0..100)
+            .filter(|n| n % 3 != 0)
+            .map(|n| (rids[n], row(n, 150)))
+            .collect();
+        assert_eq!(scanned, expected);
+    }
+
+    #[test]
+    fn test_reopen_heap() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+
+        let rids: Vec<_> = {
+            let pool = BufferPool::new(PageFile::create_new(&path).unwrap(), 64);
+            let mut heap = HeapFile::open(&pool).unwrap();
+            let rids = (0..100)
+                .map(|n| heap.insert(&pool, &row(n, 250)).unwrap())
+                .collect();
+            pool.flush_all().unwrap();
+            rids
+        };
+
+        let pool = BufferPool::new(PageFile::open(&path).unwrap(), 64);
+        let mut heap = HeapFile::open(&pool).unwrap();
+        assert_eq!(heap.get(&pool, rids[42]).unwrap().unwrap(), row(42, 250));
+        assert_eq!(heap.scan(&pool).count(), 100);
+
+        // The rebuilt free space map still finds the room on existing pages
+        let pages = heap.page_count();
+        heap.insert(&pool, b"small").unwrap();
+        assert_eq!(heap.page_count(), pages);
+    }
+
+    #[test]
+    fn test_independent_heaps() {
+        let (_dir, pool, _default) = setup();
+
+        let mut a = HeapFile::create(&pool).unwrap();
+        let mut b = HeapFile::create(&pool).unwrap();
+        for n in 0..100 {
+            a.insert(&pool, &row(n, 200)).unwrap();
+            b.insert(&pool, &row(n + 1000, 200)).unwrap();
+        }
+
+        // Neither touches the default heap in the file header
+        assert_eq!(pool.store().data_pages(), (0, 0));
+
+        let a = HeapFile::open_at(&pool, a.first_page()).unwrap();
+        let values: Vec<_> = a.scan(&pool).map(|r| r.unwrap().1).collect();
+        assert_eq!(values, (0..100).map(|n| row(n, 200)).collect::<Vec<_>>());
+        assert_eq!(b.scan(&pool).count(), 100);
+    }
+
+    #[test]
+    fn test_destroy_frees_pages() {
+        let (_dir, pool, _default) = setup();
+
+        let mut heap = HeapFile::create(&pool).unwrap();
+        for n in 0..50 {
+            heap.insert(&pool, &row(n, 300)).unwrap();
+        }
+        heap.insert(&pool, &row(0, 30_000)).unwrap();
+        let pages = pool.store().page_count();
+        heap.destroy(&pool).unwrap();
+
+        // Rebuilding the same heap fits in the freed pages
+        let mut heap = HeapFile::create(&pool).unwrap();
+        for n in 0..50 {
+            heap.insert(&pool, &row(n, 300)).unwrap();
+        }
+        heap.insert(&pool, &row(0, 30_000)).unwrap();
+        assert_eq!(pool.store().page_count(), pages);
+    }
+
+    #[test]
+    fn test_record_id_encoding() {
+        let rid = RecordId::new(123_456, 789);
+        assert_eq!(RecordId::from_bytes(&rid.to_bytes()).unwrap(), rid);
+        assert_eq!(rid.to_string(), "(123456, 789)");
+    }
+}
@@ -3,10 +3,40 @@
 //! This crate provides the low-level storage primitives including
 //! pages, B-trees, and buffer management.
 
+pub mod btree;
+pub mod buffer;
+pub mod database;
 pub mod file;
+pub mod fsck;
+pub mod heap;
+pub mod lock;
+pub mod mvcc;
+pub mod overflow;
 pub mod page;
+pub mod recovery;
+pub mod relation;
+pub mod store;
+pub mod txn;
+pub mod wal;
 
-pub use page::{Page, PageHeader, PageType, SlotEntry};
+pub use btree::{BTree, RangeIter};
+pub use buffer::{BufferPool, BufferPoolStats, EvictionPolicy, PageReadGuard, PageWriteGuard};
+pub use database::Database;
+pub use fsck::{FsckIssue, FsckReport, IssueKind};
+pub use heap::{HeapFile, HeapScan, RecordId};
+pub use lock::{LockManager, LockMode, LockTarget};
+pub use mvcc::{
+    CommandId, CommitStatus, MvccHeap, MvccScan, Snapshot, SubTxnId, TransactionIds, TupleHeader,
+    TxnStatus,
+};
+pub use page::{
+    ChecksumStatus, Page, PageHeader, PageInfo, PageType, SlotEntry, SlotInfo, SlotState,
+};
+pub use recovery::RecoveryReport;
+pub use relation::{Relation, RelationDirectory, RelationKind};
+pub use store::{FaultOp, FaultyStore, MemoryStore, PageStore};
+pub use txn::{IsolationLevel, Transaction, TransactionManager, TxnContext};
+pub use wal::{LogBody, LogRecord, Lsn, TxnId, Wal};
 
 use thiserror::Error;
 
@@ -26,6 +56,39 @@ pub enum StorageError {
 
     #[error("Checksum mismatch for page {0}")]
     ChecksumMismatch(u32),
+
+    #[error("Page {0} failed authentication")]
+    AuthenticationFailed(u32),
+
+    #[error("Wrong encryption key")]
+    WrongKey,
+
+    #[error("No unpinned frames available in buffer pool")]
+    NoFreeFrames,
+
+    #[error("Page {0} is pinned")]
+    PagePinned(u32),
+
+    #[error("Corrupt log record at LSN {0}")]
+    CorruptLogRecord(u64),
+
+    #[error("Record {0} was changed by a concurrent transaction")]
+    WriteConflict(heap::RecordId),
+
+    #[error("Transaction {0} was chosen as a deadlock victim")]
+    Deadlock(wal::TxnId),
+
+    #[error("Transaction {0} could not be serialized")]
+    SerializationFailure(wal::TxnId),
+
+    #[error("Relation '{0}' already exists")]
+    RelationExists(String),
+
+    #[error("Relation '{0}' not found")]
+    RelationNotFound(String),
+
+    #[error("Savepoint '{0}' not found")]
+    SavepointNotFound(String),
 }
 
 pub type Result<T> = std::result::Result<T, StorageError>;
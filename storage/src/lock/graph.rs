@@ -0,0 +1,65 @@
+// storage/src/lock/graph.rs
+
+use crate::wal::TxnId;
+use std::collections::{HashMap, HashSet};
+
+/// Which transactions each waiting transaction waits for.
+#[derive(Debug, Default)]
+pub(crate) struct WaitsFor {
+    edges: HashMap<TxnId, Vec<TxnId>>,
+}
+
+impl WaitsFor {
+    pub(crate) fn add(&mut self, waiter: TxnId, blockers: Vec<TxnId>) {
+        self.edges.insert(waiter, blockers);
+    }
+
+    /// A cycle through `start`, listed from `start` on, if there is one.
+    ///
+    /// Cycles elsewhere in the graph are left to the transactions on them.
+    pub(crate) fn cycle_through(&self, start: TxnId) -> Option<Vec<TxnId>> {
+        let mut path = vec![start];
+        let mut visited = HashSet::from([start]);
+        self.search(start, &mut path, &mut visited).then_some(path)
+    }
+
+    fn search(&self, txn_id: TxnId, path: &mut Vec<TxnId>, visited: &mut HashSet<TxnId>) -> bool {
+        for &next in self.edges.get(&txn_id).into_iter().flatten() {
+            if next == path[0] {
+                return true;
+            }
+            if !visited.insert(next) {
+                continue;
+            }
+
+            path.push(next);
+            if self.search(next, path, visited) {
+                return true;
+            }
+            path.pop();
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cycle_through() {
+        let mut graph = WaitsFor::default();
+        graph.add(1, vec![2]);
+        graph.add(2, vec![5, 3]);
+        graph.add(3, vec![1]);
+        graph.add(4, vec![4]);
+
+        assert_eq!(graph.cycle_through(1), Some(vec![1, 2, 3]));
+        assert_eq!(graph.cycle_through(3), Some(vec![3, 1, 2]));
+        assert_eq!(graph.cycle_through(4), Some(vec![4]));
+        // Waits on the cycle without being part of it
+        graph.add(6, vec![1]);
+        assert_eq!(graph.cycle_through(6), None);
+        assert_eq!(graph.cycle_through(5), None);
+    }
+}
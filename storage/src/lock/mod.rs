@@ -0,0 +1,452 @@
+// storage/src/lock/mod.rs
+
+mod graph;
+
+use crate::heap::RecordId;
+use crate::wal::TxnId;
+use crate::{Result, StorageError};
+use graph::WaitsFor;
+use parking_lot::{Condvar, Mutex};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::time::{Duration, Instant};
+
+/// How long `lock` waits before giving up
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Lock modes for multiple granularity locking.
+///
+/// A transaction takes an intent mode on a relation before locking records
+/// in it: `IntentShared` to read some, `IntentExclusive` to change some.
+/// `SharedIntentExclusive` reads the whole relation and changes some of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockMode {
+    IntentShared,
+    IntentExclusive,
+    Shared,
+    SharedIntentExclusive,
+    Exclusive,
+}
+
+impl LockMode {
+    /// Whether two transactions can hold these modes at once.
+    pub fn compatible(self, other: LockMode) -> bool {
+        use LockMode::*;
+        match (self, other) {
+            (Exclusive, _) | (_, Exclusive) => false,
+            (IntentShared, _) | (_, IntentShared) => true,
+            (IntentExclusive, IntentExclusive) | (Shared, Shared) => true,
+            _ => false,
+        }
+    }
+
+    /// The weakest mode that grants everything both modes do.
+    pub fn join(self, other: LockMode) -> LockMode {
+        use LockMode::*;
+        match (self, other) {
+            (Exclusive, _) | (_, Exclusive) => Exclusive,
+            (SharedIntentExclusive, _) | (_, SharedIntentExclusive) => SharedIntentExclusive,
+            (IntentExclusive, Shared) | (Shared, IntentExclusive) => SharedIntentExclusive,
+            (IntentShared, mode) | (mode, IntentShared) => mode,
+            (IntentExclusive, IntentExclusive) => IntentExclusive,
+            (Shared, Shared) => Shared,
+        }
+    }
+
+    /// The mode to hold on a relation while holding `self` on a record.
+    fn intent(self) -> LockMode {
+        match self {
+            LockMode::IntentShared | LockMode::Shared => LockMode::IntentShared,
+            _ => LockMode::IntentExclusive,
+        }
+    }
+}
+
+/// What a lock protects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LockTarget {
+    Relation(u32),
+    Record { relation: u32, rid: RecordId },
+}
+
+/// Grants locks to transactions, making them wait for conflicting ones.
+///
+/// Locking a record first takes the matching intent mode on its relation.
+/// Requests are granted in arrival order, except that a transaction
+/// strengthening a lock it already holds only waits for the other holders.
+/// Locks are held until `release_all`.
+///
+/// A transaction that starts waiting looks for a cycle through it in the
+/// waits-for graph. The youngest transaction on a cycle is the victim: its
+/// `lock` fails with `StorageError::Deadlock`, and it is expected to abort
+/// and release its locks. A wait that outlasts the timeout fails with
+/// `io::ErrorKind::TimedOut`.
+pub struct LockManager {
+    state: Mutex<LockState>,
+    changed: Condvar,
+    timeout: Duration,
+}
+
+#[derive(Default)]
+struct LockState {
+    locks: HashMap<LockTarget, LockEntry>,
+    /// Targets each transaction holds or waits for
+    held: HashMap<TxnId, HashSet<LockTarget>>,
+    /// Target each waiting transaction waits for
+    waiting: HashMap<TxnId, LockTarget>,
+    /// Waiting transactions chosen to break a deadlock
+    victims: HashSet<TxnId>,
+}
+
+#[derive(Default)]
+struct LockEntry {
+    granted: HashMap<TxnId, LockMode>,
+    /// Waiting requests, in the order they will be granted
+    queue: Vec<(TxnId, LockMode)>,
+}
+
+impl LockEntry {
+    /// Who `txn_id` must wait for before it can hold `mode`.
+    fn blockers(&self, txn_id: TxnId, mode: LockMode) -> Vec<TxnId> {
+        let mut blockers: Vec<TxnId> = self
+            .granted
+            .iter()
+            .filter(|&(&holder, &held)| holder != txn_id && !held.compatible(mode))
+            .map(|(&holder, _)| holder)
+            .collect();
+
+        if !self.granted.contains_key(&txn_id) {
+            let ahead = self
+                .queue
+                .iter()
+                .take_while(|&&(waiter, _)| waiter != txn_id);
+            blockers.extend(
+                ahead
+                    .filter(|&&(_, wanted)| !wanted.compatible(mode))
+                    .map(|&(waiter, _)| waiter),
+            );
+        }
+        blockers
+    }
+
+    fn dequeue(&mut self, txn_id: TxnId) {
+        self.queue.retain(|&(waiter, _)| waiter != txn_id);
+    }
+}
+
+impl LockState {
+    fn waits_for(&self) -> WaitsFor {
+        let mut graph = WaitsFor::default();
+        for (&txn_id, target) in &self.waiting {
+            let entry = &self.locks[target];
+            let (_, mode) = entry
+                .queue
+                .iter()
+                .find(|&&(waiter, _)| waiter == txn_id)
+                .expect("waiting transactions are queued");
+            graph.add(txn_id, entry.blockers(txn_id, *mode));
+        }
+        graph
+    }
+
+    /// Stop `txn_id` waiting for `target`.
+    fn give_up(&mut self, txn_id: TxnId, target: LockTarget) {
+        self.waiting.remove(&txn_id);
+        self.victims.remove(&txn_id);
+
+        let entry = self
+            .locks
+            .get_mut(&target)
+            .expect("waited-for target exists");
+        entry.dequeue(txn_id);
+        if entry.granted.contains_key(&txn_id) {
+            return;
+        }
+        if entry.queue.is_empty() && entry.granted.is_empty() {
+            self.locks.remove(&target);
+        }
+        if let Some(targets) = self.held.get_mut(&txn_id) {
+            targets.remove(&target);
+        }
+    }
+}
+
+impl LockManager {
+    pub fn new() -> Self {
+        Self::with_timeout(DEFAULT_LOCK_TIMEOUT)
+    }
+
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self {
+            state: Mutex::new(LockState::default()),
+            changed: Condvar::new(),
+            timeout,
+        }
+    }
+
+    /// Lock `target` in `mode` for `txn_id`, waiting up to the manager's
+    /// timeout. A lock already held is strengthened to cover `mode`.
+    pub fn lock(&self, txn_id: TxnId, target: LockTarget, mode: LockMode) -> Result<()> {
+        self.lock_with_timeout(txn_id, target, mode, self.timeout)
+    }
+
+    pub fn lock_with_timeout(
+        &self,
+        txn_id: TxnId,
+        target: LockTarget,
+        mode: LockMode,
+        timeout: Duration,
+    ) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        if let LockTarget::Record { relation, .. } = target {
+            self.acquire(
+                txn_id,
+                LockTarget::Relation(relation),
+                mode.intent(),
+                deadline,
+            )?;
+        }
+        self.acquire(txn_id, target, mode, deadline)
+    }
+
+    /// The mode `txn_id` holds on `target`, if any.
+    pub fn held(&self, txn_id: TxnId, target: LockTarget) -> Option<LockMode> {
+        let state = self.state.lock();
+        state.locks.get(&target)?.granted.get(&txn_id).copied()
+    }
+
+    /// Release every lock `txn_id` holds, at commit or abort.
+    pub fn release_all(&self, txn_id: TxnId) {
+        let mut state = self.state.lock();
+        let Some(targets) = state.held.remove(&txn_id) else {
+            return;
+        };
+        for target in targets {
+            let entry = state.locks.get_mut(&target).expect("held target exists");
+            entry.granted.remove(&txn_id);
+            entry.dequeue(txn_id);
+            if entry.granted.is_empty() && entry.queue.is_empty() {
+                state.locks.remove(&target);
+            }
+        }
+        drop(state);
+        self.changed.notify_all();
+    }
+
+    fn acquire(
+        &self,
+        txn_id: TxnId,
+        target: LockTarget,
+        mode: LockMode,
+        deadline: Instant,
+    ) -> Result<()> {
+        let mut state = self.state.lock();
+        let entry = state.locks.entry(target).or_default();
+        let mode = match entry.granted.get(&txn_id) {
+            Some(&held) if held.join(mode) == held => return Ok(()),
+            Some(&held) => {
+                // Upgrades go first, or two of them would wait on each other
+                let mode = held.join(mode);
+                entry.queue.insert(0, (txn_id, mode));
+                mode
+            }
+            None => {
+                entry.queue.push((txn_id, mode));
+                mode
+            }
+        };
+        state.held.entry(txn_id).or_default().insert(target);
+
+        let mut checked = false;
+        loop {
+            let entry = state.locks.get_mut(&target).expect("queued target exists");
+            if entry.blockers(txn_id, mode).is_empty() {
+                entry.dequeue(txn_id);
+                entry.granted.insert(txn_id, mode);
+                state.waiting.remove(&txn_id);
+                // Chosen too late; the deadlock is gone
+                state.victims.remove(&txn_id);
+                drop(state);
+                self.changed.notify_all();
+                return Ok(());
+            }
+
+            if state.victims.contains(&txn_id) {
+                state.give_up(txn_id, target);
+                drop(state);
+                self.changed.notify_all();
+                return Err(StorageError::Deadlock(txn_id));
+            }
+
+            state.waiting.insert(txn_id, target);
+            if !checked {
+                // New edges only ever close a cycle through the new waiter
+                checked = true;
+                if let Some(cycle) = state.waits_for().cycle_through(txn_id) {
+                    let victim = *cycle.iter().max().expect("cycles are not empty");
+                    state.victims.insert(victim);
+                    self.changed.notify_all();
+                    continue;
+                }
+            }
+
+            if self.changed.wait_until(&mut state, deadline).timed_out() {
+                state.give_up(txn_id, target);
+                drop(state);
+                self.changed.notify_all();
+                return Err(StorageError::Io(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("Transaction {} timed out waiting for a lock", txn_id),
+                )));
+            }
+        }
+    }
+}
+
+impl Default for LockManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn record(slot: u16) -> LockTarget {
+        LockTarget::Record {
+            relation: 1,
+            rid: RecordId::new(5, slot),
+        }
+    }
+
+    fn timed_out(result: Result<()>) -> bool {
+        matches!(result, Err(StorageError::Io(e)) if e.kind() == io::ErrorKind::TimedOut)
+    }
+
+    #[test]
+    fn test_modes() {
+        use LockMode::*;
+        let modes = [
+            IntentShared,
+            IntentExclusive,
+            Shared,
+            SharedIntentExclusive,
+            Exclusive,
+        ];
+        let matrix = [
+            [true, true, true, true, false],
+            [true, true, false, false, false],
+            [true, false, true, false, false],
+            [true, false, false, false, false],
+            [false, false, false, false, false],
+        ];
+        for (i, &a) in modes.iter().enumerate() {
+            for (j, &b) in modes.iter().enumerate() {
+                assert_eq!(a.compatible(b), matrix[i][j], "{:?} {:?}", a, b);
+                assert_eq!(a.join(b), b.join(a));
+            }
+            assert_eq!(a.join(IntentShared), a);
+            assert_eq!(a.join(Exclusive), Exclusive);
+        }
+        assert_eq!(IntentExclusive.join(Shared), SharedIntentExclusive);
+    }
+
+    #[test]
+    fn test_shared_and_exclusive() {
+        let locks = LockManager::with_timeout(Duration::from_millis(50));
+        locks.lock(1, record(0), LockMode::Shared).unwrap();
+        locks.lock(2, record(0), LockMode::Shared).unwrap();
+        assert_eq!(
+            locks.held(1, LockTarget::Relation(1)),
+            Some(LockMode::IntentShared)
+        );
+
+        assert!(timed_out(locks.lock(3, record(0), LockMode::Exclusive)));
+        assert_eq!(locks.held(3, record(0)), None);
+        // Other records of the relation are free
+        locks.lock(3, record(1), LockMode::Exclusive).unwrap();
+        assert_eq!(
+            locks.held(3, LockTarget::Relation(1)),
+            Some(LockMode::IntentExclusive)
+        );
+        assert!(timed_out(locks.lock(
+            1,
+            LockTarget::Relation(1),
+            LockMode::Shared
+        )));
+
+        locks.release_all(1);
+        locks.release_all(2);
+        assert_eq!(locks.held(1, record(0)), None);
+        locks.lock(3, record(0), LockMode::Exclusive).unwrap();
+    }
+
+    #[test]
+    fn test_waiter_granted_on_release() {
+        let locks = LockManager::new();
+        locks.lock(1, record(0), LockMode::Exclusive).unwrap();
+
+        thread::scope(|s| {
+            let waiter = s.spawn(|| locks.lock(2, record(0), LockMode::Shared));
+            thread::sleep(Duration::from_millis(20));
+            assert_eq!(locks.held(2, record(0)), None);
+
+            locks.release_all(1);
+            waiter.join().unwrap().unwrap();
+        });
+        assert_eq!(locks.held(2, record(0)), Some(LockMode::Shared));
+    }
+
+    #[test]
+    fn test_upgrade_deadlock() {
+        let locks = LockManager::new();
+        locks.lock(1, record(0), LockMode::Shared).unwrap();
+        locks.lock(1, record(0), LockMode::Exclusive).unwrap();
+        assert_eq!(locks.held(1, record(0)), Some(LockMode::Exclusive));
+        locks.release_all(1);
+
+        // Both read, then both try to write
+        locks.lock(1, record(0), LockMode::Shared).unwrap();
+        locks.lock(2, record(0), LockMode::Shared).unwrap();
+        thread::scope(|s| {
+            let older = s.spawn(|| locks.lock(1, record(0), LockMode::Exclusive));
+            thread::sleep(Duration::from_millis(20));
+
+            let result = locks.lock(2, record(0), LockMode::Exclusive);
+            assert!(matches!(result, Err(StorageError::Deadlock(2))));
+            // Still holds what it had until it aborts
+            assert_eq!(locks.held(2, record(0)), Some(LockMode::Shared));
+            locks.release_all(2);
+
+            older.join().unwrap().unwrap();
+        });
+        assert_eq!(locks.held(1, record(0)), Some(LockMode::Exclusive));
+    }
+
+    #[test]
+    fn test_deadlock_aborts_youngest() {
+        let locks = LockManager::new();
+        locks.lock(1, record(0), LockMode::Exclusive).unwrap();
+        locks.lock(2, record(1), LockMode::Exclusive).unwrap();
+
+        thread::scope(|s| {
+            let younger = s.spawn(|| {
+                let result = locks.lock(2, record(0), LockMode::Exclusive);
+                locks.release_all(2);
+                result
+            });
+            thread::sleep(Duration::from_millis(20));
+
+            // Closes the cycle, but the waiting transaction is the victim
+            locks.lock(1, record(1), LockMode::Exclusive).unwrap();
+            assert!(matches!(
+                younger.join().unwrap(),
+                Err(StorageError::Deadlock(2))
+            ));
+        });
+        assert_eq!(locks.held(1, record(1)), Some(LockMode::Exclusive));
+        assert_eq!(locks.held(2, record(0)), None);
+    }
+}
@@ -0,0 +1,492 @@
+// storage/src/mvcc/heap.rs
+
+use super::{CommitStatus, Snapshot, TupleHeader, TxnStatus, INVALID_TXN_ID};
+use crate::buffer::BufferPool;
+use crate::heap::{HeapFile, HeapScan, RecordId};
+use crate::txn::{Serializable, SireadTarget, TxnContext};
+use crate::wal::LogBody;
+use crate::{Result, StorageError};
+
+/// A `HeapFile` whose records are versioned rows.
+///
+/// Every stored record starts with a `TupleHeader`. Updates never change a
+/// value in place: they add a new version and stamp the old one with the
+/// updating transaction and the new version's id, so readers holding older
+/// snapshots keep finding the value they saw first. Deletes only stamp.
+///
+/// A row is addressed by the id of any of its versions; `get`, `update`
+/// and `delete` follow the chain of newer versions from there. Writes
+/// follow PostgreSQL's repeatable read rule: a row changed by a transaction
+/// the snapshot cannot see, or one still running, is a `WriteConflict`.
+///
+/// Every method takes either a bare `Snapshot` or the `Transaction` it
+/// belongs to. Given a transaction, every change is logged so it can be
+/// rolled back, and a serializable transaction's reads and writes are
+/// reported to its conflict tracking: `get` leaves a SIREAD lock on the
+/// row, `scan` one on the whole heap. Versions no snapshot can see any
+/// more are not reclaimed.
+pub struct MvccHeap {
+    heap: HeapFile,
+}
+
+/// A row version visible to a snapshot.
+struct Version {
+    rid: RecordId,
+    header: TupleHeader,
+    value: Vec<u8>,
+}
+
+impl MvccHeap {
+    pub fn new(heap: HeapFile) -> Self {
+        Self { heap }
+    }
+
+    pub fn heap(&self) -> &HeapFile {
+        &self.heap
+    }
+
+    pub fn into_inner(self) -> HeapFile {
+        self.heap
+    }
+
+    pub fn insert<'t>(
+        &mut self,
+        pool: &BufferPool,
+        txn: impl Into<TxnContext<'t>>,
+        data: &[u8],
+    ) -> Result<RecordId> {
+        let txn = txn.into();
+        let snapshot = txn.snapshot();
+        let header = TupleHeader {
+            subtxn_id: snapshot.subtxn_id,
+            ..TupleHeader::new(snapshot.txn_id, snapshot.command_id)
+        };
+        let rid = self.heap.insert(pool, &encode(pool, &header, data))?;
+
+        // Logged once the id is known; until then a crash can only leave
+        // a version its aborted transaction hides
+        txn.log_undo(LogBody::HeapChange {
+            page_id: rid.page_id,
+            slot: rid.slot,
+            before: None,
+        })?;
+        if let Some(ssi) = txn.serializable() {
+            ssi.write(self.heap.id(), rid)?;
+        }
+        Ok(rid)
+    }
+
+    /// The version of the row at `rid` that the snapshot sees, if any.
+    pub fn get<'t>(
+        &self,
+        pool: &BufferPool,
+        status: &dyn CommitStatus,
+        txn: impl Into<TxnContext<'t>>,
+        rid: RecordId,
+    ) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .visible_version(pool, status, txn.into(), rid)?
+            .map(|version| version.value))
+    }
+
+    /// Replace the row at `rid` with `data`, returning the new version's id,
+    /// or `None` if the snapshot does not see the row.
+    pub fn update<'t>(
+        &mut self,
+        pool: &BufferPool,
+        status: &dyn CommitStatus,
+        txn: impl Into<TxnContext<'t>>,
+        rid: RecordId,
+        data: &[u8],
+    ) -> Result<Option<RecordId>> {
+        let txn = txn.into();
+        let Some(version) = self.writable_version(pool, status, txn, rid)? else {
+            return Ok(None);
+        };
+
+        let new_rid = self.insert(pool, txn, data)?;
+        self.stamp(pool, txn, version, Some(new_rid))?;
+        Ok(Some(new_rid))
+    }
+
+    /// Delete the row at `rid`, returning whether the snapshot saw it.
+    pub fn delete<'t>(
+        &mut self,
+        pool: &BufferPool,
+        status: &dyn CommitStatus,
+        txn: impl Into<TxnContext<'t>>,
+        rid: RecordId,
+    ) -> Result<bool> {
+        let txn = txn.into();
+        let Some(version) = self.writable_version(pool, status, txn, rid)? else {
+            return Ok(false);
+        };
+
+        self.stamp(pool, txn, version, None)?;
+        Ok(true)
+    }
+
+    /// Every row version the snapshot sees, with its id.
+    pub fn scan<'a>(
+        &self,
+        pool: &'a BufferPool,
+        status: &'a dyn CommitStatus,
+        txn: impl Into<TxnContext<'a>>,
+    ) -> MvccScan<'a> {
+        let txn = txn.into();
+        MvccScan {
+            file_version: pool.store().version(),
+            inner: self.heap.scan(pool),
+            status,
+            snapshot: txn.snapshot(),
+            ssi: txn.serializable(),
+            relation: self.heap.id(),
+            locked: false,
+        }
+    }
+
+    fn read(&self, pool: &BufferPool, rid: RecordId) -> Result<Option<(TupleHeader, Vec<u8>)>> {
+        let Some(record) = self.heap.get(pool, rid)? else {
+            return Ok(None);
+        };
+        let (header, value) = TupleHeader::split_for(pool.store().version(), &record)?;
+        Ok(Some((header, value.to_vec())))
+    }
+
+    /// Walk the version chain from `rid` to the version the snapshot sees.
+    fn visible_version(
+        &self,
+        pool: &BufferPool,
+        status: &dyn CommitStatus,
+        txn: TxnContext<'_>,
+        mut rid: RecordId,
+    ) -> Result<Option<Version>> {
+        let snapshot = txn.snapshot();
+        let ssi = txn.serializable();
+        let start = rid;
+        let version = loop {
+            let Some((header, value)) = self.read(pool, rid)? else {
+                break None;
+            };
+            let visible = snapshot.is_visible(&header, status);
+            if let Some(ssi) = &ssi {
+                ssi.read_version(snapshot, status, &header, visible)?;
+            }
+            if visible {
+                break Some(Version { rid, header, value });
+            }
+
+            // A version left by an aborted update is not part of the row
+            match header.next_version {
+                Some(next) if status.status(header.xmax) != TxnStatus::Aborted => rid = next,
+                _ => break None,
+            }
+        };
+
+        // Whoever next replaces the version read, or fills the empty slot,
+        // conflicts with this read
+        if let Some(ssi) = ssi {
+            let rid = version.as_ref().map_or(start, |version| version.rid);
+            ssi.read(SireadTarget::Record {
+                relation: self.heap.id(),
+                rid,
+            })?;
+        }
+        Ok(version)
+    }
+
+    /// The version of the row at `rid` that the snapshot may replace.
+    fn writable_version(
+        &self,
+        pool: &BufferPool,
+        status: &dyn CommitStatus,
+        txn: TxnContext<'_>,
+        rid: RecordId,
+    ) -> Result<Option<Version>> {
+        let Some(version) = self.visible_version(pool, status, txn, rid)? else {
+            return Ok(None);
+        };
+
+        let xmax = version.header.xmax;
+        if xmax == INVALID_TXN_ID || status.status(xmax) == TxnStatus::Aborted {
+            Ok(Some(version))
+        } else if xmax == txn.snapshot().txn_id {
+            // Already changed by a later command of ours
+            Ok(None)
+        } else {
+            Err(StorageError::WriteConflict(version.rid))
+        }
+    }
+
+    /// Mark `version` as replaced or deleted by the snapshot's transaction.
+    fn stamp(
+        &mut self,
+        pool: &BufferPool,
+        txn: TxnContext<'_>,
+        version: Version,
+        next_version: Option<RecordId>,
+    ) -> Result<()> {
+        if let Some(ssi) = txn.serializable() {
+            ssi.write(self.heap.id(), version.rid)?;
+        }
+        txn.log_undo(LogBody::HeapChange {
+            page_id: version.rid.page_id,
+            slot: version.rid.slot,
+            before: Some(encode(pool, &version.header, &version.value)),
+        })?;
+
+        let snapshot = txn.snapshot();
+        let header = TupleHeader {
+            xmax: snapshot.txn_id,
+            command_id: snapshot.command_id,
+            subtxn_id: snapshot.subtxn_id,
+            next_version,
+            ..version.header
+        };
+        let record = encode(pool, &header, &version.value);
+        self.heap.update(pool, version.rid, &record)
+    }
+}
+
+/// Lay out a tuple as the file's format version expects.
+fn encode(pool: &BufferPool, header: &TupleHeader, data: &[u8]) -> Vec<u8> {
+    let mut record = header.to_bytes_for(pool.store().version());
+    record.extend_from_slice(data);
+    record
+}
+
+/// Iterator over the row versions of an `MvccHeap` a snapshot sees.
+pub struct MvccScan<'a> {
+    inner: HeapScan<'a>,
+    file_version: u32,
+    status: &'a dyn CommitStatus,
+    snapshot: &'a Snapshot,
+    relation: u32,
+    ssi: Option<Serializable>,
+    /// Whether the SIREAD lock on the heap has been taken
+    locked: bool,
+}
+
+impl Iterator for MvccScan<'_> {
+    type Item = Result<(RecordId, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let (Some(ssi), false) = (&self.ssi, self.locked) {
+            self.locked = true;
+            if let Err(e) = ssi.read(SireadTarget::Relation(self.relation)) {
+                return Some(Err(e));
+            }
+        }
+
+        loop {
+            let (rid, record) = match self.inner.next()? {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+            let (header, value) = match TupleHeader::split_for(self.file_version, &record) {
+                Ok(split) => split,
+                Err(e) => return Some(Err(e)),
+            };
+            let visible = self.snapshot.is_visible(&header, self.status);
+            if let Some(ssi) = &self.ssi {
+                if let Err(e) = ssi.read_version(self.snapshot, self.status, &header, visible) {
+                    return Some(Err(e));
+                }
+            }
+            if visible {
+                return Some(Ok((rid, value.to_vec())));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::PageFile;
+    use crate::mvcc::TransactionIds;
+    use tempfile::{tempdir, TempDir};
+
+    fn setup() -> (TempDir, BufferPool, MvccHeap, TransactionIds) {
+        let dir = tempdir().unwrap();
+        let pool = BufferPool::new(
+            PageFile::create_new(&dir.path().join("test.jdb")).unwrap(),
+            64,
+        );
+        let heap = MvccHeap::new(HeapFile::open(&pool).unwrap());
+        (dir, pool, heap, TransactionIds::new())
+    }
+
+    /// Insert `value` in a transaction of its own and commit it.
+    fn committed_row(
+        pool: &BufferPool,
+        heap: &mut MvccHeap,
+        ids: &TransactionIds,
+        value: &[u8],
+    ) -> RecordId {
+        let txn = ids.begin();
+        let rid = heap.insert(pool, &ids.snapshot(txn, 0), value).unwrap();
+        ids.commit(txn);
+        rid
+    }
+
+    #[test]
+    fn test_insert_visible_after_commit() {
+        let (_dir, pool, mut heap, ids) = setup();
+
+        let writer = ids.begin();
+        let mut own = ids.snapshot(writer, 0);
+        let rid = heap.insert(&pool, &own, b"value").unwrap();
+
+        // Not even to the writer until its next command
+        assert_eq!(heap.get(&pool, &ids, &own, rid).unwrap(), None);
+        own.next_command();
+        assert_eq!(heap.get(&pool, &ids, &own, rid).unwrap().unwrap(), b"value");
+
+        let reader = ids.begin();
+        let before_commit = ids.snapshot(reader, 0);
+        ids.commit(writer);
+        assert_eq!(heap.get(&pool, &ids, &before_commit, rid).unwrap(), None);
+
+        let after_commit = ids.snapshot(reader, 0);
+        assert_eq!(
+            heap.get(&pool, &ids, &after_commit, rid).unwrap().unwrap(),
+            b"value"
+        );
+    }
+
+    #[test]
+    fn test_readers_keep_their_version() {
+        let (_dir, pool, mut heap, ids) = setup();
+        let rid = committed_row(&pool, &mut heap, &ids, b"v1");
+
+        let reader = ids.begin();
+        let old = ids.snapshot(reader, 0);
+
+        let writer = ids.begin();
+        let new_rid = heap
+            .update(&pool, &ids, &ids.snapshot(writer, 0), rid, b"v2")
+            .unwrap()
+            .unwrap();
+        assert_ne!(new_rid, rid);
+        ids.commit(writer);
+
+        let new = ids.snapshot(reader, 0);
+        assert_eq!(heap.get(&pool, &ids, &old, rid).unwrap().unwrap(), b"v1");
+        assert_eq!(heap.get(&pool, &ids, &new, rid).unwrap().unwrap(), b"v2");
+        assert_eq!(heap.get(&pool, &ids, &old, new_rid).unwrap(), None);
+
+        let scanned: Vec<_> = heap.scan(&pool, &ids, &old).map(|r| r.unwrap()).collect();
+        assert_eq!(scanned, vec![(rid, b"v1".to_vec())]);
+        let scanned: Vec<_> = heap.scan(&pool, &ids, &new).map(|r| r.unwrap()).collect();
+        assert_eq!(scanned, vec![(new_rid, b"v2".to_vec())]);
+
+        // Deleted for new snapshots only
+        let deleter = ids.begin();
+        assert!(heap
+            .delete(&pool, &ids, &ids.snapshot(deleter, 0), rid)
+            .unwrap());
+        ids.commit(deleter);
+        let latest = ids.snapshot(reader, 0);
+        assert_eq!(heap.get(&pool, &ids, &latest, rid).unwrap(), None);
+        assert_eq!(heap.get(&pool, &ids, &new, rid).unwrap().unwrap(), b"v2");
+        assert_eq!(heap.scan(&pool, &ids, &latest).count(), 0);
+    }
+
+    #[test]
+    fn test_aborted_update_is_ignored() {
+        let (_dir, pool, mut heap, ids) = setup();
+        let rid = committed_row(&pool, &mut heap, &ids, b"v1");
+
+        let writer = ids.begin();
+        heap.update(&pool, &ids, &ids.snapshot(writer, 0), rid, b"lost")
+            .unwrap()
+            .unwrap();
+        ids.abort(writer);
+
+        let txn = ids.begin();
+        let mut snapshot = ids.snapshot(txn, 0);
+        assert_eq!(
+            heap.get(&pool, &ids, &snapshot, rid).unwrap().unwrap(),
+            b"v1"
+        );
+
+        // The row can be changed again
+        heap.update(&pool, &ids, &snapshot, rid, b"v2")
+            .unwrap()
+            .unwrap();
+        snapshot.next_command();
+        assert_eq!(
+            heap.get(&pool, &ids, &snapshot, rid).unwrap().unwrap(),
+            b"v2"
+        );
+        assert_eq!(heap.scan(&pool, &ids, &snapshot).count(), 1);
+    }
+
+    #[test]
+    fn test_write_conflicts() {
+        let (_dir, pool, mut heap, ids) = setup();
+        let rid = committed_row(&pool, &mut heap, &ids, b"v1");
+
+        let first = ids.begin();
+        let second = ids.begin();
+        let first_snapshot = ids.snapshot(first, 0);
+        let second_snapshot = ids.snapshot(second, 0);
+
+        heap.update(&pool, &ids, &first_snapshot, rid, b"first")
+            .unwrap()
+            .unwrap();
+        // Still running
+        assert!(matches!(
+            heap.update(&pool, &ids, &second_snapshot, rid, b"second"),
+            Err(StorageError::WriteConflict(r)) if r == rid
+        ));
+
+        // Committed after the second snapshot was taken
+        ids.commit(first);
+        assert!(matches!(
+            heap.delete(&pool, &ids, &second_snapshot, rid),
+            Err(StorageError::WriteConflict(_))
+        ));
+
+        // A fresh snapshot sees the new version and may change it
+        let fresh = ids.snapshot(second, 0);
+        assert!(heap.delete(&pool, &ids, &fresh, rid).unwrap());
+    }
+
+    #[test]
+    fn test_own_changes_by_command() {
+        let (_dir, pool, mut heap, ids) = setup();
+
+        let txn = ids.begin();
+        let mut snapshot = ids.snapshot(txn, 0);
+        let rid = heap.insert(&pool, &snapshot, b"v1").unwrap();
+        snapshot.next_command();
+
+        let new_rid = heap
+            .update(&pool, &ids, &snapshot, rid, b"v2")
+            .unwrap()
+            .unwrap();
+        // This command still sees the version it replaced, and cannot
+        // replace it twice
+        assert_eq!(
+            heap.get(&pool, &ids, &snapshot, rid).unwrap().unwrap(),
+            b"v1"
+        );
+        assert_eq!(
+            heap.update(&pool, &ids, &snapshot, rid, b"again").unwrap(),
+            None
+        );
+
+        snapshot.next_command();
+        assert_eq!(
+            heap.get(&pool, &ids, &snapshot, rid).unwrap().unwrap(),
+            b"v2"
+        );
+        assert!(heap.delete(&pool, &ids, &snapshot, new_rid).unwrap());
+
+        snapshot.next_command();
+        assert_eq!(heap.get(&pool, &ids, &snapshot, rid).unwrap(), None);
+        assert!(!heap.delete(&pool, &ids, &snapshot, rid).unwrap());
+    }
+}
@@ -0,0 +1,160 @@
+// storage/src/mvcc/ids.rs
+
+use super::{CommandId, Snapshot, SubTxnId, INVALID_TXN_ID};
+use crate::wal::TxnId;
+use parking_lot::Mutex;
+use std::collections::{BTreeSet, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxnStatus {
+    InProgress,
+    Committed,
+    Aborted,
+}
+
+/// Where visibility checks learn how a transaction ended.
+pub trait CommitStatus {
+    fn status(&self, txn_id: TxnId) -> TxnStatus;
+
+    /// Whether subtransaction `subtxn_id` of the running `txn_id` was rolled
+    /// back to a savepoint.
+    fn subtxn_aborted(&self, _txn_id: TxnId, _subtxn_id: SubTxnId) -> bool {
+        false
+    }
+}
+
+/// Hands out transaction ids and tracks, in memory, which are running and
+/// which aborted.
+///
+/// Ids increase, so one at or past the next to be handed out has not
+/// started. Every other id is running until `commit` or `abort` is called
+/// for it; only aborted ids are remembered after they finish.
+pub struct TransactionIds {
+    state: Mutex<IdState>,
+}
+
+struct IdState {
+    next_id: TxnId,
+    active: BTreeSet<TxnId>,
+    aborted: HashSet<TxnId>,
+}
+
+impl TransactionIds {
+    pub fn new() -> Self {
+        Self::starting_at(INVALID_TXN_ID + 1)
+    }
+
+    /// Hand out ids from `next_id` on, as after a restart; ids below it
+    /// count as committed.
+    pub fn starting_at(next_id: TxnId) -> Self {
+        Self {
+            state: Mutex::new(IdState {
+                next_id: next_id.max(INVALID_TXN_ID + 1),
+                active: BTreeSet::new(),
+                aborted: HashSet::new(),
+            }),
+        }
+    }
+
+    /// The id the next `begin` will hand out.
+    pub fn next_id(&self) -> TxnId {
+        self.state.lock().next_id
+    }
+
+    pub fn begin(&self) -> TxnId {
+        let mut state = self.state.lock();
+        let txn_id = state.next_id;
+        state.next_id += 1;
+        state.active.insert(txn_id);
+        txn_id
+    }
+
+    pub fn commit(&self, txn_id: TxnId) {
+        self.state.lock().active.remove(&txn_id);
+    }
+
+    pub fn abort(&self, txn_id: TxnId) {
+        let mut state = self.state.lock();
+        if state.active.remove(&txn_id) {
+            state.aborted.insert(txn_id);
+        }
+    }
+
+    /// Take a snapshot for `txn_id`, at `command_id` within it.
+    pub fn snapshot(&self, txn_id: TxnId, command_id: CommandId) -> Snapshot {
+        let state = self.state.lock();
+        Snapshot {
+            txn_id,
+            command_id,
+            subtxn_id: 0,
+            xmin: state.active.first().copied().unwrap_or(state.next_id),
+            xmax: state.next_id,
+            active: state
+                .active
+                .iter()
+                .copied()
+                .filter(|&id| id != txn_id)
+                .collect(),
+        }
+    }
+}
+
+impl Default for TransactionIds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CommitStatus for TransactionIds {
+    fn status(&self, txn_id: TxnId) -> TxnStatus {
+        let state = self.state.lock();
+        if txn_id >= state.next_id || state.active.contains(&txn_id) {
+            TxnStatus::InProgress
+        } else if state.aborted.contains(&txn_id) || txn_id == INVALID_TXN_ID {
+            TxnStatus::Aborted
+        } else {
+            TxnStatus::Committed
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ids_and_status() {
+        let ids = TransactionIds::new();
+        let a = ids.begin();
+        let b = ids.begin();
+        assert!(a > INVALID_TXN_ID && b > a);
+        assert_eq!(ids.status(a), TxnStatus::InProgress);
+
+        ids.commit(a);
+        ids.abort(b);
+        assert_eq!(ids.status(a), TxnStatus::Committed);
+        assert_eq!(ids.status(b), TxnStatus::Aborted);
+        assert_eq!(ids.status(ids.next_id()), TxnStatus::InProgress);
+
+        let restarted = TransactionIds::starting_at(ids.next_id());
+        assert_eq!(restarted.begin(), b + 1);
+        assert_eq!(restarted.status(a), TxnStatus::Committed);
+    }
+
+    #[test]
+    fn test_snapshot_bounds() {
+        let ids = TransactionIds::new();
+        let a = ids.begin();
+        let b = ids.begin();
+        let c = ids.begin();
+        ids.commit(b);
+
+        let snapshot = ids.snapshot(c, 0);
+        assert_eq!(snapshot.xmin, a);
+        assert_eq!(snapshot.xmax, c + 1);
+        assert_eq!(snapshot.active, vec![a]);
+        assert!(!snapshot.finished(a));
+        assert!(snapshot.finished(b));
+        assert!(!snapshot.finished(c + 1));
+    }
+}
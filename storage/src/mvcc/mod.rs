@@ -0,0 +1,284 @@
+// storage/src/mvcc/mod.rs
+
+mod heap;
+mod ids;
+
+pub use heap::{MvccHeap, MvccScan};
+pub use ids::{CommitStatus, TransactionIds, TxnStatus};
+
+use crate::file::FILE_VERSION;
+use crate::heap::RecordId;
+use crate::wal::TxnId;
+use crate::{Result, StorageError};
+use std::io;
+
+/// Position of a change within its transaction
+pub type CommandId = u32;
+
+/// Savepoint level a change was made at within its transaction; 0 is the
+/// transaction itself
+pub type SubTxnId = u32;
+
+/// Marks an unset `xmax`; never handed out as a transaction id
+pub const INVALID_TXN_ID: TxnId = 0;
+
+/// Versioning information stored in front of every record of an `MvccHeap`.
+///
+/// Like PostgreSQL's, a tuple is a single version of a row: `xmin` created
+/// it, `xmax` deleted or replaced it, and `next_version` points at the
+/// version that replaced it. Only one command id and subtransaction id are
+/// kept, those of the latest change: the insert until the tuple is
+/// deleted, the delete after.
+/// A tuple inserted and deleted by the same transaction therefore looks,
+/// to that transaction, as if it was inserted at the start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TupleHeader {
+    pub xmin: TxnId,
+    pub xmax: TxnId,
+    pub command_id: CommandId,
+    pub subtxn_id: SubTxnId,
+    pub next_version: Option<RecordId>,
+}
+
+impl TupleHeader {
+    pub const ENCODED_SIZE: usize = 24 + RecordId::ENCODED_SIZE;
+
+    /// Size in files of format version 1, whose tuples had no
+    /// subtransaction id
+    pub const V1_ENCODED_SIZE: usize = 20 + RecordId::ENCODED_SIZE;
+
+    /// Header of a tuple just inserted by `command_id` of `xmin`, at the
+    /// transaction's top level.
+    pub fn new(xmin: TxnId, command_id: CommandId) -> Self {
+        Self {
+            xmin,
+            xmax: INVALID_TXN_ID,
+            command_id,
+            subtxn_id: 0,
+            next_version: None,
+        }
+    }
+
+    /// Encoded size in a file of format `file_version`.
+    pub fn encoded_size(file_version: u32) -> usize {
+        match file_version {
+            1 => Self::V1_ENCODED_SIZE,
+            _ => Self::ENCODED_SIZE,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::ENCODED_SIZE] {
+        let mut bytes = [0u8; Self::ENCODED_SIZE];
+        self.write(FILE_VERSION, &mut bytes);
+        bytes
+    }
+
+    /// Encode the header as laid out in a file of format `file_version`.
+    /// Version 1 has nowhere to keep the subtransaction id, so it is lost.
+    pub fn to_bytes_for(&self, file_version: u32) -> Vec<u8> {
+        let mut bytes = vec![0u8; Self::encoded_size(file_version)];
+        self.write(file_version, &mut bytes);
+        bytes
+    }
+
+    fn write(&self, file_version: u32, bytes: &mut [u8]) {
+        bytes[0..8].copy_from_slice(&self.xmin.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.xmax.to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.command_id.to_le_bytes());
+        let next_at = match file_version {
+            1 => 20,
+            _ => {
+                bytes[20..24].copy_from_slice(&self.subtxn_id.to_le_bytes());
+                24
+            }
+        };
+        // Page 0 is the file header, so (0, 0) never names a record
+        let next = self.next_version.unwrap_or(RecordId::new(0, 0));
+        bytes[next_at..].copy_from_slice(&next.to_bytes());
+    }
+
+    /// Split a stored record into its header and the value after it.
+    pub fn split(record: &[u8]) -> Result<(Self, &[u8])> {
+        Self::split_for(FILE_VERSION, record)
+    }
+
+    /// Like `split`, for a record stored in a file of format
+    /// `file_version`. Version 1 tuples read back at subtransaction 0.
+    pub fn split_for(file_version: u32, record: &[u8]) -> Result<(Self, &[u8])> {
+        let size = Self::encoded_size(file_version);
+        if record.len() < size {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Record too short for a tuple header",
+            )));
+        }
+
+        let (bytes, value) = record.split_at(size);
+        let (subtxn_id, next_at) = match file_version {
+            1 => (0, 20),
+            _ => (
+                SubTxnId::from_le_bytes(bytes[20..24].try_into().unwrap()),
+                24,
+            ),
+        };
+        let next = RecordId::from_bytes(&bytes[next_at..])?;
+        let header = Self {
+            xmin: TxnId::from_le_bytes(bytes[0..8].try_into().unwrap()),
+            xmax: TxnId::from_le_bytes(bytes[8..16].try_into().unwrap()),
+            command_id: CommandId::from_le_bytes(bytes[16..20].try_into().unwrap()),
+            subtxn_id,
+            next_version: (next.page_id != 0).then_some(next),
+        };
+        Ok((header, value))
+    }
+}
+
+/// What a transaction can see: every transaction that had finished when
+/// the snapshot was taken, and its own changes from earlier commands.
+///
+/// Transactions below `xmin` had all finished, those from `xmax` on had
+/// not started, and `active` lists the ones in between still running.
+/// Whether a finished transaction committed is asked of a `CommitStatus`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub txn_id: TxnId,
+    pub command_id: CommandId,
+    /// Savepoint level the transaction's changes are made at
+    pub subtxn_id: SubTxnId,
+    pub xmin: TxnId,
+    pub xmax: TxnId,
+    /// Sorted
+    pub active: Vec<TxnId>,
+}
+
+impl Snapshot {
+    /// Make the transaction's changes so far visible to it.
+    pub fn next_command(&mut self) {
+        self.command_id += 1;
+    }
+
+    /// Whether `txn_id` had finished when the snapshot was taken.
+    pub fn finished(&self, txn_id: TxnId) -> bool {
+        txn_id < self.xmin || (txn_id < self.xmax && self.active.binary_search(&txn_id).is_err())
+    }
+
+    fn sees_committed(&self, txn_id: TxnId, status: &dyn CommitStatus) -> bool {
+        txn_id != self.txn_id
+            && self.finished(txn_id)
+            && status.status(txn_id) == TxnStatus::Committed
+    }
+
+    /// Whether the tuple is part of the snapshot's view.
+    pub fn is_visible(&self, tuple: &TupleHeader, status: &dyn CommitStatus) -> bool {
+        // Our latest change to the tuple was rolled back to a savepoint
+        let rolled_back = || status.subtxn_aborted(self.txn_id, tuple.subtxn_id);
+
+        if tuple.xmin == self.txn_id {
+            // Ours: inserted by an earlier command, not deleted by one
+            if tuple.xmax == self.txn_id {
+                return rolled_back() || tuple.command_id >= self.command_id;
+            }
+            return tuple.command_id < self.command_id && !rolled_back();
+        }
+        if !self.sees_committed(tuple.xmin, status) {
+            return false;
+        }
+
+        if tuple.xmax == INVALID_TXN_ID {
+            return true;
+        }
+        if tuple.xmax == self.txn_id {
+            return rolled_back() || tuple.command_id >= self.command_id;
+        }
+        !self.sees_committed(tuple.xmax, status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tuple_header_round_trip() {
+        let mut header = TupleHeader::new(7, 3);
+        let mut record = header.to_bytes().to_vec();
+        record.extend_from_slice(b"value");
+        assert_eq!(
+            TupleHeader::split(&record).unwrap(),
+            (header, &b"value"[..])
+        );
+
+        header.xmax = 9;
+        header.subtxn_id = 2;
+        header.next_version = Some(RecordId::new(4, 2));
+        assert_eq!(
+            TupleHeader::split(&header.to_bytes()).unwrap(),
+            (header, &b""[..])
+        );
+
+        assert!(TupleHeader::split(b"short").is_err());
+    }
+
+    #[test]
+    fn test_version_1_tuple_header() {
+        let header = TupleHeader {
+            xmax: 9,
+            next_version: Some(RecordId::new(4, 2)),
+            ..TupleHeader::new(7, 3)
+        };
+        let mut record = header.to_bytes_for(1);
+        assert_eq!(record.len(), TupleHeader::V1_ENCODED_SIZE);
+        record.extend_from_slice(b"value");
+        assert_eq!(
+            TupleHeader::split_for(1, &record).unwrap(),
+            (header, &b"value"[..])
+        );
+
+        // The current layout reads the same header differently
+        assert_ne!(TupleHeader::split(&record).unwrap().0, header);
+        assert_eq!(header.to_bytes_for(FILE_VERSION), header.to_bytes());
+    }
+
+    #[test]
+    fn test_visibility() {
+        let ids = TransactionIds::new();
+        let committed = ids.begin();
+        let aborted = ids.begin();
+        let running = ids.begin();
+        ids.commit(committed);
+        ids.abort(aborted);
+
+        let me = ids.begin();
+        let snapshot = ids.snapshot(me, 1);
+        let later = ids.begin();
+        ids.commit(later);
+
+        let tuple = |xmin, xmax, command_id| TupleHeader {
+            xmin,
+            xmax,
+            command_id,
+            subtxn_id: 0,
+            next_version: None,
+        };
+        let visible = |t: TupleHeader| snapshot.is_visible(&t, &ids);
+
+        assert!(visible(tuple(committed, INVALID_TXN_ID, 0)));
+        assert!(!visible(tuple(aborted, INVALID_TXN_ID, 0)));
+        assert!(!visible(tuple(running, INVALID_TXN_ID, 0)));
+        // Committed, but after the snapshot was taken
+        assert!(!visible(tuple(later, INVALID_TXN_ID, 0)));
+
+        assert!(!visible(tuple(committed, committed, 0)));
+        assert!(visible(tuple(committed, aborted, 0)));
+        assert!(visible(tuple(committed, running, 0)));
+        assert!(visible(tuple(committed, later, 0)));
+
+        // Our own changes count from the command after them
+        assert!(visible(tuple(me, INVALID_TXN_ID, 0)));
+        assert!(!visible(tuple(me, INVALID_TXN_ID, 1)));
+        assert!(!visible(tuple(committed, me, 0)));
+        assert!(visible(tuple(committed, me, 1)));
+        assert!(!visible(tuple(me, me, 0)));
+        assert!(visible(tuple(me, me, 1)));
+    }
+}
@@ -0,0 +1,425 @@
+// storage/src/overflow/mod.rs
+
+use crate::buffer::{BufferPool, PageReadGuard};
+use crate::page::{Page, PageType};
+use crate::{Result, StorageError};
+use std::io::{self, Read, Write};
+
+/// Payload bytes carried by each overflow page of `page_size` bytes
+pub const fn chunk_size(page_size: usize) -> usize {
+    Page::max_record_size(page_size)
+}
+
+/// Where a large value lives: the first page of its overflow chain and the
+/// value's total length.
+///
+/// Its encoded form is small enough to sit in a regular page as the record
+/// standing in for the value. An empty value has no chain (`first_page` 0).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverflowPointer {
+    pub first_page: u32,
+    pub length: u64,
+}
+
+impl OverflowPointer {
+    pub const ENCODED_SIZE: usize = 12;
+
+    pub fn to_bytes(self) -> [u8; Self::ENCODED_SIZE] {
+        let mut bytes = [0u8; Self::ENCODED_SIZE];
+        bytes[0..4].copy_from_slice(&self.first_page.to_le_bytes());
+        bytes[4..12].copy_from_slice(&self.length.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != Self::ENCODED_SIZE {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid overflow pointer size",
+            )));
+        }
+
+        Ok(Self {
+            first_page: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            length: u64::from_le_bytes(bytes[4..12].try_into().unwrap()),
+        })
+    }
+
+    /// Number of overflow pages holding the value in a file of
+    /// `page_size` byte pages.
+    pub fn page_count(&self, page_size: usize) -> u64 {
+        self.length.div_ceil(chunk_size(page_size) as u64)
+    }
+}
+
+/// Store `value` in a new overflow chain.
+pub fn write_value(pool: &BufferPool, value: &[u8]) -> Result<OverflowPointer> {
+    let mut writer = OverflowWriter::new(pool);
+    writer.write_all(value)?;
+    writer.finish()
+}
+
+/// Read back a whole value stored with `write_value` or `OverflowWriter`.
+pub fn read_value(pool: &BufferPool, pointer: OverflowPointer) -> Result<Vec<u8>> {
+    let mut value = Vec::with_capacity(pointer.length as usize);
+    OverflowReader::new(pool, pointer).read_to_end(&mut value)?;
+    Ok(value)
+}
+
+/// Return every page of a chain to the file's free list.
+pub fn free_chain(pool: &BufferPool, pointer: OverflowPointer) -> Result<()> {
+    let mut page_id = pointer.first_page;
+    for _ in 0..pointer.page_count(pool.page_size()) {
+        let next = read_overflow_page(pool, page_id)?.header().next_page;
+        pool.delete_page(page_id)?;
+        page_id = next;
+    }
+    Ok(())
+}
+
+fn read_overflow_page(pool: &BufferPool, page_id: u32) -> Result<PageReadGuard<'_>> {
+    let page = pool.fetch_page(page_id)?;
+    if page.header().page_type != PageType::Overflow {
+        return Err(StorageError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Page {} is not an overflow page", page_id),
+        )));
+    }
+    Ok(page)
+}
+
+fn into_io_error(error: StorageError) -> io::Error {
+    match error {
+        StorageError::Io(e) => e,
+        other => io::Error::other(other),
+    }
+}
+
+/// Streams a value into a chain of overflow pages.
+///
+/// Pages are allocated as data arrives, so the value's size need not be
+/// known up front. Nothing references the chain until `finish` returns its
+/// pointer; a writer dropped without finishing leaks the pages written.
+pub struct OverflowWriter<'a> {
+    pool: &'a BufferPool,
+    first_page: u32,
+    current_page: u32,
+    chunk: Vec<u8>,
+    chunk_size: usize,
+    length: u64,
+}
+
+impl<'a> OverflowWriter<'a> {
+    pub fn new(pool: &'a BufferPool) -> Self {
+        let chunk_size = chunk_size(pool.page_size());
+        Self {
+            pool,
+            first_page: 0,
+            current_page: 0,
+            chunk: Vec::with_capacity(chunk_size),
+            chunk_size,
+            length: 0,
+        }
+    }
+
+    /// Write out the last page and return the pointer to the chain.
+    pub fn finish(mut self) -> Result<OverflowPointer> {
+        if !self.chunk.is_empty() {
+            self.start_chain()?;
+            self.write_chunk(0)?;
+        }
+
+        Ok(OverflowPointer {
+            first_page: self.first_page,
+            length: self.length,
+        })
+    }
+
+    fn start_chain(&mut self) -> Result<()> {
+        if self.first_page == 0 {
+            self.first_page = self.allocate_page()?;
+            self.current_page = self.first_page;
+        }
+        Ok(())
+    }
+
+    fn allocate_page(&self) -> Result<u32> {
+        Ok(self.pool.new_page(PageType::Overflow)?.header().page_id)
+    }
+
+    /// Write the buffered chunk to the current page, linking it to `next`.
+    fn write_chunk(&mut self, next: u32) -> Result<()> {
+        let mut page = self.pool.fetch_page_mut(self.current_page)?;
+        page.header_mut().next_page = next;
+        page.add_record(&self.chunk)
+            .ok_or(StorageError::PageFull(self.current_page))?;
+        page.log_image()?;
+        drop(page);
+
+        self.chunk.clear();
+        self.current_page = next;
+        Ok(())
+    }
+}
+
+impl Write for OverflowWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A full chunk is only written once more data shows the chain continues
+        if self.chunk.len() == self.chunk_size && !buf.is_empty() {
+            self.start_chain().map_err(into_io_error)?;
+            let next = self.allocate_page().map_err(into_io_error)?;
+            self.write_chunk(next).map_err(into_io_error)?;
+        }
+
+        let n = buf.len().min(self.chunk_size - self.chunk.len());
+        self.chunk.extend_from_slice(&buf[..n]);
+        self.length += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Streams a value back out of its overflow chain, one page at a time.
+pub struct OverflowReader<'a> {
+    pool: &'a BufferPool,
+    next_page: u32,
+    remaining: u64,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl<'a> OverflowReader<'a> {
+    pub fn new(pool: &'a BufferPool, pointer: OverflowPointer) -> Self {
+        Self {
+            pool,
+            next_page: pointer.first_page,
+            remaining: pointer.length,
+            chunk: Vec::new(),
+            position: 0,
+        }
+    }
+
+    fn load_next_chunk(&mut self) -> Result<()> {
+        if self.next_page == 0 {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Overflow chain ends before the value does",
+            )));
+        }
+
+        let page = read_overflow_page(self.pool, self.next_page)?;
+        let record = page.get_record(0).unwrap_or_default();
+        let take = record.len().min(self.remaining as usize);
+
+        self.chunk.clear();
+        self.chunk.extend_from_slice(&record[..take]);
+        self.position = 0;
+        self.next_page = page.header().next_page;
+        Ok(())
+    }
+}
+
+impl Read for OverflowReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.remaining == 0 {
+            return Ok(0);
+        }
+
+        if self.position == self.chunk.len() {
+            self.load_next_chunk().map_err(into_io_error)?;
+            if self.chunk.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Empty page in overflow chain",
+                ));
+            }
+        }
+
+        let n = buf.len().min(self.chunk.len() - self.position);
+        buf[..n].copy_from_slice(&self.chunk[self.position..self.position + n]);
+        self.position += n;
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::FileOptions;
+    use crate::file::PageFile;
+    use crate::page::PAGE_SIZE;
+    use tempfile::{tempdir, TempDir};
+
+    const CHUNK_SIZE: usize = chunk_size(PAGE_SIZE);
+
+    fn setup() -> (TempDir, BufferPool) {
+        let dir = tempdir().unwrap();
+        let pool = BufferPool::new(
+            PageFile::create_new(&dir.path().join("test.jdb")).unwrap(),
+            64,
+        );
+        (dir, pool)
+    }
+
+    fn blob(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_round_trip_sizes() {
+        let (_dir, pool) = setup();
+
+        for len in [
+            0,
+            1,
+            CHUNK_SIZE - 1,
+            CHUNK_SIZE,
+            CHUNK_SIZE + 1,
+            3 * CHUNK_SIZE,
+            100_000,
+        ] {
+            let value = blob(len);
+            let pointer = write_value(&pool, &value).unwrap();
+
+            assert_eq!(pointer.length, len as u64);
+            assert_eq!(
+                pointer.page_count(PAGE_SIZE),
+                len.div_ceil(CHUNK_SIZE) as u64
+            );
+            assert_eq!(read_value(&pool, pointer).unwrap(), value, "len {}", len);
+        }
+    }
+
+    #[test]
+    fn test_chain_uses_overflow_pages() {
+        let (_dir, pool) = setup();
+        let pointer = write_value(&pool, &blob(2 * CHUNK_SIZE + 10)).unwrap();
+
+        let mut page_id = pointer.first_page;
+        let mut pages = 0;
+        while page_id != 0 {
+            let page = pool.fetch_page(page_id).unwrap();
+            assert_eq!(page.header().page_type, PageType::Overflow);
+            page_id = page.header().next_page;
+            pages += 1;
+        }
+        assert_eq!(pages, 3);
+        assert_eq!(pool.store().page_count(), 4);
+    }
+
+    #[test]
+    fn test_chunks_follow_page_size() {
+        let dir = tempdir().unwrap();
+        let options = FileOptions {
+            page_size: 32768,
+            ..FileOptions::default()
+        };
+        let pool = BufferPool::new(
+            PageFile::create_new_with(&dir.path().join("big.jdb"), options).unwrap(),
+            64,
+        );
+
+        let value = blob(100_000);
+        let pointer = write_value(&pool, &value).unwrap();
+        assert_eq!(pointer.page_count(32768), 4);
+        assert_eq!(pool.store().page_count(), 5);
+        assert_eq!(read_value(&pool, pointer).unwrap(), value);
+
+        free_chain(&pool, pointer).unwrap();
+        assert_eq!(write_value(&pool, &value).unwrap().first_page, 4);
+        assert_eq!(pool.store().page_count(), 5);
+    }
+
+    #[test]
+    fn test_streaming_write_and_read() {
+        let (_dir, pool) = setup();
+        let value = blob(50_000);
+
+        let mut writer = OverflowWriter::new(&pool);
+        for piece in value.chunks(777) {
+            writer.write_all(piece).unwrap();
+        }
+        let pointer = writer.finish().unwrap();
+
+        let mut reader = OverflowReader::new(&pool, pointer);
+        let mut out = Vec::new();
+        let mut buf = [0u8; 1000];
+        loop {
+            let n = reader.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(out, value);
+    }
+
+    #[test]
+    fn test_pointer_fits_in_data_page() {
+        let (_dir, pool) = setup();
+        let value = blob(20_000);
+
+        // Too big for a page, so the page stores a pointer instead
+        let mut page = Page::new(pool.store().allocate_page().unwrap(), PageType::Data);
+        assert!(page.add_record(&value).is_none());
+
+        let pointer = write_value(&pool, &value).unwrap();
+        let slot = page.add_record(&pointer.to_bytes()).unwrap();
+
+        let stored = OverflowPointer::from_bytes(page.get_record(slot).unwrap()).unwrap();
+        assert_eq!(stored, pointer);
+        assert_eq!(read_value(&pool, stored).unwrap(), value);
+    }
+
+    #[test]
+    fn test_free_chain_returns_pages() {
+        let (_dir, pool) = setup();
+        let pointer = write_value(&pool, &blob(3 * CHUNK_SIZE)).unwrap();
+        let page_count = pool.store().page_count();
+
+        free_chain(&pool, pointer).unwrap();
+
+        // A value of the same size fits entirely in the freed pages
+        let again = write_value(&pool, &blob(3 * CHUNK_SIZE)).unwrap();
+        assert_eq!(pool.store().page_count(), page_count);
+        assert_eq!(read_value(&pool, again).unwrap(), blob(3 * CHUNK_SIZE));
+    }
+
+    #[test]
+    fn test_read_rejects_bad_chains() {
+        let (_dir, pool) = setup();
+
+        // Pointer to a page that is not part of a chain
+        let page_id = pool.store().allocate_page().unwrap();
+        let bogus = OverflowPointer {
+            first_page: page_id,
+            length: 10,
+        };
+        assert!(read_value(&pool, bogus).is_err());
+
+        // Pointer claiming more data than the chain holds
+        let pointer = write_value(&pool, &blob(100)).unwrap();
+        let too_long = OverflowPointer {
+            length: CHUNK_SIZE as u64 + 1,
+            ..pointer
+        };
+        assert!(read_value(&pool, too_long).is_err());
+    }
+
+    #[test]
+    fn test_pointer_encoding() {
+        let pointer = OverflowPointer {
+            first_page: 42,
+            length: 1 << 40,
+        };
+        assert_eq!(
+            OverflowPointer::from_bytes(&pointer.to_bytes()).unwrap(),
+            pointer
+        );
+        assert!(OverflowPointer::from_bytes(&[0; 5]).is_err());
+    }
+}
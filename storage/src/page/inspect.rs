@@ -0,0 +1,322 @@
+// storage/src/page/inspect.rs
+
+use super::{Page, PageType};
+use crate::Result;
+use std::fmt::{self, Write};
+
+/// What a slot currently refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SlotState {
+    /// Holds a record
+    Live,
+    /// Deleted, but its record bytes still occupy the record area
+    Deleted,
+    /// Deleted and reclaimed by compaction; only the slot entry is left
+    Reclaimed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SlotInfo {
+    pub index: usize,
+    pub offset: u16,
+    pub length: u16,
+    pub state: SlotState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChecksumStatus {
+    Valid,
+    Invalid,
+    /// No checksum was stamped (stored as 0)
+    Unset,
+}
+
+/// A structured description of one page, for tools and debugging.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PageInfo {
+    pub page_id: u32,
+    pub page_type: PageType,
+    pub page_size: usize,
+    pub lsn: u64,
+    pub checksum: u32,
+    pub checksum_status: ChecksumStatus,
+    pub free_space_start: u16,
+    /// Decoded; see `Page::free_space_end`
+    pub free_space_end: usize,
+    pub next_page: u32,
+    pub prev_page: u32,
+    pub level: u16,
+    /// Bytes between the slot array and the records
+    pub free_space: usize,
+    pub live_records: usize,
+    /// Bytes of the record area holding live records
+    pub live_bytes: usize,
+    /// Bytes of the record area holding nothing live, reclaimable by
+    /// compaction
+    pub dead_bytes: usize,
+    /// Share of the record area that is dead, from 0.0 to 1.0
+    pub fragmentation: f64,
+    pub slots: Vec<SlotInfo>,
+}
+
+impl PageInfo {
+    /// Describe a page straight from its on-disk bytes. An unknown page
+    /// type is rejected from the raw byte, before the header is read.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Page::type_of(bytes)?;
+        Ok(Page::from_bytes(bytes)?.inspect())
+    }
+}
+
+impl Page {
+    pub fn inspect(&self) -> PageInfo {
+        let header = self.header();
+
+        let slots: Vec<SlotInfo> = (0..header.slot_count as usize)
+            .filter_map(|index| {
+                let slot = self.get_slot(index)?;
+                let state = match (slot.length, slot.offset) {
+                    (0, 0) => SlotState::Reclaimed,
+                    (0, _) => SlotState::Deleted,
+                    _ => SlotState::Live,
+                };
+                Some(SlotInfo {
+                    index,
+                    offset: slot.offset,
+                    length: slot.length,
+                    state,
+                })
+            })
+            .collect();
+
+        let live: Vec<&SlotInfo> = slots
+            .iter()
+            .filter(|slot| slot.state == SlotState::Live)
+            .collect();
+        let live_bytes = live.iter().map(|slot| slot.length as usize).sum();
+        let record_area = self.size().saturating_sub(self.free_space_end());
+        let dead_bytes = record_area.saturating_sub(live_bytes);
+        let fragmentation = if record_area == 0 {
+            0.0
+        } else {
+            dead_bytes as f64 / record_area as f64
+        };
+
+        let checksum_status = match header.checksum {
+            0 => ChecksumStatus::Unset,
+            _ if self.verify_checksum() => ChecksumStatus::Valid,
+            _ => ChecksumStatus::Invalid,
+        };
+
+        PageInfo {
+            page_id: header.page_id,
+            page_type: header.page_type,
+            page_size: self.size(),
+            lsn: header.lsn,
+            checksum: header.checksum,
+            checksum_status,
+            free_space_start: header.free_space_start,
+            free_space_end: self.free_space_end(),
+            next_page: header.next_page,
+            prev_page: header.prev_page,
+            level: header.level,
+            free_space: self.free_space(),
+            live_records: live.len(),
+            live_bytes,
+            dead_bytes,
+            fragmentation,
+            slots,
+        }
+    }
+}
+
+impl fmt::Display for PageInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Page {} ({:?}, {} bytes)",
+            self.page_id, self.page_type, self.page_size
+        )?;
+        writeln!(f, "  LSN: {}", self.lsn)?;
+        writeln!(
+            f,
+            "  Checksum: {:#010x} ({:?})",
+            self.checksum, self.checksum_status
+        )?;
+        writeln!(
+            f,
+            "  Links: next {}, prev {}, level {}",
+            self.next_page, self.prev_page, self.level
+        )?;
+        writeln!(
+            f,
+            "  Free range: {}..{} ({} bytes free)",
+            self.free_space_start, self.free_space_end, self.free_space
+        )?;
+        writeln!(
+            f,
+            "  Records: {} live, {} bytes live, {} bytes dead ({:.1}% fragmented)",
+            self.live_records,
+            self.live_bytes,
+            self.dead_bytes,
+            self.fragmentation * 100.0
+        )?;
+        write!(f, "  Slots: {}", self.slots.len())?;
+        for slot in &self.slots {
+            write!(
+                f,
+                "\n    [{}] {:?} offset {} length {}",
+                slot.index, slot.state, slot.offset, slot.length
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Format bytes the way `hexdump -C` does: offset, sixteen bytes in hex and
+/// the printable ones as text. Runs of identical lines collapse into `*`.
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    let mut previous: Option<&[u8]> = None;
+    let mut collapsed = false;
+
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        if previous == Some(chunk) {
+            if !collapsed {
+                out.push_str("*\n");
+                collapsed = true;
+            }
+            continue;
+        }
+        previous = Some(chunk);
+        collapsed = false;
+
+        write!(out, "{:08x}  ", line * 16).unwrap();
+        for i in 0..16 {
+            match chunk.get(i) {
+                Some(byte) => write!(out, "{:02x} ", byte).unwrap(),
+                None => out.push_str("   "),
+            }
+            if i == 7 {
+                out.push(' ');
+            }
+        }
+        out.push_str(" |");
+        out.extend(chunk.iter().map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            }
+        }));
+        out.push_str("|\n");
+    }
+
+    writeln!(out, "{:08x}", bytes.len()).unwrap();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inspect_slots_and_fragmentation() {
+        let mut page = Page::new(7, PageType::Data);
+        page.header_mut().lsn = 99;
+        for record in [&b"aaaa"[..], b"bbbb", b"cccc", b"dddd"] {
+            page.add_record(record).unwrap();
+        }
+        page.delete_record(1);
+        page.delete_record(2);
+
+        let info = page.inspect();
+        assert_eq!(info.page_id, 7);
+        assert_eq!(info.page_type, PageType::Data);
+        assert_eq!(info.lsn, 99);
+        assert_eq!(info.checksum_status, ChecksumStatus::Unset);
+        assert_eq!(info.live_records, 2);
+        assert_eq!(info.live_bytes, 8);
+        assert_eq!(info.dead_bytes, 8);
+        assert_eq!(info.fragmentation, 0.5);
+        assert_eq!(info.free_space, page.free_space());
+        let states: Vec<SlotState> = info.slots.iter().map(|slot| slot.state).collect();
+        assert_eq!(
+            states,
+            [
+                SlotState::Live,
+                SlotState::Deleted,
+                SlotState::Deleted,
+                SlotState::Live
+            ]
+        );
+
+        // Compaction reclaims the dead bytes
+        page.compact();
+        let info = page.inspect();
+        assert_eq!(info.dead_bytes, 0);
+        assert_eq!(info.fragmentation, 0.0);
+        assert_eq!(info.slots[1].state, SlotState::Reclaimed);
+    }
+
+    #[test]
+    fn test_inspect_checksum_status() {
+        let mut page = Page::new(1, PageType::Index);
+        page.update_checksum();
+        assert_eq!(page.inspect().checksum_status, ChecksumStatus::Valid);
+
+        page.add_record(b"changed").unwrap();
+        assert_eq!(page.inspect().checksum_status, ChecksumStatus::Invalid);
+
+        let info = PageInfo::from_bytes(page.as_bytes()).unwrap();
+        assert_eq!(info.page_type, PageType::Index);
+        assert!(info.to_string().contains("(Invalid)"));
+    }
+
+    #[test]
+    fn test_page_info_rejects_unknown_page_type() {
+        let mut bytes = Page::new(1, PageType::Data).as_bytes().to_vec();
+        bytes[Page::PAGE_TYPE_OFFSET] = 0xFF;
+
+        let error = PageInfo::from_bytes(&bytes).unwrap_err();
+        assert!(error.to_string().contains("Invalid page type: 255"));
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let mut bytes = [0u8; 64];
+        bytes[..5].copy_from_slice(b"JDB1\x01");
+        bytes[63] = 0xff;
+
+        assert_eq!(
+            hex_dump(&bytes),
+            "00000000  4a 44 42 31 01 00 00 00  00 00 00 00 00 00 00 00  |JDB1............|\n\
+             00000010  00 00 00 00 00 00 00 00  00 00 00 00 00 00 00 00  |................|\n\
+             *\n\
+             00000030  00 00 00 00 00 00 00 00  00 00 00 00 00 00 00 ff  |................|\n\
+             00000040\n"
+        );
+        assert_eq!(
+            hex_dump(b"hi"),
+            "00000000  68 69                                             |hi|\n00000002\n"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_page_info_serializes() {
+        let mut page = Page::new(3, PageType::Overflow);
+        page.add_record(b"x").unwrap();
+
+        let info = page.inspect();
+        let json = serde_json::to_string(&info).unwrap();
+        assert!(json.contains("\"page_type\":\"Overflow\""));
+        assert!(json.contains("\"state\":\"Live\""));
+        let back: PageInfo = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, info);
+    }
+}
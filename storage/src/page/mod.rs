@@ -1,16 +1,54 @@
+mod inspect;
+
+pub use inspect::{hex_dump, ChecksumStatus, PageInfo, SlotInfo, SlotState};
+
 use crate::{Result, StorageError};
 use std::io::{Error, ErrorKind};
 
+/// Page size used when a file does not choose one
 pub const PAGE_SIZE: usize = 8192;
 
+/// Smallest page size a file may use
+pub const MIN_PAGE_SIZE: usize = 4096;
+
+/// Largest page size a file may use. Offsets within a page are stored as
+/// u16, so a page this size records an empty record area as 0; see
+/// `Page::free_space_end`.
+pub const MAX_PAGE_SIZE: usize = 65536;
+
+/// Whether pages of `size` bytes are supported: a power of two from
+/// `MIN_PAGE_SIZE` to `MAX_PAGE_SIZE`.
+pub fn is_valid_page_size(size: usize) -> bool {
+    size.is_power_of_two() && (MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&size)
+}
+
 #[repr(u8)] // 1 byte
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PageType {
     Data = 0,
     Index = 1,
     Overflow = 2,
     Free = 3,
+    Directory = 4,
+    CommitLog = 5,
+}
+
+impl PageType {
+    /// The page type stored as `value`, if it names one.
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(PageType::Data),
+            1 => Some(PageType::Index),
+            2 => Some(PageType::Overflow),
+            3 => Some(PageType::Free),
+            4 => Some(PageType::Directory),
+            5 => Some(PageType::CommitLog),
+            _ => None,
+        }
+    }
 }
+
 #[repr(C)] // Ensure consistent memory layout
 #[derive(Debug, Clone, Copy)]
 pub struct PageHeader {
@@ -24,9 +62,12 @@ pub struct PageHeader {
     pub lsn: u64,              // 8 bytes at offset 16
     pub checksum: u32,         // 4 bytes at offset 24
     _padding3: [u8; 4],        // 4 bytes at offset 28
+    pub next_page: u32,        // 4 bytes at offset 32 - next page in a chain (0 = none)
+    pub prev_page: u32,        // 4 bytes at offset 36 - previous page in a chain (0 = none)
+    pub level: u16,            // 2 bytes at offset 40 - height above the leaves for index pages
 
-    // Reserve space for future use (32 more bytes to reach 64)
-    _reserved: [u8; 32], // 32 bytes at offset 32-63
+    // Reserve space for future use (22 more bytes to reach 64)
+    _reserved: [u8; 22], // 22 bytes at offset 42-63
 }
 
 // For slotted pages, we need slot entries
@@ -37,9 +78,23 @@ pub struct SlotEntry {
     pub length: u16, // 2 bytes - length of record
 }
 
+/// One page of a file, sized when the file is created.
+///
+/// `Page` is unsized: owned pages are `Box<Page>`, and `Page::view` borrows
+/// one in place, e.g. out of a memory map.
 #[repr(C, align(8))]
 pub struct Page {
-    data: [u8; PAGE_SIZE], // The actual 8KB block
+    data: [u8], // The actual block, PAGE_SIZE bytes unless the file chose otherwise
+}
+
+impl ToOwned for Page {
+    type Owned = Box<Page>;
+
+    fn to_owned(&self) -> Box<Page> {
+        let mut page = Self::alloc(self.size());
+        page.data.copy_from_slice(&self.data);
+        page
+    }
 }
 
 pub struct PageIterator<'a> {
@@ -71,80 +126,170 @@ impl<'a> Iterator for PageIterator<'a> {
 impl Page {
     pub const HEADER_SIZE: usize = std::mem::size_of::<PageHeader>();
     pub const SLOT_SIZE: usize = std::mem::size_of::<SlotEntry>();
+    /// Where the header's `page_type` byte sits
+    pub const PAGE_TYPE_OFFSET: usize = 4;
 
-    pub fn new_uninit(page_id: u32, page_type: PageType) -> Self {
-        use std::mem::MaybeUninit;
-
-        let mut uninit: MaybeUninit<[u8; PAGE_SIZE]> = MaybeUninit::uninit();
-
-        // SAFETY: We're about to initialize the header portion,
-        // and the rest will be written before being read
-        let data = unsafe {
-            let ptr = uninit.as_mut_ptr() as *mut u8;
-
-            // Zero just the header portion
-            std::ptr::write_bytes(ptr, 0, Self::HEADER_SIZE);
+    /// Largest record an empty page of `page_size` bytes can hold; bigger
+    /// values belong in an overflow chain (see `crate::overflow`).
+    pub const fn max_record_size(page_size: usize) -> usize {
+        page_size - Self::HEADER_SIZE - Self::SLOT_SIZE
+    }
 
-            // Now we can safely assume it's initialized because:
-            // 1. Header is zeroed and will be immediately overwritten
-            // 2. Rest is uninitialized but will be written before read
-            uninit.assume_init()
-        };
+    /// Allocate a zeroed page of `size` bytes.
+    fn alloc(size: usize) -> Box<Self> {
+        assert!(is_valid_page_size(size), "Unsupported page size: {}", size);
 
-        let mut page = Self { data };
+        // Allocating whole u64s gives the 8-byte alignment Page promises
+        let words = Box::into_raw(vec![0u64; size / 8].into_boxed_slice());
+        unsafe {
+            // SAFETY:
+            // - `words` is a live allocation of exactly `size` bytes with
+            //   8-byte alignment, which is the layout of a Page of `size`
+            //   bytes (#[repr(C, align(8))] around a [u8], and `size` is a
+            //   multiple of 8), so Box<Page> frees it with the same layout
+            // - Every byte is initialized (zeroed)
+            Box::from_raw(std::ptr::slice_from_raw_parts_mut(words as *mut u8, size) as *mut Self)
+        }
+    }
 
+    fn init_header(&mut self, page_id: u32, page_type: PageType) {
         let header = PageHeader {
             page_id,
             page_type,
             _padding1: [0; 1],
             free_space_start: Self::HEADER_SIZE as u16,
-            free_space_end: PAGE_SIZE as u16,
+            // A 64K page's size wraps to 0, which `free_space_end` reads back
+            free_space_end: self.size() as u16,
             slot_count: 0,
             _padding2: [0; 4],
             lsn: 0,
             checksum: 0,
             _padding3: [0; 4],
-            _reserved: [0; 32],
+            next_page: 0,
+            prev_page: 0,
+            level: 0,
+            _reserved: [0; 22], // Could use for: version, flags, timestamp, etc.
         };
 
-        page.set_header(header);
-        page
+        self.set_header(header);
     }
 
-    pub fn new(page_id: u32, page_type: PageType) -> Self {
-        let mut page = Self {
-            data: [0; PAGE_SIZE],
-        };
+    pub fn new_uninit(page_id: u32, page_type: PageType) -> Box<Self> {
+        let mut uninit: Box<[std::mem::MaybeUninit<u64>]> = Box::new_uninit_slice(PAGE_SIZE / 8);
 
-        let header = PageHeader {
-            page_id,
-            page_type,
-            _padding1: [0; 1],
-            free_space_start: Self::HEADER_SIZE as u16,
-            free_space_end: PAGE_SIZE as u16,
-            slot_count: 0,
-            _padding2: [0; 4],
-            lsn: 0,
-            checksum: 0,
-            _padding3: [0; 4],
-            _reserved: [0; 32], // Could use for: version, flags, timestamp, etc.
+        // SAFETY: We're about to initialize the header portion,
+        // and the rest will be written before being read
+        let mut page = unsafe {
+            let ptr = uninit.as_mut_ptr() as *mut u8;
+
+            // Zero just the header portion
+            std::ptr::write_bytes(ptr, 0, Self::HEADER_SIZE);
+
+            // Now we can safely assume it's initialized because:
+            // 1. Header is zeroed and will be immediately overwritten
+            // 2. Rest is uninitialized but will be written before read
+            // The allocation has the layout of a PAGE_SIZE page (see `alloc`)
+            let words = Box::into_raw(uninit) as *mut u8;
+            Box::from_raw(std::ptr::slice_from_raw_parts_mut(words, PAGE_SIZE) as *mut Self)
         };
 
-        page.set_header(header);
+        page.init_header(page_id, page_type);
         page
     }
 
-    pub fn as_bytes(&self) -> &[u8; PAGE_SIZE] {
+    /// An empty page of the default `PAGE_SIZE`.
+    pub fn new(page_id: u32, page_type: PageType) -> Box<Self> {
+        Self::with_size(PAGE_SIZE, page_id, page_type)
+    }
+
+    /// An empty page of `size` bytes, which must be a supported page size
+    /// (see `is_valid_page_size`).
+    pub fn with_size(size: usize, page_id: u32, page_type: PageType) -> Box<Self> {
+        let mut page = Self::alloc(size);
+        page.init_header(page_id, page_type);
+        page
+    }
+
+    /// Size of this page in bytes.
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
         &self.data
     }
 
-    pub fn as_bytes_mut(&mut self) -> &mut [u8; PAGE_SIZE] {
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
         &mut self.data
     }
 
-    pub fn from_bytes(bytes: &[u8; PAGE_SIZE]) -> Result<Self> {
-        let page = Self { data: *bytes };
-        let header = page.header();
+    /// Copy a page out of `bytes`, whose length is the page size.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Box<Self>> {
+        Self::check_size(bytes.len())?;
+
+        let mut page = Self::alloc(bytes.len());
+        page.data.copy_from_slice(bytes);
+        page.validate()?;
+        Ok(page)
+    }
+
+    /// Borrow `bytes` as a page without copying them, validating it the same
+    /// way `from_bytes` does. The bytes must be 8-byte aligned, as pages in a
+    /// memory-mapped file are.
+    pub fn view(bytes: &[u8]) -> Result<&Self> {
+        Self::check_size(bytes.len())?;
+
+        if bytes.as_ptr().align_offset(8) != 0 {
+            return Err(StorageError::Io(Error::new(
+                ErrorKind::InvalidInput,
+                "Page bytes are not aligned",
+            )));
+        }
+
+        let page = unsafe {
+            // SAFETY:
+            // - Page is #[repr(C, align(8))] wrapping a single [u8], so a
+            //   pointer to it carries the same length as `bytes` and covers
+            //   the same memory
+            // - The alignment was checked above, and the length is a
+            //   supported page size, so a multiple of 8
+            // - The returned reference borrows `bytes`, so it cannot outlive them
+            &*(bytes as *const [u8] as *const Self)
+        };
+        page.validate()?;
+        Ok(page)
+    }
+
+    fn check_size(size: usize) -> Result<()> {
+        if !is_valid_page_size(size) {
+            return Err(StorageError::Io(Error::new(
+                ErrorKind::InvalidData,
+                format!("Unsupported page size: {}", size),
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// The page type of the page in `bytes`, read from the raw byte so
+    /// that a value naming no `PageType` is rejected before the header is
+    /// ever looked at as a `PageHeader`.
+    pub fn type_of(bytes: &[u8]) -> Result<PageType> {
+        let value = *bytes.get(Self::PAGE_TYPE_OFFSET).ok_or_else(|| {
+            StorageError::Io(Error::new(ErrorKind::InvalidData, "Page is too short"))
+        })?;
+
+        PageType::from_u8(value).ok_or_else(|| {
+            StorageError::Io(Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid page type: {}", value),
+            ))
+        })
+    }
+
+    fn validate(&self) -> Result<()> {
+        Self::type_of(&self.data)?;
+        let header = self.header();
 
         if header.page_id == u32::MAX {
             return Err(StorageError::Io(Error::new(
@@ -153,7 +298,7 @@ impl Page {
             )));
         }
 
-        if header.free_space_end as usize > PAGE_SIZE {
+        if self.free_space_end() > self.size() {
             return Err(StorageError::Io(Error::new(
                 ErrorKind::InvalidData,
                 "free_space_end exceeds page size",
@@ -162,39 +307,41 @@ impl Page {
 
         let slot_array_end = Self::HEADER_SIZE + (header.slot_count as usize * Self::SLOT_SIZE);
 
-        if slot_array_end > PAGE_SIZE {
+        if slot_array_end > self.size() {
             return Err(StorageError::Io(Error::new(
                 ErrorKind::InvalidData,
                 "Slot array exceeds page size",
             )));
         }
 
-        if slot_array_end > header.free_space_end as usize {
+        if slot_array_end > self.free_space_end() {
             return Err(StorageError::Io(Error::new(
                 ErrorKind::InvalidData,
                 "Slot array overlaps with records",
             )));
         }
 
-        if !matches!(
-            header.page_type,
-            PageType::Data | PageType::Index | PageType::Overflow | PageType::Free
-        ) {
-            return Err(StorageError::Io(Error::new(
-                ErrorKind::InvalidData,
-                "Invalid page type",
-            )));
-        }
+        Ok(())
+    }
 
-        Ok(page)
+    /// Start of the record area, i.e. the end of the free space.
+    ///
+    /// The header stores it as a u16, which cannot hold 65536: a 64K page
+    /// with no records stores 0 instead. On any other page 0 stays 0, which
+    /// `validate` rejects since the header occupies the start of the page.
+    pub fn free_space_end(&self) -> usize {
+        match self.header().free_space_end {
+            0 if self.size() == MAX_PAGE_SIZE => self.size(),
+            end => end as usize,
+        }
     }
 
     pub fn header(&self) -> &PageHeader {
         unsafe {
             // SAFETY:
-            // - `self.data` is a [u8; PAGE_SIZE] array with PAGE_SIZE = 8192
+            // - `self.data` is a page of at least MIN_PAGE_SIZE = 4096 bytes
             // - PageHeader is #[repr(C)] ensuring predictable layout
-            // - Size of PageHeader (26 bytes) is less than PAGE_SIZE
+            // - Size of PageHeader (26 bytes) is less than MIN_PAGE_SIZE
             // - self.data.as_ptr() is aligned to at least 1 byte, PageHeader requires
             //   4-byte alignment (due to u32 fields), which is satisfied because
             //   self.data array starts at struct beginning which has natural alignment
@@ -208,9 +355,9 @@ impl Page {
     pub fn header_mut(&mut self) -> &mut PageHeader {
         unsafe {
             // SAFETY:
-            // - `self.data` is a [u8; PAGE_SIZE] array with PAGE_SIZE = 8192
+            // - `self.data` is a page of at least MIN_PAGE_SIZE = 4096 bytes
             // - PageHeader is #[repr(C)] ensuring predictable layout
-            // - Size of PageHeader (26 bytes) is less than PAGE_SIZE
+            // - Size of PageHeader (26 bytes) is less than MIN_PAGE_SIZE
             // - self.data.as_mut_ptr() is aligned to at least 1 byte, PageHeader
             //   requires 4-byte alignment (due to u32 fields), which is satisfied
             //   because self.data array starts at struct beginning
@@ -225,8 +372,8 @@ impl Page {
     fn set_header(&mut self, header: PageHeader) {
         unsafe {
             // SAFETY:
-            // - `self.data` is a [u8; PAGE_SIZE] array with PAGE_SIZE = 8192
-            // - PageHeader is #[repr(C)] with size 26 bytes, which fits in PAGE_SIZE
+            // - `self.data` is a page of at least MIN_PAGE_SIZE = 4096 bytes
+            // - PageHeader is #[repr(C)] with size 26 bytes, which fits in MIN_PAGE_SIZE
             // - self.data.as_mut_ptr() is aligned for PageHeader (see header_mut)
             // - We have exclusive access to self.data through &mut self
             // - PageHeader is Copy, so no drop concerns
@@ -242,7 +389,7 @@ impl Page {
         }
 
         let slot_offset = Self::HEADER_SIZE + (index * Self::SLOT_SIZE);
-        if slot_offset + Self::SLOT_SIZE > PAGE_SIZE {
+        if slot_offset + Self::SLOT_SIZE > self.size() {
             return None;
         }
         unsafe {
@@ -251,9 +398,9 @@ impl Page {
             //   * slot_offset = HEADER_SIZE + (index * SLOT_SIZE)
             //   * index < slot_count (checked above)
             //   * Maximum offset = HEADER_SIZE + (slot_count - 1) * SLOT_SIZE
-            //   * This must be < PAGE_SIZE - SLOT_SIZE to fit the last slot
+            //   * This must be < the page size - SLOT_SIZE to fit the last slot
             //   * We trust slot_count was set correctly when slots were added
-            // - The resulting pointer is within the bounds of self.data[0..PAGE_SIZE]
+            // - The resulting pointer is within the bounds of self.data
             // - Casting to *const SlotEntry is valid because:
             //   * SlotEntry is #[repr(C)] with a defined memory layout
             //   * SlotEntry contains only u16 fields (no padding between them)
@@ -275,10 +422,10 @@ impl Page {
     pub fn free_space(&self) -> usize {
         let header = self.header();
         let slot_array_end = Self::HEADER_SIZE + (header.slot_count as usize * Self::SLOT_SIZE);
-        let free_space_end = header.free_space_end as usize;
+        let free_space_end = self.free_space_end();
 
         // Validate bounds to prevent underflow
-        if free_space_end > PAGE_SIZE || slot_array_end > free_space_end {
+        if free_space_end > self.size() || slot_array_end > free_space_end {
             return 0; // Page is corrupted, report no free space
         }
 
@@ -286,8 +433,12 @@ impl Page {
     }
 
     pub fn has_space_for(&self, record_size: usize) -> bool {
-        // Need space for the record + a new slot entry
-        self.free_space() >= record_size + Self::SLOT_SIZE
+        // A tombstoned slot can be reused; otherwise we need a new slot entry too
+        if self.first_free_slot().is_some() {
+            self.free_space() >= record_size
+        } else {
+            self.free_space() >= record_size + Self::SLOT_SIZE
+        }
     }
 
     pub fn get_record(&self, slot_index: usize) -> Option<&[u8]> {
@@ -300,29 +451,37 @@ impl Page {
         let start = slot.offset as usize;
         let end = start + slot.length as usize;
 
-        if end <= PAGE_SIZE {
+        if end <= self.size() {
             Some(&self.data[start..end])
         } else {
             None
         }
     }
 
-    /// Add a record to the page, returning the slot index if successful
+    /// Add a record to the page, returning the slot index if successful.
+    /// Returns `None` when the page lacks room, which is always the case for
+    /// records over `MAX_RECORD_SIZE`.
+    ///
+    /// Tombstoned slots are reused before the slot array grows, so slot
+    /// numbers stay dense without ever renumbering live records.
     pub fn add_record(&mut self, record: &[u8]) -> Option<usize> {
         if !self.has_space_for(record.len()) {
             return None;
         }
 
         let record_len = record.len();
-        let slot_index = self.header().slot_count as usize;
-        let current_record_boundary = self.header().free_space_end as usize;
+        let slot_count = self.header().slot_count as usize;
+        let reused_slot = self.first_free_slot();
+        let slot_index = reused_slot.unwrap_or(slot_count);
+        let current_record_boundary = self.free_space_end();
 
-        if current_record_boundary > PAGE_SIZE || record_len > current_record_boundary {
+        if current_record_boundary > self.size() || record_len > current_record_boundary {
             return None;
         }
 
         let new_record_start = current_record_boundary - record_len;
-        let slot_array_end = Self::HEADER_SIZE + ((slot_index + 1) * Self::SLOT_SIZE);
+        let new_slot_count = slot_count.max(slot_index + 1);
+        let slot_array_end = Self::HEADER_SIZE + (new_slot_count * Self::SLOT_SIZE);
 
         if new_record_start < slot_array_end {
             return None;
@@ -338,7 +497,7 @@ impl Page {
 
         let header = self.header_mut();
         header.free_space_end = new_record_start as u16;
-        header.slot_count += 1;
+        header.slot_count = new_slot_count as u16;
 
         Some(slot_index)
     }
@@ -348,6 +507,11 @@ impl Page {
             return Vec::new();
         }
 
+        // Reusing tombstones is handled one record at a time
+        if self.first_free_slot().is_some() {
+            return self.add_records_partial(records);
+        }
+
         let total_record_size: usize = records.iter().map(|r| r.len()).sum();
         let total_slot_size = records.len() * Self::SLOT_SIZE;
 
@@ -358,7 +522,7 @@ impl Page {
         let mut results: Vec<Option<usize>> = Vec::with_capacity(records.len());
 
         let mut current_slot = self.header().slot_count as usize;
-        let mut current_boundary = self.header().free_space_end as usize;
+        let mut current_boundary = self.free_space_end();
         let slot_array_end = Self::HEADER_SIZE + ((current_slot + records.len()) * Self::SLOT_SIZE);
 
         if current_boundary < total_record_size
@@ -407,13 +571,155 @@ impl Page {
         unsafe {
             // SAFETY:
             // - We know slot_offset is valid because we control when this is called
-            // - slot_offset + SLOT_SIZE <= PAGE_SIZE (enforced by has_space_for check)
+            // - slot_offset + SLOT_SIZE <= the page size (enforced by has_space_for check)
             // - Alignment is maintained (same as get_slot)
             // - We have exclusive access through &mut self
             *(self.data.as_mut_ptr().add(slot_offset) as *mut SlotEntry) = slot;
         }
     }
 
+    /// Replace the record at `slot_index`, keeping its slot number.
+    ///
+    /// The record is rewritten in place when it fits in its current space,
+    /// otherwise it is relocated within the page (compacting first if that
+    /// frees enough room). Returns `StorageError::PageFull` when the new value
+    /// cannot fit on this page at all; the page is left unchanged so the
+    /// caller can forward the record to another page.
+    pub fn update_record(&mut self, slot_index: usize, record: &[u8]) -> Result<()> {
+        let page_id = self.header().page_id;
+        let slot = match self.get_slot(slot_index) {
+            Some(slot) if slot.length > 0 => slot,
+            _ => {
+                return Err(StorageError::InvalidSlot {
+                    page_id,
+                    index: slot_index,
+                })
+            }
+        };
+
+        if record.is_empty() {
+            // A zero-length slot is a tombstone
+            return Err(StorageError::Io(Error::new(
+                ErrorKind::InvalidInput,
+                "Cannot update a record to zero length",
+            )));
+        }
+
+        let record_len = record.len();
+        let old_len = slot.length as usize;
+
+        // Shrinking or same size: overwrite where it is
+        if record_len <= old_len {
+            let start = slot.offset as usize;
+            self.data[start..start + record_len].copy_from_slice(record);
+            self.set_slot(
+                slot_index,
+                SlotEntry {
+                    offset: slot.offset,
+                    length: record_len as u16,
+                },
+            );
+            return Ok(());
+        }
+
+        // Growing: the old copy becomes garbage, so it counts as reclaimable
+        if self.free_space() < record_len {
+            let slot_array_end =
+                Self::HEADER_SIZE + (self.header().slot_count as usize * Self::SLOT_SIZE);
+            let live_after = self.live_bytes() - old_len;
+
+            if self.size() - live_after < slot_array_end + record_len {
+                return Err(StorageError::PageFull(page_id));
+            }
+
+            // Temporarily tombstone the slot so compaction drops the old copy
+            self.set_slot(
+                slot_index,
+                SlotEntry {
+                    offset: slot.offset,
+                    length: 0,
+                },
+            );
+            self.compact_records();
+        }
+
+        let record_end = self.free_space_end();
+        let new_record_start = record_end - record_len;
+        self.data[new_record_start..record_end].copy_from_slice(record);
+        self.set_slot(
+            slot_index,
+            SlotEntry {
+                offset: new_record_start as u16,
+                length: record_len as u16,
+            },
+        );
+        self.header_mut().free_space_end = new_record_start as u16;
+
+        Ok(())
+    }
+
+    /// Place a record at a specific slot index, e.g. when replaying or
+    /// undoing a logged insert. The slot must be a tombstone or lie past the
+    /// end of the slot array, which is extended with tombstones as needed.
+    ///
+    /// Deleted records are compacted away if that makes room, since the
+    /// page the insert was logged against may have been compacted when
+    /// this copy was not.
+    pub fn insert_record_at(&mut self, slot_index: usize, record: &[u8]) -> Result<()> {
+        let page_id = self.header().page_id;
+        let slot_count = self.header().slot_count as usize;
+
+        if self.get_slot(slot_index).is_some_and(|slot| slot.length > 0) {
+            return Err(StorageError::InvalidSlot {
+                page_id,
+                index: slot_index,
+            });
+        }
+
+        let new_slot_count = slot_count.max(slot_index + 1);
+        let slot_array_end = Self::HEADER_SIZE + (new_slot_count * Self::SLOT_SIZE);
+
+        if slot_array_end + record.len() > self.size() {
+            return Err(StorageError::PageFull(page_id));
+        }
+
+        if self.free_space_end() < slot_array_end + record.len() {
+            if self.size() - self.live_bytes() < slot_array_end + record.len() {
+                return Err(StorageError::PageFull(page_id));
+            }
+            self.compact_records();
+        }
+        let record_end = self.free_space_end();
+
+        for index in slot_count..slot_index {
+            self.set_slot(index, SlotEntry { offset: 0, length: 0 });
+        }
+
+        let record_start = record_end - record.len();
+        self.data[record_start..record_end].copy_from_slice(record);
+        self.set_slot(
+            slot_index,
+            SlotEntry {
+                offset: record_start as u16,
+                length: record.len() as u16,
+            },
+        );
+
+        let header = self.header_mut();
+        header.free_space_end = record_start as u16;
+        header.slot_count = new_slot_count as u16;
+
+        Ok(())
+    }
+
+    /// Total bytes held by live records.
+    fn live_bytes(&self) -> usize {
+        (0..self.header().slot_count as usize)
+            .filter_map(|i| self.get_slot(i))
+            .map(|slot| slot.length as usize)
+            .sum()
+    }
+
     pub fn delete_record(&mut self, slot_index: usize) -> bool {
         if let Some(mut slot) = self.get_slot(slot_index) {
             slot.length = 0;
@@ -452,6 +758,20 @@ impl Page {
         count
     }
 
+    /// Tombstones whose record bytes are still sitting in the record area.
+    /// Compaction reclaims them and resets their offset to 0.
+    fn unreclaimed_count(&self) -> usize {
+        (0..self.header().slot_count as usize)
+            .filter_map(|i| self.get_slot(i))
+            .filter(|slot| slot.length == 0 && slot.offset != 0)
+            .count()
+    }
+
+    fn first_free_slot(&self) -> Option<usize> {
+        (0..self.header().slot_count as usize)
+            .find(|&i| self.get_slot(i).is_some_and(|slot| slot.length == 0))
+    }
+
     pub fn should_compact(&self) -> bool {
         let total_slots = self.header().slot_count as usize;
 
@@ -460,84 +780,80 @@ impl Page {
             return false;
         }
 
-        let deleted = self.deleted_count();
+        let deleted = self.unreclaimed_count();
 
         // Need at least 2 deleted slots AND > 20% deleted
         deleted >= 2 && (deleted * 100 / total_slots) > 20
     }
 
+    /// Reclaim the space held by deleted records.
+    ///
+    /// Slot numbers are stable: live records keep their slot index and
+    /// deleted slots remain as tombstones for `add_record` to reuse.
     pub fn compact(&mut self) {
         if !self.should_compact() {
             return;
         }
 
-        let old_slot_count = self.header().slot_count as usize;
-        let mut write_position = PAGE_SIZE;
-        let mut write_slot_index = 0;
-
-        // Two-pass in-place compaction
-        for read_slot_index in 0..old_slot_count {
-            if let Some(slot) = self.get_slot(read_slot_index) {
-                if slot.length > 0 {  // Active slot
-                    let record_len = slot.length as usize;
-                    let old_start = slot.offset as usize;
-                    let old_end = old_start + record_len;
-
-                    // Calculate new position for record (growing backwards from end)
-                    let new_start = write_position - record_len;
-
-                    // Debug assertion: verify no overlap with unprocessed records
-                    #[cfg(debug_assertions)]
-                    {
-                        // Any unprocessed slot at index > read_slot_index should not overlap
-                        for j in (read_slot_index + 1)..old_slot_count {
-                            if let Some(future_slot) = self.get_slot(j) {
-                                if future_slot.length > 0 {
-                                    let future_start = future_slot.offset as usize;
-                                    let future_end = future_start + future_slot.length as usize;
-                                    debug_assert!(
-                                        new_start >= future_end || new_start + record_len <= future_start,
-                                        "Compaction would overwrite unprocessed record at slot {}", j
-                                    );
-                                }
-                            }
-                        }
-                    }
-
-                    // Move record data if needed
-                    if new_start != old_start {
-                        // Use memmove-style copy that handles overlapping regions
-                        self.data.copy_within(old_start..old_end, new_start);
-                    }
-
-                    // Always write the compacted slot (simpler, clearer)
-                    self.set_slot(write_slot_index, SlotEntry {
-                        offset: new_start as u16,
-                        length: slot.length,
-                    });
-
-                    write_position = new_start;
-                    write_slot_index += 1;
+        self.compact_records();
+    }
+
+    fn compact_records(&mut self) {
+        let slot_count = self.header().slot_count as usize;
+
+        // Slot reuse means slot order no longer matches record order, so move
+        // records starting from the one closest to the end of the page. Each
+        // record then only ever moves towards the end, past bytes that have
+        // already been processed.
+        let mut live: Vec<(usize, SlotEntry)> = Vec::with_capacity(slot_count);
+        for index in 0..slot_count {
+            if let Some(slot) = self.get_slot(index) {
+                if slot.length > 0 {
+                    live.push((index, slot));
+                } else if slot.offset != 0 {
+                    // Mark the tombstone as reclaimed
+                    self.set_slot(index, SlotEntry { offset: 0, length: 0 });
                 }
             }
         }
+        live.sort_unstable_by_key(|(_, slot)| std::cmp::Reverse(slot.offset));
+
+        let mut write_position = self.size();
+        for (index, slot) in live {
+            let record_len = slot.length as usize;
+            let old_start = slot.offset as usize;
+            let new_start = write_position - record_len;
+
+            if new_start != old_start {
+                // Use memmove-style copy that handles overlapping regions
+                self.data
+                    .copy_within(old_start..old_start + record_len, new_start);
+            }
 
-        // Update header with new counts and free space boundary
-        let header = self.header_mut();
-        header.free_space_end = write_position as u16;
-        header.slot_count = write_slot_index as u16;  // Update to reflect only active slots
+            self.set_slot(
+                index,
+                SlotEntry {
+                    offset: new_start as u16,
+                    length: slot.length,
+                },
+            );
+
+            write_position = new_start;
+        }
+
+        self.header_mut().free_space_end = write_position as u16;
     }
 
     pub fn used_space(&self) -> usize {
         let header = self.header();
         let slots_size = header.slot_count as usize * Self::SLOT_SIZE;
-        let records_size = PAGE_SIZE - header.free_space_end as usize;
+        let records_size = self.size() - self.free_space_end();
 
         Self::HEADER_SIZE + slots_size + records_size
     }
 
     pub fn fill_percentage(&self) -> f32 {
-        (self.used_space() as f32 / PAGE_SIZE as f32) * 100.0
+        (self.used_space() as f32 / self.size() as f32) * 100.0
     }
 
     fn calculate_checksum(&self) -> u32 {
@@ -568,7 +884,7 @@ impl Page {
         self.calculate_checksum() == stored
     }
 
-    pub fn iter(&self) -> PageIterator {
+    pub fn iter(&self) -> PageIterator<'_> {
         PageIterator {
             page: self,
             current_slot: 0,
@@ -585,16 +901,7 @@ impl Page {
     }
 
     pub fn debug_layout(&self) {
-        let header = self.header();
-        println!("Page {} Layout:", header.page_id);
-        println!("  Type: {:?}", header.page_type);
-        println!("  Slots: {}", header.slot_count);
-        println!("  Free space: {} bytes", self.free_space());
-        println!(
-            "  Free range: {}..{}",
-            Self::HEADER_SIZE + (header.slot_count as usize * Self::SLOT_SIZE),
-            header.free_space_end
-        );
+        println!("{}", self.inspect());
     }
 }
 
@@ -660,7 +967,27 @@ mod tests {
     #[test]
     fn test_page_alignment() {
         // Ensure Page struct has correct alignment
-        assert_eq!(std::mem::align_of::<Page>(), 8);
+        let page = Page::new(1, PageType::Data);
+        assert_eq!(std::mem::align_of_val(&*page), 8);
+        assert_eq!(page.as_bytes().as_ptr().align_offset(8), 0);
+    }
+
+    #[test]
+    fn test_view_borrows_aligned_bytes() {
+        let mut page = Page::new(3, PageType::Data);
+        page.add_record(b"in place").unwrap();
+
+        let view = Page::view(page.as_bytes()).unwrap();
+        assert!(std::ptr::eq(view, &*page));
+        assert_eq!(view.get_record(0).unwrap(), b"in place");
+
+        // The same bytes at an address that is not 8-byte aligned
+        let mut buffer = vec![0u8; PAGE_SIZE + 8];
+        let start = (0..8)
+            .find(|&i| buffer[i..].as_ptr().align_offset(8) != 0)
+            .unwrap();
+        buffer[start..start + PAGE_SIZE].copy_from_slice(page.as_bytes());
+        assert!(Page::view(&buffer[start..start + PAGE_SIZE]).is_err());
     }
 
     #[test]
@@ -736,21 +1063,22 @@ mod tests {
         // Compact the page
         page.compact();
 
-        // After compaction: only 3 slots (all active)
-        assert_eq!(page.header().slot_count, 3);
-        assert_eq!(page.deleted_count(), 0);
+        // After compaction: slot numbers are unchanged, deleted ones are tombstones
+        assert_eq!(page.header().slot_count, 5);
+        assert_eq!(page.deleted_count(), 2);
+        assert!(!page.should_compact());
 
         // Check that we reclaimed space
         assert!(page.free_space() > space_before);
 
-        // Verify remaining records are intact (with new slot indices)
+        // Verify remaining records are intact at their original slots
         assert_eq!(page.get_record(0).unwrap(), record1);
-        assert_eq!(page.get_record(1).unwrap(), record3);
-        assert_eq!(page.get_record(2).unwrap(), record5);
+        assert_eq!(page.get_record(2).unwrap(), record3);
+        assert_eq!(page.get_record(4).unwrap(), record5);
 
-        // Old slot indices should be invalid
+        // Deleted slots stay deleted
+        assert!(page.get_record(1).is_none());
         assert!(page.get_record(3).is_none());
-        assert!(page.get_record(4).is_none());
     }
 
     #[test]
@@ -771,11 +1099,11 @@ mod tests {
         // Delete middle record (only 1 deleted - shouldn't trigger compaction)
         page.delete_record(slot2);
 
-        let data_before = page.data.clone();
+        let data_before = page.data.to_vec();
         page.compact();
 
         // Should NOT compact (only 1 deleted slot)
-        assert_eq!(page.data, data_before);
+        assert_eq!(page.as_bytes(), data_before);
         assert_eq!(page.free_space(), space_before); // Space unchanged
 
         // Records still accessible
@@ -916,6 +1244,19 @@ mod tests {
         assert!(Page::from_bytes(&bytes).is_err());
     }
 
+    #[test]
+    fn test_from_bytes_validates_page_type() {
+        let mut bytes = [0u8; PAGE_SIZE];
+        let page = Page::new(1, PageType::Directory);
+        bytes.copy_from_slice(page.as_bytes());
+        assert_eq!(Page::type_of(&bytes).unwrap(), PageType::Directory);
+
+        bytes[Page::PAGE_TYPE_OFFSET] = 6; // Names no page type
+        assert!(Page::type_of(&bytes).is_err());
+        assert!(Page::from_bytes(&bytes).is_err());
+        assert!(Page::type_of(&bytes[..4]).is_err());
+    }
+
     // Tests for Critical Issue #3: Thread-safe checksum
     #[test]
     fn test_verify_checksum_is_readonly() {
@@ -923,11 +1264,11 @@ mod tests {
         page.add_record(b"test").unwrap();
         page.update_checksum();
 
-        let original_data = page.data.clone();
+        let original_data = page.data.to_vec();
 
         // verify_checksum should not modify the page
         assert!(page.verify_checksum());
-        assert_eq!(page.data, original_data);
+        assert_eq!(page.as_bytes(), original_data);
     }
 
     // Tests for Issue #5: get_slot bounds
@@ -1001,15 +1342,15 @@ mod tests {
         let slot = page.add_record(b"test").unwrap();
         page.delete_record(slot);
 
-        let data_before = page.data.clone();
+        let data_before = page.data.to_vec();
         page.compact();
 
         // Should not compact (below threshold)
-        assert_eq!(page.data, data_before);
+        assert_eq!(page.as_bytes(), data_before);
     }
 
     #[test]
-    fn test_compaction_updates_slot_count() {
+    fn test_compaction_keeps_slot_numbers() {
         let mut page = Page::new(1, PageType::Data);
 
         // Add 10 records
@@ -1025,23 +1366,294 @@ mod tests {
             page.delete_record(i);
         }
 
-        assert_eq!(page.header().slot_count, 10);  // Still 10 slots
+        assert_eq!(page.header().slot_count, 10); // Still 10 slots
         assert_eq!(page.deleted_count(), 5);
         assert_eq!(page.active_records(), 5);
 
         // After compaction
         page.compact();
 
-        assert_eq!(page.header().slot_count, 5);   // Now only 5 slots
-        assert_eq!(page.deleted_count(), 0);       // No deleted slots
-        assert_eq!(page.active_records(), 5);      // Still 5 active records
+        assert_eq!(page.header().slot_count, 10); // Slot array is untouched
+        assert_eq!(page.deleted_count(), 5); // Deleted slots are tombstones
+        assert_eq!(page.active_records(), 5); // Still 5 active records
+
+        // The odd-numbered records are still found at their original slots
+        for i in (1..10).step_by(2) {
+            assert_eq!(
+                page.get_record(i).unwrap(),
+                format!("record{}", i).as_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn test_add_record_reuses_tombstones() {
+        let mut page = Page::new(1, PageType::Data);
+
+        for i in 0..4 {
+            page.add_record(format!("rec{}", i).as_bytes()).unwrap();
+        }
+        page.delete_record(1);
+        page.delete_record(2);
+
+        // Lowest tombstone first, and the slot array does not grow
+        assert_eq!(page.add_record(b"new1").unwrap(), 1);
+        assert_eq!(page.add_record(b"new2").unwrap(), 2);
+        assert_eq!(page.header().slot_count, 4);
+
+        // No tombstones left, so the next record appends a slot
+        assert_eq!(page.add_record(b"new4").unwrap(), 4);
+
+        assert_eq!(page.get_record(0).unwrap(), b"rec0");
+        assert_eq!(page.get_record(1).unwrap(), b"new1");
+        assert_eq!(page.get_record(2).unwrap(), b"new2");
+        assert_eq!(page.get_record(3).unwrap(), b"rec3");
+    }
+
+    #[test]
+    fn test_compaction_after_slot_reuse() {
+        let mut page = Page::new(1, PageType::Data);
+
+        for i in 0..6 {
+            page.add_record(format!("record{}", i).as_bytes()).unwrap();
+        }
+
+        // Reused slot 0 now points below the records of higher slots
+        page.delete_record(0);
+        page.add_record(b"reused-zero").unwrap();
+
+        page.delete_record(2);
+        page.delete_record(4);
+        page.compact();
+
+        assert_eq!(page.get_record(0).unwrap(), b"reused-zero");
+        assert_eq!(page.get_record(1).unwrap(), b"record1");
+        assert_eq!(page.get_record(3).unwrap(), b"record3");
+        assert_eq!(page.get_record(5).unwrap(), b"record5");
+        assert!(page.get_record(2).is_none());
+        assert!(page.get_record(4).is_none());
+
+        // The reclaimed space is usable and tombstones are handed out again
+        assert_eq!(page.add_record(b"again").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_update_record_in_place() {
+        let mut page = Page::new(1, PageType::Data);
+
+        let slot = page.add_record(b"original value").unwrap();
+        let offset = page.get_slot(slot).unwrap().offset;
+        let free_before = page.free_space();
+
+        page.update_record(slot, b"shorter").unwrap();
 
-        // Verify the 5 remaining records are the odd-numbered ones
-        assert_eq!(page.get_record(0).unwrap(), b"record1");
-        assert_eq!(page.get_record(1).unwrap(), b"record3");
-        assert_eq!(page.get_record(2).unwrap(), b"record5");
-        assert_eq!(page.get_record(3).unwrap(), b"record7");
-        assert_eq!(page.get_record(4).unwrap(), b"record9");
+        assert_eq!(page.get_record(slot).unwrap(), b"shorter");
+        assert_eq!(page.get_slot(slot).unwrap().offset, offset);
+        assert_eq!(page.free_space(), free_before);
+    }
+
+    #[test]
+    fn test_update_record_relocates_when_growing() {
+        let mut page = Page::new(1, PageType::Data);
+
+        let first = page.add_record(b"a").unwrap();
+        let second = page.add_record(b"b").unwrap();
+
+        page.update_record(first, b"a much longer value").unwrap();
+
+        assert_eq!(page.get_record(first).unwrap(), b"a much longer value");
+        assert_eq!(page.get_record(second).unwrap(), b"b");
+        assert_eq!(page.header().slot_count, 2);
+    }
+
+    #[test]
+    fn test_update_record_compacts_to_make_room() {
+        let mut page = Page::new(1, PageType::Data);
+
+        let filler = vec![b'X'; 1000];
+        let mut slots = Vec::new();
+        while let Some(slot) = page.add_record(&filler) {
+            slots.push(slot);
+        }
+
+        // Free two records; the space is only usable after compaction
+        page.delete_record(slots[1]);
+        page.delete_record(slots[2]);
+
+        let bigger = vec![b'Y'; 2500];
+        assert!(page.free_space() < bigger.len());
+        page.update_record(slots[0], &bigger).unwrap();
+
+        assert_eq!(page.get_record(slots[0]).unwrap(), bigger.as_slice());
+        for &slot in &slots[3..] {
+            assert_eq!(page.get_record(slot).unwrap(), filler.as_slice());
+        }
+    }
+
+    #[test]
+    fn test_update_record_reports_page_full() {
+        let mut page = Page::new(1, PageType::Data);
+
+        let filler = vec![b'X'; 1000];
+        let first = page.add_record(&filler).unwrap();
+        while page.add_record(&filler).is_some() {}
+
+        let data_before = page.data.to_vec();
+        let too_big = vec![b'Z'; 3000];
+
+        assert!(matches!(
+            page.update_record(first, &too_big),
+            Err(StorageError::PageFull(1))
+        ));
+
+        // The page is untouched so the caller can forward the record
+        assert_eq!(page.as_bytes(), data_before);
+    }
+
+    #[test]
+    fn test_update_record_invalid_slot() {
+        let mut page = Page::new(1, PageType::Data);
+
+        let slot = page.add_record(b"gone").unwrap();
+        page.delete_record(slot);
+
+        assert!(matches!(
+            page.update_record(slot, b"x"),
+            Err(StorageError::InvalidSlot { page_id: 1, index: 0 })
+        ));
+        assert!(matches!(
+            page.update_record(5, b"x"),
+            Err(StorageError::InvalidSlot { page_id: 1, index: 5 })
+        ));
+    }
+
+    #[test]
+    fn test_insert_record_at() {
+        let mut page = Page::new(1, PageType::Data);
+
+        page.add_record(b"zero").unwrap();
+        let one = page.add_record(b"one").unwrap();
+        page.delete_record(one);
+
+        // Into a tombstone
+        page.insert_record_at(one, b"uno").unwrap();
+        assert_eq!(page.get_record(one).unwrap(), b"uno");
+
+        // Past the end, leaving tombstones in between
+        page.insert_record_at(4, b"four").unwrap();
+        assert_eq!(page.header().slot_count, 5);
+        assert!(page.get_record(2).is_none());
+        assert!(page.get_record(3).is_none());
+        assert_eq!(page.get_record(4).unwrap(), b"four");
+
+        // The gap is reused by later inserts
+        assert_eq!(page.add_record(b"two").unwrap(), 2);
+
+        // Live slots are never overwritten
+        assert!(matches!(
+            page.insert_record_at(0, b"clobber"),
+            Err(StorageError::InvalidSlot { page_id: 1, index: 0 })
+        ));
+    }
+
+    #[test]
+    fn test_insert_record_at_compacts_to_make_room() {
+        let mut page = Page::new(1, PageType::Data);
+        let record = vec![7u8; 1000];
+
+        while page.add_record(&record).is_some() {}
+        let count = page.header().slot_count as usize;
+        page.delete_record(0);
+        page.delete_record(1);
+        assert!(page.free_space() < record.len());
+
+        // Only the deleted records' space is left
+        page.insert_record_at(1, &record).unwrap();
+        assert_eq!(page.get_record(1).unwrap(), record.as_slice());
+        assert!(page.get_record(0).is_none());
+        for slot in 2..count {
+            assert_eq!(page.get_record(slot).unwrap(), record.as_slice());
+        }
+
+        assert!(matches!(
+            page.insert_record_at(count, &[7u8; 2000]),
+            Err(StorageError::PageFull(1))
+        ));
+    }
+
+    #[test]
+    fn test_has_space_for_counts_reusable_slot() {
+        let mut page = Page::new(1, PageType::Data);
+
+        let filler = vec![b'X'; 100];
+        while page.add_record(&filler).is_some() {}
+
+        let exact = page.free_space();
+        assert!(!page.has_space_for(exact));
+
+        page.delete_record(0);
+        assert!(page.has_space_for(exact));
+    }
+
+    #[test]
+    fn test_page_sizes() {
+        for size in [4096, 8192, 16384, 32768, 65536] {
+            let mut page = Page::with_size(size, 1, PageType::Data);
+            assert_eq!(page.size(), size);
+            assert_eq!(page.free_space_end(), size);
+            assert_eq!(page.free_space(), size - Page::HEADER_SIZE);
+
+            // The largest record fills the page exactly
+            let record = vec![7u8; Page::max_record_size(size)];
+            assert_eq!(page.add_record(&record), Some(0));
+            assert_eq!(page.free_space(), 0);
+            assert_eq!(page.add_record(b"x"), None);
+            assert_eq!(page.get_record(0).unwrap(), &record[..]);
+
+            let copy = Page::from_bytes(page.as_bytes()).unwrap();
+            assert_eq!(copy.size(), size);
+            assert_eq!(copy.get_record(0).unwrap(), &record[..]);
+
+            page.delete_record(0);
+            page.compact_records();
+            page.add_record(b"small").unwrap();
+            assert_eq!(page.free_space_end(), size - 5);
+            assert_eq!(page.get_record(0).unwrap(), b"small");
+        }
+    }
+
+    #[test]
+    fn test_64k_page_stores_empty_record_area_as_zero() {
+        let mut page = Page::with_size(MAX_PAGE_SIZE, 1, PageType::Data);
+        assert_eq!(page.header().free_space_end, 0);
+        assert_eq!(page.free_space_end(), MAX_PAGE_SIZE);
+        assert!(Page::from_bytes(page.as_bytes()).is_ok());
+
+        let slot = page.add_record(b"last bytes").unwrap();
+        assert_eq!(
+            page.get_slot(slot).unwrap().offset as usize,
+            MAX_PAGE_SIZE - 10
+        );
+        page.delete_record(slot);
+        page.compact_records();
+        assert_eq!(page.header().free_space_end, 0);
+        assert_eq!(page.used_space(), Page::HEADER_SIZE + Page::SLOT_SIZE);
+
+        // Anywhere else 0 is still corrupt
+        let mut bytes = Page::new(1, PageType::Data).as_bytes().to_vec();
+        bytes[8..10].copy_from_slice(&0u16.to_le_bytes());
+        assert!(Page::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_from_bytes_rejects_unsupported_sizes() {
+        assert!(!is_valid_page_size(2048));
+        assert!(!is_valid_page_size(12288));
+        assert!(!is_valid_page_size(131072));
+
+        let page = Page::new(1, PageType::Data);
+        assert!(Page::from_bytes(&page.as_bytes()[..4000]).is_err());
+        assert!(Page::view(&page.as_bytes()[..6144]).is_err());
     }
 }
 
@@ -1184,9 +1796,9 @@ mod iterator_tests {
     fn test_iterator_skips_deleted() {
         let mut page = Page::new(1, PageType::Data);
         
-        let slot1 = page.add_record(b"a").unwrap();
+        let _slot1 = page.add_record(b"a").unwrap();
         let slot2 = page.add_record(b"b").unwrap();
-        let slot3 = page.add_record(b"c").unwrap();
+        let _slot3 = page.add_record(b"c").unwrap();
         
         page.delete_record(slot2);
         
@@ -1205,4 +1817,5 @@ mod iterator_tests {
         let items: Vec<(usize, &[u8])> = page.iter_with_slots().collect();
         assert_eq!(items, vec![(1, b"y".as_slice())]);
     }
+
 }
\ No newline at end of file
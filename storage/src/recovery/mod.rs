@@ -0,0 +1,630 @@
+// storage/src/recovery/mod.rs
+
+use crate::btree::BTree;
+use crate::buffer::BufferPool;
+use crate::heap::{HeapFile, RecordId};
+use crate::page::Page;
+use crate::wal::{LogBody, LogRecord, Lsn, TxnId, Wal, NO_TXN};
+use crate::Result;
+use std::collections::{BinaryHeap, HashMap};
+
+/// What a recovery pass found and did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// Log records read during analysis
+    pub records_scanned: usize,
+    /// Page operations reapplied during redo
+    pub records_redone: usize,
+    /// Page operations rolled back during undo
+    pub records_undone: usize,
+    /// Transactions that were in flight at the crash and have been rolled back
+    pub losers: Vec<TxnId>,
+}
+
+/// Bring the pool's store back to a consistent state by replaying `wal`,
+/// ARIES style. It must run once, before anything else uses the pool.
+///
+/// 1. Analysis scans the log from the last checkpoint's redo point, or
+///    from the start without one, seeded with the transactions the
+///    checkpoint found in flight. It finds transactions without a `Commit`
+///    or `End` record and the first LSN that may have dirtied each page.
+/// 2. Redo repeats history. The latest logged file header is replayed
+///    first, unless the stored one is as new, so the store knows every page
+///    the log does. Then every logged page operation whose LSN is newer
+///    than the page's `PageHeader::lsn` is reapplied, including those of
+///    transactions that will be rolled back.
+/// 3. Undo walks each unfinished transaction's `prev_lsn` chain backwards,
+///    applying the inverse of each operation and logging it as a
+///    compensation record so a crash during recovery never undoes twice.
+///    Logical heap and index changes are undone through the heap or tree.
+///
+/// Pages are changed in the pool, which may write them back at any time
+/// as it always does. Recovery ends with a checkpoint, so the next one
+/// starts from where this one left off.
+pub fn recover(pool: &BufferPool, wal: &Wal) -> Result<RecoveryReport> {
+    let mut report = RecoveryReport::default();
+
+    let analysis = analyze(wal, &mut report)?;
+    redo(pool, wal, &analysis, &mut report)?;
+    undo(pool, wal, &analysis, &mut report)?;
+
+    wal.checkpoint(|| pool.flush_all())?;
+
+    Ok(report)
+}
+
+struct Analysis {
+    /// Unfinished transactions and their first and most recent record
+    active: HashMap<TxnId, (Lsn, Lsn)>,
+    /// Pages touched by the log and the first LSN that touched them
+    dirty_pages: HashMap<u32, Lsn>,
+    /// Latest file header image and its LSN
+    header: Option<(Lsn, Vec<u8>)>,
+}
+
+fn analyze(wal: &Wal, report: &mut RecoveryReport) -> Result<Analysis> {
+    let mut active = HashMap::new();
+    let mut dirty_pages = HashMap::new();
+    let mut header = None;
+
+    let mut redo_lsn = 0;
+    if let Some(lsn) = wal.last_checkpoint() {
+        if let LogBody::Checkpoint {
+            redo_lsn: start,
+            active: in_flight,
+        } = wal.read_record(lsn)?.body
+        {
+            redo_lsn = start;
+            for (txn_id, first_lsn, last_lsn) in in_flight {
+                active.insert(txn_id, (first_lsn, last_lsn));
+            }
+        }
+    }
+
+    for record in wal.iter_from(redo_lsn) {
+        let record = record?;
+        report.records_scanned += 1;
+
+        match record.body {
+            LogBody::Commit | LogBody::End => {
+                active.remove(&record.txn_id);
+            }
+            // Logged outside any transaction; nothing to roll back
+            _ if record.txn_id == NO_TXN => {}
+            _ => {
+                active
+                    .entry(record.txn_id)
+                    .and_modify(|(_, last_lsn)| *last_lsn = record.lsn)
+                    .or_insert((record.lsn, record.lsn));
+            }
+        }
+
+        if let Some(page_id) = record.body.page_id() {
+            dirty_pages.entry(page_id).or_insert(record.lsn);
+        }
+        if let LogBody::FileHeader { image } = record.body {
+            header = Some((record.lsn, image));
+        }
+    }
+
+    Ok(Analysis {
+        active,
+        dirty_pages,
+        header,
+    })
+}
+
+fn redo(
+    pool: &BufferPool,
+    wal: &Wal,
+    analysis: &Analysis,
+    report: &mut RecoveryReport,
+) -> Result<()> {
+    // The header first, so pages written back while redoing are not
+    // clobbered by it later
+    if let Some((lsn, image)) = &analysis.header {
+        if pool.store().redo_header(image, *lsn)? {
+            report.records_redone += 1;
+        }
+    }
+
+    let redo_start = analysis.dirty_pages.values().min().copied();
+    for record in redo_start.into_iter().flat_map(|lsn| wal.iter_from(lsn)) {
+        let record = record?;
+        let Some(page_id) = record.body.page_id() else {
+            continue;
+        };
+
+        // A page that never reached the disk, or was torn on its way
+        // there, is rebuilt by its image
+        let replaces_page = matches!(record.body, LogBody::PageImage { .. });
+        let mut page = pool.fetch_page_for_redo(page_id, replaces_page)?;
+        if page.header().lsn >= record.lsn {
+            continue; // Already on disk
+        }
+
+        apply(&mut page, &record.body, record.lsn)?;
+        report.records_redone += 1;
+    }
+
+    Ok(())
+}
+
+fn undo(
+    pool: &BufferPool,
+    wal: &Wal,
+    analysis: &Analysis,
+    report: &mut RecoveryReport,
+) -> Result<()> {
+    let mut losers: Vec<TxnId> = analysis.active.keys().copied().collect();
+    losers.sort_unstable();
+    report.losers = losers;
+
+    // Undo in reverse LSN order across all losers
+    let mut to_undo: BinaryHeap<(Lsn, TxnId)> = analysis
+        .active
+        .iter()
+        .map(|(&txn_id, &(_, last_lsn))| (last_lsn, txn_id))
+        .collect();
+
+    for (&txn_id, &(first_lsn, last_lsn)) in &analysis.active {
+        wal.resume_txn(txn_id, first_lsn, last_lsn);
+    }
+
+    while let Some((lsn, txn_id)) = to_undo.pop() {
+        let record = wal.read_record(lsn)?;
+
+        let undo_next_lsn = match &record.body {
+            // Already undone before the crash; skip what it compensated
+            LogBody::Compensation { undo_next_lsn, .. } => *undo_next_lsn,
+            body if body.is_logical() => {
+                undo_logical(pool, wal, &record)?;
+                report.records_undone += 1;
+                record.prev_lsn
+            }
+            _ => {
+                if undo_physical(pool, &record)?.is_some() {
+                    report.records_undone += 1;
+                }
+                record.prev_lsn
+            }
+        };
+
+        if undo_next_lsn == 0 {
+            wal.append(txn_id, LogBody::End)?;
+        } else {
+            to_undo.push((undo_next_lsn, txn_id));
+        }
+    }
+
+    Ok(())
+}
+
+/// Roll back the changes `txn_id` logged after `stop_lsn`, newest first,
+/// starting from its record at `last_lsn`; a `stop_lsn` of 0 rolls back
+/// all of them. Each step is logged as a compensation record whose
+/// `undo_next_lsn` skips what has been undone. The pool must log its pages
+/// to `wal`. Returns the transaction's latest record once done.
+pub(crate) fn rollback(
+    pool: &BufferPool,
+    wal: &Wal,
+    txn_id: TxnId,
+    last_lsn: Lsn,
+    stop_lsn: Lsn,
+) -> Result<Lsn> {
+    let mut latest = last_lsn;
+    let mut lsn = last_lsn;
+    while lsn > stop_lsn {
+        let record = wal.read_record(lsn)?;
+        debug_assert_eq!(record.txn_id, txn_id);
+
+        lsn = match &record.body {
+            LogBody::Compensation { undo_next_lsn, .. } => *undo_next_lsn,
+            body if body.is_logical() => {
+                latest = undo_logical(pool, wal, &record)?;
+                record.prev_lsn
+            }
+            _ => {
+                if let Some(clr_lsn) = undo_physical(pool, &record)? {
+                    latest = clr_lsn;
+                }
+                record.prev_lsn
+            }
+        };
+    }
+
+    Ok(latest)
+}
+
+/// Apply the inverse of a page operation and log it, through the pool, as
+/// a compensation record. Returns that record's LSN, or `None` if the
+/// record has nothing to undo.
+fn undo_physical(pool: &BufferPool, record: &LogRecord) -> Result<Option<Lsn>> {
+    let (Some(page_id), Some(inverse)) = (record.body.page_id(), record.body.inverse()) else {
+        return Ok(None);
+    };
+
+    let mut page = pool.fetch_page_mut(page_id)?;
+    inverse.apply(&mut page)?;
+    page.log_change(
+        record.txn_id,
+        LogBody::Compensation {
+            undo_next_lsn: record.prev_lsn,
+            redo: Box::new(inverse),
+        },
+    )?;
+    Ok(Some(page.header().lsn))
+}
+
+/// Undo a logical change through the heap or index it was made to, then
+/// log that it was undone.
+///
+/// Undoing does not rely on the pages looking as they did when the change
+/// was made, only on no other transaction having changed the same record
+/// or key since. `MvccHeap` keeps writers off each other's records; for
+/// index keys that is up to the caller. Returns the compensation record's
+/// LSN.
+fn undo_logical(pool: &BufferPool, wal: &Wal, record: &LogRecord) -> Result<Lsn> {
+    match &record.body {
+        LogBody::HeapChange {
+            page_id,
+            slot,
+            before,
+        } => {
+            let rid = RecordId::new(*page_id, *slot);
+            let mut heap = HeapFile::unchained(pool);
+            match before {
+                Some(before) => heap.update(pool, rid, before)?,
+                // Gone already if the undo was cut short by a crash
+                None if heap.get(pool, rid)?.is_some() => heap.delete(pool, rid)?,
+                None => {}
+            }
+        }
+        LogBody::IndexChange { root, key, before } => {
+            let tree = BTree::open(pool, *root)?;
+            match before {
+                Some(before) => {
+                    tree.insert(pool, None, key, before)?;
+                }
+                None => {
+                    tree.delete(pool, None, key)?;
+                }
+            }
+        }
+        _ => unreachable!("not a logical change"),
+    }
+
+    wal.append(
+        record.txn_id,
+        LogBody::Compensation {
+            undo_next_lsn: record.prev_lsn,
+            redo: Box::new(record.body.clone()),
+        },
+    )
+}
+
+/// Apply a logged page operation and stamp its LSN into the page.
+fn apply(page: &mut Page, body: &LogBody, lsn: Lsn) -> Result<()> {
+    body.apply(page)?;
+    if body.page_id().is_some() {
+        page.header_mut().lsn = lsn;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::PageFile;
+    use crate::page::PageType;
+    use std::path::Path;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    fn open(dir: &Path) -> (BufferPool, Arc<Wal>) {
+        open_with_frames(dir, 16)
+    }
+
+    fn open_with_frames(dir: &Path, frames: usize) -> (BufferPool, Arc<Wal>) {
+        let path = dir.join("test.jdb");
+        let mut file = if path.exists() {
+            PageFile::open(&path).unwrap()
+        } else {
+            PageFile::create_new(&path).unwrap()
+        };
+        let wal = Arc::new(Wal::open(&dir.join("wal")).unwrap());
+        file.attach_wal(Arc::clone(&wal));
+        let mut pool = BufferPool::new(file, frames);
+        pool.attach_wal(Arc::clone(&wal));
+        (pool, wal)
+    }
+
+    /// Format a fresh data page and log its image.
+    fn new_logged_page(pool: &BufferPool, wal: &Wal, txn_id: TxnId) -> Box<Page> {
+        let page_id = pool.store().allocate_page().unwrap();
+        let mut page = Page::new(page_id, PageType::Data);
+        let image = page.as_bytes().to_vec();
+        wal.log_page(txn_id, &mut page, LogBody::PageImage { page_id, image })
+            .unwrap();
+        page
+    }
+
+    fn logged_insert(wal: &Wal, txn_id: TxnId, page: &mut Page, data: &[u8]) -> Lsn {
+        let slot = page.add_record(data).unwrap();
+        let body = LogBody::Insert {
+            page_id: page.header().page_id,
+            slot: slot as u16,
+            data: data.to_vec(),
+        };
+        wal.log_page(txn_id, page, body).unwrap()
+    }
+
+    /// Write a page behind the pool's back, as if it had evicted it.
+    fn write(pool: &BufferPool, page: &mut Page) {
+        page.update_checksum();
+        pool.store().write_page(page).unwrap();
+    }
+
+    #[test]
+    fn test_redo_committed_changes_missing_from_disk() {
+        let dir = tempdir().unwrap();
+
+        let (page_id, insert_lsn) = {
+            let (pool, wal) = open(dir.path());
+            let mut page = new_logged_page(&pool, &wal, 1);
+            let insert_lsn = logged_insert(&wal, 1, &mut page, b"committed");
+            wal.append(1, LogBody::Commit).unwrap();
+            wal.flush().unwrap();
+            // Crash before the page is written
+            (page.header().page_id, insert_lsn)
+        };
+
+        let (pool, wal) = open(dir.path());
+        let report = recover(&pool, &wal).unwrap();
+
+        assert_eq!(report.records_redone, 2);
+        assert!(report.losers.is_empty());
+
+        let page = pool.fetch_page(page_id).unwrap();
+        assert_eq!(page.header().page_type, PageType::Data);
+        assert_eq!(page.header().lsn, insert_lsn);
+        assert_eq!(page.get_record(0).unwrap(), b"committed");
+    }
+
+    #[test]
+    fn test_redo_page_beyond_end_of_file() {
+        let dir = tempdir().unwrap();
+
+        {
+            let (pool, wal) = open(dir.path());
+            // Allocation never reached the header, only the log
+            let mut page = Page::new(pool.store().page_count(), PageType::Data);
+            let page_id = page.header().page_id;
+            let image = page.as_bytes().to_vec();
+            wal.log_page(1, &mut page, LogBody::PageImage { page_id, image })
+                .unwrap();
+            logged_insert(&wal, 1, &mut page, b"late");
+            wal.append(1, LogBody::Commit).unwrap();
+            wal.flush().unwrap();
+        }
+
+        let (pool, wal) = open(dir.path());
+        recover(&pool, &wal).unwrap();
+
+        assert_eq!(pool.store().page_count(), 2);
+        assert_eq!(pool.fetch_page(1).unwrap().get_record(0).unwrap(), b"late");
+    }
+
+    #[test]
+    fn test_redo_evicts_through_a_small_pool() {
+        let dir = tempdir().unwrap();
+
+        let page_ids: Vec<u32> = {
+            let (pool, wal) = open(dir.path());
+            let ids = (0..8u8)
+                .map(|i| {
+                    let mut page = new_logged_page(&pool, &wal, 1);
+                    logged_insert(&wal, 1, &mut page, &[i; 16]);
+                    page.header().page_id
+                })
+                .collect();
+            wal.append(1, LogBody::Commit).unwrap();
+            wal.flush().unwrap();
+            ids
+        };
+
+        // Two frames force redone pages out to disk mid-pass
+        {
+            let (pool, wal) = open_with_frames(dir.path(), 2);
+            recover(&pool, &wal).unwrap();
+        }
+
+        let (pool, wal) = open(dir.path());
+        assert_eq!(recover(&pool, &wal).unwrap().records_redone, 0);
+        for (i, page_id) in page_ids.into_iter().enumerate() {
+            let page = pool.fetch_page(page_id).unwrap();
+            assert_eq!(page.get_record(0).unwrap(), &[i as u8; 16]);
+        }
+    }
+
+    #[test]
+    fn test_undo_uncommitted_changes_on_disk() {
+        let dir = tempdir().unwrap();
+
+        let page_id = {
+            let (pool, wal) = open(dir.path());
+            let mut page = new_logged_page(&pool, &wal, 1);
+            logged_insert(&wal, 1, &mut page, b"keep");
+            wal.append(1, LogBody::Commit).unwrap();
+
+            logged_insert(&wal, 2, &mut page, b"lose");
+            // The buffer pool stole the page before transaction 2 finished
+            write(&pool, &mut page);
+            page.header().page_id
+        };
+
+        let (pool, wal) = open(dir.path());
+        let report = recover(&pool, &wal).unwrap();
+
+        assert_eq!(report.losers, vec![2]);
+        assert_eq!(report.records_undone, 1);
+
+        let page = pool.fetch_page(page_id).unwrap();
+        assert_eq!(page.get_record(0).unwrap(), b"keep");
+        assert!(page.get_record(1).is_none());
+
+        // The rollback was logged as a compensation record and End, before
+        // the checkpoint that ends recovery
+        let tail: Vec<LogBody> = wal
+            .iter_from(0)
+            .map(|r| r.unwrap().body)
+            .filter(|body| !matches!(body, LogBody::Checkpoint { .. }))
+            .collect();
+        assert!(matches!(
+            tail[tail.len() - 2],
+            LogBody::Compensation {
+                undo_next_lsn: 0,
+                ..
+            }
+        ));
+        assert_eq!(tail[tail.len() - 1], LogBody::End);
+    }
+
+    #[test]
+    fn test_recovery_starts_from_last_checkpoint() {
+        let dir = tempdir().unwrap();
+
+        let (page_id, running) = {
+            let (pool, wal) = open(dir.path());
+            let mut page = new_logged_page(&pool, &wal, 1);
+            for _ in 0..20 {
+                logged_insert(&wal, 1, &mut page, b"keep");
+            }
+            wal.append(1, LogBody::Commit).unwrap();
+            let running = logged_insert(&wal, 2, &mut page, b"lose");
+
+            let page_id = page.header().page_id;
+            wal.checkpoint(|| {
+                write(&pool, &mut page);
+                pool.store().sync()
+            })
+            .unwrap();
+            (page_id, running)
+        };
+
+        let (pool, wal) = open(dir.path());
+        let report = recover(&pool, &wal).unwrap();
+
+        // Only the checkpoint is read, yet transaction 2 is still rolled back
+        assert_eq!(report.records_scanned, 1);
+        assert_eq!(report.records_redone, 0);
+        assert_eq!(report.losers, vec![2]);
+        assert_eq!(report.records_undone, 1);
+        assert!(wal.read_record(running).is_ok());
+
+        let page = pool.fetch_page(page_id).unwrap();
+        assert_eq!(page.get_record(19).unwrap(), b"keep");
+        assert!(page.get_record(20).is_none());
+    }
+
+    #[test]
+    fn test_undo_update_restores_before_image() {
+        let dir = tempdir().unwrap();
+
+        let page_id = {
+            let (pool, wal) = open(dir.path());
+            let mut page = new_logged_page(&pool, &wal, 1);
+            logged_insert(&wal, 1, &mut page, b"balance=100");
+            wal.append(1, LogBody::Commit).unwrap();
+
+            page.update_record(0, b"balance=999999").unwrap();
+            let page_id = page.header().page_id;
+            let body = LogBody::Update {
+                page_id,
+                slot: 0,
+                before: b"balance=100".to_vec(),
+                after: b"balance=999999".to_vec(),
+            };
+            wal.log_page(2, &mut page, body).unwrap();
+            write(&pool, &mut page);
+            page_id
+        };
+
+        let (pool, wal) = open(dir.path());
+        recover(&pool, &wal).unwrap();
+
+        let page = pool.fetch_page(page_id).unwrap();
+        assert_eq!(page.get_record(0).unwrap(), b"balance=100");
+    }
+
+    #[test]
+    fn test_recovery_is_idempotent() {
+        let dir = tempdir().unwrap();
+
+        let page_id = {
+            let (pool, wal) = open(dir.path());
+            let mut page = new_logged_page(&pool, &wal, 1);
+            logged_insert(&wal, 1, &mut page, b"committed");
+            wal.append(1, LogBody::Commit).unwrap();
+            logged_insert(&wal, 2, &mut page, b"in flight");
+            wal.flush().unwrap();
+            page.header().page_id
+        };
+
+        {
+            let (pool, wal) = open(dir.path());
+            let report = recover(&pool, &wal).unwrap();
+            assert_eq!(report.losers, vec![2]);
+        }
+
+        let (pool, wal) = open(dir.path());
+        let report = recover(&pool, &wal).unwrap();
+        assert!(report.losers.is_empty());
+        assert_eq!(report.records_redone, 0);
+        assert_eq!(report.records_undone, 0);
+
+        let page = pool.fetch_page(page_id).unwrap();
+        assert_eq!(page.get_record(0).unwrap(), b"committed");
+        assert!(page.get_record(1).is_none());
+    }
+
+    #[test]
+    fn test_crash_during_undo_resumes_from_compensation() {
+        let dir = tempdir().unwrap();
+
+        let page_id = {
+            let (pool, wal) = open(dir.path());
+            let mut page = new_logged_page(&pool, &wal, 1);
+            wal.append(1, LogBody::Commit).unwrap();
+
+            let first = logged_insert(&wal, 2, &mut page, b"first");
+            logged_insert(&wal, 2, &mut page, b"second");
+
+            // A rollback got as far as undoing the second insert
+            wal.append(2, LogBody::Abort).unwrap();
+            let inverse = LogBody::Delete {
+                page_id: page.header().page_id,
+                slot: 1,
+                data: b"second".to_vec(),
+            };
+            page.delete_record(1);
+            let clr = LogBody::Compensation {
+                undo_next_lsn: first,
+                redo: Box::new(inverse),
+            };
+            wal.log_page(2, &mut page, clr).unwrap();
+            write(&pool, &mut page);
+            page.header().page_id
+        };
+
+        let (pool, wal) = open(dir.path());
+        let report = recover(&pool, &wal).unwrap();
+
+        // Only the first insert is left to undo
+        assert_eq!(report.losers, vec![2]);
+        assert_eq!(report.records_undone, 1);
+
+        let page = pool.fetch_page(page_id).unwrap();
+        assert_eq!(page.active_records(), 0);
+    }
+}
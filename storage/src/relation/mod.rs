@@ -0,0 +1,444 @@
+// storage/src/relation/mod.rs
+
+use crate::btree::BTree;
+use crate::buffer::{BufferPool, PageReadGuard, PageWriteGuard};
+use crate::heap::HeapFile;
+use crate::page::{Page, PageType};
+use crate::{Result, StorageError};
+use std::io;
+
+/// Longest relation name, in bytes
+pub const MAX_NAME_LEN: usize = 255;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelationKind {
+    /// Records in a `HeapFile`; the root page is the heap's first page
+    Table = 0,
+    /// A `BTree`; the root page is the tree's root
+    Index = 1,
+}
+
+impl RelationKind {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(RelationKind::Table),
+            1 => Some(RelationKind::Index),
+            _ => None,
+        }
+    }
+}
+
+/// A named table or index stored in the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relation {
+    pub id: u32,
+    pub name: String,
+    pub kind: RelationKind,
+    pub root_page: u32,
+}
+
+impl Relation {
+    const FIXED_SIZE: usize = 9;
+
+    // Directory record: id u32 | kind u8 | root_page u32 | name
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::FIXED_SIZE + self.name.len());
+        bytes.extend_from_slice(&self.id.to_le_bytes());
+        bytes.push(self.kind as u8);
+        bytes.extend_from_slice(&self.root_page.to_le_bytes());
+        bytes.extend_from_slice(self.name.as_bytes());
+        bytes
+    }
+
+    fn from_bytes(page_id: u32, bytes: &[u8]) -> Result<Self> {
+        let corrupt = || {
+            StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Malformed relation entry on page {}", page_id),
+            ))
+        };
+
+        if bytes.len() < Self::FIXED_SIZE {
+            return Err(corrupt());
+        }
+
+        Ok(Self {
+            id: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            kind: RelationKind::from_u8(bytes[4]).ok_or_else(corrupt)?,
+            root_page: u32::from_le_bytes(bytes[5..9].try_into().unwrap()),
+            name: String::from_utf8(bytes[9..].to_vec()).map_err(|_| corrupt())?,
+        })
+    }
+}
+
+struct Entry {
+    relation: Relation,
+    page_id: u32,
+    slot: usize,
+}
+
+/// The catalog of relations hosted in one file.
+///
+/// Entries live in a chain of `Directory` pages starting at the page named
+/// in the file header, and are read into memory by `open`. Creating a
+/// relation allocates its storage before recording it, and dropping one
+/// removes the entry before freeing the storage, so a crash in between
+/// leaks pages rather than leaving an entry that points at freed ones.
+pub struct RelationDirectory {
+    pages: Vec<u32>,
+    entries: Vec<Entry>,
+}
+
+impl RelationDirectory {
+    /// Load the directory, creating it if the file does not have one yet.
+    pub fn open(pool: &BufferPool) -> Result<Self> {
+        if pool.store().directory_page() == 0 {
+            let mut page = pool.new_page(PageType::Directory)?;
+            page.log_image()?;
+            let page_id = page.header().page_id;
+            drop(page);
+            pool.store().set_directory_page(page_id)?;
+        }
+
+        let mut directory = Self {
+            pages: Vec::new(),
+            entries: Vec::new(),
+        };
+
+        let mut page_id = pool.store().directory_page();
+        while page_id != 0 {
+            let page = read_directory_page(pool, page_id)?;
+            for (slot, record) in page.iter_with_slots() {
+                directory.entries.push(Entry {
+                    relation: Relation::from_bytes(page_id, record)?,
+                    page_id,
+                    slot,
+                });
+            }
+            directory.pages.push(page_id);
+            page_id = page.header().next_page;
+        }
+
+        Ok(directory)
+    }
+
+    /// All relations, in no particular order.
+    pub fn relations(&self) -> impl Iterator<Item = &Relation> + '_ {
+        self.entries.iter().map(|entry| &entry.relation)
+    }
+
+    pub fn relation(&self, name: &str) -> Option<&Relation> {
+        self.find(name).map(|index| &self.entries[index].relation)
+    }
+
+    /// Create an empty table or index named `name`.
+    pub fn create_relation(
+        &mut self,
+        pool: &BufferPool,
+        name: &str,
+        kind: RelationKind,
+    ) -> Result<Relation> {
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Relation names must be 1 to {} bytes", MAX_NAME_LEN),
+            )));
+        }
+
+        if self.find(name).is_some() {
+            return Err(StorageError::RelationExists(name.to_string()));
+        }
+
+        let root_page = match kind {
+            RelationKind::Table => HeapFile::create(pool)?.first_page(),
+            RelationKind::Index => BTree::create(pool)?.root_page(),
+        };
+
+        let relation = Relation {
+            id: pool.store().allocate_relation_id()?,
+            name: name.to_string(),
+            kind,
+            root_page,
+        };
+
+        let (page_id, slot) = self.add_entry(pool, &relation.to_bytes())?;
+        self.entries.push(Entry {
+            relation: relation.clone(),
+            page_id,
+            slot,
+        });
+
+        Ok(relation)
+    }
+
+    /// Remove `name` from the directory and free all of its pages.
+    pub fn drop_relation(&mut self, pool: &BufferPool, name: &str) -> Result<()> {
+        let index = self
+            .find(name)
+            .ok_or_else(|| StorageError::RelationNotFound(name.to_string()))?;
+        let entry = &self.entries[index];
+
+        let mut page = write_directory_page(pool, entry.page_id)?;
+        page.delete_record(entry.slot);
+        if page.should_compact() {
+            page.compact();
+        }
+        page.log_image()?;
+        drop(page);
+
+        let relation = self.entries.swap_remove(index).relation;
+        match relation.kind {
+            RelationKind::Table => HeapFile::open_at(pool, relation.root_page)?.destroy(pool),
+            RelationKind::Index => BTree::open(pool, relation.root_page)?.destroy(pool),
+        }
+    }
+
+    pub fn open_table(&self, pool: &BufferPool, name: &str) -> Result<HeapFile> {
+        let relation = self.expect_kind(name, RelationKind::Table)?;
+        HeapFile::open_at(pool, relation.root_page)
+    }
+
+    pub fn open_index(&self, pool: &BufferPool, name: &str) -> Result<BTree> {
+        let relation = self.expect_kind(name, RelationKind::Index)?;
+        BTree::open(pool, relation.root_page)
+    }
+
+    fn find(&self, name: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.relation.name == name)
+    }
+
+    fn expect_kind(&self, name: &str, kind: RelationKind) -> Result<&Relation> {
+        let relation = self
+            .relation(name)
+            .ok_or_else(|| StorageError::RelationNotFound(name.to_string()))?;
+
+        if relation.kind != kind {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Relation '{}' is a {:?}, not a {:?}",
+                    name, relation.kind, kind
+                ),
+            )));
+        }
+
+        Ok(relation)
+    }
+
+    /// Store an entry on the first directory page with room, growing the
+    /// chain if every page is full.
+    fn add_entry(&mut self, pool: &BufferPool, record: &[u8]) -> Result<(u32, usize)> {
+        for &page_id in &self.pages {
+            let mut page = write_directory_page(pool, page_id)?;
+            if let Some(slot) = page.add_record(record) {
+                page.log_image()?;
+                return Ok((page_id, slot));
+            }
+        }
+
+        let mut page = pool.new_page(PageType::Directory)?;
+        let page_id = page.header().page_id;
+        let slot = page
+            .add_record(record)
+            .ok_or(StorageError::PageFull(page_id))?;
+        page.log_image()?;
+        drop(page);
+
+        // Link the new page only once it is logged
+        let last_id = *self.pages.last().expect("directory has a first page");
+        let mut last = write_directory_page(pool, last_id)?;
+        last.header_mut().next_page = page_id;
+        last.log_image()?;
+
+        self.pages.push(page_id);
+        Ok((page_id, slot))
+    }
+}
+
+fn read_directory_page(pool: &BufferPool, page_id: u32) -> Result<PageReadGuard<'_>> {
+    let page = pool.fetch_page(page_id)?;
+    check_directory_page(&page)?;
+    Ok(page)
+}
+
+fn write_directory_page(pool: &BufferPool, page_id: u32) -> Result<PageWriteGuard<'_>> {
+    let page = pool.fetch_page_mut(page_id)?;
+    check_directory_page(&page)?;
+    Ok(page)
+}
+
+fn check_directory_page(page: &Page) -> Result<()> {
+    if page.header().page_type != PageType::Directory {
+        return Err(StorageError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Page {} is not a relation directory page",
+                page.header().page_id
+            ),
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::PageFile;
+    use tempfile::{tempdir, TempDir};
+
+    fn setup() -> (TempDir, BufferPool, RelationDirectory) {
+        let dir = tempdir().unwrap();
+        let pool = BufferPool::new(
+            PageFile::create_new(&dir.path().join("test.jdb")).unwrap(),
+            64,
+        );
+        let directory = RelationDirectory::open(&pool).unwrap();
+        (dir, pool, directory)
+    }
+
+    fn names(directory: &RelationDirectory) -> Vec<String> {
+        let mut names: Vec<_> = directory.relations().map(|r| r.name.clone()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_create_and_list() {
+        let (_dir, pool, mut directory) = setup();
+
+        let users = directory
+            .create_relation(&pool, "users", RelationKind::Table)
+            .unwrap();
+        let by_email = directory
+            .create_relation(&pool, "users_by_email", RelationKind::Index)
+            .unwrap();
+
+        assert_ne!(users.id, by_email.id);
+        assert_ne!(users.root_page, by_email.root_page);
+        assert_eq!(names(&directory), ["users", "users_by_email"]);
+        assert_eq!(directory.relation("users"), Some(&users));
+        assert_eq!(directory.relation("orders"), None);
+    }
+
+    #[test]
+    fn test_duplicate_and_invalid_names() {
+        let (_dir, pool, mut directory) = setup();
+        directory
+            .create_relation(&pool, "t", RelationKind::Table)
+            .unwrap();
+
+        assert!(matches!(
+            directory.create_relation(&pool, "t", RelationKind::Index),
+            Err(StorageError::RelationExists(name)) if name == "t"
+        ));
+        assert!(directory
+            .create_relation(&pool, "", RelationKind::Table)
+            .is_err());
+        let long = "x".repeat(MAX_NAME_LEN + 1);
+        assert!(directory
+            .create_relation(&pool, &long, RelationKind::Table)
+            .is_err());
+    }
+
+    #[test]
+    fn test_relations_hold_independent_data() {
+        let (_dir, pool, mut directory) = setup();
+        directory
+            .create_relation(&pool, "a", RelationKind::Table)
+            .unwrap();
+        directory
+            .create_relation(&pool, "b", RelationKind::Table)
+            .unwrap();
+        directory
+            .create_relation(&pool, "a_idx", RelationKind::Index)
+            .unwrap();
+
+        let mut a = directory.open_table(&pool, "a").unwrap();
+        let mut b = directory.open_table(&pool, "b").unwrap();
+        let index = directory.open_index(&pool, "a_idx").unwrap();
+        for n in 0..100u32 {
+            let rid = a.insert(&pool, format!("a{}", n).as_bytes()).unwrap();
+            b.insert(&pool, format!("b{}", n).as_bytes()).unwrap();
+            index
+                .insert(&pool, None, &n.to_be_bytes(), &rid.to_bytes())
+                .unwrap();
+        }
+
+        assert_eq!(a.scan(&pool).count(), 100);
+        assert!(b.scan(&pool).all(|r| r.unwrap().1.starts_with(b"b")));
+        assert_eq!(index.iter(&pool).unwrap().count(), 100);
+
+        // Kinds are checked when opening
+        assert!(directory.open_index(&pool, "a").is_err());
+        assert!(directory.open_table(&pool, "a_idx").is_err());
+    }
+
+    #[test]
+    fn test_drop_relation_frees_pages() {
+        let (_dir, pool, mut directory) = setup();
+
+        let fill = |directory: &RelationDirectory, pool: &BufferPool| {
+            let mut heap = directory.open_table(pool, "t").unwrap();
+            for n in 0..200 {
+                heap.insert(pool, &vec![n as u8; 300]).unwrap();
+            }
+        };
+
+        directory
+            .create_relation(&pool, "t", RelationKind::Table)
+            .unwrap();
+        fill(&directory, &pool);
+        let pages = pool.store().page_count();
+
+        directory.drop_relation(&pool, "t").unwrap();
+        assert_eq!(directory.relation("t"), None);
+        assert!(matches!(
+            directory.drop_relation(&pool, "t"),
+            Err(StorageError::RelationNotFound(_))
+        ));
+
+        // Recreating reuses the freed pages, and the id is not reused
+        let recreated = directory
+            .create_relation(&pool, "t", RelationKind::Table)
+            .unwrap();
+        fill(&directory, &pool);
+        assert_eq!(pool.store().page_count(), pages);
+        assert_eq!(recreated.id, 2);
+    }
+
+    #[test]
+    fn test_directory_spans_pages_and_survives_reopen() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+
+        let expected: Vec<String> = (0..100)
+            .map(|n| format!("{:03}_{}", n, "r".repeat(200)))
+            .collect();
+
+        {
+            let pool = BufferPool::new(PageFile::create_new(&path).unwrap(), 64);
+            let mut directory = RelationDirectory::open(&pool).unwrap();
+            for name in &expected {
+                directory
+                    .create_relation(&pool, name, RelationKind::Index)
+                    .unwrap();
+            }
+            assert!(directory.pages.len() > 1);
+
+            directory.drop_relation(&pool, &expected[0]).unwrap();
+            pool.flush_all().unwrap();
+        }
+
+        let pool = BufferPool::new(PageFile::open(&path).unwrap(), 64);
+        let mut directory = RelationDirectory::open(&pool).unwrap();
+        assert_eq!(names(&directory), &expected[1..]);
+
+        let next = directory
+            .create_relation(&pool, "after_reopen", RelationKind::Table)
+            .unwrap();
+        assert_eq!(next.id, 101);
+    }
+}
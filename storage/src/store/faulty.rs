@@ -0,0 +1,370 @@
+// storage/src/store/faulty.rs
+
+use super::PageStore;
+use crate::page::Page;
+use crate::wal::{Lsn, TxnId};
+use crate::{Result, StorageError};
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+
+/// Operations a `FaultyStore` can be told to fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FaultOp {
+    Read,
+    Write,
+    Allocate,
+    Free,
+    Sync,
+}
+
+/// Wraps a `PageStore` to simulate what a disk does when things go wrong.
+///
+/// Page writes are held back until `sync`, the way a volatile write cache
+/// holds them, so `crash` can throw away everything written since the last
+/// sync and `crash_tearing` can leave chosen pages half-written. Torn pages
+/// read back exactly as they were left until they are rewritten. Any
+/// operation can also be made to fail with an I/O error via `fail_next`.
+///
+/// Allocating and freeing pages, and the header fields, go straight to the
+/// inner store, so only the contents of pages are subject to loss.
+pub struct FaultyStore<S> {
+    inner: S,
+    state: Mutex<FaultState>,
+}
+
+#[derive(Default)]
+struct FaultState {
+    /// Latest image of every page written since the last sync
+    unsynced: BTreeMap<u32, Box<[u8]>>,
+    /// Pages a crash left half-written
+    torn: HashMap<u32, Box<[u8]>>,
+    /// How many more times each operation should fail
+    failures: HashMap<FaultOp, usize>,
+}
+
+impl<S: PageStore> FaultyStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            state: Mutex::new(FaultState::default()),
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// The inner store, without any writes still waiting for a sync.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Make the next `count` calls of `op` fail with an I/O error.
+    pub fn fail_next(&self, op: FaultOp, count: usize) {
+        self.state.lock().failures.insert(op, count);
+    }
+
+    /// Lose power: every page write since the last sync is gone.
+    pub fn crash(&self) {
+        self.state.lock().unsynced.clear();
+    }
+
+    /// Lose power midway through writing `pages`: each of them that has an
+    /// unsynced write keeps the first half of the new image and the second
+    /// half of the old one. Other unsynced writes are lost as with `crash`.
+    pub fn crash_tearing(&self, pages: &[u32]) {
+        let mut state = self.state.lock();
+        let unsynced = std::mem::take(&mut state.unsynced);
+
+        for &page_id in pages {
+            let Some(new) = unsynced.get(&page_id) else {
+                continue;
+            };
+
+            let mut torn = match state.torn.remove(&page_id) {
+                Some(old) => old,
+                None => match self.inner.read_page(page_id) {
+                    Ok(old) => old.as_bytes().into(),
+                    Err(_) => vec![0; new.len()].into_boxed_slice(),
+                },
+            };
+            let half = new.len() / 2;
+            torn[..half].copy_from_slice(&new[..half]);
+            state.torn.insert(page_id, torn);
+        }
+    }
+
+    fn check(&self, op: FaultOp) -> Result<()> {
+        let mut state = self.state.lock();
+        match state.failures.get_mut(&op) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                Err(StorageError::Io(io::Error::other(format!(
+                    "Injected {:?} failure",
+                    op
+                ))))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn forget(&self, page_id: u32) {
+        let mut state = self.state.lock();
+        state.unsynced.remove(&page_id);
+        state.torn.remove(&page_id);
+    }
+}
+
+impl<S: PageStore> PageStore for FaultyStore<S> {
+    fn read_page(&self, page_id: u32) -> Result<Box<Page>> {
+        self.check(FaultOp::Read)?;
+
+        let state = self.state.lock();
+        if let Some(bytes) = state.torn.get(&page_id) {
+            let page = Page::from_bytes(bytes)?;
+            if self.inner.checksums_enabled() && !page.verify_checksum() {
+                return Err(StorageError::ChecksumMismatch(page_id));
+            }
+            return Ok(page);
+        }
+        if let Some(bytes) = state.unsynced.get(&page_id) {
+            return Page::from_bytes(bytes);
+        }
+        drop(state);
+
+        self.inner.read_page(page_id)
+    }
+
+    fn write_page(&self, page: &Page) -> Result<()> {
+        self.check(FaultOp::Write)?;
+
+        let page_id = page.header().page_id;
+        if page_id == 0 {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Page 0 is reserved",
+            )));
+        }
+
+        if page.size() != self.inner.page_size() {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Page is {} bytes but the store uses {} byte pages",
+                    page.size(),
+                    self.inner.page_size()
+                ),
+            )));
+        }
+
+        // Stamp now, as the inner store would, so a tear is detectable
+        let mut stamped = Page::from_bytes(page.as_bytes())?;
+        if self.inner.checksums_enabled() {
+            stamped.update_checksum();
+        }
+
+        let mut state = self.state.lock();
+        state.torn.remove(&page_id);
+        state.unsynced.insert(page_id, stamped.as_bytes().into());
+        Ok(())
+    }
+
+    fn allocate_page(&self) -> Result<u32> {
+        self.check(FaultOp::Allocate)?;
+
+        let page_id = self.inner.allocate_page()?;
+        self.forget(page_id);
+        Ok(page_id)
+    }
+
+    fn free_page(&self, page_id: u32) -> Result<()> {
+        self.check(FaultOp::Free)?;
+
+        self.inner.free_page(page_id)?;
+        self.forget(page_id);
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.check(FaultOp::Sync)?;
+
+        let mut state = self.state.lock();
+        while let Some((&page_id, bytes)) = state.unsynced.first_key_value() {
+            self.inner.write_page(&Page::from_bytes(bytes)?)?;
+            state.unsynced.remove(&page_id);
+        }
+        drop(state);
+
+        self.inner.sync()
+    }
+
+    fn page_count(&self) -> u32 {
+        let state = self.state.lock();
+        let written = state
+            .unsynced
+            .keys()
+            .chain(state.torn.keys())
+            .map(|&page_id| page_id + 1)
+            .max()
+            .unwrap_or(0);
+        self.inner.page_count().max(written)
+    }
+
+    fn page_size(&self) -> usize {
+        self.inner.page_size()
+    }
+
+    fn checksums_enabled(&self) -> bool {
+        self.inner.checksums_enabled()
+    }
+
+    fn version(&self) -> u32 {
+        self.inner.version()
+    }
+
+    fn data_pages(&self) -> (u32, u32) {
+        self.inner.data_pages()
+    }
+
+    fn set_data_pages(&self, first: u32, last: u32) -> Result<()> {
+        self.inner.set_data_pages(first, last)
+    }
+
+    fn directory_page(&self) -> u32 {
+        self.inner.directory_page()
+    }
+
+    fn set_directory_page(&self, page_id: u32) -> Result<()> {
+        self.inner.set_directory_page(page_id)
+    }
+
+    fn allocate_relation_id(&self) -> Result<u32> {
+        self.inner.allocate_relation_id()
+    }
+
+    fn next_txn_id(&self) -> TxnId {
+        self.inner.next_txn_id()
+    }
+
+    fn set_next_txn_id(&self, txn_id: TxnId) -> Result<()> {
+        self.inner.set_next_txn_id(txn_id)
+    }
+
+    fn commit_log_page(&self) -> u32 {
+        self.inner.commit_log_page()
+    }
+
+    fn set_commit_log_page(&self, page_id: u32) -> Result<()> {
+        self.inner.set_commit_log_page(page_id)
+    }
+
+    fn redo_header(&self, image: &[u8], lsn: Lsn) -> Result<bool> {
+        self.inner.redo_header(image, lsn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page::PageType;
+    use crate::store::MemoryStore;
+
+    fn record_page(page_id: u32, record: &[u8]) -> Box<Page> {
+        let mut page = Page::new(page_id, PageType::Data);
+        page.add_record(record).unwrap();
+        page
+    }
+
+    fn record(store: &dyn PageStore, page_id: u32) -> Vec<u8> {
+        store
+            .read_page(page_id)
+            .unwrap()
+            .get_record(0)
+            .unwrap()
+            .to_vec()
+    }
+
+    #[test]
+    fn test_crash_drops_unsynced_writes() {
+        let store = FaultyStore::new(MemoryStore::new());
+        let page_id = store.allocate_page().unwrap();
+
+        store.write_page(&record_page(page_id, b"synced")).unwrap();
+        store.sync().unwrap();
+        store
+            .write_page(&record_page(page_id, b"unsynced"))
+            .unwrap();
+        assert_eq!(record(&store, page_id), b"unsynced");
+
+        store.crash();
+        assert_eq!(record(&store, page_id), b"synced");
+        assert_eq!(record(&store.into_inner(), page_id), b"synced");
+    }
+
+    #[test]
+    fn test_crash_tearing_leaves_detectable_pages() {
+        let store = FaultyStore::new(MemoryStore::new());
+        let torn = store.allocate_page().unwrap();
+        let lost = store.allocate_page().unwrap();
+        store.write_page(&record_page(torn, b"old")).unwrap();
+        store.sync().unwrap();
+
+        // Records are placed at the end of the page, in the half that tears
+        store.write_page(&record_page(torn, b"new")).unwrap();
+        store.write_page(&record_page(lost, b"lost")).unwrap();
+        store.crash_tearing(&[torn, 99]);
+
+        assert!(matches!(
+            store.read_page(torn),
+            Err(StorageError::ChecksumMismatch(id)) if id == torn
+        ));
+        assert_eq!(
+            store.read_page(lost).unwrap().header().page_type,
+            PageType::Free
+        );
+
+        // Rewriting the page repairs it
+        store.write_page(&record_page(torn, b"fixed")).unwrap();
+        store.sync().unwrap();
+        assert_eq!(record(&store, torn), b"fixed");
+        assert_eq!(record(store.inner(), torn), b"fixed");
+    }
+
+    #[test]
+    fn test_injected_failures() {
+        let store = FaultyStore::new(MemoryStore::new());
+        let page_id = store.allocate_page().unwrap();
+
+        store.fail_next(FaultOp::Write, 2);
+        assert!(store.write_page(&record_page(page_id, b"a")).is_err());
+        assert!(store.write_page(&record_page(page_id, b"a")).is_err());
+        store.write_page(&record_page(page_id, b"a")).unwrap();
+
+        store.fail_next(FaultOp::Sync, 1);
+        assert!(matches!(store.sync(), Err(StorageError::Io(_))));
+
+        // The failed sync lost nothing
+        store.sync().unwrap();
+        store.crash();
+        assert_eq!(record(&store, page_id), b"a");
+
+        store.fail_next(FaultOp::Read, 1);
+        assert!(store.read_page(page_id).is_err());
+        store.fail_next(FaultOp::Allocate, 1);
+        assert!(store.allocate_page().is_err());
+        store.fail_next(FaultOp::Free, 1);
+        assert!(store.free_page(page_id).is_err());
+        assert_eq!(record(&store, page_id), b"a");
+    }
+
+    #[test]
+    fn test_page_count_covers_unsynced_writes() {
+        let store = FaultyStore::new(MemoryStore::new());
+        store.write_page(&record_page(3, b"ahead")).unwrap();
+        assert_eq!(store.page_count(), 4);
+
+        store.crash();
+        assert_eq!(store.page_count(), 1);
+    }
+}
@@ -0,0 +1,527 @@
+// storage/src/store/mod.rs
+
+//! Where pages live. `PageStore` is the small interface the buffer pool,
+//! and everything built on it, needs from its backing storage: pages, and
+//! the few header fields saying where the heap, relation directory and
+//! commit log start. `PageFile` is the real implementation, `MemoryStore`
+//! keeps everything in memory for fast tests, and `FaultyStore` wraps
+//! either to simulate crashes and I/O errors.
+
+mod faulty;
+
+pub use faulty::{FaultOp, FaultyStore};
+
+use crate::file::{PageFile, FILE_VERSION};
+use crate::page::{self, Page, PageType, PAGE_SIZE};
+use crate::wal::{Lsn, TxnId};
+use crate::{Result, StorageError};
+use parking_lot::Mutex;
+use std::io;
+use std::sync::Arc;
+
+/// A numbered collection of pages. Page 0 is reserved, as it holds the
+/// header of a `PageFile`.
+pub trait PageStore: Send + Sync {
+    fn read_page(&self, page_id: u32) -> Result<Box<Page>>;
+
+    /// Store a page at the id in its header, growing the store if needed.
+    fn write_page(&self, page: &Page) -> Result<()>;
+
+    /// Hand out a page id holding a clean free page.
+    fn allocate_page(&self) -> Result<u32>;
+
+    /// Return a page so a later `allocate_page` can reuse it.
+    fn free_page(&self, page_id: u32) -> Result<()>;
+
+    /// Make every completed write durable.
+    fn sync(&self) -> Result<()>;
+
+    /// Number of pages, including the reserved page 0.
+    fn page_count(&self) -> u32;
+
+    /// Size of every page in the store; pages of any other size are
+    /// rejected by `write_page`.
+    fn page_size(&self) -> usize;
+
+    /// Whether written pages carry checksums that reads verify.
+    fn checksums_enabled(&self) -> bool;
+
+    /// Format version the stored records are laid out in.
+    fn version(&self) -> u32 {
+        FILE_VERSION
+    }
+
+    /// First and last page of the default heap's chain (0 = none).
+    fn data_pages(&self) -> (u32, u32);
+
+    fn set_data_pages(&self, first: u32, last: u32) -> Result<()>;
+
+    /// First page of the relation directory (0 = none yet).
+    fn directory_page(&self) -> u32;
+
+    fn set_directory_page(&self, page_id: u32) -> Result<()>;
+
+    /// Hand out a relation id; ids are never reused within a store.
+    fn allocate_relation_id(&self) -> Result<u32>;
+
+    /// No transaction id from this one on has been handed out.
+    fn next_txn_id(&self) -> TxnId;
+
+    fn set_next_txn_id(&self, txn_id: TxnId) -> Result<()>;
+
+    /// First page of the commit log (0 = none yet).
+    fn commit_log_page(&self) -> u32;
+
+    fn set_commit_log_page(&self, page_id: u32) -> Result<()>;
+
+    /// Replay a header image logged at `lsn` unless the stored header is
+    /// already as new, returning whether it was replayed. Stores that do
+    /// not log their header have nothing to replay.
+    fn redo_header(&self, _image: &[u8], _lsn: Lsn) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+impl PageStore for PageFile {
+    fn read_page(&self, page_id: u32) -> Result<Box<Page>> {
+        PageFile::read_page(self, page_id)
+    }
+
+    fn write_page(&self, page: &Page) -> Result<()> {
+        PageFile::write_page(self, page)
+    }
+
+    fn allocate_page(&self) -> Result<u32> {
+        PageFile::allocate_page(self)
+    }
+
+    fn free_page(&self, page_id: u32) -> Result<()> {
+        PageFile::free_page(self, page_id)
+    }
+
+    fn sync(&self) -> Result<()> {
+        PageFile::sync(self)
+    }
+
+    fn page_count(&self) -> u32 {
+        PageFile::page_count(self)
+    }
+
+    fn page_size(&self) -> usize {
+        PageFile::page_size(self)
+    }
+
+    fn checksums_enabled(&self) -> bool {
+        PageFile::checksums_enabled(self)
+    }
+
+    fn version(&self) -> u32 {
+        PageFile::version(self)
+    }
+
+    fn data_pages(&self) -> (u32, u32) {
+        PageFile::data_pages(self)
+    }
+
+    fn set_data_pages(&self, first: u32, last: u32) -> Result<()> {
+        PageFile::set_data_pages(self, first, last)
+    }
+
+    fn directory_page(&self) -> u32 {
+        PageFile::directory_page(self)
+    }
+
+    fn set_directory_page(&self, page_id: u32) -> Result<()> {
+        PageFile::set_directory_page(self, page_id)
+    }
+
+    fn allocate_relation_id(&self) -> Result<u32> {
+        PageFile::allocate_relation_id(self)
+    }
+
+    fn next_txn_id(&self) -> TxnId {
+        PageFile::next_txn_id(self)
+    }
+
+    fn set_next_txn_id(&self, txn_id: TxnId) -> Result<()> {
+        PageFile::set_next_txn_id(self, txn_id)
+    }
+
+    fn commit_log_page(&self) -> u32 {
+        PageFile::commit_log_page(self)
+    }
+
+    fn set_commit_log_page(&self, page_id: u32) -> Result<()> {
+        PageFile::set_commit_log_page(self, page_id)
+    }
+
+    fn redo_header(&self, image: &[u8], lsn: Lsn) -> Result<bool> {
+        PageFile::redo_header(self, image, lsn)
+    }
+}
+
+/// Lets a test keep a handle on a store it has given to a buffer pool.
+impl<S: PageStore + ?Sized> PageStore for Arc<S> {
+    fn read_page(&self, page_id: u32) -> Result<Box<Page>> {
+        (**self).read_page(page_id)
+    }
+
+    fn write_page(&self, page: &Page) -> Result<()> {
+        (**self).write_page(page)
+    }
+
+    fn allocate_page(&self) -> Result<u32> {
+        (**self).allocate_page()
+    }
+
+    fn free_page(&self, page_id: u32) -> Result<()> {
+        (**self).free_page(page_id)
+    }
+
+    fn sync(&self) -> Result<()> {
+        (**self).sync()
+    }
+
+    fn page_count(&self) -> u32 {
+        (**self).page_count()
+    }
+
+    fn page_size(&self) -> usize {
+        (**self).page_size()
+    }
+
+    fn checksums_enabled(&self) -> bool {
+        (**self).checksums_enabled()
+    }
+
+    fn version(&self) -> u32 {
+        (**self).version()
+    }
+
+    fn data_pages(&self) -> (u32, u32) {
+        (**self).data_pages()
+    }
+
+    fn set_data_pages(&self, first: u32, last: u32) -> Result<()> {
+        (**self).set_data_pages(first, last)
+    }
+
+    fn directory_page(&self) -> u32 {
+        (**self).directory_page()
+    }
+
+    fn set_directory_page(&self, page_id: u32) -> Result<()> {
+        (**self).set_directory_page(page_id)
+    }
+
+    fn allocate_relation_id(&self) -> Result<u32> {
+        (**self).allocate_relation_id()
+    }
+
+    fn next_txn_id(&self) -> TxnId {
+        (**self).next_txn_id()
+    }
+
+    fn set_next_txn_id(&self, txn_id: TxnId) -> Result<()> {
+        (**self).set_next_txn_id(txn_id)
+    }
+
+    fn commit_log_page(&self) -> u32 {
+        (**self).commit_log_page()
+    }
+
+    fn set_commit_log_page(&self, page_id: u32) -> Result<()> {
+        (**self).set_commit_log_page(page_id)
+    }
+
+    fn redo_header(&self, image: &[u8], lsn: Lsn) -> Result<bool> {
+        (**self).redo_header(image, lsn)
+    }
+}
+
+/// A `PageStore` held entirely in memory. It follows the same rules as
+/// `PageFile` with checksums on, so code tested against it behaves the
+/// same on disk; `sync` has nothing to do, and header fields take effect
+/// as soon as they are set.
+pub struct MemoryStore {
+    page_size: usize,
+    state: Mutex<MemoryState>,
+}
+
+struct MemoryState {
+    /// Indexed by page id; entry 0 stands in for the file header
+    pages: Vec<Box<[u8]>>,
+    /// Freed pages, reused most recent first
+    free: Vec<u32>,
+    // What a `PageFile` keeps in its header
+    data_pages: (u32, u32),
+    directory_page: u32,
+    next_relation_id: u32,
+    next_txn_id: TxnId,
+    commit_log_page: u32,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::with_page_size(PAGE_SIZE)
+    }
+
+    /// A store of `page_size` byte pages, which must be a supported size
+    /// (see `page::is_valid_page_size`).
+    pub fn with_page_size(page_size: usize) -> Self {
+        assert!(
+            page::is_valid_page_size(page_size),
+            "Unsupported page size: {}",
+            page_size
+        );
+
+        Self {
+            page_size,
+            state: Mutex::new(MemoryState {
+                pages: vec![vec![0; page_size].into_boxed_slice()],
+                free: Vec::new(),
+                data_pages: (0, 0),
+                directory_page: 0,
+                next_relation_id: 1,
+                next_txn_id: 0,
+                commit_log_page: 0,
+            }),
+        }
+    }
+
+    fn check_page_id(state: &MemoryState, page_id: u32) -> Result<()> {
+        if page_id == 0 {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Page 0 is reserved",
+            )));
+        }
+
+        if page_id as usize >= state.pages.len() {
+            return Err(StorageError::PageNotFound(page_id));
+        }
+
+        Ok(())
+    }
+
+    fn store(&self, state: &mut MemoryState, page: &Page) -> Result<()> {
+        if page.size() != self.page_size {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Page is {} bytes but the store uses {} byte pages",
+                    page.size(),
+                    self.page_size
+                ),
+            )));
+        }
+
+        let mut stamped = Page::from_bytes(page.as_bytes())?;
+        stamped.update_checksum();
+
+        let page_id = page.header().page_id as usize;
+        if page_id >= state.pages.len() {
+            state
+                .pages
+                .resize_with(page_id + 1, || vec![0; self.page_size].into_boxed_slice());
+        }
+        state.pages[page_id].copy_from_slice(stamped.as_bytes());
+        Ok(())
+    }
+
+    fn free_page_image(&self, page_id: u32) -> Box<Page> {
+        Page::with_size(self.page_size, page_id, PageType::Free)
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PageStore for MemoryStore {
+    fn read_page(&self, page_id: u32) -> Result<Box<Page>> {
+        let state = self.state.lock();
+        Self::check_page_id(&state, page_id)?;
+
+        let page = Page::from_bytes(&state.pages[page_id as usize])?;
+        if !page.verify_checksum() {
+            return Err(StorageError::ChecksumMismatch(page_id));
+        }
+        Ok(page)
+    }
+
+    fn write_page(&self, page: &Page) -> Result<()> {
+        if page.header().page_id == 0 {
+            return Err(StorageError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Page 0 is reserved",
+            )));
+        }
+
+        self.store(&mut self.state.lock(), page)
+    }
+
+    fn allocate_page(&self) -> Result<u32> {
+        let mut state = self.state.lock();
+        let page_id = match state.free.pop() {
+            Some(page_id) => page_id,
+            None => state.pages.len() as u32,
+        };
+
+        self.store(&mut state, &self.free_page_image(page_id))?;
+        Ok(page_id)
+    }
+
+    fn free_page(&self, page_id: u32) -> Result<()> {
+        let mut state = self.state.lock();
+        Self::check_page_id(&state, page_id)?;
+
+        self.store(&mut state, &self.free_page_image(page_id))?;
+        state.free.push(page_id);
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn page_count(&self) -> u32 {
+        self.state.lock().pages.len() as u32
+    }
+
+    fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    fn checksums_enabled(&self) -> bool {
+        true
+    }
+
+    fn data_pages(&self) -> (u32, u32) {
+        self.state.lock().data_pages
+    }
+
+    fn set_data_pages(&self, first: u32, last: u32) -> Result<()> {
+        self.state.lock().data_pages = (first, last);
+        Ok(())
+    }
+
+    fn directory_page(&self) -> u32 {
+        self.state.lock().directory_page
+    }
+
+    fn set_directory_page(&self, page_id: u32) -> Result<()> {
+        self.state.lock().directory_page = page_id;
+        Ok(())
+    }
+
+    fn allocate_relation_id(&self) -> Result<u32> {
+        let mut state = self.state.lock();
+        let id = state.next_relation_id;
+        state.next_relation_id += 1;
+        Ok(id)
+    }
+
+    fn next_txn_id(&self) -> TxnId {
+        self.state.lock().next_txn_id
+    }
+
+    fn set_next_txn_id(&self, txn_id: TxnId) -> Result<()> {
+        self.state.lock().next_txn_id = txn_id;
+        Ok(())
+    }
+
+    fn commit_log_page(&self) -> u32 {
+        self.state.lock().commit_log_page
+    }
+
+    fn set_commit_log_page(&self, page_id: u32) -> Result<()> {
+        self.state.lock().commit_log_page = page_id;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::FileOptions;
+    use tempfile::tempdir;
+
+    /// Behaviour every store must share, run against each implementation.
+    fn exercise(store: &dyn PageStore) {
+        assert_eq!(store.page_count(), 1);
+        assert!(store.read_page(0).is_err());
+        assert!(matches!(
+            store.read_page(1),
+            Err(StorageError::PageNotFound(1))
+        ));
+
+        let first = store.allocate_page().unwrap();
+        let second = store.allocate_page().unwrap();
+        assert_eq!((first, second), (1, 2));
+        assert_eq!(store.page_count(), 3);
+        assert_eq!(
+            store.read_page(first).unwrap().header().page_type,
+            PageType::Free
+        );
+
+        let mut page = Page::with_size(store.page_size(), second, PageType::Data);
+        page.add_record(b"stored").unwrap();
+        store.write_page(&page).unwrap();
+        store.sync().unwrap();
+        let read = store.read_page(second).unwrap();
+        assert_eq!(read.get_record(0).unwrap(), b"stored");
+        assert!(read.verify_checksum());
+
+        // A freed page comes back clean
+        store.free_page(second).unwrap();
+        assert_eq!(store.allocate_page().unwrap(), second);
+        assert_eq!(store.read_page(second).unwrap().active_records(), 0);
+
+        assert!(store.free_page(0).is_err());
+        assert!(store
+            .write_page(&Page::with_size(store.page_size(), 0, PageType::Data))
+            .is_err());
+
+        // Pages must match the store's page size
+        let other_size = if store.page_size() == PAGE_SIZE {
+            4096
+        } else {
+            PAGE_SIZE
+        };
+        assert!(store
+            .write_page(&Page::with_size(other_size, second, PageType::Data))
+            .is_err());
+    }
+
+    #[test]
+    fn test_memory_store() {
+        exercise(&MemoryStore::new());
+        exercise(&MemoryStore::with_page_size(32768));
+    }
+
+    #[test]
+    fn test_page_file_store() {
+        let dir = tempdir().unwrap();
+        exercise(&PageFile::create_new(&dir.path().join("test.jdb")).unwrap());
+
+        let options = FileOptions {
+            page_size: 16384,
+            ..FileOptions::default()
+        };
+        exercise(&PageFile::create_new_with(&dir.path().join("big.jdb"), options).unwrap());
+    }
+
+    #[test]
+    fn test_memory_store_grows_on_write() {
+        let store = MemoryStore::new();
+        store.write_page(&Page::new(4, PageType::Data)).unwrap();
+        assert_eq!(store.page_count(), 5);
+        assert_eq!(store.read_page(4).unwrap().header().page_id, 4);
+
+        // The gap was never written
+        assert!(store.read_page(2).is_err());
+    }
+}
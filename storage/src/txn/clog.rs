@@ -0,0 +1,192 @@
+// storage/src/txn/clog.rs
+
+use crate::buffer::{BufferPool, PageReadGuard, PageWriteGuard};
+use crate::mvcc::{SubTxnId, TxnStatus};
+use crate::page::{Page, PageType};
+use crate::wal::{LogBody, TxnId};
+use crate::{Result, StorageError};
+use std::collections::HashMap;
+use std::io;
+use std::ops::RangeInclusive;
+
+/// Bytes in each record of a commit log page
+const CHUNK_SIZE: usize = 64;
+
+/// Two bits per transaction
+const TXNS_PER_BYTE: u64 = 4;
+
+const STATUS_COMMITTED: u8 = 1;
+const STATUS_ABORTED: u8 = 2;
+
+/// How every finished transaction ended, two bits each, in a chain of
+/// `CommitLog` pages starting at the header's `commit_log_page`.
+///
+/// Each page holds fixed-size records covering consecutive ids, so a status
+/// change rewrites one small record. The change is logged under the
+/// transaction it describes and redone by recovery like any other page
+/// operation. A transaction with no status, like one in flight at a crash,
+/// counts as aborted.
+///
+/// Subtransactions rolled back to a savepoint are only remembered in
+/// memory, while their transaction runs: their changes are undone before
+/// it ends, and all of them are undone if it is cut short by a crash.
+pub(crate) struct CommitLog {
+    pages: Vec<u32>,
+    chunks_per_page: usize,
+    /// The records of every page, in id order
+    bits: Vec<u8>,
+    /// Rolled back subtransactions of running transactions
+    aborted_subtxns: HashMap<TxnId, Vec<RangeInclusive<SubTxnId>>>,
+}
+
+impl CommitLog {
+    pub(crate) fn load(pool: &BufferPool) -> Result<Self> {
+        let chunks_per_page =
+            (pool.page_size() - Page::HEADER_SIZE) / (CHUNK_SIZE + Page::SLOT_SIZE);
+        let mut pages = Vec::new();
+        let mut bits = Vec::new();
+
+        let mut page_id = pool.store().commit_log_page();
+        while page_id != 0 {
+            let page = read_log_page(pool, page_id)?;
+            for slot in 0..chunks_per_page {
+                match page.get_record(slot) {
+                    Some(chunk) if chunk.len() == CHUNK_SIZE => bits.extend_from_slice(chunk),
+                    _ => return Err(damaged(page_id)),
+                }
+            }
+            pages.push(page_id);
+            page_id = page.header().next_page;
+        }
+
+        Ok(Self {
+            pages,
+            chunks_per_page,
+            bits,
+            aborted_subtxns: HashMap::new(),
+        })
+    }
+
+    fn txns_per_page(&self) -> u64 {
+        (self.chunks_per_page * CHUNK_SIZE) as u64 * TXNS_PER_BYTE
+    }
+
+    /// How `txn_id` ended, assuming it has.
+    pub(crate) fn status(&self, txn_id: TxnId) -> TxnStatus {
+        let byte = self.bits.get((txn_id / TXNS_PER_BYTE) as usize);
+        let shift = (txn_id % TXNS_PER_BYTE) * 2;
+        match byte.map_or(0, |byte| (byte >> shift) & 0b11) {
+            STATUS_COMMITTED => TxnStatus::Committed,
+            _ => TxnStatus::Aborted,
+        }
+    }
+
+    /// Record that the running `txn_id` rolled back `subtxns`.
+    pub(crate) fn abort_subtxns(&mut self, txn_id: TxnId, subtxns: RangeInclusive<SubTxnId>) {
+        self.aborted_subtxns
+            .entry(txn_id)
+            .or_default()
+            .push(subtxns);
+    }
+
+    pub(crate) fn subtxn_aborted(&self, txn_id: TxnId, subtxn_id: SubTxnId) -> bool {
+        self.aborted_subtxns
+            .get(&txn_id)
+            .is_some_and(|aborted| aborted.iter().any(|range| range.contains(&subtxn_id)))
+    }
+
+    /// Add pages until every id below `end` has room.
+    pub(crate) fn extend(&mut self, pool: &BufferPool, end: TxnId) -> Result<()> {
+        while (self.pages.len() as u64) * self.txns_per_page() < end {
+            let mut page = pool.new_page(PageType::CommitLog)?;
+            let page_id = page.header().page_id;
+            for _ in 0..self.chunks_per_page {
+                page.add_record(&[0; CHUNK_SIZE])
+                    .expect("records are sized to fill the page");
+            }
+            page.log_image()?;
+            drop(page);
+
+            // Linked once logged, so a crash in between only leaks it
+            match self.pages.last() {
+                None => pool.store().set_commit_log_page(page_id)?,
+                Some(&last) => {
+                    let mut last = write_log_page(pool, last)?;
+                    last.header_mut().next_page = page_id;
+                    last.log_image()?;
+                }
+            }
+
+            self.pages.push(page_id);
+            self.bits
+                .resize(self.bits.len() + self.chunks_per_page * CHUNK_SIZE, 0);
+        }
+        Ok(())
+    }
+
+    /// Record how `txn_id` ended, logging the change under `txn_id`.
+    pub(crate) fn set(
+        &mut self,
+        pool: &BufferPool,
+        txn_id: TxnId,
+        status: TxnStatus,
+    ) -> Result<()> {
+        // Whatever its rolled back subtransactions did is undone by now
+        self.aborted_subtxns.remove(&txn_id);
+
+        let code = match status {
+            TxnStatus::InProgress => 0,
+            TxnStatus::Committed => STATUS_COMMITTED,
+            TxnStatus::Aborted => STATUS_ABORTED,
+        };
+
+        let byte = (txn_id / TXNS_PER_BYTE) as usize;
+        let chunk = byte / CHUNK_SIZE;
+        let page_id = *self
+            .pages
+            .get(chunk / self.chunks_per_page)
+            .expect("ids are only handed out once the log covers them");
+        let slot = chunk % self.chunks_per_page;
+        let range = chunk * CHUNK_SIZE..(chunk + 1) * CHUNK_SIZE;
+
+        let before = self.bits[range.clone()].to_vec();
+        let shift = (txn_id % TXNS_PER_BYTE) * 2;
+        self.bits[byte] = (self.bits[byte] & !(0b11 << shift)) | (code << shift);
+        let after = self.bits[range].to_vec();
+
+        let mut page = write_log_page(pool, page_id)?;
+        page.update_record(slot, &after)?;
+        page.log_change(
+            txn_id,
+            LogBody::Update {
+                page_id,
+                slot: slot as u16,
+                before,
+                after,
+            },
+        )
+    }
+}
+
+fn read_log_page(pool: &BufferPool, page_id: u32) -> Result<PageReadGuard<'_>> {
+    let page = pool.fetch_page(page_id)?;
+    if page.header().page_type != PageType::CommitLog {
+        return Err(damaged(page_id));
+    }
+    Ok(page)
+}
+
+fn write_log_page(pool: &BufferPool, page_id: u32) -> Result<PageWriteGuard<'_>> {
+    let page = pool.fetch_page_mut(page_id)?;
+    if page.header().page_type != PageType::CommitLog {
+        return Err(damaged(page_id));
+    }
+    Ok(page)
+}
+
+fn damaged(page_id: u32) -> StorageError {
+    StorageError::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Commit log page {} is damaged", page_id),
+    ))
+}
@@ -0,0 +1,918 @@
+// storage/src/txn/mod.rs
+
+mod clog;
+mod ssi;
+
+use crate::buffer::BufferPool;
+use crate::lock::LockManager;
+use crate::mvcc::{CommitStatus, Snapshot, SubTxnId, TransactionIds, TxnStatus};
+use crate::recovery;
+use crate::wal::{LogBody, Lsn, TxnId, Wal};
+use crate::{Result, StorageError};
+use clog::CommitLog;
+use parking_lot::Mutex;
+use ssi::SsiTracker;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+pub(crate) use ssi::{Serializable, SireadTarget};
+
+/// Transaction ids are reserved in the file header this many at a time
+const TXN_ID_BATCH: TxnId = 1024;
+
+/// Segments of log written since the redo point that make a commit take a
+/// checkpoint
+const CHECKPOINT_SEGMENTS: u64 = 4;
+
+/// Begins, commits and aborts transactions over the pages of a
+/// `BufferPool`.
+///
+/// Changes made through `MvccHeap` and `BTree` for a transaction are
+/// logged with what it takes to undo them. Commit records the outcome in
+/// the commit log and flushes the log up to the `Commit` record; the pages
+/// the transaction wrote are not forced, as the log holds their changes to
+/// redo from. Once enough log has built up since the last checkpoint, the
+/// committing transaction takes another one, so recovery has less to read
+/// and old segments are dropped. Abort undoes the logged changes newest
+/// first. Transactions cut short by a crash are rolled back by recovery,
+/// which must have run on the pool before the manager is created;
+/// `Database` does both.
+///
+/// The manager is also the `CommitStatus` snapshots of the file are judged
+/// against, and holds the `LockManager` its transactions lock through;
+/// their locks are released once they commit or abort.
+pub struct TransactionManager {
+    shared: Arc<Shared>,
+}
+
+/// What the manager keeps, shared with its transactions so one dropped
+/// before it finished can abort itself
+struct Shared {
+    pool: Arc<BufferPool>,
+    wal: Arc<Wal>,
+    ids: TransactionIds,
+    locks: LockManager,
+    ssi: Arc<SsiTracker>,
+    state: Mutex<ManagerState>,
+}
+
+struct ManagerState {
+    clog: CommitLog,
+    /// Ids below this are reserved in the file header
+    reserved: TxnId,
+}
+
+impl TransactionManager {
+    /// Load the commit log of a recovered pool whose changes are logged
+    /// to `wal`.
+    pub fn new(pool: Arc<BufferPool>, wal: Arc<Wal>) -> Result<Self> {
+        let clog = CommitLog::load(&pool)?;
+
+        // Ids reserved before a restart but never used are skipped
+        let next_id = pool.store().next_txn_id().max(1);
+        let shared = Shared {
+            pool,
+            wal,
+            ids: TransactionIds::starting_at(next_id),
+            locks: LockManager::new(),
+            ssi: Arc::default(),
+            state: Mutex::new(ManagerState {
+                clog,
+                reserved: next_id,
+            }),
+        };
+        Ok(Self {
+            shared: Arc::new(shared),
+        })
+    }
+
+    pub fn pool(&self) -> &BufferPool {
+        &self.shared.pool
+    }
+
+    pub fn locks(&self) -> &LockManager {
+        &self.shared.locks
+    }
+
+    pub fn begin(&self) -> Result<Transaction> {
+        self.begin_with(IsolationLevel::default())
+    }
+
+    pub fn begin_with(&self, isolation: IsolationLevel) -> Result<Transaction> {
+        let shared = &self.shared;
+        let mut state = shared.state.lock();
+        let next_id = shared.ids.next_id();
+        if next_id >= state.reserved {
+            // Durable before any id of the batch is used, so none is
+            // handed out twice
+            let reserved = next_id + TXN_ID_BATCH;
+            state.clog.extend(&shared.pool, reserved)?;
+            shared.pool.store().set_next_txn_id(reserved)?;
+            shared.pool.store().sync()?;
+            state.reserved = reserved;
+        }
+        let txn_id = shared.ids.begin();
+        drop(state);
+
+        let ssi = match isolation {
+            IsolationLevel::Snapshot => None,
+            IsolationLevel::Serializable => {
+                shared.ssi.begin(txn_id);
+                Some(Arc::clone(&shared.ssi))
+            }
+        };
+        Ok(Transaction {
+            snapshot: shared.ids.snapshot(txn_id, 0),
+            manager: Arc::clone(shared),
+            last_lsn: AtomicU64::new(0),
+            ssi,
+            savepoints: Vec::new(),
+            last_subtxn: 0,
+            finished: false,
+        })
+    }
+
+    /// Commit `txn`. A serializable transaction that cannot commit without
+    /// breaking serializability is aborted instead, and the error returned.
+    /// So is one whose commit fails to be logged, since the caller has no
+    /// transaction left to abort.
+    pub fn commit(&self, mut txn: Transaction) -> Result<()> {
+        let shared = &self.shared;
+        let txn_id = txn.id();
+        if txn.ssi.is_some() {
+            if let Err(e) = shared.ssi.commit(txn_id) {
+                self.abort(txn)?;
+                return Err(e);
+            }
+        }
+
+        txn.finished = true;
+        if let Err(e) = shared.log_commit(txn_id) {
+            if let Err(abort_error) = shared.abort(txn_id, txn.last_lsn()) {
+                log::warn!(
+                    "Abort of transaction {} after its commit failed: {}",
+                    txn_id,
+                    abort_error
+                );
+            }
+            return Err(e);
+        }
+
+        shared.ids.commit(txn_id);
+        shared.locks.release_all(txn_id);
+
+        // The commit stands either way, so a failed checkpoint is only
+        // retried by a later one
+        let wal = &shared.wal;
+        if wal.next_lsn() - wal.redo_lsn() >= CHECKPOINT_SEGMENTS * wal.segment_size() {
+            if let Err(e) = wal.checkpoint(|| shared.pool.flush_all()) {
+                log::warn!("Checkpoint after commit of {} failed: {}", txn_id, e);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn abort(&self, mut txn: Transaction) -> Result<()> {
+        txn.finished = true;
+        self.shared.abort(txn.id(), txn.last_lsn())
+    }
+
+    /// Undo every change `txn` made since the savepoint `name` was taken,
+    /// and forget the savepoints taken after it. The savepoint itself is
+    /// kept, and `txn` carries on in a new subtransaction. Locks taken
+    /// since are kept too.
+    pub fn rollback_to_savepoint(&self, txn: &mut Transaction, name: &str) -> Result<()> {
+        let position = txn.find_savepoint(name)?;
+        txn.savepoints.truncate(position + 1);
+        let savepoint = &txn.savepoints[position];
+
+        // Every subtransaction started since, released ones included, as
+        // ids only grow. Hidden before being undone, so a rollback cut
+        // short leaves nothing of them visible.
+        let shared = &self.shared;
+        let aborted = savepoint.subtxn..=txn.last_subtxn;
+        shared.state.lock().clog.abort_subtxns(txn.id(), aborted);
+
+        let (last_lsn, stop_lsn) = (txn.last_lsn(), savepoint.lsn);
+        if last_lsn > stop_lsn {
+            let latest =
+                recovery::rollback(&shared.pool, &shared.wal, txn.id(), last_lsn, stop_lsn)?;
+            txn.last_lsn.store(latest, Ordering::Release);
+        }
+
+        txn.enter_subtxn();
+        Ok(())
+    }
+}
+
+impl Shared {
+    /// Record `txn_id` as committed. Durable once the `Commit` record is;
+    /// the pages the transaction wrote are redone from the log after a
+    /// crash.
+    fn log_commit(&self, txn_id: TxnId) -> Result<()> {
+        self.state
+            .lock()
+            .clog
+            .set(&self.pool, txn_id, TxnStatus::Committed)?;
+        let lsn = self.wal.append(txn_id, LogBody::Commit)?;
+        self.wal.flush_to(lsn)
+    }
+
+    /// Undo `txn_id`'s changes from its latest record `last_lsn` and
+    /// record it as aborted. The transaction lets go of its id, locks and
+    /// conflict tracking even if that fails, so it cannot hold up others;
+    /// a rollback cut short is finished by recovery.
+    fn abort(&self, txn_id: TxnId, last_lsn: Lsn) -> Result<()> {
+        let mut result = Ok(());
+        if last_lsn != 0 {
+            result = self.wal.append(txn_id, LogBody::Abort).and_then(|_| {
+                recovery::rollback(&self.pool, &self.wal, txn_id, last_lsn, 0).map(drop)
+            });
+        }
+
+        // Kept in memory even if the page cannot be written
+        let recorded = self
+            .state
+            .lock()
+            .clog
+            .set(&self.pool, txn_id, TxnStatus::Aborted);
+        let result = result
+            .and(recorded)
+            .and_then(|()| self.wal.append(txn_id, LogBody::End).map(drop));
+
+        self.ids.abort(txn_id);
+        self.locks.release_all(txn_id);
+        self.ssi.abort(txn_id);
+        result
+    }
+}
+
+impl CommitStatus for TransactionManager {
+    fn status(&self, txn_id: TxnId) -> TxnStatus {
+        match self.shared.ids.status(txn_id) {
+            TxnStatus::InProgress => TxnStatus::InProgress,
+            _ => self.shared.state.lock().clog.status(txn_id),
+        }
+    }
+
+    fn subtxn_aborted(&self, txn_id: TxnId, subtxn_id: SubTxnId) -> bool {
+        self.shared
+            .state
+            .lock()
+            .clog
+            .subtxn_aborted(txn_id, subtxn_id)
+    }
+}
+
+/// A running transaction, handed out by `TransactionManager::begin`.
+///
+/// Reads go through its snapshot, taken when it began. Changes are only
+/// logged when the transaction itself is passed to `MvccHeap` or `BTree`,
+/// rather than just its snapshot. A transaction dropped without being
+/// committed or aborted is aborted then, and one cut short by a crash is
+/// rolled back by recovery the next time the database is opened.
+///
+/// A serializable transaction's reads are only tracked when it is passed
+/// to `MvccHeap` too, so it should read through it as well.
+pub struct Transaction {
+    snapshot: Snapshot,
+    manager: Arc<Shared>,
+    /// Latest record logged for the transaction
+    last_lsn: AtomicU64,
+    ssi: Option<Arc<SsiTracker>>,
+    /// Oldest first
+    savepoints: Vec<Savepoint>,
+    /// Last subtransaction id handed out
+    last_subtxn: SubTxnId,
+    /// Committed or aborted through the manager
+    finished: bool,
+}
+
+/// A point a transaction can roll back to.
+struct Savepoint {
+    name: String,
+    /// The transaction's latest record when it was taken
+    lsn: Lsn,
+    /// Subtransaction the transaction was in when it was taken
+    parent: SubTxnId,
+    /// Subtransaction the savepoint started
+    subtxn: SubTxnId,
+}
+
+/// How far a transaction is kept apart from concurrent ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IsolationLevel {
+    /// Reads see a snapshot taken at begin and concurrent writes to the
+    /// same row conflict; write skew is possible
+    #[default]
+    Snapshot,
+    /// Snapshot isolation, plus aborts with
+    /// `StorageError::SerializationFailure` wherever the outcome could
+    /// differ from running the serializable transactions one at a time
+    Serializable,
+}
+
+impl Transaction {
+    pub fn id(&self) -> TxnId {
+        self.snapshot.txn_id
+    }
+
+    pub fn snapshot(&self) -> &Snapshot {
+        &self.snapshot
+    }
+
+    pub fn isolation(&self) -> IsolationLevel {
+        match self.ssi {
+            Some(_) => IsolationLevel::Serializable,
+            None => IsolationLevel::Snapshot,
+        }
+    }
+
+    /// Make the transaction's changes so far visible to it.
+    pub fn next_command(&mut self) {
+        self.snapshot.next_command();
+    }
+
+    /// Take a savepoint called `name` and carry on in a new subtransaction,
+    /// whose changes are stamped with its id. A savepoint with the same
+    /// name as an older one hides it until released or rolled back past.
+    pub fn savepoint(&mut self, name: &str) {
+        let parent = self.snapshot.subtxn_id;
+        self.enter_subtxn();
+        self.savepoints.push(Savepoint {
+            name: name.to_string(),
+            lsn: self.last_lsn(),
+            parent,
+            subtxn: self.snapshot.subtxn_id,
+        });
+    }
+
+    /// Forget the savepoint `name` and those taken after it, keeping every
+    /// change made since as part of the subtransaction it was taken in.
+    pub fn release_savepoint(&mut self, name: &str) -> Result<()> {
+        let position = self.find_savepoint(name)?;
+        self.snapshot.subtxn_id = self.savepoints[position].parent;
+        self.savepoints.truncate(position);
+        Ok(())
+    }
+
+    fn find_savepoint(&self, name: &str) -> Result<usize> {
+        self.savepoints
+            .iter()
+            .rposition(|savepoint| savepoint.name == name)
+            .ok_or_else(|| StorageError::SavepointNotFound(name.to_string()))
+    }
+
+    fn enter_subtxn(&mut self) {
+        self.last_subtxn += 1;
+        self.snapshot.subtxn_id = self.last_subtxn;
+    }
+
+    /// Log how to undo a change about to be made for the transaction.
+    pub(crate) fn log_undo(&self, body: LogBody) -> Result<()> {
+        let lsn = self.manager.wal.append(self.id(), body)?;
+        self.last_lsn.store(lsn, Ordering::Release);
+        Ok(())
+    }
+
+    /// Conflict tracking for the transaction, if it is serializable.
+    pub(crate) fn serializable(&self) -> Option<Serializable> {
+        let tracker = Arc::clone(self.ssi.as_ref()?);
+        Some(Serializable {
+            txn_id: self.id(),
+            tracker,
+        })
+    }
+
+    fn last_lsn(&self) -> Lsn {
+        self.last_lsn.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        if let Err(e) = self.manager.abort(self.id(), self.last_lsn()) {
+            log::warn!("Abort of dropped transaction {} failed: {}", self.id(), e);
+        }
+    }
+}
+
+/// Who a read or write through `MvccHeap` is made for: a `Transaction`,
+/// whose changes are logged and whose reads and writes are tracked if it
+/// is serializable, or a bare `Snapshot`, which gets neither.
+#[derive(Clone, Copy)]
+pub struct TxnContext<'a> {
+    snapshot: &'a Snapshot,
+    txn: Option<&'a Transaction>,
+}
+
+impl<'a> TxnContext<'a> {
+    pub fn snapshot(&self) -> &'a Snapshot {
+        self.snapshot
+    }
+
+    pub(crate) fn log_undo(&self, body: LogBody) -> Result<()> {
+        match self.txn {
+            Some(txn) => txn.log_undo(body),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn serializable(&self) -> Option<Serializable> {
+        self.txn?.serializable()
+    }
+}
+
+impl<'a> From<&'a Transaction> for TxnContext<'a> {
+    fn from(txn: &'a Transaction) -> Self {
+        Self {
+            snapshot: &txn.snapshot,
+            txn: Some(txn),
+        }
+    }
+}
+
+impl<'a> From<&'a Snapshot> for TxnContext<'a> {
+    fn from(snapshot: &'a Snapshot) -> Self {
+        Self {
+            snapshot,
+            txn: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::btree::BTree;
+    use crate::database::Database;
+    use crate::file::PageFile;
+    use crate::heap::{HeapFile, RecordId};
+    use crate::mvcc::{MvccHeap, TupleHeader};
+    use crate::page::PageType;
+    use crate::store::{FaultOp, FaultyStore, MemoryStore};
+    use crate::StorageError;
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    fn open(dir: &Path) -> Database {
+        let path = dir.join("test.jdb");
+        if path.exists() {
+            Database::open(&path).unwrap()
+        } else {
+            Database::create(&path).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_commit_survives_reopen() {
+        let dir = tempdir().unwrap();
+        let (rid, txn_id) = {
+            let db = open(dir.path());
+            let (pool, manager) = (db.pool(), db.transactions());
+            let mut heap = MvccHeap::new(HeapFile::open(pool).unwrap());
+
+            let txn = manager.begin().unwrap();
+            let rid = heap.insert(pool, &txn, b"value").unwrap();
+            let txn_id = txn.id();
+            manager.commit(txn).unwrap();
+
+            let reader = manager.begin().unwrap();
+            assert_eq!(
+                heap.get(pool, manager, reader.snapshot(), rid)
+                    .unwrap()
+                    .unwrap(),
+                b"value"
+            );
+            (rid, txn_id)
+        };
+
+        let db = open(dir.path());
+        let (pool, manager) = (db.pool(), db.transactions());
+        assert!(db.recovery_report().losers.is_empty());
+        assert_eq!(manager.status(txn_id), TxnStatus::Committed);
+
+        let heap = MvccHeap::new(HeapFile::open(pool).unwrap());
+        let reader = manager.begin().unwrap();
+        assert!(reader.id() > txn_id);
+        assert_eq!(
+            heap.get(pool, manager, reader.snapshot(), rid)
+                .unwrap()
+                .unwrap(),
+            b"value"
+        );
+    }
+
+    #[test]
+    fn test_commit_redone_from_log() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.jdb");
+        let (rid, txn_id) = {
+            let db = open(dir.path());
+            let (pool, manager) = (db.pool(), db.transactions());
+            let mut heap = MvccHeap::new(HeapFile::open(pool).unwrap());
+            let txn = manager.begin().unwrap();
+            pool.flush_all().unwrap();
+            let before = std::fs::read(&path).unwrap();
+
+            let rid = heap.insert(pool, &txn, b"value").unwrap();
+            let txn_id = txn.id();
+            manager.commit(txn).unwrap();
+
+            // Crash: only the log is known to have reached the disk
+            std::mem::forget(db);
+            std::fs::write(&path, before).unwrap();
+            (rid, txn_id)
+        };
+
+        let db = open(dir.path());
+        let (pool, manager) = (db.pool(), db.transactions());
+        assert!(db.recovery_report().losers.is_empty());
+        assert_eq!(manager.status(txn_id), TxnStatus::Committed);
+
+        let heap = MvccHeap::new(HeapFile::open(pool).unwrap());
+        let reader = manager.begin().unwrap();
+        assert_eq!(
+            heap.get(pool, manager, reader.snapshot(), rid)
+                .unwrap()
+                .unwrap(),
+            b"value"
+        );
+    }
+
+    #[test]
+    fn test_abort_rolls_back() {
+        let dir = tempdir().unwrap();
+        let db = open(dir.path());
+        let (pool, manager) = (db.pool(), db.transactions());
+        let mut heap = MvccHeap::new(HeapFile::open(pool).unwrap());
+        let tree = BTree::create(pool).unwrap();
+
+        let setup = manager.begin().unwrap();
+        let (kept, deleted) = {
+            let kept = heap.insert(pool, &setup, b"kept").unwrap();
+            let deleted = heap.insert(pool, &setup, b"deleted").unwrap();
+            tree.insert(pool, Some(&setup), b"a", b"1").unwrap();
+            tree.insert(pool, Some(&setup), b"b", b"2").unwrap();
+            (kept, deleted)
+        };
+        manager.commit(setup).unwrap();
+
+        let txn = manager.begin().unwrap();
+        let inserted = {
+            let status = manager;
+            let inserted = heap.insert(pool, &txn, b"new").unwrap();
+            heap.update(pool, status, &txn, kept, b"changed")
+                .unwrap()
+                .unwrap();
+            assert!(heap.delete(pool, status, &txn, deleted).unwrap());
+            tree.insert(pool, Some(&txn), b"a", b"changed").unwrap();
+            tree.delete(pool, Some(&txn), b"b").unwrap();
+            tree.insert(pool, Some(&txn), b"c", b"3").unwrap();
+            inserted
+        };
+        let txn_id = txn.id();
+        manager.abort(txn).unwrap();
+        assert_eq!(manager.status(txn_id), TxnStatus::Aborted);
+
+        let reader = manager.begin().unwrap();
+        let get = |pool: &BufferPool, rid| heap.get(pool, manager, reader.snapshot(), rid).unwrap();
+        assert_eq!(get(pool, kept).unwrap(), b"kept");
+        assert_eq!(get(pool, deleted).unwrap(), b"deleted");
+        assert_eq!(get(pool, inserted), None);
+        // Gone from the heap, not just hidden
+        assert_eq!(heap.heap().get(pool, inserted).unwrap(), None);
+
+        assert_eq!(tree.get(pool, b"a").unwrap().unwrap(), b"1");
+        assert_eq!(tree.get(pool, b"b").unwrap().unwrap(), b"2");
+        assert_eq!(tree.get(pool, b"c").unwrap(), None);
+
+        // The rolled back row can be changed again
+        let writer = manager.begin().unwrap();
+        let status = manager;
+        assert!(heap
+            .update(pool, status, &writer, kept, b"again")
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_dropped_transaction_is_aborted() {
+        let dir = tempdir().unwrap();
+        let db = open(dir.path());
+        let (pool, manager) = (db.pool(), db.transactions());
+        let mut heap = MvccHeap::new(HeapFile::open(pool).unwrap());
+
+        let setup = manager.begin().unwrap();
+        let kept = heap.insert(pool, &setup, b"kept").unwrap();
+        manager.commit(setup).unwrap();
+
+        // Left behind, as by an early return on an error
+        let txn_id = {
+            let txn = manager.begin().unwrap();
+            heap.update(pool, manager, &txn, kept, b"changed")
+                .unwrap()
+                .unwrap();
+            txn.id()
+        };
+        assert_eq!(manager.status(txn_id), TxnStatus::Aborted);
+
+        // Its write no longer stands in anyone's way
+        let writer = manager.begin().unwrap();
+        assert!(heap
+            .update(pool, manager, &writer, kept, b"again")
+            .unwrap()
+            .is_some());
+        manager.commit(writer).unwrap();
+    }
+
+    #[test]
+    fn test_failed_commit_is_aborted() {
+        let dir = tempdir().unwrap();
+        let store = Arc::new(FaultyStore::new(MemoryStore::new()));
+        let wal = Arc::new(Wal::open(dir.path()).unwrap());
+        let mut pool = BufferPool::new(Arc::clone(&store), 4);
+        pool.attach_wal(Arc::clone(&wal));
+        let pool = Arc::new(pool);
+        let manager = TransactionManager::new(Arc::clone(&pool), wal).unwrap();
+        let mut heap = MvccHeap::new(HeapFile::open(&pool).unwrap());
+
+        let txn = manager.begin().unwrap();
+        let txn_id = txn.id();
+        let rid = heap.insert(&pool, &txn, b"lost").unwrap();
+
+        // The commit log page is evicted and cannot be read back
+        for _ in 0..pool.capacity() {
+            pool.new_page(PageType::Data).unwrap();
+        }
+        store.fail_next(FaultOp::Read, 1);
+        assert!(manager.commit(txn).is_err());
+        assert_eq!(manager.status(txn_id), TxnStatus::Aborted);
+
+        let reader = manager.begin().unwrap();
+        let status = &manager;
+        assert_eq!(
+            heap.get(&pool, status, reader.snapshot(), rid).unwrap(),
+            None
+        );
+        assert_eq!(heap.heap().get(&pool, rid).unwrap(), None);
+    }
+
+    /// Two on-call doctors; each transaction takes one off call if the
+    /// other is still on.
+    fn write_skew(isolation: IsolationLevel) -> (Result<()>, Result<()>) {
+        let dir = tempdir().unwrap();
+        let db = open(dir.path());
+        let (pool, manager) = (db.pool(), db.transactions());
+        let mut heap = MvccHeap::new(HeapFile::open(pool).unwrap());
+
+        let setup = manager.begin().unwrap();
+        let doctors = {
+            [
+                heap.insert(pool, &setup, b"on").unwrap(),
+                heap.insert(pool, &setup, b"on").unwrap(),
+            ]
+        };
+        manager.commit(setup).unwrap();
+
+        let txns = [
+            manager.begin_with(isolation).unwrap(),
+            manager.begin_with(isolation).unwrap(),
+        ];
+        for (txn, (mine, other)) in txns.iter().zip([(0, 1), (1, 0)]) {
+            let on_call = heap.get(pool, manager, txn, doctors[other]).unwrap();
+            assert_eq!(on_call.unwrap(), b"on");
+            heap.update(pool, manager, txn, doctors[mine], b"off")
+                .unwrap()
+                .unwrap();
+        }
+
+        let [first, second] = txns;
+        let second_id = second.id();
+        let results = (manager.commit(first), manager.commit(second));
+        if results.1.is_err() {
+            assert_eq!(manager.status(second_id), TxnStatus::Aborted);
+        }
+        results
+    }
+
+    #[test]
+    fn test_serializable_prevents_write_skew() {
+        let (first, second) = write_skew(IsolationLevel::Snapshot);
+        first.unwrap();
+        second.unwrap();
+
+        let (first, second) = write_skew(IsolationLevel::Serializable);
+        first.unwrap();
+        assert!(matches!(second, Err(StorageError::SerializationFailure(_))));
+    }
+
+    #[test]
+    fn test_serializable_phantom() {
+        let dir = tempdir().unwrap();
+        let db = open(dir.path());
+        let (pool, manager) = (db.pool(), db.transactions());
+        let mut heap = MvccHeap::new(HeapFile::open(pool).unwrap());
+
+        // Each counts the rows, then inserts one if there are none
+        let serializable = IsolationLevel::Serializable;
+        let txns = [
+            manager.begin_with(serializable).unwrap(),
+            manager.begin_with(serializable).unwrap(),
+        ];
+        for txn in &txns {
+            let rows = heap.scan(pool, manager, txn).count();
+            assert_eq!(rows, 0);
+            heap.insert(pool, txn, b"row").unwrap();
+        }
+
+        // Neither saw the other's row, so only one may commit
+        let [first, second] = txns;
+        manager.commit(first).unwrap();
+        assert!(matches!(
+            manager.commit(second),
+            Err(StorageError::SerializationFailure(_))
+        ));
+
+        let reader = manager.begin().unwrap();
+        let rows = heap.scan(pool, manager, reader.snapshot()).count();
+        assert_eq!(rows, 1);
+    }
+
+    #[test]
+    fn test_savepoints() {
+        fn subtxn(pool: &BufferPool, heap: &MvccHeap, rid: RecordId) -> SubTxnId {
+            let record = heap.heap().get(pool, rid).unwrap().unwrap();
+            TupleHeader::split(&record).unwrap().0.subtxn_id
+        }
+        fn get(
+            pool: &BufferPool,
+            heap: &MvccHeap,
+            manager: &TransactionManager,
+            txn: &Transaction,
+            rid: RecordId,
+        ) -> Option<Vec<u8>> {
+            heap.get(pool, manager, txn.snapshot(), rid).unwrap()
+        }
+
+        let dir = tempdir().unwrap();
+        let db = open(dir.path());
+        let (pool, manager) = (db.pool(), db.transactions());
+        let mut heap = MvccHeap::new(HeapFile::open(pool).unwrap());
+        let tree = BTree::create(pool).unwrap();
+
+        let setup = manager.begin().unwrap();
+        let kept = heap.insert(pool, &setup, b"kept").unwrap();
+        manager.commit(setup).unwrap();
+
+        let mut txn = manager.begin().unwrap();
+        let before = heap.insert(pool, &txn, b"before").unwrap();
+
+        txn.savepoint("outer");
+        let inner_rows = {
+            heap.update(pool, manager, &txn, kept, b"changed")
+                .unwrap()
+                .unwrap();
+            tree.insert(pool, Some(&txn), b"key", b"value").unwrap();
+            vec![heap.insert(pool, &txn, b"outer").unwrap()]
+        };
+        txn.savepoint("inner");
+        let inner = heap.insert(pool, &txn, b"inner").unwrap();
+
+        assert_eq!(subtxn(pool, &heap, before), 0);
+        assert_eq!(subtxn(pool, &heap, inner_rows[0]), 1);
+        assert_eq!(subtxn(pool, &heap, inner), 2);
+
+        manager.rollback_to_savepoint(&mut txn, "outer").unwrap();
+        assert!(matches!(
+            txn.release_savepoint("inner"),
+            Err(StorageError::SavepointNotFound(_))
+        ));
+        for rid in [inner_rows[0], inner] {
+            assert_eq!(heap.heap().get(pool, rid).unwrap(), None);
+        }
+        assert_eq!(tree.get(pool, b"key").unwrap(), None);
+
+        // Still usable, and the savepoint can be rolled back to again
+        txn.next_command();
+        assert_eq!(get(pool, &heap, manager, &txn, kept).unwrap(), b"kept");
+        assert_eq!(get(pool, &heap, manager, &txn, before).unwrap(), b"before");
+        assert_eq!(txn.snapshot().subtxn_id, 3);
+
+        // Our changes from the rolled back subtransactions are hidden, even
+        // if one had been left behind
+        let ours = |subtxn_id| TupleHeader {
+            subtxn_id,
+            ..TupleHeader::new(txn.id(), 0)
+        };
+        let visible = |subtxn_id| txn.snapshot().is_visible(&ours(subtxn_id), manager);
+        assert!(visible(0));
+        assert!(!visible(1));
+        assert!(!visible(2));
+        assert!(visible(3));
+
+        let again = heap.insert(pool, &txn, b"again").unwrap();
+        assert_eq!(subtxn(pool, &heap, again), 3);
+
+        txn.release_savepoint("outer").unwrap();
+        assert_eq!(txn.snapshot().subtxn_id, 0);
+        manager.commit(txn).unwrap();
+
+        let reader = manager.begin().unwrap();
+        assert_eq!(get(pool, &heap, manager, &reader, kept).unwrap(), b"kept");
+        assert_eq!(
+            get(pool, &heap, manager, &reader, before).unwrap(),
+            b"before"
+        );
+        assert_eq!(get(pool, &heap, manager, &reader, again).unwrap(), b"again");
+
+        // Aborting after a partial rollback undoes the rest
+        let mut txn = manager.begin().unwrap();
+        txn.savepoint("s");
+        let rolled_back = heap.insert(pool, &txn, b"x").unwrap();
+        manager.rollback_to_savepoint(&mut txn, "s").unwrap();
+        let aborted = heap.insert(pool, &txn, b"y").unwrap();
+        manager.abort(txn).unwrap();
+        for rid in [rolled_back, aborted] {
+            assert_eq!(heap.heap().get(pool, rid).unwrap(), None);
+        }
+        assert!(heap.heap().get(pool, before).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_commit_takes_checkpoint_once_log_builds_up() {
+        let dir = tempdir().unwrap();
+        let wal = Arc::new(Wal::with_segment_size(&dir.path().join("wal"), 4096).unwrap());
+        let mut file = PageFile::create_new(&dir.path().join("test.jdb")).unwrap();
+        file.attach_wal(Arc::clone(&wal));
+        let mut pool = BufferPool::new(file, 16);
+        pool.attach_wal(Arc::clone(&wal));
+        let pool = Arc::new(pool);
+        let manager = TransactionManager::new(Arc::clone(&pool), Arc::clone(&wal)).unwrap();
+        let mut heap = MvccHeap::new(HeapFile::open(&pool).unwrap());
+
+        assert_eq!(wal.last_checkpoint(), None);
+        for n in 0..200u32 {
+            let txn = manager.begin().unwrap();
+            heap.insert(&pool, &txn, &[n as u8; 100]).unwrap();
+            manager.commit(txn).unwrap();
+        }
+
+        assert!(wal.last_checkpoint().is_some());
+        assert!(wal.next_lsn() - wal.redo_lsn() < CHECKPOINT_SEGMENTS * wal.segment_size());
+    }
+
+    #[test]
+    fn test_unfinished_rolled_back_on_open() {
+        let dir = tempdir().unwrap();
+        let (first, kept, lost, txn_id) = {
+            let db = open(dir.path());
+            let (pool, manager) = (db.pool(), db.transactions());
+            let mut heap = MvccHeap::new(HeapFile::open(pool).unwrap());
+            let tree = BTree::create(pool).unwrap();
+
+            let setup = manager.begin().unwrap();
+            let kept = heap.insert(pool, &setup, b"kept").unwrap();
+            manager.commit(setup).unwrap();
+
+            let txn = manager.begin().unwrap();
+            let lost = {
+                heap.update(pool, manager, &txn, kept, b"changed")
+                    .unwrap()
+                    .unwrap();
+                tree.insert(pool, Some(&txn), b"key", b"value").unwrap();
+                heap.insert(pool, &txn, b"lost").unwrap()
+            };
+            let txn_id = txn.id();
+            // Cut short: neither committed nor aborted
+            std::mem::forget(txn);
+            (tree.root_page(), kept, lost, txn_id)
+        };
+
+        let db = open(dir.path());
+        let (pool, manager) = (db.pool(), db.transactions());
+        assert_eq!(db.recovery_report().losers, vec![txn_id]);
+        assert_eq!(manager.status(txn_id), TxnStatus::Aborted);
+
+        let heap = MvccHeap::new(HeapFile::open(pool).unwrap());
+        let tree = BTree::open(pool, first).unwrap();
+        let reader = manager.begin().unwrap();
+        assert!(reader.id() > txn_id);
+        assert_eq!(
+            heap.get(pool, manager, reader.snapshot(), kept)
+                .unwrap()
+                .unwrap(),
+            b"kept"
+        );
+        assert_eq!(heap.heap().get(pool, lost).unwrap(), None);
+        assert_eq!(tree.get(pool, b"key").unwrap(), None);
+
+        // Nothing left to undo the next time
+        drop(db);
+        let db = open(dir.path());
+        assert!(db.recovery_report().losers.is_empty());
+    }
+}
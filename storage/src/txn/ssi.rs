@@ -0,0 +1,489 @@
+// storage/src/txn/ssi.rs
+
+use crate::heap::RecordId;
+use crate::mvcc::{CommitStatus, Snapshot, TupleHeader, TxnStatus, INVALID_TXN_ID};
+use crate::wal::TxnId;
+use crate::{Result, StorageError};
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Record locks a transaction may hold on one page before they are traded
+/// for a lock on the page
+const MAX_RECORD_LOCKS_PER_PAGE: usize = 2;
+
+/// Page locks a transaction may hold in one heap before they are traded
+/// for a lock on the heap
+const MAX_PAGE_LOCKS_PER_RELATION: usize = 32;
+
+/// What a serializable transaction has read. Heaps are identified by
+/// `HeapFile::id`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum SireadTarget {
+    Relation(u32),
+    Page { relation: u32, page_id: u32 },
+    Record { relation: u32, rid: RecordId },
+}
+
+impl SireadTarget {
+    /// The next coarser target covering this one.
+    fn parent(self) -> Option<SireadTarget> {
+        match self {
+            SireadTarget::Relation(_) => None,
+            SireadTarget::Page { relation, .. } => Some(SireadTarget::Relation(relation)),
+            SireadTarget::Record { relation, rid } => Some(SireadTarget::Page {
+                relation,
+                page_id: rid.page_id,
+            }),
+        }
+    }
+
+    fn covered_by(self, other: SireadTarget) -> bool {
+        let mut target = Some(self);
+        while let Some(current) = target {
+            if current == other {
+                return true;
+            }
+            target = current.parent();
+        }
+        false
+    }
+}
+
+/// Tracks the rw-antidependencies between serializable transactions, as
+/// PostgreSQL's serializable snapshot isolation does.
+///
+/// Reads leave SIREAD locks, which never block anyone. A transaction that
+/// writes what a concurrent one holds a SIREAD lock on, or that wrote a
+/// version a concurrent reader could not see, gets an rw-conflict from the
+/// reader. A pivot with a conflict in and a conflict out is only safe if
+/// the transaction it conflicts out to did not commit first; otherwise the
+/// pivot, or the transaction conflicting in to it if the pivot committed
+/// already, fails with `StorageError::SerializationFailure`.
+///
+/// Committed transactions are remembered, SIREAD locks and all, until
+/// every transaction that overlapped them has finished.
+#[derive(Default)]
+pub(crate) struct SsiTracker {
+    state: Mutex<SsiState>,
+}
+
+#[derive(Default)]
+struct SsiState {
+    /// Orders begins and commits
+    seq: u64,
+    txns: HashMap<TxnId, SsiTxn>,
+    locks: HashMap<SireadTarget, HashSet<TxnId>>,
+}
+
+struct SsiTxn {
+    begin_seq: u64,
+    commit_seq: Option<u64>,
+    /// Chosen to fail to break a dangerous structure
+    doomed: bool,
+    /// Concurrent transactions that read something this one wrote
+    conflicts_in: HashSet<TxnId>,
+    /// Concurrent transactions that wrote something this one read
+    conflicts_out: HashSet<TxnId>,
+    locks: HashSet<SireadTarget>,
+}
+
+impl SsiState {
+    fn failure(&self, txn_id: TxnId) -> Result<()> {
+        match self.txns.get(&txn_id) {
+            Some(txn) if txn.doomed => Err(StorageError::SerializationFailure(txn_id)),
+            _ => Ok(()),
+        }
+    }
+
+    fn add_lock(&mut self, txn_id: TxnId, target: SireadTarget) {
+        let txn = self.txns.get_mut(&txn_id).expect("tracked transaction");
+        if txn.locks.iter().any(|&held| target.covered_by(held)) {
+            return;
+        }
+
+        // Finer locks the new one covers are redundant
+        let covered: Vec<SireadTarget> = txn
+            .locks
+            .iter()
+            .copied()
+            .filter(|held| held.covered_by(target))
+            .collect();
+        for held in &covered {
+            txn.locks.remove(held);
+        }
+        txn.locks.insert(target);
+
+        let siblings = target.parent().map(|parent| {
+            let count = txn
+                .locks
+                .iter()
+                .filter(|held| held.parent() == Some(parent))
+                .count();
+            (parent, count)
+        });
+
+        for held in covered {
+            self.remove_holder(held, txn_id);
+        }
+        self.locks.entry(target).or_default().insert(txn_id);
+
+        let limit = match target {
+            SireadTarget::Record { .. } => MAX_RECORD_LOCKS_PER_PAGE,
+            _ => MAX_PAGE_LOCKS_PER_RELATION,
+        };
+        if let Some((parent, count)) = siblings {
+            if count > limit {
+                self.add_lock(txn_id, parent);
+            }
+        }
+    }
+
+    fn remove_holder(&mut self, target: SireadTarget, txn_id: TxnId) {
+        if let Some(holders) = self.locks.get_mut(&target) {
+            holders.remove(&txn_id);
+            if holders.is_empty() {
+                self.locks.remove(&target);
+            }
+        }
+    }
+
+    /// Note that `reader` read something `writer` wrote, then look for a
+    /// dangerous structure through either.
+    fn add_conflict(&mut self, reader: TxnId, writer: TxnId) {
+        if reader == writer || !self.txns.contains_key(&reader) {
+            return;
+        }
+        let Some(txn) = self.txns.get_mut(&writer) else {
+            return;
+        };
+        txn.conflicts_in.insert(reader);
+        let txn = self.txns.get_mut(&reader).expect("tracked transaction");
+        txn.conflicts_out.insert(writer);
+
+        self.check(reader);
+        self.check(writer);
+    }
+
+    /// Doom a transaction if `pivot` has a conflict in from one transaction
+    /// and out to another that committed before both.
+    fn check(&mut self, pivot: TxnId) {
+        if let Some(victim) = self.victim(pivot) {
+            self.txns.get_mut(&victim).expect("tracked").doomed = true;
+        }
+    }
+
+    fn victim(&self, pivot: TxnId) -> Option<TxnId> {
+        let txn = &self.txns[&pivot];
+        let commit_seq = |txn_id: &TxnId| self.txns[txn_id].commit_seq;
+
+        for out_seq in txn.conflicts_out.iter().filter_map(commit_seq) {
+            if txn.commit_seq.is_some_and(|seq| seq < out_seq) {
+                continue;
+            }
+            for reader in &txn.conflicts_in {
+                // The same transaction on both sides is a cycle of two
+                if commit_seq(reader).is_some_and(|seq| seq < out_seq) {
+                    continue;
+                }
+                if txn.commit_seq.is_none() {
+                    return Some(pivot);
+                }
+                if commit_seq(reader).is_none() {
+                    return Some(*reader);
+                }
+            }
+        }
+        None
+    }
+
+    /// Forget committed transactions no running one overlaps.
+    fn cleanup(&mut self) {
+        let oldest = self
+            .txns
+            .values()
+            .filter(|txn| txn.commit_seq.is_none())
+            .map(|txn| txn.begin_seq)
+            .min()
+            .unwrap_or(u64::MAX);
+        let finished: Vec<TxnId> = self
+            .txns
+            .iter()
+            .filter(|(_, txn)| txn.commit_seq.is_some_and(|seq| seq < oldest))
+            .map(|(&txn_id, _)| txn_id)
+            .collect();
+        for txn_id in finished {
+            self.forget(txn_id);
+        }
+    }
+
+    fn forget(&mut self, txn_id: TxnId) {
+        let Some(txn) = self.txns.remove(&txn_id) else {
+            return;
+        };
+        for target in txn.locks {
+            self.remove_holder(target, txn_id);
+        }
+        for other in txn.conflicts_in.iter().chain(&txn.conflicts_out) {
+            if let Some(other) = self.txns.get_mut(other) {
+                other.conflicts_in.remove(&txn_id);
+                other.conflicts_out.remove(&txn_id);
+            }
+        }
+    }
+}
+
+impl SsiTracker {
+    pub(crate) fn begin(&self, txn_id: TxnId) {
+        let mut state = self.state.lock();
+        state.seq += 1;
+        let txn = SsiTxn {
+            begin_seq: state.seq,
+            commit_seq: None,
+            doomed: false,
+            conflicts_in: HashSet::new(),
+            conflicts_out: HashSet::new(),
+            locks: HashSet::new(),
+        };
+        state.txns.insert(txn_id, txn);
+    }
+
+    /// Decide whether `txn_id` may commit; if so, it counts as committed
+    /// from here on.
+    pub(crate) fn commit(&self, txn_id: TxnId) -> Result<()> {
+        let mut state = self.state.lock();
+        state.check(txn_id);
+        state.failure(txn_id)?;
+
+        state.seq += 1;
+        let seq = state.seq;
+        let txn = state.txns.get_mut(&txn_id).expect("tracked transaction");
+        txn.commit_seq = Some(seq);
+
+        // Pivots this one conflicts out of may now have committed too late
+        let pivots: Vec<TxnId> = txn.conflicts_in.iter().copied().collect();
+        for pivot in pivots {
+            state.check(pivot);
+        }
+        state.cleanup();
+        Ok(())
+    }
+
+    pub(crate) fn abort(&self, txn_id: TxnId) {
+        let mut state = self.state.lock();
+        state.forget(txn_id);
+        state.cleanup();
+    }
+}
+
+/// The conflict tracking of one serializable transaction, as seen by the
+/// heap it reads and writes.
+#[derive(Clone)]
+pub(crate) struct Serializable {
+    pub(crate) txn_id: TxnId,
+    pub(crate) tracker: Arc<SsiTracker>,
+}
+
+impl Serializable {
+    /// Leave a SIREAD lock on `target`.
+    pub(crate) fn read(&self, target: SireadTarget) -> Result<()> {
+        let mut state = self.tracker.state.lock();
+        state.failure(self.txn_id)?;
+        state.add_lock(self.txn_id, target);
+        Ok(())
+    }
+
+    /// Note a conflict out to whoever made `tuple` look different to
+    /// `snapshot` than it will to later transactions: the writer of a
+    /// version it cannot see yet, or the deleter of one it still sees.
+    pub(crate) fn read_version(
+        &self,
+        snapshot: &Snapshot,
+        status: &dyn CommitStatus,
+        tuple: &TupleHeader,
+        visible: bool,
+    ) -> Result<()> {
+        let writer = if visible { tuple.xmax } else { tuple.xmin };
+        if writer == INVALID_TXN_ID
+            || writer == self.txn_id
+            || snapshot.finished(writer)
+            || status.status(writer) == TxnStatus::Aborted
+        {
+            return Ok(());
+        }
+
+        let mut state = self.tracker.state.lock();
+        state.add_conflict(self.txn_id, writer);
+        state.failure(self.txn_id)
+    }
+
+    /// Note conflicts in from every concurrent transaction holding a SIREAD
+    /// lock covering the record about to be written.
+    pub(crate) fn write(&self, relation: u32, rid: RecordId) -> Result<()> {
+        let mut state = self.tracker.state.lock();
+        state.failure(self.txn_id)?;
+
+        let begin_seq = state.txns[&self.txn_id].begin_seq;
+        let targets = [
+            SireadTarget::Record { relation, rid },
+            SireadTarget::Page {
+                relation,
+                page_id: rid.page_id,
+            },
+            SireadTarget::Relation(relation),
+        ];
+        let readers: Vec<TxnId> = targets
+            .iter()
+            .filter_map(|target| state.locks.get(target))
+            .flatten()
+            .copied()
+            .filter(|reader| {
+                // Committed before this one began, so it read nothing of ours
+                state.txns[reader]
+                    .commit_seq
+                    .is_none_or(|seq| seq > begin_seq)
+            })
+            .collect();
+        for reader in readers {
+            state.add_conflict(reader, self.txn_id);
+        }
+        state.failure(self.txn_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker(txn_ids: &[TxnId]) -> Arc<SsiTracker> {
+        let tracker = Arc::new(SsiTracker::default());
+        for &txn_id in txn_ids {
+            tracker.begin(txn_id);
+        }
+        tracker
+    }
+
+    fn handle(tracker: &Arc<SsiTracker>, txn_id: TxnId) -> Serializable {
+        Serializable {
+            txn_id,
+            tracker: Arc::clone(tracker),
+        }
+    }
+
+    fn record(slot: u16) -> SireadTarget {
+        SireadTarget::Record {
+            relation: 0,
+            rid: RecordId::new(3, slot),
+        }
+    }
+
+    fn failed(result: Result<()>, txn_id: TxnId) -> bool {
+        matches!(result, Err(StorageError::SerializationFailure(id)) if id == txn_id)
+    }
+
+    #[test]
+    fn test_lock_promotion() {
+        let tracker = tracker(&[1]);
+        let txn = handle(&tracker, 1);
+        for slot in 0..MAX_RECORD_LOCKS_PER_PAGE as u16 {
+            txn.read(record(slot)).unwrap();
+        }
+        let locks = |tracker: &SsiTracker| tracker.state.lock().txns[&1].locks.clone();
+        assert_eq!(locks(&tracker).len(), MAX_RECORD_LOCKS_PER_PAGE);
+
+        txn.read(record(100)).unwrap();
+        let page = SireadTarget::Page {
+            relation: 0,
+            page_id: 3,
+        };
+        assert_eq!(locks(&tracker), HashSet::from([page]));
+        assert!(!tracker.state.lock().locks.contains_key(&record(0)));
+
+        // Covered already
+        txn.read(record(0)).unwrap();
+        assert_eq!(locks(&tracker), HashSet::from([page]));
+
+        txn.read(SireadTarget::Relation(0)).unwrap();
+        assert_eq!(locks(&tracker), HashSet::from([SireadTarget::Relation(0)]));
+    }
+
+    #[test]
+    fn test_write_skew() {
+        let tracker = tracker(&[1, 2]);
+        let (t1, t2) = (handle(&tracker, 1), handle(&tracker, 2));
+
+        // Each reads both records and writes the one the other reads
+        for txn in [&t1, &t2] {
+            txn.read(record(0)).unwrap();
+            txn.read(record(1)).unwrap();
+        }
+        t1.write(0, RecordId::new(3, 0)).unwrap();
+        t2.write(0, RecordId::new(3, 1)).unwrap();
+
+        tracker.commit(1).unwrap();
+        assert!(failed(tracker.commit(2), 2));
+    }
+
+    #[test]
+    fn test_conflicts_without_cycle() {
+        let tracker = tracker(&[1, 2]);
+        let (t1, t2) = (handle(&tracker, 1), handle(&tracker, 2));
+
+        // 1 reads what 2 writes: fine in either commit order
+        t1.read(record(0)).unwrap();
+        t2.write(0, RecordId::new(3, 0)).unwrap();
+        tracker.commit(2).unwrap();
+        tracker.commit(1).unwrap();
+
+        // Readers that committed before a writer began do not conflict
+        tracker.begin(3);
+        handle(&tracker, 3).read(record(0)).unwrap();
+        tracker.commit(3).unwrap();
+        tracker.begin(4);
+        handle(&tracker, 4).write(0, RecordId::new(3, 0)).unwrap();
+        assert!(tracker.state.lock().txns[&4].conflicts_in.is_empty());
+        tracker.commit(4).unwrap();
+
+        assert!(tracker.state.lock().txns.is_empty());
+        assert!(tracker.state.lock().locks.is_empty());
+    }
+
+    #[test]
+    fn test_pivot_fails() {
+        // 2 reads what 3 writes and writes what 1 read; 3 commits first
+        let tracker = tracker(&[1, 2, 3]);
+        let (t1, t2, t3) = (
+            handle(&tracker, 1),
+            handle(&tracker, 2),
+            handle(&tracker, 3),
+        );
+        t1.read(record(1)).unwrap();
+        t2.read(record(0)).unwrap();
+        t3.write(0, RecordId::new(3, 0)).unwrap();
+        tracker.commit(3).unwrap();
+
+        assert!(failed(t2.write(0, RecordId::new(3, 1)), 2));
+        tracker.abort(2);
+        tracker.commit(1).unwrap();
+    }
+
+    #[test]
+    fn test_committed_pivot_dooms_reader() {
+        let tracker = tracker(&[1, 2, 3]);
+        let (t1, t2, t3) = (
+            handle(&tracker, 1),
+            handle(&tracker, 2),
+            handle(&tracker, 3),
+        );
+        t2.read(record(0)).unwrap();
+        t3.write(0, RecordId::new(3, 0)).unwrap();
+        tracker.commit(3).unwrap();
+        t2.write(0, RecordId::new(3, 1)).unwrap();
+        tracker.commit(2).unwrap();
+
+        // 1 then misses a version 2 wrote; only 1 can still fail
+        tracker.state.lock().add_conflict(1, 2);
+        assert!(failed(t1.read(record(2)), 1));
+        assert!(failed(tracker.commit(1), 1));
+    }
+}
@@ -0,0 +1,799 @@
+// storage/src/wal/mod.rs
+
+use crate::page::Page;
+use crate::{Result, StorageError};
+use parking_lot::{Mutex, RwLock, RwLockReadGuard};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+mod record;
+
+pub use record::{LogBody, LogRecord};
+
+/// Log sequence number: the byte position of a record in the log stream.
+/// 0 is never a valid LSN and means "no record".
+pub type Lsn = u64;
+
+/// Transaction identifier carried by every log record
+pub type TxnId = u64;
+
+/// Transaction id of records logged outside any transaction. They are
+/// redo-only, so recovery never has anything of theirs to roll back.
+pub const NO_TXN: TxnId = 0;
+
+/// Magic number at the start of every segment file
+const SEGMENT_MAGIC: [u8; 8] = *b"JDBWAL01";
+
+const SEGMENT_HEADER_SIZE: u64 = 16; // magic + start LSN
+
+pub const DEFAULT_SEGMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+/// LSN of the very first record in a log
+const FIRST_LSN: Lsn = 1;
+
+/// File in the log directory naming the last completed checkpoint
+const CONTROL_FILE: &str = "checkpoint";
+
+const CONTROL_MAGIC: [u8; 8] = *b"JDBCKP01";
+
+const CONTROL_SIZE: usize = 20; // magic + checkpoint LSN + crc32
+
+/// An append-only write-ahead log split into fixed-size segment files.
+///
+/// Each segment is named after the LSN of its first record, and records
+/// never span segments. The log is safe to share between threads: appends
+/// are serialised internally and `flushed_lsn` can be read without locking.
+///
+/// `checkpoint` bounds how much of the log recovery reads and keeps: the
+/// last completed checkpoint is named in a small control file next to the
+/// segments, and segments nothing needs any more are deleted.
+pub struct Wal {
+    dir: PathBuf,
+    segment_size: u64,
+    writer: Mutex<WalWriter>,
+    flushed_lsn: AtomicU64, // every record below this LSN is on stable storage
+    /// Kept from moving while a change is checked against it and logged
+    redo_lsn: RwLock<Lsn>,
+    /// Last completed checkpoint; held while one is taken
+    checkpoint: Mutex<Option<Lsn>>,
+}
+
+struct WalWriter {
+    file: File,
+    segment_start: Lsn,
+    segments: Vec<Lsn>, // start LSN of every segment, ascending
+    next_lsn: Lsn,
+    txn_first_lsn: HashMap<TxnId, Lsn>,
+    txn_last_lsn: HashMap<TxnId, Lsn>,
+}
+
+impl Wal {
+    pub fn open(dir: &Path) -> Result<Self> {
+        Self::with_segment_size(dir, DEFAULT_SEGMENT_SIZE)
+    }
+
+    pub fn with_segment_size(dir: &Path, segment_size: u64) -> Result<Self> {
+        fs::create_dir_all(dir).map_err(StorageError::Io)?;
+
+        let segments = list_segments(dir)?;
+        let writer = match segments.last() {
+            None => WalWriter {
+                file: create_segment(dir, FIRST_LSN)?,
+                segment_start: FIRST_LSN,
+                segments: vec![FIRST_LSN],
+                next_lsn: FIRST_LSN,
+                txn_first_lsn: HashMap::new(),
+                txn_last_lsn: HashMap::new(),
+            },
+            Some(&segment_start) => {
+                let mut file = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(segment_path(dir, segment_start))
+                    .map_err(StorageError::Io)?;
+
+                // Anything after the last intact record is a torn write
+                let end = find_segment_end(&mut file, segment_start)?;
+                file.set_len(SEGMENT_HEADER_SIZE + (end - segment_start))
+                    .map_err(StorageError::Io)?;
+                file.seek(SeekFrom::End(0)).map_err(StorageError::Io)?;
+
+                WalWriter {
+                    file,
+                    segment_start,
+                    segments,
+                    next_lsn: end,
+                    txn_first_lsn: HashMap::new(),
+                    txn_last_lsn: HashMap::new(),
+                }
+            }
+        };
+
+        let checkpoint = read_control(dir)?;
+        let wal = Self {
+            dir: dir.to_path_buf(),
+            segment_size,
+            flushed_lsn: AtomicU64::new(writer.next_lsn),
+            redo_lsn: RwLock::new(writer.segments[0]),
+            writer: Mutex::new(writer),
+            checkpoint: Mutex::new(checkpoint),
+        };
+
+        if let Some(lsn) = checkpoint {
+            match wal.read_record(lsn)?.body {
+                LogBody::Checkpoint { redo_lsn, .. } => {
+                    *wal.redo_lsn.write() = redo_lsn;
+                }
+                _ => return Err(StorageError::CorruptLogRecord(lsn)),
+            }
+        }
+        Ok(wal)
+    }
+
+    /// Append a record for `txn_id` and return its LSN.
+    ///
+    /// The record is handed to the OS but not synced; call `flush` or
+    /// `flush_to` before relying on it surviving a crash.
+    pub fn append(&self, txn_id: TxnId, body: LogBody) -> Result<Lsn> {
+        let mut writer = self.writer.lock();
+
+        let lsn = writer.next_lsn;
+        let prev_lsn = writer.txn_last_lsn.get(&txn_id).copied().unwrap_or(0);
+        let ends_txn = txn_id == NO_TXN || matches!(body, LogBody::Commit | LogBody::End);
+
+        let bytes = LogRecord {
+            lsn,
+            prev_lsn,
+            txn_id,
+            body,
+        }
+        .encode();
+
+        let used = lsn - writer.segment_start;
+        if used > 0 && used + bytes.len() as u64 > self.segment_size {
+            self.roll_segment(&mut writer)?;
+        }
+
+        writer.file.write_all(&bytes).map_err(StorageError::Io)?;
+        writer.next_lsn += bytes.len() as u64;
+
+        if ends_txn {
+            writer.txn_first_lsn.remove(&txn_id);
+            writer.txn_last_lsn.remove(&txn_id);
+        } else {
+            writer.txn_first_lsn.entry(txn_id).or_insert(lsn);
+            writer.txn_last_lsn.insert(txn_id, lsn);
+        }
+
+        Ok(lsn)
+    }
+
+    /// Continue a transaction's `prev_lsn` chain from `last_lsn`, e.g. when
+    /// recovery rolls back a transaction that was in flight at a crash.
+    /// Its records from `first_lsn` on are kept until it ends.
+    pub fn resume_txn(&self, txn_id: TxnId, first_lsn: Lsn, last_lsn: Lsn) {
+        let mut writer = self.writer.lock();
+        writer.txn_first_lsn.insert(txn_id, first_lsn);
+        writer.txn_last_lsn.insert(txn_id, last_lsn);
+    }
+
+    /// Log a change to `page` and stamp the record's LSN into its header.
+    pub fn log_page(&self, txn_id: TxnId, page: &mut Page, body: LogBody) -> Result<Lsn> {
+        debug_assert_eq!(body.page_id(), Some(page.header().page_id));
+
+        let lsn = self.append(txn_id, body)?;
+        page.header_mut().lsn = lsn;
+        Ok(lsn)
+    }
+
+    /// Make every appended record durable.
+    pub fn flush(&self) -> Result<()> {
+        let writer = self.writer.lock();
+        writer.file.sync_data().map_err(StorageError::Io)?;
+        self.flushed_lsn.store(writer.next_lsn, Ordering::Release);
+        Ok(())
+    }
+
+    /// Make sure the record at `lsn` (and everything before it) is durable.
+    pub fn flush_to(&self, lsn: Lsn) -> Result<()> {
+        if lsn < self.flushed_lsn() {
+            return Ok(());
+        }
+        self.flush()
+    }
+
+    /// Records below this LSN are on stable storage.
+    pub fn flushed_lsn(&self) -> Lsn {
+        self.flushed_lsn.load(Ordering::Acquire)
+    }
+
+    /// Bytes a segment is allowed to grow to.
+    pub fn segment_size(&self) -> u64 {
+        self.segment_size
+    }
+
+    /// LSN the next appended record will get.
+    pub fn next_lsn(&self) -> Lsn {
+        self.writer.lock().next_lsn
+    }
+
+    /// Where recovery starts redoing once the checkpoint in progress, or
+    /// else the last one, has completed. A page last logged before it must
+    /// log an image with its next change, since its next write may tear.
+    pub fn redo_lsn(&self) -> Lsn {
+        *self.redo_lsn.read()
+    }
+
+    /// The redo point, kept from moving until the guard is dropped, so a
+    /// change checked against it is logged before any checkpoint starts.
+    pub(crate) fn hold_redo_lsn(&self) -> RwLockReadGuard<'_, Lsn> {
+        self.redo_lsn.read()
+    }
+
+    /// The `Checkpoint` record recovery starts from, if any.
+    pub fn last_checkpoint(&self) -> Option<Lsn> {
+        *self.checkpoint.lock()
+    }
+
+    /// Take a checkpoint. The redo point moves to the end of the log, then
+    /// `flush_pages` must make every page changed before it durable. A
+    /// `Checkpoint` record listing the transactions in flight is logged
+    /// and made the one recovery starts from, and segments holding nothing
+    /// recovery or a running transaction could need are deleted.
+    ///
+    /// Returns the LSN of the `Checkpoint` record. Checkpoints are taken
+    /// one at a time.
+    pub fn checkpoint(&self, flush_pages: impl FnOnce() -> Result<()>) -> Result<Lsn> {
+        let mut last = self.checkpoint.lock();
+
+        let redo_lsn = {
+            let mut redo = self.redo_lsn.write();
+            *redo = self.next_lsn();
+            *redo
+        };
+        flush_pages()?;
+
+        let active = {
+            let writer = self.writer.lock();
+            let mut active: Vec<_> = writer
+                .txn_last_lsn
+                .iter()
+                .map(|(&txn_id, &last_lsn)| (txn_id, writer.txn_first_lsn[&txn_id], last_lsn))
+                .collect();
+            active.sort_unstable();
+            active
+        };
+        let lsn = self.append(NO_TXN, LogBody::Checkpoint { redo_lsn, active })?;
+        self.flush()?;
+
+        write_control(&self.dir, lsn)?;
+        *last = Some(lsn);
+
+        self.truncate(redo_lsn)?;
+        Ok(lsn)
+    }
+
+    /// Delete the segments that end before both `redo_lsn` and the first
+    /// record of every transaction in flight.
+    fn truncate(&self, redo_lsn: Lsn) -> Result<()> {
+        let mut writer = self.writer.lock();
+        let keep_from = writer
+            .txn_first_lsn
+            .values()
+            .copied()
+            .fold(redo_lsn, Lsn::min);
+
+        // A segment ends where the next one starts
+        let obsolete = writer
+            .segments
+            .partition_point(|&start| start <= keep_from)
+            .saturating_sub(1);
+        for start in writer.segments.drain(..obsolete) {
+            match fs::remove_file(segment_path(&self.dir, start)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(StorageError::Io(e));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read the record starting at `lsn`.
+    pub fn read_record(&self, lsn: Lsn) -> Result<LogRecord> {
+        let (segment_start, end) = {
+            let writer = self.writer.lock();
+            if lsn < FIRST_LSN || lsn >= writer.next_lsn {
+                return Err(StorageError::Io(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("LSN {} is outside the log", lsn),
+                )));
+            }
+
+            let index = writer.segments.partition_point(|&start| start <= lsn);
+            if index == 0 {
+                return Err(StorageError::Io(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("LSN {} has been truncated from the log", lsn),
+                )));
+            }
+            (writer.segments[index - 1], writer.next_lsn)
+        };
+
+        let mut file =
+            File::open(segment_path(&self.dir, segment_start)).map_err(StorageError::Io)?;
+        file.seek(SeekFrom::Start(SEGMENT_HEADER_SIZE + (lsn - segment_start)))
+            .map_err(StorageError::Io)?;
+
+        let mut header = [0u8; 4];
+        file.read_exact(&mut header).map_err(StorageError::Io)?;
+        let len = LogRecord::encoded_len(&header)
+            .filter(|&len| lsn + len as u64 <= end)
+            .ok_or(StorageError::CorruptLogRecord(lsn))?;
+
+        let mut bytes = vec![0u8; len];
+        bytes[0..4].copy_from_slice(&header);
+        file.read_exact(&mut bytes[4..]).map_err(StorageError::Io)?;
+
+        LogRecord::decode(&bytes, lsn)
+    }
+
+    /// Iterate over the records from `lsn` up to the current end of the
+    /// log, or from the oldest record kept if that one has been truncated.
+    pub fn iter_from(&self, lsn: Lsn) -> WalIterator {
+        let writer = self.writer.lock();
+        WalIterator {
+            dir: self.dir.clone(),
+            segments: writer.segments.clone(),
+            next_lsn: lsn.max(writer.segments[0]),
+            end_lsn: writer.next_lsn,
+            loaded: None,
+        }
+    }
+
+    fn roll_segment(&self, writer: &mut WalWriter) -> Result<()> {
+        // Earlier segments are never synced again, so make this one durable
+        writer.file.sync_data().map_err(StorageError::Io)?;
+
+        let start = writer.next_lsn;
+        writer.file = create_segment(&self.dir, start)?;
+        writer.segment_start = start;
+        writer.segments.push(start);
+
+        Ok(())
+    }
+}
+
+/// Reads log records in LSN order. Yields an error and stops at the first
+/// record that cannot be read.
+pub struct WalIterator {
+    dir: PathBuf,
+    segments: Vec<Lsn>,
+    next_lsn: Lsn,
+    end_lsn: Lsn,
+    loaded: Option<(Lsn, Vec<u8>)>, // start LSN and record bytes of one segment
+}
+
+impl Iterator for WalIterator {
+    type Item = Result<LogRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next_lsn >= self.end_lsn {
+            return None;
+        }
+
+        let result = self.read_next();
+        if result.is_err() {
+            // Don't keep yielding errors for the same position
+            self.next_lsn = self.end_lsn;
+        }
+        Some(result)
+    }
+}
+
+impl WalIterator {
+    fn read_next(&mut self) -> Result<LogRecord> {
+        let lsn = self.next_lsn;
+        let index = self.segments.partition_point(|&start| start <= lsn);
+        if index == 0 {
+            return Err(StorageError::CorruptLogRecord(lsn));
+        }
+        let segment_start = self.segments[index - 1];
+
+        if self.loaded.as_ref().map(|(start, _)| *start) != Some(segment_start) {
+            let bytes =
+                fs::read(segment_path(&self.dir, segment_start)).map_err(StorageError::Io)?;
+            let records = bytes
+                .get(SEGMENT_HEADER_SIZE as usize..)
+                .ok_or(StorageError::CorruptLogRecord(lsn))?
+                .to_vec();
+            self.loaded = Some((segment_start, records));
+        }
+
+        let (_, records) = self.loaded.as_ref().unwrap();
+        let offset = (lsn - segment_start) as usize;
+
+        // Records never span segments, so the end of one moves us to the next
+        if offset == records.len() && index < self.segments.len() {
+            self.next_lsn = self.segments[index];
+            return self.read_next();
+        }
+
+        let bytes = &records[offset.min(records.len())..];
+        let len = LogRecord::encoded_len(bytes)
+            .filter(|&len| len <= bytes.len())
+            .ok_or(StorageError::CorruptLogRecord(lsn))?;
+
+        let record = LogRecord::decode(&bytes[..len], lsn)?;
+        self.next_lsn = lsn + len as u64;
+        Ok(record)
+    }
+}
+
+fn segment_path(dir: &Path, start: Lsn) -> PathBuf {
+    dir.join(format!("{:016X}.wal", start))
+}
+
+fn list_segments(dir: &Path) -> Result<Vec<Lsn>> {
+    let mut segments = Vec::new();
+
+    for entry in fs::read_dir(dir).map_err(StorageError::Io)? {
+        let name = entry.map_err(StorageError::Io)?.file_name();
+        let name = name.to_string_lossy();
+
+        if let Some(stem) = name.strip_suffix(".wal") {
+            if let Ok(start) = Lsn::from_str_radix(stem, 16) {
+                segments.push(start);
+            }
+        }
+    }
+
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+/// The checkpoint named by the control file, if there is one.
+fn read_control(dir: &Path) -> Result<Option<Lsn>> {
+    let bytes = match fs::read(dir.join(CONTROL_FILE)) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(StorageError::Io(e)),
+    };
+
+    let intact = bytes.len() == CONTROL_SIZE
+        && bytes[0..8] == CONTROL_MAGIC
+        && crc32fast::hash(&bytes[..16]).to_le_bytes() == bytes[16..20];
+    if !intact {
+        return Err(StorageError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "WAL control file is damaged",
+        )));
+    }
+
+    Ok(Some(u64::from_le_bytes(bytes[8..16].try_into().unwrap())))
+}
+
+/// Name `checkpoint_lsn` in the control file. The new file replaces the
+/// old one by a rename, so a crash leaves one or the other.
+fn write_control(dir: &Path, checkpoint_lsn: Lsn) -> Result<()> {
+    let mut bytes = Vec::with_capacity(CONTROL_SIZE);
+    bytes.extend_from_slice(&CONTROL_MAGIC);
+    bytes.extend_from_slice(&checkpoint_lsn.to_le_bytes());
+    let crc = crc32fast::hash(&bytes);
+    bytes.extend_from_slice(&crc.to_le_bytes());
+
+    let temp = dir.join(format!("{}.tmp", CONTROL_FILE));
+    let mut file = File::create(&temp).map_err(StorageError::Io)?;
+    file.write_all(&bytes).map_err(StorageError::Io)?;
+    file.sync_all().map_err(StorageError::Io)?;
+
+    fs::rename(&temp, dir.join(CONTROL_FILE)).map_err(StorageError::Io)?;
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .map_err(StorageError::Io)
+}
+
+fn create_segment(dir: &Path, start: Lsn) -> Result<File> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(segment_path(dir, start))
+        .map_err(StorageError::Io)?;
+
+    let mut header = [0u8; SEGMENT_HEADER_SIZE as usize];
+    header[0..8].copy_from_slice(&SEGMENT_MAGIC);
+    header[8..16].copy_from_slice(&start.to_le_bytes());
+    file.write_all(&header).map_err(StorageError::Io)?;
+
+    Ok(file)
+}
+
+/// Validate a segment and return the LSN just past its last intact record.
+fn find_segment_end(file: &mut File, segment_start: Lsn) -> Result<Lsn> {
+    let mut bytes = Vec::new();
+    file.seek(SeekFrom::Start(0)).map_err(StorageError::Io)?;
+    file.read_to_end(&mut bytes).map_err(StorageError::Io)?;
+
+    let header_ok = bytes.len() >= SEGMENT_HEADER_SIZE as usize
+        && bytes[0..8] == SEGMENT_MAGIC
+        && bytes[8..16] == segment_start.to_le_bytes();
+    if !header_ok {
+        return Err(StorageError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Invalid WAL segment header for segment {:016X}",
+                segment_start
+            ),
+        )));
+    }
+
+    let records = &bytes[SEGMENT_HEADER_SIZE as usize..];
+    let mut offset = 0;
+    while let Some(len) = LogRecord::encoded_len(&records[offset..]) {
+        let Some(record) = records.get(offset..offset + len) else {
+            break;
+        };
+        if LogRecord::decode(record, segment_start + offset as u64).is_err() {
+            break;
+        }
+        offset += len;
+    }
+
+    Ok(segment_start + offset as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::PageFile;
+    use crate::page::PageType;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    fn insert(page_id: u32, data: &[u8]) -> LogBody {
+        LogBody::Insert {
+            page_id,
+            slot: 0,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_append_and_read_back() {
+        let dir = tempdir().unwrap();
+        let wal = Wal::open(dir.path()).unwrap();
+
+        let first = wal.append(1, insert(1, b"a")).unwrap();
+        let second = wal.append(1, LogBody::Commit).unwrap();
+
+        assert_eq!(first, FIRST_LSN);
+        assert!(second > first);
+        assert_eq!(wal.read_record(first).unwrap().body, insert(1, b"a"));
+        assert_eq!(wal.read_record(second).unwrap().body, LogBody::Commit);
+    }
+
+    #[test]
+    fn test_prev_lsn_chains_per_transaction() {
+        let dir = tempdir().unwrap();
+        let wal = Wal::open(dir.path()).unwrap();
+
+        let a1 = wal.append(1, insert(1, b"a1")).unwrap();
+        let b1 = wal.append(2, insert(2, b"b1")).unwrap();
+        let a2 = wal.append(1, insert(1, b"a2")).unwrap();
+        let a3 = wal.append(1, LogBody::Commit).unwrap();
+        let a4 = wal.append(1, insert(1, b"next")).unwrap();
+        let a5 = wal.append(1, LogBody::Abort).unwrap();
+        let a6 = wal.append(1, LogBody::End).unwrap();
+        let a7 = wal.append(1, insert(1, b"after end")).unwrap();
+
+        assert_eq!(wal.read_record(a1).unwrap().prev_lsn, 0);
+        assert_eq!(wal.read_record(b1).unwrap().prev_lsn, 0);
+        assert_eq!(wal.read_record(a2).unwrap().prev_lsn, a1);
+        assert_eq!(wal.read_record(a3).unwrap().prev_lsn, a2);
+        // Commit ends the chain
+        assert_eq!(wal.read_record(a4).unwrap().prev_lsn, 0);
+        // Abort does not (compensation records follow), End does
+        assert_eq!(wal.read_record(a5).unwrap().prev_lsn, a4);
+        assert_eq!(wal.read_record(a6).unwrap().prev_lsn, a5);
+        assert_eq!(wal.read_record(a7).unwrap().prev_lsn, 0);
+    }
+
+    #[test]
+    fn test_log_page_stamps_increasing_lsns() {
+        let dir = tempdir().unwrap();
+        let wal = Wal::open(dir.path()).unwrap();
+        let mut page = Page::new(5, PageType::Data);
+
+        let mut last = 0;
+        for i in 0..10u8 {
+            let lsn = wal.log_page(1, &mut page, insert(5, &[i])).unwrap();
+            assert!(lsn > last);
+            assert_eq!(page.header().lsn, lsn);
+            last = lsn;
+        }
+    }
+
+    #[test]
+    fn test_iterate_across_segments() {
+        let dir = tempdir().unwrap();
+        let wal = Wal::with_segment_size(dir.path(), 128).unwrap();
+
+        let lsns: Vec<Lsn> = (0..20u8)
+            .map(|i| wal.append(1, insert(1, &[i; 20])).unwrap())
+            .collect();
+        assert!(list_segments(dir.path()).unwrap().len() > 1);
+
+        let records: Vec<LogRecord> = wal.iter_from(0).map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 20);
+        for (record, (i, &lsn)) in records.iter().zip(lsns.iter().enumerate()) {
+            assert_eq!(record.lsn, lsn);
+            assert_eq!(record.body, insert(1, &[i as u8; 20]));
+        }
+
+        // Random access into a later segment
+        assert_eq!(wal.read_record(lsns[15]).unwrap().lsn, lsns[15]);
+
+        // Iteration can start mid-log
+        assert_eq!(wal.iter_from(lsns[10]).count(), 10);
+    }
+
+    #[test]
+    fn test_reopen_continues_after_last_record() {
+        let dir = tempdir().unwrap();
+
+        let end = {
+            let wal = Wal::open(dir.path()).unwrap();
+            wal.append(1, insert(1, b"x")).unwrap();
+            wal.append(1, LogBody::Commit).unwrap();
+            wal.flush().unwrap();
+            wal.next_lsn()
+        };
+
+        let wal = Wal::open(dir.path()).unwrap();
+        assert_eq!(wal.next_lsn(), end);
+        assert_eq!(wal.flushed_lsn(), end);
+        assert_eq!(wal.append(2, LogBody::Commit).unwrap(), end);
+        assert_eq!(wal.iter_from(0).count(), 3);
+    }
+
+    #[test]
+    fn test_reopen_drops_torn_tail() {
+        let dir = tempdir().unwrap();
+
+        let (intact_end, torn_lsn) = {
+            let wal = Wal::open(dir.path()).unwrap();
+            wal.append(1, insert(1, b"kept")).unwrap();
+            let intact_end = wal.next_lsn();
+            let torn_lsn = wal.append(1, insert(1, b"lost in a crash")).unwrap();
+            wal.flush().unwrap();
+            (intact_end, torn_lsn)
+        };
+
+        // Simulate a torn write by chopping the last record short
+        let path = segment_path(dir.path(), FIRST_LSN);
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+
+        let wal = Wal::open(dir.path()).unwrap();
+        assert_eq!(wal.next_lsn(), intact_end);
+        assert_eq!(intact_end, torn_lsn);
+        assert_eq!(wal.iter_from(0).count(), 1);
+    }
+
+    #[test]
+    fn test_checkpoint_drops_segments_nothing_needs() {
+        let dir = tempdir().unwrap();
+        let wal = Wal::with_segment_size(dir.path(), 128).unwrap();
+
+        let old: Vec<Lsn> = (0..10u8)
+            .map(|i| wal.append(1, insert(1, &[i; 20])).unwrap())
+            .collect();
+        wal.append(1, LogBody::Commit).unwrap();
+        let running = wal.append(2, insert(2, b"running")).unwrap();
+        for i in 0..10u8 {
+            wal.append(3, insert(3, &[i; 20])).unwrap();
+        }
+        wal.append(3, LogBody::Commit).unwrap();
+
+        let redo_lsn = wal.next_lsn();
+        let lsn = wal.checkpoint(|| Ok(())).unwrap();
+        assert_eq!(wal.redo_lsn(), redo_lsn);
+        assert_eq!(wal.last_checkpoint(), Some(lsn));
+        assert_eq!(
+            wal.read_record(lsn).unwrap().body,
+            LogBody::Checkpoint {
+                redo_lsn,
+                active: vec![(2, running, running)],
+            }
+        );
+
+        // Transaction 2 may still have to be rolled back
+        assert!(wal.read_record(old[0]).is_err());
+        assert_eq!(
+            wal.read_record(running).unwrap().body,
+            insert(2, b"running")
+        );
+
+        // Once it ends, the next checkpoint drops the rest
+        wal.append(2, LogBody::End).unwrap();
+        wal.checkpoint(|| Ok(())).unwrap();
+        assert!(wal.read_record(running).is_err());
+        assert_eq!(list_segments(dir.path()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_checkpoint_survives_reopen() {
+        let dir = tempdir().unwrap();
+
+        let (redo_lsn, lsn) = {
+            let wal = Wal::open(dir.path()).unwrap();
+            wal.append(1, insert(1, b"x")).unwrap();
+            wal.append(1, LogBody::Commit).unwrap();
+            let lsn = wal.checkpoint(|| Ok(())).unwrap();
+            (wal.redo_lsn(), lsn)
+        };
+
+        let wal = Wal::open(dir.path()).unwrap();
+        assert_eq!(wal.last_checkpoint(), Some(lsn));
+        assert_eq!(wal.redo_lsn(), redo_lsn);
+    }
+
+    #[test]
+    fn test_failed_page_flush_leaves_last_checkpoint() {
+        let dir = tempdir().unwrap();
+        let wal = Wal::open(dir.path()).unwrap();
+        wal.append(1, insert(1, b"x")).unwrap();
+        let lsn = wal.checkpoint(|| Ok(())).unwrap();
+
+        wal.append(1, insert(1, b"y")).unwrap();
+        let failed = wal.checkpoint(|| Err(StorageError::Io(io::Error::other("disk full"))));
+        assert!(failed.is_err());
+
+        drop(wal);
+        assert_eq!(Wal::open(dir.path()).unwrap().last_checkpoint(), Some(lsn));
+    }
+
+    #[test]
+    fn test_damaged_control_file_is_reported() {
+        let dir = tempdir().unwrap();
+        Wal::open(dir.path())
+            .unwrap()
+            .checkpoint(|| Ok(()))
+            .unwrap();
+
+        let path = dir.path().join(CONTROL_FILE);
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[10] ^= 0xFF;
+        fs::write(&path, bytes).unwrap();
+
+        assert!(Wal::open(dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_write_page_flushes_log_first() {
+        let dir = tempdir().unwrap();
+        let wal = Arc::new(Wal::open(&dir.path().join("wal")).unwrap());
+        let mut file = PageFile::create_new(&dir.path().join("test.jdb")).unwrap();
+        file.attach_wal(Arc::clone(&wal));
+
+        let page_id = file.allocate_page().unwrap();
+        let mut page = Page::new(page_id, PageType::Data);
+        page.add_record(b"row").unwrap();
+        let lsn = wal.log_page(7, &mut page, insert(page_id, b"row")).unwrap();
+        page.update_checksum();
+
+        assert!(wal.flushed_lsn() <= lsn);
+        file.write_page(&page).unwrap();
+        assert!(wal.flushed_lsn() > lsn);
+    }
+}
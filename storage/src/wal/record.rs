@@ -0,0 +1,626 @@
+// storage/src/wal/record.rs
+
+use super::{Lsn, TxnId};
+use crate::page::Page;
+use crate::{Result, StorageError};
+use std::io;
+
+/// Size of the fixed part of every record on disk
+pub(crate) const RECORD_HEADER_SIZE: usize = 33;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RecordKind {
+    Insert = 1,
+    Delete = 2,
+    Update = 3,
+    PageImage = 4,
+    Commit = 5,
+    Abort = 6,
+    Compensation = 7,
+    End = 8,
+    HeapChange = 9,
+    IndexChange = 10,
+    FileHeader = 11,
+    Checkpoint = 12,
+}
+
+impl RecordKind {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(RecordKind::Insert),
+            2 => Some(RecordKind::Delete),
+            3 => Some(RecordKind::Update),
+            4 => Some(RecordKind::PageImage),
+            5 => Some(RecordKind::Commit),
+            6 => Some(RecordKind::Abort),
+            7 => Some(RecordKind::Compensation),
+            8 => Some(RecordKind::End),
+            9 => Some(RecordKind::HeapChange),
+            10 => Some(RecordKind::IndexChange),
+            11 => Some(RecordKind::FileHeader),
+            12 => Some(RecordKind::Checkpoint),
+            _ => None,
+        }
+    }
+}
+
+/// What a log record describes.
+///
+/// Page operations carry enough data to be both redone and undone: deletes
+/// keep the removed bytes and updates keep the before image. The heap and
+/// B+ tree log their record changes this way outside any transaction,
+/// which makes them redo-only.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogBody {
+    Insert {
+        page_id: u32,
+        slot: u16,
+        data: Vec<u8>,
+    },
+    Delete {
+        page_id: u32,
+        slot: u16,
+        data: Vec<u8>,
+    },
+    Update {
+        page_id: u32,
+        slot: u16,
+        before: Vec<u8>,
+        after: Vec<u8>,
+    },
+    /// A complete copy of a page. Redo-only: used when a page is formatted,
+    /// for every change logged through `PageWriteGuard::log_image`, and for
+    /// a page's first change since the redo point, so a torn write of it
+    /// can be rebuilt.
+    PageImage {
+        page_id: u32,
+        image: Vec<u8>,
+    },
+    Commit,
+    /// The transaction is rolling back; compensation records follow
+    Abort,
+    /// Redo-only record describing an undo step. `redo` is the inverse page
+    /// operation that was applied, or the logical change that was undone,
+    /// and `undo_next_lsn` is the next record of the transaction still to
+    /// be undone (0 = none).
+    Compensation {
+        undo_next_lsn: Lsn,
+        redo: Box<LogBody>,
+    },
+    /// The transaction is finished: committed, or fully rolled back
+    End,
+    /// A record of a transactional heap was inserted (`before` is `None`)
+    /// or rewritten. Undo-only, and undone through the heap rather than
+    /// the page, so other transactions' changes to the page survive; the
+    /// page itself is redone from its own records.
+    HeapChange {
+        page_id: u32,
+        slot: u16,
+        before: Option<Vec<u8>>,
+    },
+    /// An index entry was added (`before` is `None`), replaced or removed.
+    /// Undo-only, like `HeapChange`.
+    IndexChange {
+        root: u32,
+        key: Vec<u8>,
+        before: Option<Vec<u8>>,
+    },
+    /// A complete copy of the data file's header. Redo-only; recovery
+    /// replays the latest one.
+    FileHeader {
+        image: Vec<u8>,
+    },
+    /// Every page changed before `redo_lsn` is on disk, so recovery can
+    /// start there. `active` lists the transactions in flight when the
+    /// record was written, as `(txn_id, first_lsn, last_lsn)`.
+    Checkpoint {
+        redo_lsn: Lsn,
+        active: Vec<(TxnId, Lsn, Lsn)>,
+    },
+}
+
+impl LogBody {
+    /// The page this record modifies, if any.
+    pub fn page_id(&self) -> Option<u32> {
+        match self {
+            LogBody::Insert { page_id, .. }
+            | LogBody::Delete { page_id, .. }
+            | LogBody::Update { page_id, .. }
+            | LogBody::PageImage { page_id, .. } => Some(*page_id),
+            LogBody::Compensation { redo, .. } => redo.page_id(),
+            LogBody::Commit
+            | LogBody::Abort
+            | LogBody::End
+            | LogBody::HeapChange { .. }
+            | LogBody::IndexChange { .. }
+            | LogBody::FileHeader { .. }
+            | LogBody::Checkpoint { .. } => None,
+        }
+    }
+
+    /// Whether this record is undone through the heap or an index instead
+    /// of by a page operation.
+    pub fn is_logical(&self) -> bool {
+        matches!(
+            self,
+            LogBody::HeapChange { .. } | LogBody::IndexChange { .. }
+        )
+    }
+
+    /// The page operation that reverses this one, for page operations that
+    /// can be undone.
+    pub fn inverse(&self) -> Option<LogBody> {
+        match self {
+            LogBody::Insert {
+                page_id,
+                slot,
+                data,
+            } => Some(LogBody::Delete {
+                page_id: *page_id,
+                slot: *slot,
+                data: data.clone(),
+            }),
+            LogBody::Delete {
+                page_id,
+                slot,
+                data,
+            } => Some(LogBody::Insert {
+                page_id: *page_id,
+                slot: *slot,
+                data: data.clone(),
+            }),
+            LogBody::Update {
+                page_id,
+                slot,
+                before,
+                after,
+            } => Some(LogBody::Update {
+                page_id: *page_id,
+                slot: *slot,
+                before: after.clone(),
+                after: before.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Make this record's change to `page`, which must be the page it
+    /// modifies. Records that modify no page leave it alone.
+    pub(crate) fn apply(&self, page: &mut Page) -> Result<()> {
+        match self {
+            LogBody::Insert { slot, data, .. } => page.insert_record_at(*slot as usize, data),
+            LogBody::Delete { slot, .. } => {
+                page.delete_record(*slot as usize);
+                Ok(())
+            }
+            LogBody::Update { slot, after, .. } => page.update_record(*slot as usize, after),
+            LogBody::PageImage { image, .. } => {
+                if image.len() != page.size() {
+                    return Err(StorageError::Io(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Page image has the wrong size",
+                    )));
+                }
+                let image = Page::from_bytes(image)?;
+                page.as_bytes_mut().copy_from_slice(image.as_bytes());
+                Ok(())
+            }
+            LogBody::Compensation { redo, .. } => redo.apply(page),
+            LogBody::Commit
+            | LogBody::Abort
+            | LogBody::End
+            | LogBody::HeapChange { .. }
+            | LogBody::IndexChange { .. }
+            | LogBody::FileHeader { .. }
+            | LogBody::Checkpoint { .. } => Ok(()),
+        }
+    }
+
+    fn kind(&self) -> RecordKind {
+        match self {
+            LogBody::Insert { .. } => RecordKind::Insert,
+            LogBody::Delete { .. } => RecordKind::Delete,
+            LogBody::Update { .. } => RecordKind::Update,
+            LogBody::PageImage { .. } => RecordKind::PageImage,
+            LogBody::Commit => RecordKind::Commit,
+            LogBody::Abort => RecordKind::Abort,
+            LogBody::Compensation { .. } => RecordKind::Compensation,
+            LogBody::End => RecordKind::End,
+            LogBody::HeapChange { .. } => RecordKind::HeapChange,
+            LogBody::IndexChange { .. } => RecordKind::IndexChange,
+            LogBody::FileHeader { .. } => RecordKind::FileHeader,
+            LogBody::Checkpoint { .. } => RecordKind::Checkpoint,
+        }
+    }
+
+    fn encode_payload(&self, out: &mut Vec<u8>) {
+        match self {
+            LogBody::Insert {
+                page_id,
+                slot,
+                data,
+            }
+            | LogBody::Delete {
+                page_id,
+                slot,
+                data,
+            } => {
+                out.extend_from_slice(&page_id.to_le_bytes());
+                out.extend_from_slice(&slot.to_le_bytes());
+                put_bytes(out, data);
+            }
+            LogBody::Update {
+                page_id,
+                slot,
+                before,
+                after,
+            } => {
+                out.extend_from_slice(&page_id.to_le_bytes());
+                out.extend_from_slice(&slot.to_le_bytes());
+                put_bytes(out, before);
+                put_bytes(out, after);
+            }
+            LogBody::PageImage { page_id, image } => {
+                out.extend_from_slice(&page_id.to_le_bytes());
+                put_bytes(out, image);
+            }
+            LogBody::Compensation {
+                undo_next_lsn,
+                redo,
+            } => {
+                out.extend_from_slice(&undo_next_lsn.to_le_bytes());
+                out.push(redo.kind() as u8);
+                redo.encode_payload(out);
+            }
+            LogBody::HeapChange {
+                page_id,
+                slot,
+                before,
+            } => {
+                out.extend_from_slice(&page_id.to_le_bytes());
+                out.extend_from_slice(&slot.to_le_bytes());
+                put_optional_bytes(out, before.as_deref());
+            }
+            LogBody::IndexChange { root, key, before } => {
+                out.extend_from_slice(&root.to_le_bytes());
+                put_bytes(out, key);
+                put_optional_bytes(out, before.as_deref());
+            }
+            LogBody::FileHeader { image } => put_bytes(out, image),
+            LogBody::Checkpoint { redo_lsn, active } => {
+                out.extend_from_slice(&redo_lsn.to_le_bytes());
+                out.extend_from_slice(&(active.len() as u32).to_le_bytes());
+                for (txn_id, first_lsn, last_lsn) in active {
+                    out.extend_from_slice(&txn_id.to_le_bytes());
+                    out.extend_from_slice(&first_lsn.to_le_bytes());
+                    out.extend_from_slice(&last_lsn.to_le_bytes());
+                }
+            }
+            LogBody::Commit | LogBody::Abort | LogBody::End => {}
+        }
+    }
+
+    fn decode_payload(kind: RecordKind, reader: &mut Reader<'_>) -> Option<Self> {
+        let body = match kind {
+            RecordKind::Insert => LogBody::Insert {
+                page_id: reader.u32()?,
+                slot: reader.u16()?,
+                data: reader.bytes()?,
+            },
+            RecordKind::Delete => LogBody::Delete {
+                page_id: reader.u32()?,
+                slot: reader.u16()?,
+                data: reader.bytes()?,
+            },
+            RecordKind::Update => LogBody::Update {
+                page_id: reader.u32()?,
+                slot: reader.u16()?,
+                before: reader.bytes()?,
+                after: reader.bytes()?,
+            },
+            RecordKind::PageImage => LogBody::PageImage {
+                page_id: reader.u32()?,
+                image: reader.bytes()?,
+            },
+            RecordKind::Commit => LogBody::Commit,
+            RecordKind::Abort => LogBody::Abort,
+            RecordKind::Compensation => {
+                let undo_next_lsn = reader.u64()?;
+                let redo_kind = reader.u8().and_then(RecordKind::from_u8)?;
+                // Only page operations and logical changes can be compensated
+                if !matches!(
+                    redo_kind,
+                    RecordKind::Insert
+                        | RecordKind::Delete
+                        | RecordKind::Update
+                        | RecordKind::HeapChange
+                        | RecordKind::IndexChange
+                ) {
+                    return None;
+                }
+                LogBody::Compensation {
+                    undo_next_lsn,
+                    redo: Box::new(Self::decode_payload(redo_kind, reader)?),
+                }
+            }
+            RecordKind::End => LogBody::End,
+            RecordKind::HeapChange => LogBody::HeapChange {
+                page_id: reader.u32()?,
+                slot: reader.u16()?,
+                before: reader.optional_bytes()?,
+            },
+            RecordKind::IndexChange => LogBody::IndexChange {
+                root: reader.u32()?,
+                key: reader.bytes()?,
+                before: reader.optional_bytes()?,
+            },
+            RecordKind::FileHeader => LogBody::FileHeader {
+                image: reader.bytes()?,
+            },
+            RecordKind::Checkpoint => {
+                let redo_lsn = reader.u64()?;
+                let count = reader.u32()? as usize;
+                let mut active = Vec::new();
+                for _ in 0..count {
+                    active.push((reader.u64()?, reader.u64()?, reader.u64()?));
+                }
+                LogBody::Checkpoint { redo_lsn, active }
+            }
+        };
+        Some(body)
+    }
+}
+
+/// A record as stored in the log.
+///
+/// Layout on disk (little endian):
+/// `total_len u32 | crc32 u32 | lsn u64 | prev_lsn u64 | txn_id u64 | kind u8 | payload`
+/// where the CRC covers everything after the CRC field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    pub lsn: Lsn,
+    /// Previous record written by the same transaction (0 = none)
+    pub prev_lsn: Lsn,
+    pub txn_id: TxnId,
+    pub body: LogBody,
+}
+
+impl LogRecord {
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(RECORD_HEADER_SIZE + 16);
+        out.extend_from_slice(&[0u8; 8]); // length and CRC, filled in below
+        out.extend_from_slice(&self.lsn.to_le_bytes());
+        out.extend_from_slice(&self.prev_lsn.to_le_bytes());
+        out.extend_from_slice(&self.txn_id.to_le_bytes());
+        out.push(self.body.kind() as u8);
+        self.body.encode_payload(&mut out);
+
+        let total_len = out.len() as u32;
+        out[0..4].copy_from_slice(&total_len.to_le_bytes());
+        let crc = crc32fast::hash(&out[8..]);
+        out[4..8].copy_from_slice(&crc.to_le_bytes());
+
+        out
+    }
+
+    /// Length of the record starting at `bytes`, read from its header.
+    pub(crate) fn encoded_len(bytes: &[u8]) -> Option<usize> {
+        let len = u32::from_le_bytes(bytes.get(0..4)?.try_into().ok()?) as usize;
+        (len >= RECORD_HEADER_SIZE).then_some(len)
+    }
+
+    /// Decode a full record, checking it is intact and sits at `expected_lsn`.
+    pub(crate) fn decode(bytes: &[u8], expected_lsn: Lsn) -> Result<Self> {
+        let corrupt = || StorageError::CorruptLogRecord(expected_lsn);
+
+        let total_len = Self::encoded_len(bytes).ok_or_else(corrupt)?;
+        if bytes.len() != total_len {
+            return Err(corrupt());
+        }
+
+        let stored_crc = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        if crc32fast::hash(&bytes[8..]) != stored_crc {
+            return Err(corrupt());
+        }
+
+        let mut reader = Reader {
+            bytes: &bytes[8..],
+            position: 0,
+        };
+        let lsn = reader.u64().ok_or_else(corrupt)?;
+        let prev_lsn = reader.u64().ok_or_else(corrupt)?;
+        let txn_id = reader.u64().ok_or_else(corrupt)?;
+        let kind = reader
+            .u8()
+            .and_then(RecordKind::from_u8)
+            .ok_or_else(corrupt)?;
+        let body = LogBody::decode_payload(kind, &mut reader).ok_or_else(corrupt)?;
+
+        if lsn != expected_lsn || !reader.is_empty() {
+            return Err(corrupt());
+        }
+
+        Ok(Self {
+            lsn,
+            prev_lsn,
+            txn_id,
+            body,
+        })
+    }
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn put_optional_bytes(out: &mut Vec<u8>, bytes: Option<&[u8]>) {
+    match bytes {
+        Some(bytes) => {
+            out.push(1);
+            put_bytes(out, bytes);
+        }
+        None => out.push(0),
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Option<&[u8]> {
+        let end = self.position.checked_add(len)?;
+        let slice = self.bytes.get(self.position..end)?;
+        self.position = end;
+        Some(slice)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2)
+            .map(|b| u16::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Option<Vec<u8>> {
+        let len = self.u32()? as usize;
+        self.take(len).map(|b| b.to_vec())
+    }
+
+    fn optional_bytes(&mut self) -> Option<Option<Vec<u8>>> {
+        match self.u8()? {
+            0 => Some(None),
+            1 => self.bytes().map(Some),
+            _ => None,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_every_kind() {
+        let bodies = vec![
+            LogBody::Insert {
+                page_id: 3,
+                slot: 7,
+                data: b"row".to_vec(),
+            },
+            LogBody::Delete {
+                page_id: 3,
+                slot: 7,
+                data: b"row".to_vec(),
+            },
+            LogBody::Update {
+                page_id: 9,
+                slot: 1,
+                before: b"old".to_vec(),
+                after: b"newer".to_vec(),
+            },
+            LogBody::PageImage {
+                page_id: 4,
+                image: vec![0xAB; 64],
+            },
+            LogBody::Commit,
+            LogBody::Abort,
+            LogBody::Compensation {
+                undo_next_lsn: 42,
+                redo: Box::new(LogBody::Delete {
+                    page_id: 3,
+                    slot: 7,
+                    data: b"row".to_vec(),
+                }),
+            },
+            LogBody::End,
+            LogBody::HeapChange {
+                page_id: 5,
+                slot: 2,
+                before: None,
+            },
+            LogBody::HeapChange {
+                page_id: 5,
+                slot: 2,
+                before: Some(b"old".to_vec()),
+            },
+            LogBody::IndexChange {
+                root: 8,
+                key: b"key".to_vec(),
+                before: Some(b"value".to_vec()),
+            },
+            LogBody::Compensation {
+                undo_next_lsn: 43,
+                redo: Box::new(LogBody::IndexChange {
+                    root: 8,
+                    key: b"key".to_vec(),
+                    before: None,
+                }),
+            },
+            LogBody::FileHeader {
+                image: vec![0xCD; 512],
+            },
+            LogBody::Checkpoint {
+                redo_lsn: 77,
+                active: vec![(12, 60, 90), (13, 70, 71)],
+            },
+        ];
+
+        for (i, body) in bodies.into_iter().enumerate() {
+            let record = LogRecord {
+                lsn: 100 + i as u64,
+                prev_lsn: 50,
+                txn_id: 12,
+                body,
+            };
+            let bytes = record.encode();
+            assert_eq!(LogRecord::encoded_len(&bytes), Some(bytes.len()));
+            assert_eq!(LogRecord::decode(&bytes, record.lsn).unwrap(), record);
+        }
+    }
+
+    #[test]
+    fn test_decode_rejects_damage() {
+        let record = LogRecord {
+            lsn: 1,
+            prev_lsn: 0,
+            txn_id: 1,
+            body: LogBody::Insert {
+                page_id: 1,
+                slot: 0,
+                data: b"payload".to_vec(),
+            },
+        };
+        let mut bytes = record.encode();
+
+        // Wrong position
+        assert!(LogRecord::decode(&bytes, 2).is_err());
+
+        // Flipped payload bit
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x01;
+        assert!(matches!(
+            LogRecord::decode(&bytes, 1),
+            Err(StorageError::CorruptLogRecord(1))
+        ));
+    }
+}